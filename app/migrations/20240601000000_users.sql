@@ -0,0 +1,30 @@
+-- Kept to types both SQLite and Postgres understand, booleans are stored as 0/1
+CREATE TABLE users (
+    email TEXT PRIMARY KEY,
+    user_uuid TEXT NOT NULL,
+    display_name TEXT NOT NULL,
+    hashed_password TEXT NOT NULL,
+    email_verified BIGINT NOT NULL DEFAULT 0,
+    account_creation_time BIGINT NOT NULL,
+    version BIGINT NOT NULL DEFAULT 0
+);
+
+CREATE TABLE sessions (
+    session_id TEXT PRIMARY KEY,
+    email TEXT NOT NULL REFERENCES users (email) ON DELETE CASCADE,
+    session_expiry BIGINT NOT NULL
+);
+
+CREATE INDEX sessions_email ON sessions (email);
+
+CREATE TABLE verification_tokens (
+    token TEXT PRIMARY KEY,
+    email TEXT NOT NULL REFERENCES users (email) ON DELETE CASCADE,
+    request_time BIGINT NOT NULL
+);
+
+CREATE TABLE entitlements (
+    email TEXT NOT NULL REFERENCES users (email) ON DELETE CASCADE,
+    game_id TEXT NOT NULL,
+    PRIMARY KEY (email, game_id)
+);
@@ -0,0 +1,2 @@
+-- Set at the account's first checkout, and reused for every checkout after it
+ALTER TABLE users ADD COLUMN stripe_customer_id TEXT;
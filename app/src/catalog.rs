@@ -0,0 +1,268 @@
+use crate::currency::format_amount;
+use serde::{Deserialize, Serialize};
+
+/// The currency prices are shown and charged in when nothing more specific is known, and
+/// when a product has no price in the one asked for. Every product needs a price in it.
+pub const DEFAULT_CURRENCY: &str = "usd";
+
+/// Something we sell. The catalog itself is bundled with the server (see `server::catalog`),
+/// this type is shared so the store pages can render it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Product {
+    /// Also the id of the entitlement buying it grants
+    pub id: String,
+    pub title: String,
+    pub description: String,
+    #[serde(default)]
+    pub media: Vec<ProductMedia>,
+    /// Platforms builds are published for, matching the platform segment of download urls
+    #[serde(default)]
+    pub platforms: Vec<String>,
+    /// Third-party storefronts (e.g. `steam`) a key is handed out for with every purchase, from
+    /// the pools admins import
+    #[serde(default)]
+    pub key_platforms: Vec<String>,
+    pub prices: Vec<ProductPrice>,
+    /// Unix timestamp (seconds) the product goes on sale, if it isn't on sale already
+    #[serde(default)]
+    pub available_from: Option<i64>,
+    /// Unix timestamp (seconds) the product stops being sold
+    #[serde(default)]
+    pub available_until: Option<i64>,
+    /// Discounts that apply automatically while their window is open
+    #[serde(default)]
+    pub sales: Vec<Sale>,
+    /// Ids of the products a bundle is made of. Buying a bundle grants their entitlements
+    /// instead of its own.
+    #[serde(default)]
+    pub bundle: Vec<String>,
+    /// Collaborators paid a cut of every sale. Each is a percentage of what the buyer paid,
+    /// less tax, and together they must stay under 100.
+    #[serde(default)]
+    pub revenue_shares: Vec<RevenueShare>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProductMedia {
+    pub kind: MediaKind,
+    pub url: String,
+    #[serde(default)]
+    pub alt: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MediaKind {
+    Image,
+    Video,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProductPrice {
+    /// Lowercase ISO currency code, as Stripe uses
+    pub currency: String,
+    pub stripe_price_id: String,
+    /// In the currency's smallest unit, for display only. Stripe charges whatever the price says.
+    pub unit_amount: i64,
+}
+
+/// A collaborator's cut of the sales of a product
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RevenueShare {
+    /// The id of a collaborator added by an admin
+    pub collaborator_id: String,
+    pub percent: u8,
+}
+
+impl RevenueShare {
+    /// The share of `net` (a sale's amount less tax), rounded down so the shares never add up
+    /// to more than was taken
+    pub fn amount_of(&self, net: i64) -> i64 {
+        net.max(0) * i64::from(self.percent) / 100
+    }
+}
+
+/// A discount on a product between two times, e.g. a launch discount or a seasonal sale
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Sale {
+    /// Shown next to the price, e.g. "Launch discount"
+    pub name: String,
+    /// Unix timestamp (seconds)
+    pub starts_at: i64,
+    /// Unix timestamp (seconds)
+    pub ends_at: i64,
+    pub discount: SaleDiscount,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SaleDiscount {
+    /// A percentage off every price, charged by applying the Stripe coupon. Checkouts can't
+    /// take a promotion code as well.
+    Percentage {
+        percent_off: u8,
+        stripe_coupon_id: String,
+    },
+    /// Prices charged instead of the regular ones. Currencies without one aren't discounted.
+    Prices(Vec<ProductPrice>),
+}
+
+/// What a product costs right now in one currency
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Offer {
+    pub regular: ProductPrice,
+    /// What's charged. For percentage sales this is the regular Stripe price, with the amount
+    /// the coupon brings it down to.
+    pub price: ProductPrice,
+    /// The sale bringing the price down, if any
+    pub sale: Option<Sale>,
+    /// Taken off a bundle's price for the items of it the buyer already owns. Already
+    /// subtracted from `price`.
+    #[serde(default)]
+    pub owned_credit: i64,
+}
+
+impl Sale {
+    pub fn is_active_at(&self, now: i64) -> bool {
+        self.starts_at <= now && now < self.ends_at
+    }
+}
+
+impl Offer {
+    /// How much less than the regular price is charged
+    pub fn discount(&self) -> i64 {
+        self.regular.unit_amount - self.price.unit_amount
+    }
+
+    /// Whether `price` is exactly what its Stripe price charges (after the sale's coupon, if
+    /// any). Otherwise the amount has to be charged with an inline price.
+    pub fn matches_stripe_price(&self) -> bool {
+        self.owned_credit == 0
+    }
+
+    /// The Stripe coupon the sale needs applied at checkout
+    pub fn stripe_coupon_id(&self) -> Option<&str> {
+        match self.sale.as_ref().map(|sale| &sale.discount) {
+            Some(SaleDiscount::Percentage {
+                stripe_coupon_id, ..
+            }) => Some(stripe_coupon_id),
+            _ => None,
+        }
+    }
+}
+
+impl Product {
+    pub fn is_available_at(&self, now: i64) -> bool {
+        self.available_from.is_none_or(|from| from <= now)
+            && self.available_until.is_none_or(|until| now < until)
+    }
+
+    pub fn price_for(&self, currency: &str) -> Option<&ProductPrice> {
+        find_price(&self.prices, currency)
+    }
+
+    /// The price in `currency` at `now`, with the first sale running then applied
+    pub fn offer_for(&self, currency: &str, now: i64) -> Option<Offer> {
+        let regular = self.price_for(currency)?.clone();
+        let sale_price = |sale: &Sale| match &sale.discount {
+            SaleDiscount::Percentage { percent_off, .. } => Some(ProductPrice {
+                unit_amount: regular.unit_amount * (100 - i64::from((*percent_off).min(100))) / 100,
+                ..regular.clone()
+            }),
+            SaleDiscount::Prices(prices) => find_price(prices, currency).cloned(),
+        };
+        let sale = self
+            .sales
+            .iter()
+            .filter(|sale| sale.is_active_at(now))
+            .find_map(|sale| sale_price(sale).map(|price| (sale.clone(), price)));
+        Some(match sale {
+            Some((sale, price)) => Offer {
+                regular,
+                price,
+                sale: Some(sale),
+                owned_credit: 0,
+            },
+            None => Offer {
+                price: regular.clone(),
+                regular,
+                sale: None,
+                owned_credit: 0,
+            },
+        })
+    }
+
+    pub fn is_bundle(&self) -> bool {
+        !self.bundle.is_empty()
+    }
+
+    /// The entitlements buying this grants: its own, or those of a bundle's items
+    pub fn entitlements(&self) -> Vec<String> {
+        match self.is_bundle() {
+            true => self.bundle.clone(),
+            false => vec![self.id.clone()],
+        }
+    }
+
+    /// Whether someone with `owned` entitlements would get nothing new from buying this
+    pub fn is_owned_by(&self, owned: &[String]) -> bool {
+        self.entitlements()
+            .iter()
+            .all(|entitlement| owned.contains(entitlement))
+    }
+
+    /// The offer for someone who already has the `owned` entitlements. A bundle is cheaper by
+    /// the share of its items' regular prices they own, so completing a bundle never costs more
+    /// than it would have as a whole. Bundles with an item not priced in `currency` (or missing
+    /// from `catalog`) aren't discounted.
+    pub fn offer_for_owner(
+        &self,
+        catalog: &[Product],
+        currency: &str,
+        now: i64,
+        owned: &[String],
+    ) -> Option<Offer> {
+        let mut offer = self.offer_for(currency, now)?;
+        let item_prices: Option<Vec<(&str, i64)>> = self
+            .bundle
+            .iter()
+            .map(|item_id| {
+                let item = catalog.iter().find(|product| &product.id == item_id)?;
+                Some((item_id.as_str(), item.price_for(currency)?.unit_amount))
+            })
+            .collect();
+        let Some(item_prices) = item_prices else {
+            return Some(offer);
+        };
+        let total: i64 = item_prices.iter().map(|(_, amount)| amount).sum();
+        let owned_value: i64 = item_prices
+            .iter()
+            .filter(|(item_id, _)| owned.iter().any(|owned| owned == item_id))
+            .map(|(_, amount)| amount)
+            .sum();
+        if total > 0 && owned_value > 0 {
+            offer.owned_credit = offer.price.unit_amount * owned_value / total;
+            offer.price.unit_amount -= offer.owned_credit;
+        }
+        Some(offer)
+    }
+
+    /// The offer in `currency`, or in the default currency if the product isn't priced in it
+    pub fn offer_with_fallback(&self, currency: &str, now: i64) -> Option<Offer> {
+        self.offer_for(currency, now)
+            .or_else(|| self.offer_for(DEFAULT_CURRENCY, now))
+    }
+}
+
+fn find_price<'a>(prices: &'a [ProductPrice], currency: &str) -> Option<&'a ProductPrice> {
+    prices
+        .iter()
+        .find(|price| price.currency.eq_ignore_ascii_case(currency))
+}
+
+impl ProductPrice {
+    /// e.g. `19.99 USD`, or `1999 JPY` for currencies without minor units
+    pub fn display(&self) -> String {
+        format_amount(self.unit_amount, &self.currency)
+    }
+}
@@ -78,7 +78,7 @@ pub fn Header(
                     "Help"
                 </A>
                 <A
-                    href="checkout"
+                    href="store"
                     class=" text-t-color p-1.5 rounded-md bg-primary-color hover:bg-hover-accent-color glow-hover"
                 >
                     "Buy Game"
@@ -92,6 +92,12 @@ pub fn Header(
                             >
                                 "Download"
                             </A>
+                            <A
+                                href="account"
+                                class=" text-t-color p-1.5 rounded-md hover:bg-hover-accent-color glow-hover"
+                            >
+                                "Account"
+                            </A>
                         }
                             .into_view()
                     }
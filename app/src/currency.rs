@@ -0,0 +1,118 @@
+use serde::{Deserialize, Serialize};
+
+/// Stripe charges these in whole units, so their amounts have no decimals
+const ZERO_DECIMAL_CURRENCIES: &[&str] = &[
+    "bif", "clp", "djf", "gnf", "jpy", "kmf", "krw", "mga", "pyg", "rwf", "ugx", "vnd", "vuv",
+    "xaf", "xof", "xpf",
+];
+
+/// Countries that use the euro
+const EUROZONE: &[&str] = &[
+    "at", "be", "cy", "de", "ee", "es", "fi", "fr", "gr", "hr", "ie", "it", "lt", "lu", "lv", "mt",
+    "nl", "pt", "si", "sk",
+];
+
+/// The currency the store page shows and checkouts charge in, and the others it could
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CurrencySettings {
+    pub selected: String,
+    /// Every currency at least one product has a price in
+    pub available: Vec<String>,
+    /// Whether `selected` was picked by the user rather than guessed from their language
+    pub overridden: bool,
+}
+
+/// Formats an amount in the currency's smallest unit, e.g. `19.99 USD` or `1999 JPY`
+pub fn format_amount(amount: i64, currency: &str) -> String {
+    let code = currency.to_uppercase();
+    if ZERO_DECIMAL_CURRENCIES.contains(&currency.to_lowercase().as_str()) {
+        return format!("{} {}", amount, code);
+    }
+    let sign = if amount < 0 { "-" } else { "" };
+    let amount = amount.abs();
+    format!("{}{}.{:02} {}", sign, amount / 100, amount % 100, code)
+}
+
+/// The currency of a country, by ISO 3166 code
+pub fn currency_for_region(region: &str) -> Option<&'static str> {
+    let region = region.to_lowercase();
+    if EUROZONE.contains(&region.as_str()) {
+        return Some("eur");
+    }
+    Some(match region.as_str() {
+        "us" => "usd",
+        "ca" => "cad",
+        "gb" => "gbp",
+        "au" => "aud",
+        "nz" => "nzd",
+        "jp" => "jpy",
+        "kr" => "krw",
+        "ch" => "chf",
+        "se" => "sek",
+        "no" => "nok",
+        "dk" => "dkk",
+        "pl" => "pln",
+        "cz" => "czk",
+        "br" => "brl",
+        "mx" => "mxn",
+        "in" => "inr",
+        _ => return None,
+    })
+}
+
+/// The currency of a language spoken in only one currency area, for tags without a region
+fn currency_for_language(language: &str) -> Option<&'static str> {
+    Some(match language.to_lowercase().as_str() {
+        "ja" => "jpy",
+        "ko" => "krw",
+        "pl" => "pln",
+        "cs" => "czk",
+        "sv" => "sek",
+        "da" => "dkk",
+        _ => return None,
+    })
+}
+
+/// The currencies an `Accept-Language` header points to, most preferred first
+pub fn currencies_from_accept_language(header: &str) -> Vec<&'static str> {
+    let mut tags: Vec<(&str, f32)> = header
+        .split(',')
+        .filter_map(|entry| {
+            let mut parts = entry.split(';');
+            let tag = parts.next()?.trim();
+            let quality = parts
+                .filter_map(|part| part.trim().strip_prefix("q="))
+                .find_map(|q| q.trim().parse::<f32>().ok())
+                .unwrap_or(1.0);
+            (!tag.is_empty() && tag != "*" && quality > 0.0).then_some((tag, quality))
+        })
+        .collect();
+    // Stable, so equally preferred tags keep their order
+    tags.sort_by(|a, b| b.1.total_cmp(&a.1));
+    let mut currencies = Vec::new();
+    for (tag, _) in tags {
+        let mut subtags = tag.split(['-', '_']);
+        let language = subtags.next().unwrap_or_default();
+        let currency = subtags
+            .find(|subtag| subtag.len() == 2 && subtag.chars().all(|c| c.is_ascii_alphabetic()))
+            .and_then(currency_for_region)
+            .or_else(|| currency_for_language(language));
+        if let Some(currency) = currency {
+            if !currencies.contains(&currency) {
+                currencies.push(currency);
+            }
+        }
+    }
+    currencies
+}
+
+/// The first of `preferences` that's `available`, if any
+pub fn choose_currency<'a, S: AsRef<str>>(
+    preferences: impl IntoIterator<Item = &'a str>,
+    available: &[S],
+) -> Option<String> {
+    preferences
+        .into_iter()
+        .map(str::to_lowercase)
+        .find(|currency| available.iter().any(|a| a.as_ref() == currency))
+}
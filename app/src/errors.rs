@@ -21,6 +21,42 @@ pub enum NexusError {
     EmailAlreadyInUse,
     AccountNotVerified,
     BadEmailAddress,
+    ConcurrentModification,
+    DynamoThrottled,
+    DynamoConditionalCheckFailed,
+    DynamoValidationError,
+    DynamoTimeout,
+    ProductNotFound,
+    ProductUnavailable,
+    NoPriceForCurrency,
+    NotAuthorized,
+    PurchaseNotFound,
+    WebhookEventMalformed,
+    GiftMessageTooLong,
+    GiftNotFound,
+    GiftAlreadyClaimed,
+    GiftNotClaimable,
+    KeyInvalid,
+    KeyAlreadyRedeemed,
+    /// The key is the account's, but the product couldn't be granted yet. Redeeming it again is
+    /// safe and finishes the job.
+    KeyRedemptionIncomplete,
+    KeyBatchNotFound,
+    KeyBatchSizeInvalid,
+    KeyPlatformNotOffered,
+    TooManyAttempts,
+    AlreadyOwned,
+    CartEmpty,
+    CartFull,
+    NotRefundable,
+    RefundAlreadyRequested,
+    RefundRequestNotFound,
+    CollaboratorNotFound,
+    CollaboratorAlreadyExists,
+    CollaboratorIdInvalid,
+    ReportRangeInvalid,
+    ReconciliationRangeInvalid,
+    BuildNotFound,
     #[serde(other)]
     Unhandled,
 }
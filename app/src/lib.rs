@@ -1,6 +1,9 @@
+pub mod catalog;
 pub mod common;
+pub mod currency;
 pub mod error_template;
 pub mod errors;
+pub mod orders;
 pub mod pages;
 pub mod public;
 pub mod site;
@@ -12,12 +15,13 @@ use crate::{
     common::{footer::Footer, header::Header},
     error_template::{AppError, ErrorTemplate},
     pages::{
-        about::About, checkout::Checkout, checkout_cancel::CheckoutCancel,
-        checkout_success::CheckoutSuccess, credits::Credits, download::Download,
-        email_verification::EmailVerification,
+        about::About, account::Account, cart::Cart, checkout::Checkout,
+        checkout_cancel::CheckoutCancel, checkout_success::CheckoutSuccess, credits::Credits,
+        download::Download, email_verification::EmailVerification,
         email_verification_attempt::EmailVerificationAttempt,
-        end_user_license_agreement::EndUserLicenseAgreement, home::Home,
-        login_and_signup::LoginAndSignup, support_faq::SupportFAQ,
+        end_user_license_agreement::EndUserLicenseAgreement, gift::Gift, gifts::Gifts, home::Home,
+        login_and_signup::LoginAndSignup, orders::Orders, redeem::Redeem, reports::SalesReports,
+        store::Store, support_faq::SupportFAQ,
     },
 };
 use leptos::{
@@ -28,16 +32,15 @@ use leptos_meta::{provide_meta_context, Stylesheet, Title};
 use leptos_router::{ProtectedRoute, Route, Router, Routes};
 use public::{Login, Logout};
 
-#[derive(PartialEq, Debug, Clone, Copy)]
-#[derive(Default)]
+#[derive(PartialEq, Debug, Clone, Copy, Default)]
 pub enum AccountState {
     LoggedIn,
     #[default]
     LoggedOut,
 }
 
-
 #[derive(Debug, Clone)]
+#[allow(dead_code)]
 struct CSRFToken {
     value: String,
 }
@@ -46,7 +49,7 @@ struct CSRFToken {
 pub fn NexusApp() -> impl IntoView {
     // Provides context that manages stylesheets, titles, meta tags, etc.
     provide_meta_context();
-    let (bought_game, set_bought_game) = create_signal(false);
+    let (_bought_game, _set_bought_game) = create_signal(false);
     let csrf_token: Option<CSRFToken> = Option::None;
     provide_context(csrf_token);
     let login = create_server_action::<Login>();
@@ -119,9 +122,18 @@ pub fn NexusApp() -> impl IntoView {
                         />
                         <Route path="email_verification" view=EmailVerification/>
                         <Route path="email_verification/:uuid" view=EmailVerificationAttempt/>
-                        <Route path="checkout" view=Checkout/>
+                        <Route path="store" view=Store/>
+                        <Route path="cart" view=Cart/>
+                        <Route path="cart/checkout" view=Checkout/>
+                        <Route path="checkout/:product_id" view=Checkout/>
                         <Route path="checkout/cancel" view=CheckoutCancel/>
                         <Route path="checkout/success" view=CheckoutSuccess/>
+                        <Route path="gift/:claim_token" view=Gift/>
+                        <Route path="gifts" view=Gifts/>
+                        <Route path="redeem" view=Redeem/>
+                        <Route path="orders" view=Orders/>
+                        <Route path="account" view=Account/>
+                        <Route path="admin/reports" view=SalesReports/>
                     </Routes>
                 </main>
                 <Footer/>
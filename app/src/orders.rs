@@ -0,0 +1,454 @@
+use serde::{Deserialize, Serialize};
+
+/// Where a checkout stands, for the page Stripe sends the buyer back to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CheckoutStatus {
+    /// Paid for, the game is (or is about to be) in the buyer's library
+    Paid,
+    /// A delayed payment method was used, and the money hasn't arrived yet
+    PaymentPending,
+    /// The delayed payment failed
+    PaymentFailed,
+    /// Paid for, but since refunded, disputed or flagged
+    Revoked,
+    /// Paid for as a gift, and on its way to the recipient
+    GiftSent,
+}
+
+/// Longest gift message we accept. Stripe metadata values can't be any longer.
+pub const GIFT_MESSAGE_MAX_LENGTH: usize = 500;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GiftStatus {
+    Unclaimed,
+    Claimed,
+    /// Refunded, disputed or flagged, so it can't be claimed (or no longer grants the game)
+    Revoked,
+}
+
+/// A gift as the recipient sees it on the claim page
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GiftPreview {
+    pub product_id: String,
+    pub product_title: String,
+    pub sender_name: String,
+    pub message: String,
+    pub recipient_email: String,
+    pub status: GiftStatus,
+}
+
+/// A gift as the buyer sees it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SentGift {
+    pub product_id: String,
+    pub product_title: String,
+    pub recipient_email: String,
+    /// Unix timestamp (seconds)
+    pub sent_at: i64,
+    pub claimed_at: Option<i64>,
+    pub status: GiftStatus,
+}
+
+/// Most keys an admin can generate in one batch
+pub const KEY_BATCH_MAX_SIZE: usize = 1000;
+
+/// A batch of product keys, as admins see it in the list of batches
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyBatchSummary {
+    pub batch_id: String,
+    pub product_id: String,
+    /// What the batch is for, e.g. "PAX 2024 booth"
+    pub label: String,
+    pub created_by: String,
+    /// Unix timestamp (seconds)
+    pub created_at: i64,
+    pub key_count: i64,
+}
+
+/// A key from a batch that someone has redeemed
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RedeemedKey {
+    /// The last group of the key
+    pub key_hint: String,
+    pub redeemed_by_user_uuid: String,
+    /// Unix timestamp (seconds)
+    pub redeemed_at: i64,
+}
+
+/// Which keys of a batch have been redeemed, and which haven't
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyBatchReport {
+    pub batch: KeyBatchSummary,
+    /// Oldest redemption first
+    pub redeemed: Vec<RedeemedKey>,
+    /// Hints of the keys nobody has redeemed yet
+    pub unredeemed: Vec<String>,
+}
+
+/// A third-party storefront key in the buyer's library
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OwnedExternalKey {
+    pub product_id: String,
+    pub product_title: String,
+    /// e.g. `steam`
+    pub platform: String,
+    pub key: String,
+}
+
+/// What importing a CSV of third-party keys did
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyImportSummary {
+    pub imported: usize,
+    /// Keys that were already in the pool
+    pub duplicates: usize,
+    /// 1-based line numbers that didn't hold a key
+    pub rejected_lines: Vec<usize>,
+    /// Keys left to hand out, now that these were added
+    pub available: i64,
+}
+
+/// Where a purchase stands, as its buyer sees it in their order history
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OrderStatus {
+    PaymentPending,
+    PaymentFailed,
+    Paid,
+    Refunded,
+    /// Disputed with the buyer's bank, whether or not the dispute is settled
+    Disputed,
+    /// Held back while a fraud warning is looked into
+    UnderReview,
+}
+
+/// A purchase in the buyer's order history
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Order {
+    /// The id of the checkout session (or redeemed key) the purchase was made with
+    pub order_id: String,
+    pub product_id: String,
+    pub product_title: String,
+    /// In the currency's smallest unit, including tax
+    pub amount_total: i64,
+    pub amount_tax: i64,
+    pub amount_discount: i64,
+    pub currency: String,
+    pub status: OrderStatus,
+    /// Unix timestamp (seconds)
+    pub created_at: i64,
+    pub gift: bool,
+    /// Where the buyer's request for a refund of this stands, if they made one
+    pub refund_request: Option<RefundRequestStatus>,
+}
+
+/// Most products a cart can hold
+pub const CART_MAX_ITEMS: usize = 20;
+
+/// A product in a cart, priced for whoever's cart it is
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CartLine {
+    pub product_id: String,
+    pub product_title: String,
+    /// The regular price, in the cart's currency's smallest unit
+    pub regular_amount: i64,
+    /// What's charged, after sales and credit for owned items
+    pub amount: i64,
+    pub sale: Option<String>,
+    /// Taken off a bundle for the items of it already owned
+    pub owned_credit: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Cart {
+    pub currency: String,
+    pub lines: Vec<CartLine>,
+    pub total: i64,
+}
+
+/// Longest explanation we accept with a refund request
+pub const REFUND_REASON_MAX_LENGTH: usize = 1000;
+
+/// Where a request for a refund stands
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RefundRequestStatus {
+    /// Accepted, and the refund is being made. Requests stuck here are retried when asked again.
+    Refunding,
+    /// Outside the refund policy, waiting for an admin to decide
+    PendingReview,
+    Refunded,
+    /// Turned down by an admin
+    Denied,
+}
+
+impl RefundRequestStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            RefundRequestStatus::Refunding => "refunding",
+            RefundRequestStatus::PendingReview => "pending_review",
+            RefundRequestStatus::Refunded => "refunded",
+            RefundRequestStatus::Denied => "denied",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "refunding" => Some(RefundRequestStatus::Refunding),
+            "pending_review" => Some(RefundRequestStatus::PendingReview),
+            "refunded" => Some(RefundRequestStatus::Refunded),
+            "denied" => Some(RefundRequestStatus::Denied),
+            _ => None,
+        }
+    }
+}
+
+/// What became of a refund request right away
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RefundOutcome {
+    Refunded,
+    /// Outside the refund policy, so an admin will look at it
+    QueuedForReview,
+}
+
+/// A buyer's request for their money back on one purchase
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RefundRequest {
+    /// The ledger id of the purchase to refund
+    pub purchase_id: String,
+    pub user_uuid: String,
+    /// The buyer's email when they asked
+    pub email: String,
+    pub product_id: String,
+    /// What a refund gives back, in the currency's smallest unit
+    pub amount: i64,
+    pub currency: String,
+    /// The buyer's explanation
+    pub reason: String,
+    /// Why the request fell outside the refund policy, for whoever reviews it
+    pub ineligibility: Option<String>,
+    pub status: RefundRequestStatus,
+    /// Unix timestamp (seconds)
+    pub requested_at: i64,
+    pub reviewed_by: Option<String>,
+    pub reviewed_at: Option<i64>,
+    pub stripe_refund_id: Option<String>,
+}
+
+/// Someone paid a share of the sales of the products they worked on, through their Stripe
+/// connected account
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Collaborator {
+    /// What the catalog's revenue shares refer to them by
+    pub collaborator_id: String,
+    pub name: String,
+    /// Where their onboarding links are sent
+    pub email: String,
+    pub stripe_account_id: String,
+    /// Whether their account has finished onboarding and can be transferred to
+    pub transfers_enabled: bool,
+    /// Unix timestamp (seconds)
+    pub created_at: i64,
+}
+
+/// Where a collaborator's share of one purchase stands
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RevenueTransferStatus {
+    /// Waiting for the purchase to clear the refund window, or for the collaborator to finish
+    /// onboarding
+    Pending,
+    Transferred,
+    /// The purchase was refunded (or its dispute lost) before anything was transferred
+    Cancelled,
+    /// Taken back from the collaborator after the purchase was refunded (or its dispute lost)
+    Reversed,
+}
+
+impl RevenueTransferStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            RevenueTransferStatus::Pending => "pending",
+            RevenueTransferStatus::Transferred => "transferred",
+            RevenueTransferStatus::Cancelled => "cancelled",
+            RevenueTransferStatus::Reversed => "reversed",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "pending" => Some(RevenueTransferStatus::Pending),
+            "transferred" => Some(RevenueTransferStatus::Transferred),
+            "cancelled" => Some(RevenueTransferStatus::Cancelled),
+            "reversed" => Some(RevenueTransferStatus::Reversed),
+            _ => None,
+        }
+    }
+}
+
+/// A collaborator's share of one purchase, in the ledger of what they've been paid
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RevenueTransfer {
+    /// `{purchase_id}#{collaborator_id}`
+    pub transfer_id: String,
+    /// The ledger id of the purchase the share is of
+    pub purchase_id: String,
+    pub collaborator_id: String,
+    pub product_id: String,
+    /// In the purchase's currency's smallest unit
+    pub amount: i64,
+    pub currency: String,
+    pub status: RevenueTransferStatus,
+    /// Unix timestamp (seconds) the share can be transferred from, once refunds of the purchase
+    /// are no longer granted without a review
+    pub available_at: i64,
+    pub stripe_transfer_id: Option<String>,
+    pub stripe_reversal_id: Option<String>,
+    /// Unix timestamp (seconds)
+    pub created_at: i64,
+    /// Unix timestamp (seconds)
+    pub updated_at: i64,
+}
+
+/// What one run of the revenue share payouts did
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RevenueSharePayouts {
+    pub transferred: usize,
+    /// Not yet available, or waiting on the purchase or the collaborator
+    pub held: usize,
+    /// Left pending to be tried again on the next run
+    pub failed: usize,
+}
+
+/// How a sales report groups purchases over time. Days and weeks are in UTC, and weeks start
+/// on Monday.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ReportPeriod {
+    #[default]
+    Daily,
+    Weekly,
+}
+
+impl ReportPeriod {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ReportPeriod::Daily => "daily",
+            ReportPeriod::Weekly => "weekly",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "daily" => Some(ReportPeriod::Daily),
+            "weekly" => Some(ReportPeriod::Weekly),
+            _ => None,
+        }
+    }
+}
+
+/// Sales figures summed over a group of purchases. Amounts are in the currency's smallest
+/// unit, so figures are only ever summed within one currency.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SalesFigures {
+    /// Purchases that were paid for, including ones refunded or disputed since
+    pub purchases: i64,
+    /// What buyers paid, tax included
+    pub gross: i64,
+    pub tax: i64,
+    /// Taken off regular prices by sales and promotion codes
+    pub discounts: i64,
+    /// What we kept after tax: purchases that were refunded or are (or were lost) in a dispute
+    /// count for nothing
+    pub net: i64,
+    /// Of `purchases`, how many were refunded
+    pub refunds: i64,
+    /// Of `purchases`, how many are disputed or lost their dispute
+    pub disputes: i64,
+}
+
+impl SalesFigures {
+    /// The fraction of purchases that were refunded
+    pub fn refund_rate(&self) -> f64 {
+        rate(self.refunds, self.purchases)
+    }
+
+    /// The fraction of purchases that were disputed
+    pub fn dispute_rate(&self) -> f64 {
+        rate(self.disputes, self.purchases)
+    }
+}
+
+fn rate(count: i64, purchases: i64) -> f64 {
+    match purchases {
+        0 => 0.0,
+        purchases => count as f64 / purchases as f64,
+    }
+}
+
+/// Sales of one product in one currency to one country, over one day or week
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SalesReportRow {
+    /// Unix timestamp (seconds) of the midnight the day or week starts at
+    pub period_start: i64,
+    pub product_id: String,
+    pub currency: String,
+    /// ISO 3166-1 alpha-2 code of the buyers' billing address, empty where it isn't known
+    pub country: String,
+    pub figures: SalesFigures,
+}
+
+/// Sales in one currency over the whole report
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CurrencySales {
+    pub currency: String,
+    pub figures: SalesFigures,
+}
+
+/// Sales recorded in the purchase ledger between two dates. Purchases count towards the
+/// period they were made in, so refunds and disputes show against the sales they undid.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SalesReport {
+    pub period: ReportPeriod,
+    /// Unix timestamp (seconds) of the first purchase the report could include
+    pub from: i64,
+    /// Unix timestamp (seconds) the report ends before
+    pub to: i64,
+    /// By period, then product, currency and country
+    pub rows: Vec<SalesReportRow>,
+    /// By currency
+    pub totals: Vec<CurrencySales>,
+}
+
+/// Something wrong that reconciling Stripe's checkouts with the ledger turned up
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum AnomalyKind {
+    /// A completed checkout is missing from the ledger, i.e. its webhook never made it
+    MissingPurchase,
+    /// Stripe says the delayed payment arrived, but the ledger still has it pending
+    UnsettledPayment,
+    /// Paid for, but the buyer's library is missing the product (or part of a bundle)
+    MissingEntitlement,
+    /// The buyer has a product that the ledger says they have no paid purchase of, or that
+    /// Stripe says wasn't paid for
+    EntitlementWithoutPayment,
+}
+
+/// One purchase that didn't add up
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReconciliationAnomaly {
+    pub kind: AnomalyKind,
+    /// The ledger id of the purchase
+    pub purchase_id: String,
+    pub user_uuid: String,
+    pub product_id: String,
+    /// Fixed by the reconciliation. Entitlements without payment are only ever reported, since
+    /// revoking them is for an admin to decide.
+    pub repaired: bool,
+}
+
+/// What one reconciliation run found
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReconciliationReport {
+    /// Unix timestamp (seconds) of the oldest checkout that was looked at
+    pub since: i64,
+    pub checkouts: usize,
+    /// Checkouts that couldn't be checked, e.g. because Stripe or the ledger was unavailable
+    pub failed: usize,
+    pub anomalies: Vec<ReconciliationAnomaly>,
+}
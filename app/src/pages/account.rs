@@ -0,0 +1,56 @@
+use crate::{errors::NexusError, public::DeleteAccount};
+use leptos::{component, create_server_action, view, IntoView, ServerFnError, SignalGet};
+use leptos_router::{ActionForm, A};
+
+/// Settings for the logged in account. Deleting it asks for the password again, so a session
+/// left open on a shared computer can't be used to do it.
+#[component]
+pub fn Account() -> impl IntoView {
+    let delete_account = create_server_action::<DeleteAccount>();
+
+    view! {
+        <h1>"Account"</h1>
+        <h2>"Delete your account"</h2>
+        <p>
+            "This removes your account and your library for good. Games you bought can't be downloaded again."
+        </p>
+        <ActionForm action=delete_account class="flex flex-col w-80">
+            <label>"Password:"</label>
+            <input
+                type="password"
+                name="password"
+                autocomplete="current-password"
+                required
+                class="text-gray-900"
+            />
+            <input
+                type="submit"
+                value="Delete my account"
+                class="w-max my-1 py-1 px-2 rounded-md bg-primary-color hover:bg-hover-accent-color glow-hover"
+            />
+        </ActionForm>
+        {move || match delete_account.value().get() {
+            None => None,
+            Some(Ok(_)) => {
+                Some(
+                    view! {
+                        <p>"Your account has been deleted. " <A href="/">"Back to the home page"</A></p>
+                    }
+                        .into_view(),
+                )
+            }
+            Some(Err(e)) => {
+                let message = match e {
+                    ServerFnError::WrappedServerError(NexusError::IncorrectPassword) => {
+                        "That password isn't right."
+                    }
+                    ServerFnError::WrappedServerError(NexusError::InvalidSession) => {
+                        "Log in again to delete your account."
+                    }
+                    _ => "Couldn't delete your account. Try again later.",
+                };
+                Some(view! { <p class="error">{message}</p> }.into_view())
+            }
+        }}
+    }
+}
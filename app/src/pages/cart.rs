@@ -0,0 +1,106 @@
+use crate::{
+    currency::format_amount,
+    errors::NexusError,
+    public::{list_cart, RemoveFromCart},
+};
+use leptos::{
+    component, create_resource, create_server_action, view, CollectView, IntoView, ServerFnError,
+    SignalGet, Suspense,
+};
+use leptos_router::{ActionForm, A};
+
+/// What the logged in user is about to buy
+#[component]
+pub fn Cart() -> impl IntoView {
+    let remove = create_server_action::<RemoveFromCart>();
+    let cart = create_resource(
+        move || remove.version().get(),
+        |_| async move { list_cart().await },
+    );
+
+    view! {
+        <h1>"Cart"</h1>
+        <Suspense fallback=move || {
+            view! { <p>"Loading..."</p> }
+        }>
+            {move || match cart.get() {
+                None => view! { <div>"Loading your cart..."</div> }.into_view(),
+                Some(Err(ServerFnError::WrappedServerError(NexusError::InvalidSession))) => {
+                    view! { <div class="error">"Log in to see your cart."</div> }.into_view()
+                }
+                Some(Err(_)) => {
+                    view! { <div class="error">"Could not load your cart."</div> }.into_view()
+                }
+                Some(Ok(cart)) if cart.lines.is_empty() => {
+                    view! {
+                        <div>"Your cart is empty. " <A href="/store">"Back to the store"</A></div>
+                    }
+                        .into_view()
+                }
+                Some(Ok(cart)) => {
+                    let currency = cart.currency.clone();
+                    view! {
+                        <table>
+                            {cart
+                                .lines
+                                .into_iter()
+                                .map(|line| {
+                                    let note = match (line.owned_credit, line.sale) {
+                                        (0, None) => String::new(),
+                                        (0, Some(sale)) => sale,
+                                        (credit, _) => {
+                                            format!(
+                                                "{} off for what you already own",
+                                                format_amount(credit, &currency),
+                                            )
+                                        }
+                                    };
+                                    view! {
+                                        <tr>
+                                            <td>{line.product_title}</td>
+                                            <td>
+                                                {(line.amount != line.regular_amount)
+                                                    .then(|| {
+                                                        view! {
+                                                            <s class="opacity-60">
+                                                                {format_amount(line.regular_amount, &currency)}
+                                                            </s>
+                                                            " "
+                                                        }
+                                                    })}
+                                                {format_amount(line.amount, &currency)}
+                                            </td>
+                                            <td class="text-sm">{note}</td>
+                                            <td>
+                                                <ActionForm action=remove>
+                                                    <input type="hidden" name="product_id" value=line.product_id/>
+                                                    <input type="submit" value="Remove" class="py-1 px-2 rounded-md"/>
+                                                </ActionForm>
+                                            </td>
+                                        </tr>
+                                    }
+                                })
+                                .collect_view()}
+                            <tr>
+                                <td>
+                                    <strong>"Total"</strong>
+                                </td>
+                                <td>
+                                    <strong>{format_amount(cart.total, &cart.currency)}</strong>
+                                </td>
+                            </tr>
+                        </table>
+                        <A
+                            href="/cart/checkout"
+                            class="text-color p-1.5 bg-primary-color rounded-md hover:bg-hover-accent-color glow-hover"
+                        >
+                            "Check out"
+                        </A>
+                    }
+                        .into_view()
+                }
+            }}
+
+        </Suspense>
+    }
+}
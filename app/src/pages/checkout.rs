@@ -1,9 +1,34 @@
 use crate::public::create_checkout;
-use leptos::{component, create_resource, view, ErrorBoundary, IntoView, SignalGet, Suspense};
+use leptos::{
+    component, create_resource, view, ErrorBoundary, IntoView, SignalGet, SignalWith, Suspense,
+};
+use leptos_router::{use_params_map, use_query_map};
 
 #[component]
 pub fn Checkout() -> impl IntoView {
-    let checkout_resource = create_resource(|| (), |_| async move { create_checkout().await });
+    let params = use_params_map();
+    // Filled in by the store's gift form
+    let query = use_query_map();
+    let checkout = move || {
+        let product_id =
+            params.with(|params| params.get("product_id").cloned().unwrap_or_default());
+        query.with(|query| {
+            (
+                product_id,
+                query
+                    .get("gift_recipient_email")
+                    .cloned()
+                    .unwrap_or_default(),
+                query.get("gift_message").cloned().unwrap_or_default(),
+            )
+        })
+    };
+    let checkout_resource = create_resource(
+        checkout,
+        |(product_id, gift_recipient_email, gift_message)| async move {
+            create_checkout(product_id, gift_recipient_email, gift_message).await
+        },
+    );
 
     let script = format!(
         "
@@ -1,7 +1,57 @@
-use leptos::{component, view, IntoView};
+use crate::{orders::CheckoutStatus, public::get_checkout_status};
+use leptos::{component, create_resource, view, IntoView, SignalGet, SignalWith, Suspense};
+use leptos_router::{use_query_map, A};
 
 #[component]
 pub fn CheckoutSuccess() -> impl IntoView {
-    view! { <div>"Checkout was successful, you should be able to download the game now."</div> }
-}
+    let query = use_query_map();
+    let session_id =
+        move || query.with(|query| query.get("session_id").cloned().unwrap_or_default());
+    let status = create_resource(session_id, |session_id| async move {
+        get_checkout_status(session_id).await
+    });
+
+    view! {
+        <Suspense fallback=move || {
+            view! { <p>"Loading..."</p> }
+        }>
+            {move || match status.get() {
+                None => view! { <div>"Checking your payment..."</div> },
+                Some(Ok(CheckoutStatus::Paid)) => {
+                    view! {
+                        <div>
+                            "Checkout was successful, you should be able to download the game now."
+                        </div>
+                    }
+                }
+                Some(Ok(CheckoutStatus::PaymentPending)) => {
+                    view! {
+                        <div>
+                            "Payment pending. Your payment method takes a few days to clear, we'll email you as soon as the game is yours."
+                        </div>
+                    }
+                }
+                Some(Ok(CheckoutStatus::PaymentFailed)) => {
+                    view! {
+                        <div>"Your payment didn't go through. Check your email for a link to try again."</div>
+                    }
+                }
+                Some(Ok(CheckoutStatus::GiftSent)) => {
+                    view! {
+                        <div>
+                            "Checkout was successful, we've emailed your gift to its recipient. "
+                            <A href="/gifts">"See whether it's been claimed"</A>
+                        </div>
+                    }
+                }
+                Some(Ok(CheckoutStatus::Revoked)) => {
+                    view! { <div>"This purchase has been refunded or is under review."</div> }
+                }
+                Some(Err(_)) => {
+                    view! { <div class="error">"We couldn't find this checkout."</div> }
+                }
+            }}
 
+        </Suspense>
+    }
+}
@@ -1,6 +1,54 @@
-use leptos::{component, view, IntoView};
+use crate::public::list_external_keys;
+use leptos::{component, create_resource, view, CollectView, IntoView, SignalGet, Suspense};
 
 #[component]
 pub fn Download() -> impl IntoView {
-    view! { <h1>"Download"</h1> }
+    view! {
+        <h1>"Download"</h1>
+        <ExternalKeys/>
+    }
+}
+
+/// Keys for other storefronts that came with the user's purchases
+#[component]
+fn ExternalKeys() -> impl IntoView {
+    let keys = create_resource(|| (), |_| async move { list_external_keys().await });
+
+    view! {
+        <Suspense fallback=move || {
+            view! { <p>"Loading..."</p> }
+        }>
+            {move || match keys.get() {
+                Some(Ok(keys)) if !keys.is_empty() => {
+                    view! {
+                        <h2 class="text-2xl">"Your keys"</h2>
+                        <table>
+                            <tr>
+                                <th>"Game"</th>
+                                <th>"Platform"</th>
+                                <th>"Key"</th>
+                            </tr>
+                            {keys
+                                .into_iter()
+                                .map(|key| {
+                                    view! {
+                                        <tr>
+                                            <td>{key.product_title}</td>
+                                            <td>{key.platform}</td>
+                                            <td>
+                                                <code>{key.key}</code>
+                                            </td>
+                                        </tr>
+                                    }
+                                })
+                                .collect_view()}
+                        </table>
+                    }
+                        .into_view()
+                }
+                _ => ().into_view(),
+            }}
+
+        </Suspense>
+    }
 }
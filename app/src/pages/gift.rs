@@ -0,0 +1,112 @@
+use crate::{
+    orders::{GiftPreview, GiftStatus},
+    public::{get_gift, ClaimGift, ClaimGiftWithNewAccount},
+};
+use leptos::{
+    component, create_resource, create_server_action, view, IntoView, SignalGet, SignalWith,
+    Suspense,
+};
+use leptos_router::{use_params_map, ActionForm, A};
+
+/// Where the link in a gift email leads
+#[component]
+pub fn Gift() -> impl IntoView {
+    let params = use_params_map();
+    let claim_token =
+        move || params.with(|params| params.get("claim_token").cloned().unwrap_or_default());
+    let gift = create_resource(claim_token, |claim_token| async move {
+        get_gift(claim_token).await
+    });
+
+    view! {
+        <h1>"You've been sent a gift"</h1>
+        <Suspense fallback=move || {
+            view! { <p>"Loading..."</p> }
+        }>
+            {move || match gift.get() {
+                None => view! { <div>"Loading your gift..."</div> }.into_view(),
+                Some(Err(_)) => {
+                    view! { <div class="error">"We couldn't find this gift."</div> }.into_view()
+                }
+                Some(Ok(gift)) => view! { <GiftDetails gift claim_token=claim_token()/> },
+            }}
+
+        </Suspense>
+    }
+}
+
+#[component]
+fn GiftDetails(gift: GiftPreview, claim_token: String) -> impl IntoView {
+    let claim = create_server_action::<ClaimGift>();
+    let claim_with_new_account = create_server_action::<ClaimGiftWithNewAccount>();
+    let claimed = move || {
+        matches!(claim.value().get(), Some(Ok(())))
+            || matches!(claim_with_new_account.value().get(), Some(Ok(())))
+    };
+    let message =
+        (!gift.message.is_empty()).then(|| view! { <blockquote>{gift.message}</blockquote> });
+    let new_account_claim_token = claim_token.clone();
+    let claim_forms = match gift.status {
+        GiftStatus::Claimed => view! { <p>"This gift has already been claimed."</p> }.into_view(),
+        GiftStatus::Revoked => view! { <p>"This gift is no longer available."</p> }.into_view(),
+        GiftStatus::Unclaimed => view! {
+            <div class="w-full flex flex-row box-border justify-evenly">
+                <ActionForm action=claim class="flex flex-col w-60">
+                    <h2 class="text-2xl">"I have an account"</h2>
+                    <p>"Log in first, then claim the gift for the account you're logged in as."</p>
+                    <input type="hidden" name="claim_token" value=claim_token/>
+                    <input
+                        type="submit"
+                        value="Claim"
+                        class="w-max py-1 px-2 rounded-md bg-primary-color hover:bg-hover-accent-color glow-hover"
+                    />
+                    {move || {
+                        matches!(claim.value().get(), Some(Err(_)))
+                            .then(|| view! { <p class="error">"Couldn't claim the gift. Are you logged in?"</p> })
+                    }}
+
+                </ActionForm>
+                <ActionForm action=claim_with_new_account class="flex flex-col w-60">
+                    <h2 class="text-2xl">"Create an account"</h2>
+                    <p>{format!("Your account will use {}.", gift.recipient_email)}</p>
+                    <input type="hidden" name="claim_token" value=new_account_claim_token/>
+                    <label>"Display name:"</label>
+                    <input type="text" name="display_name" maxlength="64" required class="text-gray-900"/>
+                    <label>"Password:"</label>
+                    <input type="password" name="password" minlength="10" required class="text-gray-900"/>
+                    <label>"Repeat password:"</label>
+                    <input
+                        type="password"
+                        name="password_confirmation"
+                        minlength="10"
+                        required
+                        class="text-gray-900"
+                    />
+                    <input
+                        type="submit"
+                        value="Create account and claim"
+                        class="w-max my-1 py-1 px-2 rounded-md bg-primary-color hover:bg-hover-accent-color glow-hover"
+                    />
+                    {move || {
+                        matches!(claim_with_new_account.value().get(), Some(Err(_)))
+                            .then(|| view! { <p class="error">"Couldn't create the account. If you already have one, log in and claim the gift with it."</p> })
+                    }}
+
+                </ActionForm>
+            </div>
+        }
+        .into_view(),
+    };
+
+    view! {
+        <p>{format!("{} sent you {}.", gift.sender_name, gift.product_title)}</p>
+        {message}
+        {move || match claimed() {
+            true => view! {
+                <p>"The game is yours! " <A href="/download">"Go to your library"</A></p>
+            }
+            .into_view(),
+            false => claim_forms.clone(),
+        }}
+    }
+}
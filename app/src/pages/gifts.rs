@@ -0,0 +1,68 @@
+use crate::{orders::GiftStatus, public::list_sent_gifts};
+use chrono::DateTime;
+use leptos::{component, create_resource, view, CollectView, IntoView, SignalGet, Suspense};
+
+fn format_date(timestamp: i64) -> String {
+    DateTime::from_timestamp(timestamp, 0)
+        .map(|date| date.format("%Y-%m-%d").to_string())
+        .unwrap_or_default()
+}
+
+/// The gifts the logged in user has bought
+#[component]
+pub fn Gifts() -> impl IntoView {
+    let gifts = create_resource(|| (), |_| async move { list_sent_gifts().await });
+
+    view! {
+        <h1>"Gifts you've sent"</h1>
+        <Suspense fallback=move || {
+            view! { <p>"Loading..."</p> }
+        }>
+            {move || match gifts.get() {
+                None => view! { <div>"Loading gifts..."</div> }.into_view(),
+                Some(Err(_)) => {
+                    view! { <div class="error">"Log in to see the gifts you've sent."</div> }
+                        .into_view()
+                }
+                Some(Ok(gifts)) if gifts.is_empty() => {
+                    view! { <div>"You haven't sent any gifts yet."</div> }.into_view()
+                }
+                Some(Ok(gifts)) => {
+                    view! {
+                        <table>
+                            <tr>
+                                <th>"Game"</th>
+                                <th>"Sent to"</th>
+                                <th>"Sent"</th>
+                                <th>"Status"</th>
+                            </tr>
+                            {gifts
+                                .into_iter()
+                                .map(|gift| {
+                                    let status = match (gift.status, gift.claimed_at) {
+                                        (GiftStatus::Claimed, Some(claimed_at)) => {
+                                            format!("Claimed on {}", format_date(claimed_at))
+                                        }
+                                        (GiftStatus::Claimed, None) => "Claimed".to_string(),
+                                        (GiftStatus::Unclaimed, _) => "Not claimed yet".to_string(),
+                                        (GiftStatus::Revoked, _) => "Refunded or under review".to_string(),
+                                    };
+                                    view! {
+                                        <tr>
+                                            <td>{gift.product_title}</td>
+                                            <td>{gift.recipient_email}</td>
+                                            <td>{format_date(gift.sent_at)}</td>
+                                            <td>{status}</td>
+                                        </tr>
+                                    }
+                                })
+                                .collect_view()}
+                        </table>
+                    }
+                        .into_view()
+                }
+            }}
+
+        </Suspense>
+    }
+}
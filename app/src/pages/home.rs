@@ -10,7 +10,7 @@ pub fn Home() -> impl IntoView {
     #[cfg(feature = "hydrate")]
     let game_action = create_action(|_: &()| async move { run(()).await });
 
-    #[cfg(feature = "ssr")]
+    #[cfg(not(feature = "hydrate"))]
     let game_action = create_action(|_: &()| async move { Ok::<(), ()>(()) });
 
     let (invisible, set_invisible) = create_signal(false);
@@ -1,4 +1,6 @@
 pub mod about;
+pub mod account;
+pub mod cart;
 pub mod checkout;
 pub mod checkout_cancel;
 pub mod checkout_success;
@@ -7,6 +9,12 @@ pub mod download;
 pub mod email_verification;
 pub mod email_verification_attempt;
 pub mod end_user_license_agreement;
+pub mod gift;
+pub mod gifts;
 pub mod home;
 pub mod login_and_signup;
+pub mod orders;
+pub mod redeem;
+pub mod reports;
+pub mod store;
 pub mod support_faq;
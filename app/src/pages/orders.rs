@@ -0,0 +1,191 @@
+use crate::{
+    currency::format_amount,
+    errors::NexusError,
+    orders::{OrderStatus, RefundOutcome, RefundRequestStatus, REFUND_REASON_MAX_LENGTH},
+    public::{list_purchases, RequestRefund, ResendReceipt},
+};
+use chrono::DateTime;
+use leptos::{
+    component, create_resource, create_server_action, view, CollectView, IntoView, ServerFnError,
+    SignalGet, Suspense,
+};
+use leptos_router::ActionForm;
+
+fn format_date(timestamp: i64) -> String {
+    DateTime::from_timestamp(timestamp, 0)
+        .map(|date| date.format("%Y-%m-%d").to_string())
+        .unwrap_or_default()
+}
+
+/// Everything the logged in user has bought, with their receipts
+#[component]
+pub fn Orders() -> impl IntoView {
+    let request_refund = create_server_action::<RequestRefund>();
+    let orders = create_resource(
+        move || request_refund.version().get(),
+        |_| async move { list_purchases().await },
+    );
+    let resend_receipt = create_server_action::<ResendReceipt>();
+
+    view! {
+        <h1>"Order history"</h1>
+        {move || match resend_receipt.value().get() {
+            None => None,
+            Some(Ok(_)) => Some(view! { <p>"The receipt is on its way to your inbox."</p> }.into_view()),
+            Some(Err(e)) => {
+                let message = match e {
+                    ServerFnError::WrappedServerError(NexusError::TooManyAttempts) => {
+                        "You've asked for a lot of receipts. Try again in an hour."
+                    }
+                    _ => "Couldn't send the receipt. Try again later.",
+                };
+                Some(view! { <p class="error">{message}</p> }.into_view())
+            }
+        }}
+        {move || match request_refund.value().get() {
+            None => None,
+            Some(Ok(RefundOutcome::Refunded)) => {
+                Some(view! { <p>"Refunded. The money will be back on your statement in 5 to 10 days."</p> }.into_view())
+            }
+            Some(Ok(RefundOutcome::QueuedForReview)) => {
+                Some(view! { <p>"This falls outside our refund policy, so we'll look at it and email you."</p> }.into_view())
+            }
+            Some(Err(e)) => {
+                let message = match e {
+                    ServerFnError::WrappedServerError(NexusError::RefundAlreadyRequested) => {
+                        "You've already asked for a refund of this."
+                    }
+                    ServerFnError::WrappedServerError(NexusError::NotRefundable) => {
+                        "There's nothing to refund for this order."
+                    }
+                    ServerFnError::WrappedServerError(NexusError::TooManyAttempts) => {
+                        "You've asked for a lot of refunds. Try again tomorrow."
+                    }
+                    _ => "Couldn't request the refund. Try again later.",
+                };
+                Some(view! { <p class="error">{message}</p> }.into_view())
+            }
+        }}
+        <Suspense fallback=move || {
+            view! { <p>"Loading..."</p> }
+        }>
+            {move || match orders.get() {
+                None => view! { <div>"Loading orders..."</div> }.into_view(),
+                Some(Err(_)) => {
+                    view! { <div class="error">"Log in to see your orders."</div> }.into_view()
+                }
+                Some(Ok(orders)) if orders.is_empty() => {
+                    view! { <div>"You haven't bought anything yet."</div> }.into_view()
+                }
+                Some(Ok(orders)) => {
+                    view! {
+                        <table>
+                            <tr>
+                                <th>"Date"</th>
+                                <th>"Game"</th>
+                                <th>"Total"</th>
+                                <th>"Tax"</th>
+                                <th>"Status"</th>
+                                <th>"Order"</th>
+                                <th>"Refund"</th>
+                                <th></th>
+                            </tr>
+                            {orders
+                                .into_iter()
+                                .map(|order| {
+                                    let status = match order.status {
+                                        OrderStatus::PaymentPending => "Waiting for payment",
+                                        OrderStatus::PaymentFailed => "Payment failed",
+                                        OrderStatus::Paid if order.gift => "Paid (gift)",
+                                        OrderStatus::Paid => "Paid",
+                                        OrderStatus::Refunded => "Refunded",
+                                        OrderStatus::Disputed => "Disputed",
+                                        OrderStatus::UnderReview => "Under review",
+                                    };
+                                    // Redeemed keys weren't charged for, so they have no currency
+                                    // and no receipt
+                                    let charged = !order.currency.is_empty();
+                                    let has_receipt = charged
+                                        && !matches!(
+                                            order.status,
+                                            OrderStatus::PaymentPending | OrderStatus::PaymentFailed
+                                        );
+                                    let refund_order_id = order.order_id.clone();
+                                    let refundable = charged && order.status == OrderStatus::Paid
+                                        && order.refund_request.is_none();
+                                    let refund_status = order
+                                        .refund_request
+                                        .map(|status| match status {
+                                            RefundRequestStatus::Refunding => "Refund in progress",
+                                            RefundRequestStatus::PendingReview => "Refund under review",
+                                            RefundRequestStatus::Refunded => "Refunded",
+                                            RefundRequestStatus::Denied => "Refund declined",
+                                        });
+                                    let (total, tax) = match charged {
+                                        true => (
+                                            format_amount(order.amount_total, &order.currency),
+                                            format_amount(order.amount_tax, &order.currency),
+                                        ),
+                                        false => ("Free".to_string(), String::new()),
+                                    };
+                                    view! {
+                                        <tr>
+                                            <td>{format_date(order.created_at)}</td>
+                                            <td>{order.product_title}</td>
+                                            <td>{total}</td>
+                                            <td>{tax}</td>
+                                            <td>{status}</td>
+                                            <td class="text-sm">{order.order_id.clone()}</td>
+                                            <td>
+                                                {refund_status}
+                                                {refundable
+                                                    .then(|| {
+                                                        view! {
+                                                            <details>
+                                                                <summary>"Request refund"</summary>
+                                                                <ActionForm action=request_refund class="flex flex-col w-60">
+                                                                    <input type="hidden" name="order_id" value=refund_order_id/>
+                                                                    <label>"Why? (optional)"</label>
+                                                                    <textarea
+                                                                        name="reason"
+                                                                        maxlength=REFUND_REASON_MAX_LENGTH
+                                                                        class="text-gray-900"
+                                                                    ></textarea>
+                                                                    <input
+                                                                        type="submit"
+                                                                        value="Request refund"
+                                                                        class="w-max my-1 py-1 px-2 rounded-md bg-primary-color hover:bg-hover-accent-color glow-hover"
+                                                                    />
+                                                                </ActionForm>
+                                                            </details>
+                                                        }
+                                                    })}
+                                            </td>
+                                            <td>
+                                                {has_receipt
+                                                    .then(|| {
+                                                        view! {
+                                                            <ActionForm action=resend_receipt>
+                                                                <input type="hidden" name="order_id" value=order.order_id/>
+                                                                <input
+                                                                    type="submit"
+                                                                    value="Re-send receipt"
+                                                                    class="py-1 px-2 rounded-md bg-primary-color hover:bg-hover-accent-color glow-hover"
+                                                                />
+                                                            </ActionForm>
+                                                        }
+                                                    })}
+                                            </td>
+                                        </tr>
+                                    }
+                                })
+                                .collect_view()}
+                        </table>
+                    }
+                        .into_view()
+                }
+            }}
+
+        </Suspense>
+    }
+}
@@ -0,0 +1,61 @@
+use crate::{errors::NexusError, public::RedeemKey};
+use leptos::{component, create_server_action, view, IntoView, ServerFnError, SignalGet};
+use leptos_router::{ActionForm, A};
+
+/// Where keys handed out at conventions, to press and in bundles are redeemed
+#[component]
+pub fn Redeem() -> impl IntoView {
+    let redeem = create_server_action::<RedeemKey>();
+
+    view! {
+        <h1>"Redeem a key"</h1>
+        <ActionForm action=redeem class="flex flex-col w-80">
+            <label>"Key:"</label>
+            <input
+                type="text"
+                name="key"
+                placeholder="XXXXX-XXXXX-XXXXX-XXXXX-XXXXX"
+                autocomplete="off"
+                required
+                class="text-gray-900"
+            />
+            <input
+                type="submit"
+                value="Redeem"
+                class="w-max my-1 py-1 px-2 rounded-md bg-primary-color hover:bg-hover-accent-color glow-hover"
+            />
+        </ActionForm>
+        {move || match redeem.value().get() {
+            None => None,
+            Some(Ok(_)) => {
+                Some(
+                    view! {
+                        <p>"The game is yours! " <A href="/download">"Go to your library"</A></p>
+                    }
+                        .into_view(),
+                )
+            }
+            Some(Err(e)) => {
+                let message = match e {
+                    ServerFnError::WrappedServerError(NexusError::KeyInvalid) => {
+                        "That key isn't valid. Check it for typos."
+                    }
+                    ServerFnError::WrappedServerError(NexusError::KeyAlreadyRedeemed) => {
+                        "That key has already been redeemed."
+                    }
+                    ServerFnError::WrappedServerError(NexusError::KeyRedemptionIncomplete) => {
+                        "The key is yours, but the game couldn't be added to your library yet. Redeem the key again to finish."
+                    }
+                    ServerFnError::WrappedServerError(NexusError::TooManyAttempts) => {
+                        "Too many attempts. Try again in an hour."
+                    }
+                    ServerFnError::WrappedServerError(NexusError::InvalidSession) => {
+                        "Log in to redeem a key."
+                    }
+                    _ => "Couldn't redeem the key. Try again later.",
+                };
+                Some(view! { <p class="error">{message}</p> }.into_view())
+            }
+        }}
+    }
+}
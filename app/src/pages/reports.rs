@@ -0,0 +1,210 @@
+use crate::{
+    currency::format_amount,
+    errors::NexusError,
+    orders::{SalesFigures, SalesReport},
+    public::{ExportSalesReport, GetSalesReport},
+};
+use chrono::DateTime;
+use leptos::{
+    component, create_server_action, view, Action, CollectView, IntoView, ServerFnError, SignalGet,
+};
+use leptos_router::ActionForm;
+
+fn format_date(timestamp: i64) -> String {
+    DateTime::from_timestamp(timestamp, 0)
+        .map(|date| date.format("%Y-%m-%d").to_string())
+        .unwrap_or_default()
+}
+
+fn format_rate(rate: f64) -> String {
+    format!("{:.1}%", rate * 100.0)
+}
+
+/// A link target holding `body`, so what a server function returned can be downloaded
+fn data_uri(media_type: &str, body: &str) -> String {
+    let mut uri = format!("data:{};charset=utf-8,", media_type);
+    for byte in body.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                uri.push(char::from(byte))
+            }
+            byte => uri.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    uri
+}
+
+fn figure_cells(figures: SalesFigures, currency: &str) -> impl IntoView {
+    view! {
+        <td>{figures.purchases}</td>
+        <td>{format_amount(figures.gross, currency)}</td>
+        <td>{format_amount(figures.tax, currency)}</td>
+        <td>{format_amount(figures.discounts, currency)}</td>
+        <td>{format_amount(figures.net, currency)}</td>
+        <td>{figures.refunds} " (" {format_rate(figures.refund_rate())} ")"</td>
+        <td>{figures.disputes} " (" {format_rate(figures.dispute_rate())} ")"</td>
+    }
+}
+
+fn figure_headers() -> impl IntoView {
+    view! {
+        <th>"Sales"</th>
+        <th>"Gross"</th>
+        <th>"Tax"</th>
+        <th>"Discounts"</th>
+        <th>"Net"</th>
+        <th>"Refunds"</th>
+        <th>"Disputes"</th>
+    }
+}
+
+/// Downloads the report on the page as `format`
+#[component]
+fn ExportForm(
+    report: SalesReport,
+    format: &'static str,
+    export: Action<ExportSalesReport, Result<String, ServerFnError<NexusError>>>,
+) -> impl IntoView {
+    view! {
+        <ActionForm action=export>
+            <input type="hidden" name="from" value=format_date(report.from)/>
+            // The report ends before midnight of the day after its last
+            <input type="hidden" name="to" value=format_date(report.to - 1)/>
+            <input type="hidden" name="period" value=report.period.as_str()/>
+            <input type="hidden" name="format" value=format/>
+            <input
+                type="submit"
+                value=format!("Export {}", format.to_uppercase())
+                class="py-1 px-2 rounded-md bg-primary-color hover:bg-hover-accent-color glow-hover"
+            />
+        </ActionForm>
+        {move || match export.value().get() {
+            None => None,
+            Some(Ok(body)) => {
+                let media_type = match format {
+                    "json" => "application/json",
+                    _ => "text/csv",
+                };
+                Some(
+                    view! {
+                        <a href=data_uri(media_type, &body) download=format!("sales.{}", format)>
+                            "Download"
+                        </a>
+                    }
+                        .into_view(),
+                )
+            }
+            Some(Err(_)) => {
+                Some(view! { <p class="error">"Couldn't export the report."</p> }.into_view())
+            }
+        }}
+    }
+}
+
+/// Admin only. Sales by day or week, product, currency and country, from the purchase ledger.
+#[component]
+pub fn SalesReports() -> impl IntoView {
+    let run_report = create_server_action::<GetSalesReport>();
+    let export_csv = create_server_action::<ExportSalesReport>();
+    let export_json = create_server_action::<ExportSalesReport>();
+
+    view! {
+        <h1>"Sales"</h1>
+        <ActionForm action=run_report class="flex gap-2 items-center py-2">
+            <label>"From"</label>
+            <input type="date" name="from" class="text-gray-900"/>
+            <label>"To"</label>
+            <input type="date" name="to" class="text-gray-900"/>
+            <select name="period" class="text-gray-900">
+                <option value="daily">"Daily"</option>
+                <option value="weekly">"Weekly"</option>
+            </select>
+            <input
+                type="submit"
+                value="Run report"
+                class="w-max py-1 px-2 rounded-md bg-primary-color hover:bg-hover-accent-color glow-hover"
+            />
+        </ActionForm>
+        <p class="text-sm">"Leave the dates empty for the last 30 days."</p>
+        {move || match run_report.value().get() {
+            None => None,
+            Some(Err(e)) => {
+                let message = match e {
+                    ServerFnError::WrappedServerError(
+                        NexusError::NotAuthorized | NexusError::InvalidSession,
+                    ) => "Only admins can see sales reports.",
+                    ServerFnError::WrappedServerError(NexusError::ReportRangeInvalid) => {
+                        "The start date has to be on or before the end date."
+                    }
+                    _ => "Couldn't run the report. Try again later.",
+                };
+                Some(view! { <p class="error">{message}</p> }.into_view())
+            }
+            Some(Ok(report)) if report.rows.is_empty() => {
+                Some(
+                    view! {
+                        <p>
+                            "Nothing was sold from " {format_date(report.from)} " to "
+                            {format_date(report.to - 1)} "."
+                        </p>
+                    }
+                        .into_view(),
+                )
+            }
+            Some(Ok(report)) => {
+                Some(
+                    view! {
+                        <h2>
+                            "From " {format_date(report.from)} " to " {format_date(report.to - 1)}
+                        </h2>
+                        <div class="flex gap-2 py-2">
+                            <ExportForm report=report.clone() format="csv" export=export_csv/>
+                            <ExportForm report=report.clone() format="json" export=export_json/>
+                        </div>
+                        <table>
+                            <tr>
+                                <th>"Currency"</th>
+                                {figure_headers()}
+                            </tr>
+                            {report
+                                .totals
+                                .into_iter()
+                                .map(|total| {
+                                    view! {
+                                        <tr>
+                                            <td>{total.currency.to_uppercase()}</td>
+                                            {figure_cells(total.figures, &total.currency)}
+                                        </tr>
+                                    }
+                                })
+                                .collect_view()}
+                        </table>
+                        <table>
+                            <tr>
+                                <th>"Period"</th>
+                                <th>"Product"</th>
+                                <th>"Country"</th>
+                                {figure_headers()}
+                            </tr>
+                            {report
+                                .rows
+                                .into_iter()
+                                .map(|row| {
+                                    view! {
+                                        <tr>
+                                            <td>{format_date(row.period_start)}</td>
+                                            <td>{row.product_id}</td>
+                                            <td>{row.country}</td>
+                                            {figure_cells(row.figures, &row.currency)}
+                                        </tr>
+                                    }
+                                })
+                                .collect_view()}
+                        </table>
+                    }
+                        .into_view(),
+                )
+            }
+        }}
+    }
+}
@@ -0,0 +1,190 @@
+use crate::{
+    catalog::{Offer, DEFAULT_CURRENCY},
+    currency::CurrencySettings,
+    errors::NexusError,
+    orders::GIFT_MESSAGE_MAX_LENGTH,
+    public::{get_currency_settings, list_products, AddToCart, SetCurrency},
+};
+use chrono::Utc;
+use leptos::{
+    component, create_resource, create_server_action, view, Action, CollectView, IntoView,
+    ServerFnError, SignalGet, Suspense,
+};
+use leptos_router::{ActionForm, Form, A};
+
+#[component]
+pub fn Store() -> impl IntoView {
+    let products = create_resource(|| (), |_| async move { list_products().await });
+    let set_currency = create_server_action::<SetCurrency>();
+    let add_to_cart = create_server_action::<AddToCart>();
+    let currency_settings = create_resource(
+        move || set_currency.version().get(),
+        |_| async move { get_currency_settings().await },
+    );
+
+    view! {
+        <h1>"Store"</h1>
+        <A href="/cart" class="underline">
+            "View cart"
+        </A>
+        {move || match add_to_cart.value().get() {
+            None => None,
+            Some(Ok(cart)) => {
+                Some(view! { <p>{format!("Added to your cart ({} items).", cart.lines.len())}</p> }.into_view())
+            }
+            Some(Err(e)) => {
+                let message = match e {
+                    ServerFnError::WrappedServerError(NexusError::AlreadyOwned) => {
+                        "You already own everything in that."
+                    }
+                    ServerFnError::WrappedServerError(NexusError::CartFull) => "Your cart is full.",
+                    ServerFnError::WrappedServerError(NexusError::ProductUnavailable) => {
+                        "That isn't on sale right now."
+                    }
+                    _ => "Log in to add games to your cart.",
+                };
+                Some(view! { <p class="error">{message}</p> }.into_view())
+            }
+        }}
+        <Suspense fallback=move || {
+            view! { <p>"Loading..."</p> }
+        }>
+            {move || {
+                currency_settings
+                    .get()
+                    .and_then(Result::ok)
+                    .map(|settings| view! { <CurrencyPicker settings set_currency/> })
+            }}
+            {move || match (products.get(), currency_settings.get()) {
+                (None, _) | (_, None) => view! { <div>"Loading products..."</div> }.into_view(),
+                (Some(Err(_)), _) => view! { <div class="error">"Could not load the store."</div> }.into_view(),
+                (Some(Ok(products)), Some(settings)) => {
+                    // Showing default currency prices beats showing none
+                    let currency = settings
+                        .map(|settings| settings.selected)
+                        .unwrap_or_else(|_| DEFAULT_CURRENCY.to_string());
+                    products
+                        .into_iter()
+                        .map(|product| {
+                            let offer = product
+                                .offer_with_fallback(&currency, Utc::now().timestamp());
+                            let image = product.media.first().cloned();
+                            let product_id = product.id.clone();
+                            view! {
+                                <div class="p-4">
+                                    {image
+                                        .map(|image| {
+                                            view! { <img src=image.url alt=image.alt class="w-64"/> }
+                                        })}
+                                    <h2 class="text-2xl">{product.title}</h2>
+                                    <p>{product.description}</p>
+                                    {offer.map(|offer| view! { <Price offer/> })}
+                                    <A
+                                        href=format!("/checkout/{}", product.id)
+                                        class="text-color p-1.5 bg-primary-color rounded-md hover:bg-hover-accent-color glow-hover"
+                                    >
+                                        "Buy"
+                                    </A>
+                                    <ActionForm action=add_to_cart class="inline">
+                                        <input type="hidden" name="product_id" value=product_id/>
+                                        <input
+                                            type="submit"
+                                            value="Add to cart"
+                                            class="mx-2 py-1 px-2 rounded-md bg-primary-color hover:bg-hover-accent-color glow-hover"
+                                        />
+                                    </ActionForm>
+                                    <details class="py-2">
+                                        <summary>"Buy as a gift"</summary>
+                                        <Form
+                                            method="GET"
+                                            action=format!("/checkout/{}", product.id)
+                                            class="flex flex-col w-60"
+                                        >
+                                            <label>"Recipient's email:"</label>
+                                            <input
+                                                type="email"
+                                                name="gift_recipient_email"
+                                                maxlength="64"
+                                                required
+                                                class="text-gray-900"
+                                            />
+                                            <label>"Message (optional):"</label>
+                                            <textarea
+                                                name="gift_message"
+                                                maxlength=GIFT_MESSAGE_MAX_LENGTH
+                                                class="text-gray-900"
+                                            ></textarea>
+                                            <input
+                                                type="submit"
+                                                value="Buy as a gift"
+                                                class="w-max my-1 py-1 px-2 rounded-md bg-primary-color hover:bg-hover-accent-color glow-hover"
+                                            />
+                                        </Form>
+                                    </details>
+                                </div>
+                            }
+                        })
+                        .collect_view()
+                }
+            }}
+
+        </Suspense>
+    }
+}
+
+/// Lets visitors override the currency guessed from their browser's language
+#[component]
+fn CurrencyPicker(
+    settings: CurrencySettings,
+    set_currency: Action<SetCurrency, Result<(), ServerFnError<NexusError>>>,
+) -> impl IntoView {
+    if settings.available.len() < 2 {
+        return None;
+    }
+    let selected = settings.overridden.then_some(settings.selected);
+    Some(view! {
+        <ActionForm action=set_currency class="flex gap-2 items-center py-2">
+            <label>"Currency:"</label>
+            <select name="currency" class="text-gray-900">
+                <option value="" selected=selected.is_none()>
+                    "Automatic"
+                </option>
+                {settings
+                    .available
+                    .into_iter()
+                    .map(|currency| {
+                        let is_selected = selected.as_ref() == Some(&currency);
+                        view! {
+                            <option value=currency.clone() selected=is_selected>
+                                {currency.to_uppercase()}
+                            </option>
+                        }
+                    })
+                    .collect_view()}
+            </select>
+            <input
+                type="submit"
+                value="Change"
+                class="w-max py-1 px-2 rounded-md bg-primary-color hover:bg-hover-accent-color glow-hover"
+            />
+        </ActionForm>
+    })
+}
+
+/// The price, struck through next to the sale price while a sale is on
+#[component]
+fn Price(offer: Offer) -> impl IntoView {
+    match offer.sale {
+        Some(sale) => view! {
+            <p>
+                <s class="opacity-60">{offer.regular.display()}</s>
+                " "
+                <strong>{offer.price.display()}</strong>
+                " "
+                <span class="text-sm">{sale.name}</span>
+            </p>
+        }
+        .into_view(),
+        None => view! { <p>{offer.price.display()}</p> }.into_view(),
+    }
+}
@@ -1,7 +1,17 @@
-use crate::errors::NexusError;
+use crate::{
+    catalog::Product,
+    currency::CurrencySettings,
+    errors::NexusError,
+    orders::{
+        Cart, CheckoutStatus, Collaborator, GiftPreview, KeyBatchReport, KeyBatchSummary,
+        KeyImportSummary, Order, OwnedExternalKey, ReconciliationReport, RefundOutcome,
+        RefundRequest, RefundRequestStatus, RevenueSharePayouts, RevenueTransfer, SalesReport,
+        SentGift,
+    },
+};
 use leptos::{server, ServerFnError};
 
-/// Contains all the public-facing API calls.
+// Contains all the public-facing API calls.
 // TODO: Implement rate limiting? Handle on API Gateway instead of here maybe?
 
 /// Logs the given user in
@@ -63,7 +73,285 @@ pub async fn change_password(new_password: String) -> Result<(), ServerFnError<N
     crate::server::change_profile::change_password(new_password).await
 }
 
+#[server(DeleteAccount, "/api", "Url", "delete_account")]
+pub async fn delete_account(password: String) -> Result<(), ServerFnError<NexusError>> {
+    crate::server::change_profile::delete_account(password).await
+}
+
+/// Products that are on sale right now
+#[server(ListProducts, "/api", "Url", "list_products")]
+pub async fn list_products() -> Result<Vec<Product>, ServerFnError<NexusError>> {
+    Ok(crate::server::catalog::list_products())
+}
+
+#[server(GetProduct, "/api", "Url", "get_product")]
+pub async fn get_product(product_id: String) -> Result<Product, ServerFnError<NexusError>> {
+    crate::server::catalog::get_product(&product_id)
+}
+
+/// The currency prices are shown and charged in for this visitor, and the ones they can pick
+#[server(GetCurrencySettings, "/api", "Url", "get_currency_settings")]
+pub async fn get_currency_settings() -> Result<CurrencySettings, ServerFnError<NexusError>> {
+    crate::server::currency::preferred_currency().await
+}
+
+/// Picks the currency to show and charge in. An empty one goes back to guessing it from the
+/// browser's language.
+#[server(SetCurrency, "/api", "Url", "set_currency")]
+pub async fn set_currency(
+    #[server(default)] currency: String,
+) -> Result<(), ServerFnError<NexusError>> {
+    crate::server::currency::set_currency(currency).await
+}
+
+/// The logged in user's cart, priced for them
+#[server(ListCart, "/api", "Url", "list_cart")]
+pub async fn list_cart() -> Result<Cart, ServerFnError<NexusError>> {
+    crate::server::cart::list_cart().await
+}
+
+#[server(AddToCart, "/api", "Url", "add_to_cart")]
+pub async fn add_to_cart(product_id: String) -> Result<Cart, ServerFnError<NexusError>> {
+    crate::server::cart::add_to_cart(product_id).await
+}
+
+#[server(RemoveFromCart, "/api", "Url", "remove_from_cart")]
+pub async fn remove_from_cart(product_id: String) -> Result<Cart, ServerFnError<NexusError>> {
+    crate::server::cart::remove_from_cart(product_id).await
+}
+
+/// Starts an embedded Stripe checkout for the given product, or for the cart if `product_id`
+/// is empty, returning its client secret
 #[server(CreateCheckout, "/api", "Url", "create_checkout")]
-pub async fn create_checkout() -> Result<String, ServerFnError<NexusError>> {
-    crate::server::create_checkout::create_checkout().await
+pub async fn create_checkout(
+    #[server(default)] product_id: String,
+    #[server(default)] gift_recipient_email: String,
+    #[server(default)] gift_message: String,
+) -> Result<String, ServerFnError<NexusError>> {
+    crate::server::create_checkout::create_checkout(product_id, gift_recipient_email, gift_message)
+        .await
+}
+
+/// Whether one of the logged in user's checkouts has been paid for yet
+#[server(GetCheckoutStatus, "/api", "Url", "get_checkout_status")]
+pub async fn get_checkout_status(
+    session_id: String,
+) -> Result<CheckoutStatus, ServerFnError<NexusError>> {
+    crate::server::checkout_status::checkout_status(session_id).await
+}
+
+/// Every purchase of the logged in user, newest first
+#[server(ListPurchases, "/api", "Url", "list_purchases")]
+pub async fn list_purchases() -> Result<Vec<Order>, ServerFnError<NexusError>> {
+    crate::server::order_history::list_purchases().await
+}
+
+/// Emails the receipt of one of the logged in user's purchases to them again
+#[server(ResendReceipt, "/api", "Url", "resend_receipt")]
+pub async fn resend_receipt(order_id: String) -> Result<(), ServerFnError<NexusError>> {
+    crate::server::order_history::resend_receipt(order_id).await
+}
+
+/// Asks for a refund of one of the logged in user's purchases. Ones within the refund policy
+/// are refunded right away, the rest are queued for an admin to review.
+#[server(RequestRefund, "/api", "Url", "request_refund")]
+pub async fn request_refund(
+    order_id: String,
+    #[server(default)] reason: String,
+) -> Result<RefundOutcome, ServerFnError<NexusError>> {
+    crate::server::refunds::request_refund(order_id, reason).await
+}
+
+/// Admin only. Refund requests waiting for review.
+#[server(ListRefundRequests, "/api", "Url", "list_refund_requests")]
+pub async fn list_refund_requests() -> Result<Vec<RefundRequest>, ServerFnError<NexusError>> {
+    crate::server::refunds::list_refund_requests().await
+}
+
+/// Admin only. Refunds or turns down a refund request waiting for review.
+#[server(ReviewRefundRequest, "/api", "Url", "review_refund_request")]
+pub async fn review_refund_request(
+    purchase_id: String,
+    approve: bool,
+) -> Result<RefundRequestStatus, ServerFnError<NexusError>> {
+    crate::server::refunds::review_refund_request(purchase_id, approve).await
+}
+
+/// The gift behind a claim link
+#[server(GetGift, "/api", "Url", "get_gift")]
+pub async fn get_gift(claim_token: String) -> Result<GiftPreview, ServerFnError<NexusError>> {
+    crate::server::gifts::get_gift_preview(claim_token).await
+}
+
+/// Claims a gift for the logged in user
+#[server(ClaimGift, "/api", "Url", "claim_gift")]
+pub async fn claim_gift(claim_token: String) -> Result<(), ServerFnError<NexusError>> {
+    crate::server::gifts::claim_gift(claim_token).await
+}
+
+/// Claims a gift by creating an account for the email it was sent to
+#[server(ClaimGiftWithNewAccount, "/api", "Url", "claim_gift_with_new_account")]
+pub async fn claim_gift_with_new_account(
+    claim_token: String,
+    display_name: String,
+    password: String,
+    password_confirmation: String,
+) -> Result<(), ServerFnError<NexusError>> {
+    crate::server::gifts::claim_gift_with_new_account(
+        claim_token,
+        display_name,
+        password,
+        password_confirmation,
+    )
+    .await
+}
+
+/// The gifts the logged in user has bought, and whether they've been claimed
+#[server(ListSentGifts, "/api", "Url", "list_sent_gifts")]
+pub async fn list_sent_gifts() -> Result<Vec<SentGift>, ServerFnError<NexusError>> {
+    crate::server::gifts::list_sent_gifts().await
+}
+
+/// Redeems a product key for the logged in user, returning the id of the product it granted
+#[server(RedeemKey, "/api", "Url", "redeem_key")]
+pub async fn redeem_key(key: String) -> Result<String, ServerFnError<NexusError>> {
+    crate::server::product_keys::redeem_key(key).await
+}
+
+/// Admin only. Generates a batch of keys for a product, returning them. They can't be seen
+/// again afterwards.
+#[server(GenerateKeyBatch, "/api", "Url", "generate_key_batch")]
+pub async fn generate_key_batch(
+    product_id: String,
+    count: usize,
+    #[server(default)] label: String,
+) -> Result<Vec<String>, ServerFnError<NexusError>> {
+    crate::server::product_keys::generate_key_batch(product_id, count, label).await
+}
+
+/// Admin only. Every batch of product keys, newest first.
+#[server(ListKeyBatches, "/api", "Url", "list_key_batches")]
+pub async fn list_key_batches() -> Result<Vec<KeyBatchSummary>, ServerFnError<NexusError>> {
+    crate::server::product_keys::list_key_batches().await
+}
+
+/// Admin only. Which keys of a batch have been redeemed, and by whom.
+#[server(GetKeyBatchReport, "/api", "Url", "get_key_batch_report")]
+pub async fn get_key_batch_report(
+    batch_id: String,
+) -> Result<KeyBatchReport, ServerFnError<NexusError>> {
+    crate::server::product_keys::get_key_batch_report(batch_id).await
+}
+
+/// Admin only. Adds the keys of a CSV export from a third-party storefront to the pool handed
+/// out with purchases of a product.
+#[server(ImportExternalKeys, "/api", "Url", "import_external_keys")]
+pub async fn import_external_keys(
+    product_id: String,
+    platform: String,
+    csv: String,
+) -> Result<KeyImportSummary, ServerFnError<NexusError>> {
+    crate::server::external_keys::import_external_keys(product_id, platform, csv).await
+}
+
+/// The third-party storefront keys (e.g. Steam) handed out to the logged in user
+#[server(ListExternalKeys, "/api", "Url", "list_external_keys")]
+pub async fn list_external_keys() -> Result<Vec<OwnedExternalKey>, ServerFnError<NexusError>> {
+    crate::server::external_keys::list_external_keys().await
+}
+
+/// Admin only. Adds someone to pay a share of sales to, as the catalog's revenue shares name
+/// them, and emails them the link to set up their Stripe account. Returns the link.
+#[server(AddCollaborator, "/api", "Url", "add_collaborator")]
+pub async fn add_collaborator(
+    collaborator_id: String,
+    name: String,
+    email: String,
+    #[server(default)] country: String,
+) -> Result<String, ServerFnError<NexusError>> {
+    crate::server::collaborators::add_collaborator(collaborator_id, name, email, country).await
+}
+
+/// Admin only. Emails a collaborator a new link to set up their Stripe account, returning it.
+#[server(ResendOnboardingLink, "/api", "Url", "resend_onboarding_link")]
+pub async fn resend_onboarding_link(
+    collaborator_id: String,
+) -> Result<String, ServerFnError<NexusError>> {
+    crate::server::collaborators::resend_onboarding_link(collaborator_id).await
+}
+
+/// Admin only. Every collaborator, and whether they can be paid yet.
+#[server(ListCollaborators, "/api", "Url", "list_collaborators")]
+pub async fn list_collaborators() -> Result<Vec<Collaborator>, ServerFnError<NexusError>> {
+    crate::server::collaborators::list_all_collaborators().await
+}
+
+/// Admin only. Collaborators' shares of purchases with the given status (pending if empty).
+#[server(ListRevenueTransfers, "/api", "Url", "list_revenue_transfers")]
+pub async fn list_revenue_transfers(
+    #[server(default)] status: String,
+) -> Result<Vec<RevenueTransfer>, ServerFnError<NexusError>> {
+    crate::server::collaborators::list_revenue_transfers(status).await
+}
+
+/// Admin only. Transfers the revenue shares that are due. This also happens whenever Stripe
+/// makes funds available.
+#[server(PayRevenueShares, "/api", "Url", "pay_revenue_shares")]
+pub async fn pay_revenue_shares() -> Result<RevenueSharePayouts, ServerFnError<NexusError>> {
+    crate::server::collaborators::pay_revenue_shares_now().await
+}
+
+/// Admin only. Sales from one `YYYY-MM-DD` date to another (both included), by day or week
+/// (`daily` if empty), product, currency and country. The dates default to the last 30 days.
+#[server(GetSalesReport, "/api", "Url", "get_sales_report")]
+pub async fn get_sales_report(
+    #[server(default)] from: String,
+    #[server(default)] to: String,
+    #[server(default)] period: String,
+) -> Result<SalesReport, ServerFnError<NexusError>> {
+    crate::server::reports::get_sales_report(from, to, period).await
+}
+
+/// Admin only. The sales report as a `csv` (if `format` is empty) or `json` document.
+#[server(ExportSalesReport, "/api", "Url", "export_sales_report")]
+pub async fn export_sales_report(
+    #[server(default)] from: String,
+    #[server(default)] to: String,
+    #[server(default)] period: String,
+    #[server(default)] format: String,
+) -> Result<String, ServerFnError<NexusError>> {
+    crate::server::reports::export_sales_report(from, to, period, format).await
+}
+
+/// Admin only. Checks the checkouts completed in the last `days` days (3 if 0) against the
+/// purchase ledger and entitlements, grants what a missed webhook didn't, and emails the admins
+/// about anything wrong.
+#[server(ReconcileCheckouts, "/api", "Url", "reconcile_checkouts")]
+pub async fn reconcile_checkouts(
+    #[server(default)] days: u32,
+) -> Result<ReconciliationReport, ServerFnError<NexusError>> {
+    crate::server::reconciliation::reconcile_checkouts(days).await
+}
+
+/// Admin only. Hashes the files uploaded for a build and publishes its manifest for the launcher,
+/// returning how many files it lists.
+#[server(PublishBuildManifest, "/api", "Url", "publish_build_manifest")]
+pub async fn publish_build_manifest(
+    game: String,
+    platform: String,
+    version: String,
+) -> Result<usize, ServerFnError<NexusError>> {
+    crate::server::download::manifest::publish_build_manifest(game, platform, version).await
+}
+
+/// Admin only. Diffs the files that changed between two builds with published manifests and
+/// publishes the patch for the launcher, returning how many bytes updating downloads.
+#[server(PublishPatch, "/api", "Url", "publish_patch")]
+pub async fn publish_patch(
+    game: String,
+    platform: String,
+    from: String,
+    to: String,
+) -> Result<u64, ServerFnError<NexusError>> {
+    crate::server::download::patch::publish_patch(game, platform, from, to).await
 }
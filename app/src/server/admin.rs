@@ -0,0 +1,84 @@
+use super::{
+    email::send_email,
+    repository::UserRepository,
+    utilities::{check_if_session_is_valid, get_session_cookie, kms_client, user_repository},
+};
+use crate::{errors::NexusError, site::constants::SITE_DOMAIN};
+use aws_sdk_kms::Client as KeyClient;
+use aws_sdk_ses::Client as SesClient;
+use leptos::ServerFnError;
+
+/// Comma separated emails of the accounts allowed to use admin tools
+const ADMIN_EMAILS_ENV_VAR: &str = "ADMIN_EMAILS";
+
+pub fn is_admin(email: &str) -> bool {
+    match std::env::var(ADMIN_EMAILS_ENV_VAR) {
+        Ok(admin_emails) => email_in_list(&admin_emails, email),
+        Err(_) => false,
+    }
+}
+
+pub fn admin_emails() -> Vec<String> {
+    std::env::var(ADMIN_EMAILS_ENV_VAR)
+        .map(|admin_emails| parse_email_list(&admin_emails))
+        .unwrap_or_default()
+}
+
+pub fn parse_email_list(list: &str) -> Vec<String> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|admin| !admin.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+pub fn email_in_list(list: &str, email: &str) -> bool {
+    parse_email_list(list)
+        .iter()
+        .any(|admin| admin.eq_ignore_ascii_case(email))
+}
+
+/// Returns the email of the session's user if they are an admin
+pub async fn require_admin(
+    session_id: String,
+    repository: &dyn UserRepository,
+    kms_client: &KeyClient,
+) -> Result<String, ServerFnError<NexusError>> {
+    let (valid, email) =
+        check_if_session_is_valid(session_id, String::new(), repository, kms_client).await?;
+    if !valid {
+        return Err(ServerFnError::from(NexusError::InvalidSession));
+    }
+    if !is_admin(&email) {
+        log::error!("{} tried to use an admin tool", email);
+        return Err(ServerFnError::from(NexusError::NotAuthorized));
+    }
+    Ok(email)
+}
+
+/// Returns the email of the user making the request if they are an admin, for admin server
+/// functions
+pub async fn current_admin() -> Result<String, ServerFnError<NexusError>> {
+    let repository = user_repository()?;
+    let kms_client = kms_client()?;
+    require_admin(
+        get_session_cookie().await?,
+        repository.as_ref(),
+        &kms_client,
+    )
+    .await
+}
+
+/// Emails every admin. Failing to is only logged, since whatever prompted the notice already
+/// happened and has been recorded.
+pub async fn notify_admins(ses_client: &SesClient, subject: &str, body: &str) {
+    let admins = admin_emails();
+    if admins.is_empty() {
+        log::error!("No {} to notify about: {}", ADMIN_EMAILS_ENV_VAR, subject);
+        return;
+    }
+    let subject = format!("[{}] {}", SITE_DOMAIN, subject);
+    if let Err(e) = send_email(ses_client, &admins, &subject, body).await {
+        log::error!("Could not notify admins about {} {:?}", subject, e);
+    }
+}
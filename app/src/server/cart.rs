@@ -0,0 +1,213 @@
+use super::{
+    catalog::{all_products, get_product},
+    currency::preferred_currency,
+    repository::{
+        carts::{add_to_cart as add_products, get_cart, remove_from_cart as remove_products},
+        UserRepository,
+    },
+    utilities::{dynamo_client, get_session_cookie, logged_in_user, user_repository, LoggedInUser},
+};
+use crate::{
+    catalog::{Offer, Product, DEFAULT_CURRENCY},
+    errors::NexusError,
+    orders::{Cart, CartLine, CART_MAX_ITEMS},
+};
+use chrono::Utc;
+use leptos::ServerFnError;
+use sha2::{Digest, Sha256};
+
+/// Carts are forgotten this long after they were last changed
+const CART_LIFETIME_SECONDS: i64 = 60 * 60 * 24 * 30;
+
+/// What the products in a cart cost together
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PricedCart {
+    /// Every line is charged in this one currency, as a checkout can't mix them
+    pub currency: String,
+    pub lines: Vec<(Product, Offer)>,
+    /// Ids in the cart that can't be bought any more: gone from the catalog, off sale, already
+    /// owned, or part of a bundle that's also in the cart
+    pub stale: Vec<String>,
+}
+
+/// The cart of a session. Its id is a hash of the session id, so the Carts table holds nothing
+/// that could be used to log in.
+pub fn cart_id(session_id: &str) -> String {
+    Sha256::digest(session_id.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Prices the products in a cart for someone who has the `owned` entitlements. The cart is
+/// charged in `preferred_currency` if every product in it is priced in it, and in the default
+/// currency otherwise.
+pub fn price_cart(
+    product_ids: &[String],
+    catalog: &[Product],
+    owned: &[String],
+    preferred_currency: &str,
+    now: i64,
+) -> PricedCart {
+    let in_cart: Vec<&Product> = product_ids
+        .iter()
+        .filter_map(|product_id| catalog.iter().find(|product| &product.id == product_id))
+        .collect();
+    let bundled: Vec<String> = in_cart
+        .iter()
+        .filter(|product| product.is_bundle())
+        .flat_map(|product| product.bundle.clone())
+        .collect();
+    let buyable: Vec<&Product> = in_cart
+        .into_iter()
+        .filter(|product| {
+            product.is_available_at(now)
+                && !product.is_owned_by(owned)
+                && (product.is_bundle() || !bundled.contains(&product.id))
+        })
+        .collect();
+    let currency = match buyable
+        .iter()
+        .all(|product| product.price_for(preferred_currency).is_some())
+    {
+        true => preferred_currency.to_string(),
+        false => DEFAULT_CURRENCY.to_string(),
+    };
+    let lines: Vec<(Product, Offer)> = buyable
+        .into_iter()
+        .filter_map(|product| {
+            let offer = product.offer_for_owner(catalog, &currency, now, owned)?;
+            Some((product.clone(), offer))
+        })
+        .collect();
+    let stale = product_ids
+        .iter()
+        .filter(|product_id| !lines.iter().any(|(product, _)| &&product.id == product_id))
+        .cloned()
+        .collect();
+    PricedCart {
+        currency,
+        lines,
+        stale,
+    }
+}
+
+impl PricedCart {
+    /// The cart as its page shows it
+    pub fn summary(&self) -> Cart {
+        Cart {
+            currency: self.currency.clone(),
+            lines: self
+                .lines
+                .iter()
+                .map(|(product, offer)| CartLine {
+                    product_id: product.id.clone(),
+                    product_title: product.title.clone(),
+                    regular_amount: offer.regular.unit_amount,
+                    amount: offer.price.unit_amount,
+                    sale: offer.sale.as_ref().map(|sale| sale.name.clone()),
+                    owned_credit: offer.owned_credit,
+                })
+                .collect(),
+            total: self
+                .lines
+                .iter()
+                .map(|(_, offer)| offer.price.unit_amount)
+                .sum(),
+        }
+    }
+}
+
+/// The logged in user and the id of their session's cart
+async fn current_cart(
+    repository: &dyn UserRepository,
+) -> Result<(LoggedInUser, String), ServerFnError<NexusError>> {
+    let user = logged_in_user(repository).await?;
+    let session_id = get_session_cookie().await?;
+    Ok((user, cart_id(&session_id)))
+}
+
+/// The logged in user's cart, priced for them. Products that can't be bought any more are
+/// dropped from it.
+pub async fn checkout_cart() -> Result<(LoggedInUser, PricedCart), ServerFnError<NexusError>> {
+    let repository = user_repository()?;
+    let (user, cart_id) = current_cart(repository.as_ref()).await?;
+    let dynamodb_client = dynamo_client()?;
+    let product_ids = get_cart(&dynamodb_client, &cart_id).await?;
+    let owned = repository.entitlements(&user.email).await?;
+    let currency = preferred_currency().await?.selected;
+    let priced = price_cart(
+        &product_ids,
+        all_products(),
+        &owned,
+        &currency,
+        Utc::now().timestamp(),
+    );
+    if !priced.stale.is_empty() {
+        log::info!("Dropping {:?} from cart {}", priced.stale, cart_id);
+        remove_products(
+            &dynamodb_client,
+            &cart_id,
+            &priced.stale,
+            Utc::now().timestamp(),
+        )
+        .await?;
+    }
+    Ok((user, priced))
+}
+
+pub async fn list_cart() -> Result<Cart, ServerFnError<NexusError>> {
+    Ok(checkout_cart().await?.1.summary())
+}
+
+/// Puts a product in the logged in user's cart. A bundle replaces the items of it already
+/// there.
+pub async fn add_to_cart(product_id: String) -> Result<Cart, ServerFnError<NexusError>> {
+    let product = get_product(&product_id)?;
+    let now = Utc::now().timestamp();
+    if !product.is_available_at(now) {
+        return Err(ServerFnError::from(NexusError::ProductUnavailable));
+    }
+    let repository = user_repository()?;
+    let (user, cart_id) = current_cart(repository.as_ref()).await?;
+    if product.is_owned_by(&repository.entitlements(&user.email).await?) {
+        return Err(ServerFnError::from(NexusError::AlreadyOwned));
+    }
+    let dynamodb_client = dynamo_client()?;
+    let product_ids = get_cart(&dynamodb_client, &cart_id).await?;
+    if !product_ids.contains(&product.id) && product_ids.len() >= CART_MAX_ITEMS {
+        return Err(ServerFnError::from(NexusError::CartFull));
+    }
+    add_products(
+        &dynamodb_client,
+        &cart_id,
+        std::slice::from_ref(&product.id),
+        now,
+        now + CART_LIFETIME_SECONDS,
+    )
+    .await?;
+    let replaced: Vec<String> = product
+        .bundle
+        .iter()
+        .filter(|item| product_ids.contains(item))
+        .cloned()
+        .collect();
+    if !replaced.is_empty() {
+        remove_products(&dynamodb_client, &cart_id, &replaced, now).await?;
+    }
+    list_cart().await
+}
+
+pub async fn remove_from_cart(product_id: String) -> Result<Cart, ServerFnError<NexusError>> {
+    let repository = user_repository()?;
+    let (_, cart_id) = current_cart(repository.as_ref()).await?;
+    let dynamodb_client = dynamo_client()?;
+    remove_products(
+        &dynamodb_client,
+        &cart_id,
+        std::slice::from_ref(&product_id),
+        Utc::now().timestamp(),
+    )
+    .await?;
+    list_cart().await
+}
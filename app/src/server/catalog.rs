@@ -0,0 +1,51 @@
+use crate::{catalog::Product, errors::NexusError};
+use chrono::Utc;
+use leptos::ServerFnError;
+use std::sync::LazyLock;
+
+/// The catalog ships with the server, so adding a product or a price is a deploy.
+/// Parsed once per instance; a malformed catalog fails on first use rather than at checkout.
+static CATALOG: LazyLock<Vec<Product>> = LazyLock::new(|| {
+    parse_catalog(include_str!("../../catalog.json")).expect("catalog.json is not a valid catalog")
+});
+
+pub fn parse_catalog(json: &str) -> Result<Vec<Product>, serde_json::Error> {
+    serde_json::from_str(json)
+}
+
+/// Every product, including ones that aren't (or are no longer) on sale. Use this when looking
+/// up things that were already bought.
+pub fn all_products() -> &'static [Product] {
+    &CATALOG
+}
+
+/// Products that can be bought right now
+pub fn list_products() -> Vec<Product> {
+    let now = Utc::now().timestamp();
+    all_products()
+        .iter()
+        .filter(|product| product.is_available_at(now))
+        .cloned()
+        .collect()
+}
+
+pub fn get_product(product_id: &str) -> Result<Product, ServerFnError<NexusError>> {
+    find_product(all_products(), product_id)
+        .cloned()
+        .ok_or_else(|| {
+            log::error!("No product with id {} in the catalog", product_id);
+            ServerFnError::from(NexusError::ProductNotFound)
+        })
+}
+
+pub fn find_product<'a>(products: &'a [Product], product_id: &str) -> Option<&'a Product> {
+    products.iter().find(|product| product.id == product_id)
+}
+
+/// The entitlements buying `product_id` grants. Products no longer in the catalog are assumed
+/// to have been plain products, granting their own.
+pub fn entitlements_for(product_id: &str) -> Vec<String> {
+    find_product(all_products(), product_id)
+        .map(Product::entitlements)
+        .unwrap_or_else(|| vec![product_id.to_string()])
+}
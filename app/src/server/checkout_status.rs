@@ -0,0 +1,73 @@
+use super::{
+    gifts::GiftRequest,
+    repository::purchases::{get_purchase, PurchaseStatus},
+    utilities::{dynamo_client, logged_in_user, stripe_client, user_repository},
+};
+use crate::{
+    errors::{NexusError, UNHANDLED},
+    orders::CheckoutStatus,
+};
+use leptos::ServerFnError;
+use stripe::{CheckoutSession, CheckoutSessionId, CheckoutSessionPaymentStatus};
+
+pub fn checkout_status_for(status: PurchaseStatus) -> CheckoutStatus {
+    match status {
+        PurchaseStatus::Paid => CheckoutStatus::Paid,
+        PurchaseStatus::Pending => CheckoutStatus::PaymentPending,
+        PurchaseStatus::Failed => CheckoutStatus::PaymentFailed,
+        PurchaseStatus::Refunded
+        | PurchaseStatus::Disputed
+        | PurchaseStatus::DisputeLost
+        | PurchaseStatus::FraudWarning => CheckoutStatus::Revoked,
+    }
+}
+
+/// The status of one of the logged in user's checkouts. The buyer usually lands on the success
+/// page before its webhook arrives, in which case Stripe is asked instead.
+pub async fn checkout_status(
+    checkout_session_id: String,
+) -> Result<CheckoutStatus, ServerFnError<NexusError>> {
+    let repository = user_repository()?;
+    let user = logged_in_user(repository.as_ref()).await?;
+    let dynamodb_client = dynamo_client()?;
+    if let Some(purchase) = get_purchase(&dynamodb_client, &checkout_session_id).await? {
+        if purchase.user_uuid != user.user_uuid {
+            log::error!(
+                "{} asked about someone else's checkout {}",
+                user.email,
+                checkout_session_id
+            );
+            return Err(ServerFnError::from(NexusError::PurchaseNotFound));
+        }
+        return Ok(match checkout_status_for(purchase.status) {
+            CheckoutStatus::Paid if purchase.gift => CheckoutStatus::GiftSent,
+            status => status,
+        });
+    }
+
+    let id = checkout_session_id
+        .parse::<CheckoutSessionId>()
+        .map_err(|_| ServerFnError::from(NexusError::PurchaseNotFound))?;
+    let stripe_client = stripe_client()?;
+    let checkout_session = CheckoutSession::retrieve(&stripe_client, &id, &[])
+        .await
+        .map_err(|e| {
+            log::error!("{:?}", e);
+            UNHANDLED
+        })?;
+    if checkout_session.client_reference_id.as_deref() != Some(user.user_uuid.as_str()) {
+        return Err(ServerFnError::from(NexusError::PurchaseNotFound));
+    }
+    let gift = GiftRequest::from_metadata(checkout_session.metadata.as_ref()).is_some();
+    Ok(match checkout_session.payment_status {
+        CheckoutSessionPaymentStatus::Unpaid => CheckoutStatus::PaymentPending,
+        CheckoutSessionPaymentStatus::Paid | CheckoutSessionPaymentStatus::NoPaymentRequired
+            if gift =>
+        {
+            CheckoutStatus::GiftSent
+        }
+        CheckoutSessionPaymentStatus::Paid | CheckoutSessionPaymentStatus::NoPaymentRequired => {
+            CheckoutStatus::Paid
+        }
+    })
+}
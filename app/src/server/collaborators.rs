@@ -0,0 +1,229 @@
+use super::{
+    admin::current_admin,
+    email::{escape_html, send_email},
+    repository::{
+        collaborators::{create_collaborator, get_collaborator, list_collaborators},
+        revenue_transfers::list_revenue_transfers_by_status,
+    },
+    revenue_share::{pay_revenue_shares, refresh_transfers_enabled},
+    utilities::{dynamo_client, ses_client, stripe_client},
+};
+use crate::{
+    errors::{NexusError, UNHANDLED},
+    orders::{Collaborator, RevenueSharePayouts, RevenueTransfer, RevenueTransferStatus},
+    site::constants::{SITE_DOMAIN, SITE_FULL_DOMAIN},
+};
+use chrono::Utc;
+use leptos::ServerFnError;
+use std::collections::HashMap;
+use stripe::{
+    Account, AccountId, AccountLink, AccountLinkType, AccountType, Client as StripeClient,
+    CreateAccount, CreateAccountCapabilities, CreateAccountCapabilitiesTransfers,
+    CreateAccountLink, RequestStrategy,
+};
+
+/// Key of the connected account metadata entry holding the collaborator's id
+pub const COLLABORATOR_ID_METADATA_KEY: &str = "collaborator_id";
+
+/// Collaborator ids end up in ledger ids, which use `#` as a separator
+pub fn is_valid_collaborator_id(collaborator_id: &str) -> bool {
+    !collaborator_id.is_empty()
+        && collaborator_id.len() <= 64
+        && collaborator_id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+/// Creates a Stripe onboarding link for the collaborator and emails it to them. Links expire
+/// within minutes of being opened, so one that has is replaced by asking for a new one.
+async fn send_onboarding_link(
+    stripe_client: &StripeClient,
+    collaborator: &Collaborator,
+) -> Result<String, ServerFnError<NexusError>> {
+    let account_id = collaborator
+        .stripe_account_id
+        .parse::<AccountId>()
+        .map_err(|e| {
+            log::error!(
+                "Collaborator {} has an invalid account id {:?}",
+                collaborator.collaborator_id,
+                e
+            );
+            UNHANDLED
+        })?;
+    let site = format!("https://{}/", SITE_FULL_DOMAIN);
+    let mut params = CreateAccountLink::new(account_id, AccountLinkType::AccountOnboarding);
+    params.refresh_url = Some(&site);
+    params.return_url = Some(&site);
+    let link = AccountLink::create(stripe_client, params)
+        .await
+        .map_err(|e| {
+            log::error!(
+                "Could not create an onboarding link for {} {:?}",
+                collaborator.collaborator_id,
+                e
+            );
+            UNHANDLED
+        })?;
+    let ses_client = ses_client()?;
+    send_email(
+        &ses_client,
+        std::slice::from_ref(&collaborator.email),
+        &format!("[{}] Set up your payouts", SITE_DOMAIN),
+        &format!(
+            "Hello {},
+To get paid your share of sales on {}, set up your payout details with Stripe here:
+
+{}
+
+The link can only be used once. If it has expired, let us know and we'll send another.",
+            escape_html(&collaborator.name),
+            SITE_DOMAIN,
+            link.url
+        ),
+    )
+    .await?;
+    Ok(link.url)
+}
+
+/// Adds a collaborator, with a Stripe connected account to pay them through, and sends them
+/// the link to set it up. Returns the link.
+pub async fn add_collaborator(
+    collaborator_id: String,
+    name: String,
+    email: String,
+    country: String,
+) -> Result<String, ServerFnError<NexusError>> {
+    let admin = current_admin().await?;
+    let collaborator_id = collaborator_id.trim().to_string();
+    if !is_valid_collaborator_id(&collaborator_id) {
+        return Err(ServerFnError::from(NexusError::CollaboratorIdInvalid));
+    }
+    let email = email.trim().to_string();
+    if !email.contains('@') {
+        return Err(ServerFnError::from(NexusError::BadEmailAddress));
+    }
+    let dynamodb_client = dynamo_client()?;
+    if get_collaborator(&dynamodb_client, &collaborator_id)
+        .await?
+        .is_some()
+    {
+        return Err(ServerFnError::from(NexusError::CollaboratorAlreadyExists));
+    }
+    let country = country.trim().to_uppercase();
+    let mut params = CreateAccount::new();
+    params.type_ = Some(AccountType::Express);
+    params.email = Some(&email);
+    if !country.is_empty() {
+        params.country = Some(&country);
+    }
+    params.capabilities = Some(CreateAccountCapabilities {
+        transfers: Some(CreateAccountCapabilitiesTransfers {
+            requested: Some(true),
+        }),
+        ..Default::default()
+    });
+    params.metadata = Some(HashMap::from([(
+        COLLABORATOR_ID_METADATA_KEY.to_string(),
+        collaborator_id.clone(),
+    )]));
+    let stripe_client = stripe_client()?;
+    // Adding the same collaborator again after a failure mustn't leave a stray account
+    let idempotent_client = (*stripe_client)
+        .clone()
+        .with_strategy(RequestStrategy::Idempotent(format!(
+            "collaborator-{}",
+            collaborator_id
+        )));
+    let account = Account::create(&idempotent_client, params)
+        .await
+        .map_err(|e| {
+            log::error!(
+                "Could not create a connected account for {} {:?}",
+                collaborator_id,
+                e
+            );
+            UNHANDLED
+        })?;
+    let collaborator = Collaborator {
+        collaborator_id,
+        name: name.trim().to_string(),
+        email,
+        stripe_account_id: account.id.to_string(),
+        transfers_enabled: false,
+        created_at: Utc::now().timestamp(),
+    };
+    if !create_collaborator(&dynamodb_client, &collaborator).await? {
+        return Err(ServerFnError::from(NexusError::CollaboratorAlreadyExists));
+    }
+    log::info!(
+        "{} added collaborator {} with account {}",
+        admin,
+        collaborator.collaborator_id,
+        collaborator.stripe_account_id
+    );
+    send_onboarding_link(&stripe_client, &collaborator).await
+}
+
+/// Sends a collaborator a new link to set up their payouts, returning it
+pub async fn resend_onboarding_link(
+    collaborator_id: String,
+) -> Result<String, ServerFnError<NexusError>> {
+    current_admin().await?;
+    let dynamodb_client = dynamo_client()?;
+    let collaborator = get_collaborator(&dynamodb_client, &collaborator_id)
+        .await?
+        .ok_or_else(|| ServerFnError::from(NexusError::CollaboratorNotFound))?;
+    let stripe_client = stripe_client()?;
+    send_onboarding_link(&stripe_client, &collaborator).await
+}
+
+/// Every collaborator, checking with Stripe whether the ones that hadn't finished onboarding
+/// have since
+pub async fn list_all_collaborators() -> Result<Vec<Collaborator>, ServerFnError<NexusError>> {
+    current_admin().await?;
+    let dynamodb_client = dynamo_client()?;
+    let stripe_client = stripe_client()?;
+    let mut collaborators = list_collaborators(&dynamodb_client).await?;
+    for collaborator in collaborators.iter_mut() {
+        if collaborator.transfers_enabled {
+            continue;
+        }
+        if let Err(e) =
+            refresh_transfers_enabled(&dynamodb_client, &stripe_client, collaborator).await
+        {
+            log::error!(
+                "Could not check on collaborator {} {:?}",
+                collaborator.collaborator_id,
+                e
+            );
+        }
+    }
+    Ok(collaborators)
+}
+
+/// The revenue share ledger entries with `status` (`pending` if empty), oldest first
+pub async fn list_revenue_transfers(
+    status: String,
+) -> Result<Vec<RevenueTransfer>, ServerFnError<NexusError>> {
+    current_admin().await?;
+    let status = match status.as_str() {
+        "" => RevenueTransferStatus::Pending,
+        status => RevenueTransferStatus::parse(status).ok_or_else(|| {
+            log::error!("Unknown revenue transfer status {}", status);
+            UNHANDLED
+        })?,
+    };
+    let dynamodb_client = dynamo_client()?;
+    list_revenue_transfers_by_status(&dynamodb_client, status).await
+}
+
+/// Transfers the revenue shares that are due now, rather than when Stripe next makes funds
+/// available
+pub async fn pay_revenue_shares_now() -> Result<RevenueSharePayouts, ServerFnError<NexusError>> {
+    let admin = current_admin().await?;
+    log::info!("{} is paying out revenue shares", admin);
+    let dynamodb_client = dynamo_client()?;
+    let stripe_client = stripe_client()?;
+    pay_revenue_shares(&dynamodb_client, &stripe_client, Utc::now().timestamp()).await
+}
@@ -1,68 +1,224 @@
-use crate::errors::UNHANDLED;
+use crate::{
+    catalog::{Offer, Product, DEFAULT_CURRENCY},
+    errors::UNHANDLED,
+    server::catalog::{all_products, get_product},
+};
+use chrono::Utc;
 use leptos::ServerFnError;
+use std::collections::HashMap;
 use stripe::{
-    CheckoutSession, CheckoutSessionMode, CreateCheckoutSession, CreateCheckoutSessionLineItems,
-    CreateCustomer, Customer,
+    CheckoutSession, CheckoutSessionMode, CreateCheckoutSession, CreateCheckoutSessionDiscounts,
+    CreateCheckoutSessionLineItems, CreateCheckoutSessionLineItemsPriceData,
+    CreateCheckoutSessionLineItemsPriceDataProductData, Currency,
 };
 
-#[allow(unused_imports)]
 use crate::{
     errors::NexusError,
-    server::utilities::{
-        check_if_session_is_valid, dynamo_client, get_session_cookie, stripe_client,
+    server::{
+        cart::checkout_cart,
+        currency::preferred_currency,
+        gifts::GiftRequest,
+        stripe_customer::customer_for,
+        utilities::{logged_in_user, stripe_client, user_repository, LoggedInUser},
     },
     site::constants::SITE_FULL_DOMAIN,
 };
 
-pub async fn create_checkout() -> Result<String, ServerFnError<NexusError>> {
-    let stripe_client = stripe_client()?;
-    #[allow(unused_mut)]
-    let mut email = "example@example.com".to_owned();
-    #[cfg(not(debug_assertions))]
-    {
-        log::error!("release");
-        let session_id_cookie = get_session_cookie().await?;
-        let dynamo_client = dynamo_client()?;
-        let (valid, email_fetched) =
-            check_if_session_is_valid(session_id_cookie, &dynamo_client).await?;
-        if !valid {
-            return Err(UNHANDLED);
-        }
-        email = email_fetched;
-    }
-    let customer = Customer::create(
-        &stripe_client,
-        CreateCustomer {
-            email: Some(email.as_str()),
-            metadata: Some(std::collections::HashMap::from([(
-                String::from("async-stripe"),
-                String::from("true"),
-            )])),
+/// Key of the checkout session metadata entry holding the id of the product being bought
+pub const PRODUCT_ID_METADATA_KEY: &str = "product_id";
+/// Key of the checkout session metadata entry holding the name of the sale running at checkout
+pub const SALE_METADATA_KEY: &str = "sale";
+/// Key of the checkout session metadata entry holding how far the price was marked down below
+/// its Stripe price, by a fixed price sale or by credit for the items of a bundle already owned.
+/// Stripe only knows about discounts it applied itself.
+pub const SALE_DISCOUNT_METADATA_KEY: &str = "sale_discount";
+/// Key of the metadata entry of a cart checkout holding the comma separated ids of the products
+/// being bought, in the order of the line items. Single product checkouts use
+/// [`PRODUCT_ID_METADATA_KEY`] instead.
+pub const PRODUCT_IDS_METADATA_KEY: &str = "product_ids";
+
+/// Key of a cart checkout's metadata entry `key` about one of its products, e.g.
+/// `sale:some-game`
+pub fn line_metadata_key(key: &str, product_id: &str) -> String {
+    format!("{}:{}", key, product_id)
+}
+
+/// The coupon to apply for `offer`. Stripe takes one discount per checkout, so coupons are
+/// only used for checkouts of one product, and only when the Stripe price is what's charged.
+fn coupon_for(offer: &Offer, single: bool) -> Option<&str> {
+    offer
+        .stripe_coupon_id()
+        .filter(|_| single && offer.matches_stripe_price())
+}
 
+/// The line item charging `offer` for `product`: its Stripe price where that's what's charged,
+/// otherwise an inline price for the exact amount
+fn line_item(
+    product: &Product,
+    offer: &Offer,
+    single: bool,
+) -> Result<CreateCheckoutSessionLineItems, ServerFnError<NexusError>> {
+    if offer.matches_stripe_price() && (offer.stripe_coupon_id().is_none() || single) {
+        return Ok(CreateCheckoutSessionLineItems {
+            quantity: Some(1),
+            price: Some(offer.price.stripe_price_id.clone()),
             ..Default::default()
-        },
-    )
-    .await
-    .map_err(|e| {
-        log::error!("{:?}", e);
-        UNHANDLED
-    })?;
+        });
+    }
+    let currency = offer
+        .price
+        .currency
+        .to_lowercase()
+        .parse::<Currency>()
+        .map_err(|e| {
+            log::error!(
+                "{} is priced in unknown currency {} {:?}",
+                product.id,
+                offer.price.currency,
+                e
+            );
+            UNHANDLED
+        })?;
+    Ok(CreateCheckoutSessionLineItems {
+        quantity: Some(1),
+        price_data: Some(CreateCheckoutSessionLineItemsPriceData {
+            currency,
+            unit_amount: Some(offer.price.unit_amount),
+            product_data: Some(CreateCheckoutSessionLineItemsPriceDataProductData {
+                name: product.title.clone(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }),
+        ..Default::default()
+    })
+}
 
-    log::info!(
-        "created a customer at https://dashboard.stripe.com/test/customers/{}",
-        customer.id
-    );
+/// What the webhook needs to know about the products being bought
+fn line_metadata(lines: &[(Product, Offer)]) -> HashMap<String, String> {
+    let single = lines.len() == 1;
+    let key = |key: &str, product_id: &str| match single {
+        true => key.to_string(),
+        false => line_metadata_key(key, product_id),
+    };
+    let mut metadata = match single {
+        true => HashMap::from([(PRODUCT_ID_METADATA_KEY.to_string(), lines[0].0.id.clone())]),
+        false => HashMap::from([(
+            PRODUCT_IDS_METADATA_KEY.to_string(),
+            lines
+                .iter()
+                .map(|(product, _)| product.id.as_str())
+                .collect::<Vec<_>>()
+                .join(","),
+        )]),
+    };
+    for (product, offer) in lines {
+        if let Some(sale) = &offer.sale {
+            metadata.insert(key(SALE_METADATA_KEY, &product.id), sale.name.clone());
+        }
+        if coupon_for(offer, single).is_none() && offer.discount() > 0 {
+            metadata.insert(
+                key(SALE_DISCOUNT_METADATA_KEY, &product.id),
+                offer.discount().to_string(),
+            );
+        }
+    }
+    metadata
+}
+
+/// Starts a checkout for `product_id`, or for everything in the cart if it's empty. Filling in
+/// `gift_recipient_email` makes it a gift, which is emailed to the recipient instead of going
+/// into the buyer's library.
+pub async fn create_checkout(
+    product_id: String,
+    gift_recipient_email: String,
+    gift_message: String,
+) -> Result<String, ServerFnError<NexusError>> {
+    let gift = GiftRequest::from_checkout(gift_recipient_email, gift_message)?;
+    // The webhook grants the purchase to whoever started the checkout, so there has to be
+    // someone logged in
+    let repository = user_repository()?;
+    let (LoggedInUser { email, user_uuid }, lines) = match product_id.is_empty() {
+        true => {
+            if gift.is_some() {
+                log::error!("Gifts are bought one product at a time, not from the cart");
+                return Err(ServerFnError::from(NexusError::ProductNotFound));
+            }
+            let (user, cart) = checkout_cart().await?;
+            if cart.lines.is_empty() {
+                return Err(ServerFnError::from(NexusError::CartEmpty));
+            }
+            (user, cart.lines)
+        }
+        false => {
+            let product = get_product(&product_id)?;
+            let now = Utc::now().timestamp();
+            if !product.is_available_at(now) {
+                log::error!("Tried to check out {}, which isn't on sale", product.id);
+                return Err(ServerFnError::from(NexusError::ProductUnavailable));
+            }
+            let user = logged_in_user(repository.as_ref()).await?;
+            // A gift goes into someone else's library, so what the buyer owns doesn't matter
+            let owned = match &gift {
+                Some(_) => Vec::new(),
+                None => repository.entitlements(&user.email).await?,
+            };
+            if product.is_owned_by(&owned) {
+                return Err(ServerFnError::from(NexusError::AlreadyOwned));
+            }
+            // Products not priced in the visitor's currency are charged in the default one,
+            // which is also what the store shows for them
+            let currency = preferred_currency().await?.selected;
+            let offer = product
+                .offer_for_owner(all_products(), &currency, now, &owned)
+                .or_else(|| product.offer_for_owner(all_products(), DEFAULT_CURRENCY, now, &owned))
+                .ok_or_else(|| {
+                    log::error!(
+                        "{} has no {} or default currency price",
+                        product.id,
+                        currency
+                    );
+                    ServerFnError::from(NexusError::NoPriceForCurrency)
+                })?;
+            (user, vec![(product, offer)])
+        }
+    };
+    let single = lines.len() == 1;
+    let stripe_client = stripe_client()?;
+    let customer_id = customer_for(&stripe_client, repository.as_ref(), &email, &user_uuid).await?;
     // finally, create a checkout session for this product / price
     let mut params = CreateCheckoutSession::new();
-    let redirect_url = format!("https://{}/download", SITE_FULL_DOMAIN);
+    // Stripe fills in the session id, so the page can tell whether the payment is still pending
+    let redirect_url = format!(
+        "https://{}/checkout/success?session_id={{CHECKOUT_SESSION_ID}}",
+        SITE_FULL_DOMAIN
+    );
     params.return_url = Some(&redirect_url);
-    params.customer = Some(customer.id);
+    params.customer = Some(customer_id);
+    params.client_reference_id = Some(&user_uuid);
     params.mode = Some(CheckoutSessionMode::Payment);
-    params.line_items = Some(vec![CreateCheckoutSessionLineItems {
-        quantity: Some(1),
-        price: Some("price_1OgmNmFM3dbbE2EswStQyvJa".to_string()),
-        ..Default::default()
-    }]);
+    params.line_items = Some(
+        lines
+            .iter()
+            .map(|(product, offer)| line_item(product, offer, single))
+            .collect::<Result<_, _>>()?,
+    );
+    // Stripe takes either a coupon or a promotion code, so during a percentage sale the sale's
+    // coupon wins
+    match coupon_for(&lines[0].1, single) {
+        Some(coupon) => {
+            params.discounts = Some(vec![CreateCheckoutSessionDiscounts {
+                coupon: Some(coupon.to_string()),
+                promotion_code: None,
+            }]);
+        }
+        None => params.allow_promotion_codes = Some(true),
+    }
+    let mut metadata = line_metadata(&lines);
+    if let Some(gift) = &gift {
+        metadata.extend(gift.to_metadata());
+    }
+    params.metadata = Some(metadata);
     params.expand = &["line_items", "line_items.data.price.product"];
     params.ui_mode = Some(stripe::CheckoutSessionUiMode::Embedded);
     let checkout_session = CheckoutSession::create(&stripe_client, params)
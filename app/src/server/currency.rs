@@ -0,0 +1,95 @@
+use super::catalog::list_products;
+use crate::{
+    catalog::{Product, DEFAULT_CURRENCY},
+    currency::{choose_currency, currencies_from_accept_language, CurrencySettings},
+    errors::{NexusError, UNHANDLED},
+};
+use axum_extra::extract::CookieJar;
+use http::{header, HeaderMap, HeaderValue};
+use leptos::{expect_context, ServerFnError};
+use leptos_axum::{extract, ResponseOptions};
+
+/// Cookie holding the currency the visitor picked, overriding the one guessed from their language
+pub const CURRENCY_COOKIE: &str = "currency";
+/// A year, so the choice sticks between visits
+const CURRENCY_COOKIE_MAX_AGE: i64 = 60 * 60 * 24 * 365;
+
+/// Every currency at least one product is priced in, sorted
+pub fn available_currencies(products: &[Product]) -> Vec<String> {
+    let mut currencies: Vec<String> = products
+        .iter()
+        .flat_map(|product| &product.prices)
+        .map(|price| price.currency.to_lowercase())
+        .collect();
+    currencies.sort();
+    currencies.dedup();
+    currencies
+}
+
+/// The currency to use: the one the visitor picked if it's still offered, otherwise the first
+/// offered one their languages point to, otherwise the default
+pub fn resolve_currency(
+    picked: Option<&str>,
+    accept_language: Option<&str>,
+    available: Vec<String>,
+) -> CurrencySettings {
+    if let Some(selected) = picked.and_then(|picked| choose_currency([picked], &available)) {
+        return CurrencySettings {
+            selected,
+            available,
+            overridden: true,
+        };
+    }
+    let selected = accept_language
+        .and_then(|header| choose_currency(currencies_from_accept_language(header), &available))
+        .unwrap_or_else(|| DEFAULT_CURRENCY.to_string());
+    CurrencySettings {
+        selected,
+        available,
+        overridden: false,
+    }
+}
+
+/// The currency of the current request, from its cookie and `Accept-Language` header
+pub async fn preferred_currency() -> Result<CurrencySettings, ServerFnError<NexusError>> {
+    let cookie_jar: CookieJar = extract().await.map_err(|e| {
+        log::error!("Could not get cookie jar {:?}", e);
+        UNHANDLED
+    })?;
+    let headers: HeaderMap = extract().await.map_err(|e| {
+        log::error!("Could not get headers {:?}", e);
+        UNHANDLED
+    })?;
+    let accept_language = headers
+        .get(header::ACCEPT_LANGUAGE)
+        .and_then(|value| value.to_str().ok());
+    Ok(resolve_currency(
+        cookie_jar.get(CURRENCY_COOKIE).map(|cookie| cookie.value()),
+        accept_language,
+        available_currencies(&list_products()),
+    ))
+}
+
+/// Remembers `currency` as the visitor's pick. An empty one forgets the pick, going back to
+/// guessing from their language.
+pub async fn set_currency(currency: String) -> Result<(), ServerFnError<NexusError>> {
+    let currency = currency.trim().to_lowercase();
+    let cookie = if currency.is_empty() {
+        format!("{}=;Max-Age=0;Secure;SameSite=Lax; Path=/", CURRENCY_COOKIE)
+    } else {
+        if !available_currencies(&list_products()).contains(&currency) {
+            log::error!("Tried to pick {}, which nothing is priced in", currency);
+            return Err(ServerFnError::from(NexusError::NoPriceForCurrency));
+        }
+        format!(
+            "{}={};Max-Age={};Secure;SameSite=Lax; Path=/",
+            CURRENCY_COOKIE, currency, CURRENCY_COOKIE_MAX_AGE
+        )
+    };
+    let cookie = HeaderValue::from_str(&cookie).map_err(|e| {
+        log::error!("Unable to create cookie {}: {:?}", cookie, e);
+        UNHANDLED
+    })?;
+    expect_context::<ResponseOptions>().append_header(header::SET_COOKIE, cookie);
+    Ok(())
+}
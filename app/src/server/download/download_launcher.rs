@@ -1,17 +1,17 @@
 use super::{
     super::globals::app_state::AppState,
-    download_utils::{download_file_from_s3, SessionId, LAUNCHER_BUCKET_NAME},
+    download_utils::{serve_download, Download, SessionId, LAUNCHER_BUCKET_NAME},
 };
 use axum::{
-    body::Body,
-    extract::{Extension, Path},
+    extract::{Path, State},
     response::IntoResponse,
 };
-use http::{Response, StatusCode};
+use http::{HeaderMap, StatusCode};
 
 pub async fn download_launcher(
     Path(os_type): Path<String>,
-    Extension(state): Extension<AppState>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
     _session_id: SessionId,
 ) -> impl IntoResponse {
     // TODO: Check content_types
@@ -25,31 +25,15 @@ pub async fn download_launcher(
         _ => return (StatusCode::BAD_REQUEST, "Unsupported platform").into_response(),
     };
 
-    match download_file_from_s3(
-        &state.s3_client,
-        LAUNCHER_BUCKET_NAME.to_owned(),
-        launcher_key.to_owned(),
-    )
-    .await
-    {
-        Ok(file_bytes) => {
-            let file_name = launcher_key
-                .split('/')
-                .last()
-                .expect("Invalid launcher file path");
-            Response::builder()
-                .status(StatusCode::OK)
-                .header("Content-Type", content_type)
-                .header(
-                    "Content-Disposition",
-                    format!("attachment; filename=\"{}\"", file_name),
-                )
-                .body(Body::from(file_bytes))
-                .unwrap()
-        }
-        Err(error) => Response::builder()
-            .status(StatusCode::INTERNAL_SERVER_ERROR)
-            .body(Body::from(error))
-            .unwrap(),
-    }
+    let file_name = launcher_key
+        .split('/')
+        .next_back()
+        .expect("Invalid launcher file path");
+    let download = Download {
+        bucket: LAUNCHER_BUCKET_NAME,
+        key: launcher_key.to_owned(),
+        file_name: file_name.to_owned(),
+        content_type,
+    };
+    serve_download(&state.s3_client, &headers, download).await
 }
@@ -1,11 +1,30 @@
-use super::super::globals::env_var::get_host_prefix;
+use super::super::{
+    globals::{app_state::AppState, env_var::get_host_prefix},
+    repository::downloads::record_download,
+    utilities::check_if_session_is_valid,
+};
 use async_trait::async_trait;
 use aws_sdk_s3::Client as S3Client;
+use aws_sdk_s3::{
+    error::ProvideErrorMetadata, presigning::PresigningConfig, primitives::ByteStream,
+};
 use axum::{
-    body::{Body, Bytes},
+    body::Body,
     extract::{FromRequest, Request},
+    response::{IntoResponse, Redirect, Response as HttpResponse},
+    Json,
+};
+use chrono::Utc;
+use http::{
+    header::{
+        ACCEPT, ACCEPT_RANGES, CONTENT_DISPOSITION, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE,
+        RANGE,
+    },
+    HeaderMap, HeaderName, StatusCode,
 };
-use http::{HeaderName, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tokio_util::io::ReaderStream;
 
 pub const GAME_BUCKET_NAME: &str = "games";
 pub const LAUNCHER_BUCKET_NAME: &str = "launchers";
@@ -42,24 +61,320 @@ impl<S: Sync> FromRequest<S, Body> for SessionId {
     }
 }
 
-pub async fn download_file_from_s3(
+/// The email of the session's account, unless the session isn't valid or the account doesn't
+/// own `game`
+pub async fn authorize_download(
+    state: &AppState,
+    session_id: String,
+    game: &str,
+) -> Result<String, HttpResponse> {
+    let repository = state.user_repository.as_ref();
+    // TODO: fix this
+    let session_valid_result =
+        check_if_session_is_valid(session_id, "".to_string(), repository, &state.key_client).await;
+
+    let unhandled_error = || -> HttpResponse { handle_error("unhandled".to_string()) };
+
+    if session_valid_result.is_err() {
+        return Err((
+            StatusCode::UNAUTHORIZED,
+            "Session expired or otherwise invalid",
+        )
+            .into_response());
+    }
+
+    let (valid, email) = session_valid_result.unwrap();
+
+    if !valid {
+        return Err((
+            StatusCode::UNAUTHORIZED,
+            "Session expired or otherwise invalid",
+        )
+            .into_response());
+    }
+
+    let bought_game = repository
+        .entitlements(&email)
+        .await
+        .map_err(|_| unhandled_error())?
+        .iter()
+        .any(|entitlement| entitlement == game);
+
+    if !bought_game {
+        return Err((StatusCode::UNAUTHORIZED, "Hasn't bought game").into_response());
+    }
+    Ok(email)
+}
+
+/// Counted for the refund policy. Not being able to count it shouldn't stop the download.
+pub async fn count_download(state: &AppState, email: &str, game: &str) {
+    match state.user_repository.find_user_uuid(email).await {
+        Ok(Some(user_uuid)) => {
+            if let Err(e) = record_download(
+                &state.dynamodb_client,
+                &user_uuid,
+                game,
+                Utc::now().timestamp(),
+            )
+            .await
+            {
+                log::error!("Could not count a download of {} {:?}", game, e);
+            }
+        }
+        result => log::error!("Could not find who downloaded {} {:?}", game, result),
+    }
+}
+
+fn handle_error(msg: String) -> HttpResponse {
+    log::error!("{}", msg);
+    (StatusCode::INTERNAL_SERVER_ERROR, "Unknown error").into_response()
+}
+
+/// `presigned` (the default) answers downloads with a short-lived S3 link, `stream` pipes the
+/// object through this server instead, for containers that don't have Lambda's response limits
+const DOWNLOAD_MODE_ENV_VAR: &str = "DOWNLOAD_MODE";
+
+/// How long a presigned download link stays valid
+pub const PRESIGNED_URL_SECONDS: u64 = 300;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DownloadMode {
+    Presigned,
+    Stream,
+}
+
+impl DownloadMode {
+    pub fn parse(mode: &str) -> Option<Self> {
+        match mode.trim() {
+            "presigned" => Some(Self::Presigned),
+            "stream" => Some(Self::Stream),
+            _ => None,
+        }
+    }
+}
+
+pub fn download_mode() -> DownloadMode {
+    match std::env::var(DOWNLOAD_MODE_ENV_VAR) {
+        Ok(mode) => DownloadMode::parse(&mode).unwrap_or_else(|| {
+            log::error!(
+                "Unknown {} {}, using presigned links",
+                DOWNLOAD_MODE_ENV_VAR,
+                mode
+            );
+            DownloadMode::Presigned
+        }),
+        Err(_) => DownloadMode::Presigned,
+    }
+}
+
+/// An S3 object handed out as a file
+pub struct Download<'a> {
+    pub bucket: &'a str,
+    pub key: String,
+    pub file_name: String,
+    pub content_type: &'a str,
+}
+
+impl Download<'_> {
+    fn content_disposition(&self) -> String {
+        format!("attachment; filename=\"{}\"", self.file_name)
+    }
+}
+
+/// What downloads answer with when the client asked for JSON instead of a redirect
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PresignedDownload {
+    pub url: String,
+    pub expires_at: i64,
+}
+
+/// A link anyone can `GET` the object from for [`PRESIGNED_URL_SECONDS`]. S3 answers it with the
+/// download's file name and content type, like [`stream_download`] would.
+pub async fn presigned_download(
     s3_client: &S3Client,
-    bucket: String,
-    key: String,
-) -> Result<Bytes, String> {
-    let resp = s3_client
+    download: &Download<'_>,
+    now: i64,
+) -> Result<PresignedDownload, String> {
+    let expires_in = Duration::from_secs(PRESIGNED_URL_SECONDS);
+    let config = PresigningConfig::expires_in(expires_in).map_err(|e| e.to_string())?;
+    let request = s3_client
         .get_object()
-        .bucket(bucket)
-        .key(key)
-        .send()
+        .bucket(download.bucket)
+        .key(&download.key)
+        .response_content_disposition(download.content_disposition())
+        .response_content_type(download.content_type)
+        .presigned(config)
         .await
         .map_err(|e| e.to_string())?;
+    Ok(PresignedDownload {
+        url: request.uri().to_string(),
+        expires_at: now + expires_in.as_secs() as i64,
+    })
+}
+
+/// A single range out of a `Range` header. S3 serves one range per request, so requests for
+/// several get the whole object.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteRange {
+    /// `bytes=start-` or `bytes=start-end`, `end` included
+    From { start: u64, end: Option<u64> },
+    /// `bytes=-length`, the last `length` bytes
+    Last(u64),
+}
+
+impl ByteRange {
+    pub fn parse(header: &str) -> Option<Self> {
+        let (start, end) = header.trim().strip_prefix("bytes=")?.split_once('-')?;
+        match (start.trim(), end.trim()) {
+            ("", length) => length.parse().ok().map(Self::Last),
+            (start, "") => start
+                .parse()
+                .ok()
+                .map(|start| Self::From { start, end: None }),
+            (start, end) => {
+                let (start, end) = (start.parse().ok()?, end.parse().ok()?);
+                (start <= end).then_some(Self::From {
+                    start,
+                    end: Some(end),
+                })
+            }
+        }
+    }
+
+    pub fn header(&self) -> String {
+        match self {
+            Self::From { start, end: None } => format!("bytes={}-", start),
+            Self::From {
+                start,
+                end: Some(end),
+            } => format!("bytes={}-{}", start, end),
+            Self::Last(length) => format!("bytes=-{}", length),
+        }
+    }
+
+    pub fn starts_at_beginning(&self) -> bool {
+        matches!(self, Self::From { start: 0, .. })
+    }
+}
+
+/// The range the request asks for, if S3 can serve it
+pub fn requested_range(headers: &HeaderMap) -> Option<ByteRange> {
+    headers
+        .get(RANGE)
+        .and_then(|range| range.to_str().ok())
+        .and_then(ByteRange::parse)
+}
+
+/// Whether the request starts a download, rather than resuming one or fetching another part of
+/// it. Only those are counted for the refund policy.
+pub fn starts_download(headers: &HeaderMap) -> bool {
+    requested_range(headers).is_none_or(|range| range.starts_at_beginning())
+}
+
+/// The body of the object, to read a chunk at a time. `None` if there's no object with that key.
+pub async fn open_object(
+    s3_client: &S3Client,
+    bucket: &str,
+    key: &str,
+) -> Result<Option<ByteStream>, String> {
+    match s3_client.get_object().bucket(bucket).key(key).send().await {
+        Ok(object) => Ok(Some(object.body)),
+        Err(error) if error.as_service_error().and_then(|e| e.code()) == Some("NoSuchKey") => {
+            Ok(None)
+        }
+        Err(error) => Err(error.to_string()),
+    }
+}
+
+/// The whole object, for small ones or ones that have to be in memory anyway. `None` if there's
+/// no object with that key.
+pub async fn read_object(
+    s3_client: &S3Client,
+    bucket: &str,
+    key: &str,
+) -> Result<Option<Vec<u8>>, String> {
+    let Some(body) = open_object(s3_client, bucket, key).await? else {
+        return Ok(None);
+    };
+    let bytes = body.collect().await.map_err(|e| e.to_string())?;
+    Ok(Some(bytes.to_vec()))
+}
+
+/// Pipes the object (or the requested range of it) through without holding it in memory
+pub async fn stream_download(
+    s3_client: &S3Client,
+    download: &Download<'_>,
+    range: Option<ByteRange>,
+) -> HttpResponse {
+    let object = s3_client
+        .get_object()
+        .bucket(download.bucket)
+        .key(&download.key)
+        .set_range(range.map(|range| range.header()))
+        .send()
+        .await;
+    let object = match object {
+        Ok(object) => object,
+        Err(error) => {
+            let status = match error.as_service_error().and_then(|e| e.code()) {
+                Some("NoSuchKey") => StatusCode::NOT_FOUND,
+                Some("InvalidRange") => StatusCode::RANGE_NOT_SATISFIABLE,
+                _ => {
+                    log::error!("Could not get {} {:?}", download.key, error);
+                    StatusCode::INTERNAL_SERVER_ERROR
+                }
+            };
+            return status.into_response();
+        }
+    };
 
-    let data = resp.body.collect().await.map_err(|e| e.to_string())?;
+    let mut response = HttpResponse::builder()
+        .header(CONTENT_TYPE, download.content_type)
+        .header(CONTENT_DISPOSITION, download.content_disposition())
+        .header(ACCEPT_RANGES, "bytes");
+    if let Some(content_length) = object.content_length() {
+        response = response.header(CONTENT_LENGTH, content_length);
+    }
+    response = match object.content_range() {
+        Some(content_range) => response
+            .status(StatusCode::PARTIAL_CONTENT)
+            .header(CONTENT_RANGE, content_range),
+        None => response.status(StatusCode::OK),
+    };
+    let body = ReaderStream::new(object.body.into_async_read());
+    response.body(Body::from_stream(body)).unwrap_or_else(|e| {
+        log::error!("Could not build the response for {} {:?}", download.key, e);
+        StatusCode::INTERNAL_SERVER_ERROR.into_response()
+    })
+}
 
-    Ok(data.into_bytes())
+/// Answers with the file in the configured [`DownloadMode`]. Presigned links are returned as
+/// JSON when the request `Accept`s it, and otherwise redirected to.
+pub async fn serve_download(
+    s3_client: &S3Client,
+    headers: &HeaderMap,
+    download: Download<'_>,
+) -> HttpResponse {
+    match download_mode() {
+        DownloadMode::Presigned => {
+            match presigned_download(s3_client, &download, Utc::now().timestamp()).await {
+                Ok(presigned) if accepts_json(headers) => Json(presigned).into_response(),
+                Ok(presigned) => Redirect::to(&presigned.url).into_response(),
+                Err(error) => {
+                    log::error!("Could not presign {} {}", download.key, error);
+                    StatusCode::INTERNAL_SERVER_ERROR.into_response()
+                }
+            }
+        }
+        DownloadMode::Stream => {
+            stream_download(s3_client, &download, requested_range(headers)).await
+        }
+    }
 }
 
-// TODO: Check out itch.io's cool 'wharf' protocol
-// pub async fn download() -> Result<String, ServerFnError<NexusError>> {
-// }
+fn accepts_json(headers: &HeaderMap) -> bool {
+    headers
+        .get(ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .is_some_and(|accept| accept.contains("application/json"))
+}
@@ -0,0 +1,281 @@
+use super::{
+    super::{admin::current_admin, globals::app_state::AppState, utilities::s3_client},
+    download_game_version::find_latest_version,
+    download_utils::{
+        authorize_download, read_object, serve_download, Download, SessionId, GAME_BUCKET_NAME,
+    },
+};
+use crate::errors::{NexusError, UNHANDLED};
+use aws_sdk_s3::{primitives::ByteStream, Client as S3Client};
+use axum::{
+    extract::{Path, State},
+    response::{IntoResponse, Response as HttpResponse},
+    Json,
+};
+use http::{HeaderMap, StatusCode};
+use leptos::ServerFnError;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+
+/// One file of a build. `path` is relative to the install directory and uses `/`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestFile {
+    pub path: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+/// Every file of a build, sorted by path
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BuildManifest {
+    pub game: String,
+    pub platform: String,
+    pub version: String,
+    pub files: Vec<ManifestFile>,
+}
+
+impl BuildManifest {
+    pub fn total_size(&self) -> u64 {
+        self.files.iter().map(|file| file.size).sum()
+    }
+
+    /// The files an install with `installed` still needs: those it's missing and those whose
+    /// contents don't match, e.g. because they were corrupted
+    pub fn files_to_download(&self, installed: &[ManifestFile]) -> Vec<&ManifestFile> {
+        let installed: HashMap<&str, &ManifestFile> = installed
+            .iter()
+            .map(|file| (file.path.as_str(), file))
+            .collect();
+        self.files
+            .iter()
+            .filter(|file| match installed.get(file.path.as_str()) {
+                Some(installed) => installed.size != file.size || installed.sha256 != file.sha256,
+                None => true,
+            })
+            .collect()
+    }
+}
+
+/// Where the manifest of a build is stored in [`GAME_BUCKET_NAME`], next to its `game.zip`
+pub fn manifest_key(game: &str, platform: &str, version: &str) -> String {
+    format!("{}/{}/{}/manifest.json", game, platform, version)
+}
+
+/// The files of a build are uploaded unpacked under this prefix, next to its `game.zip`
+pub fn build_files_prefix(game: &str, platform: &str, version: &str) -> String {
+    format!("{}/{}/{}/files/", game, platform, version)
+}
+
+pub fn sha256_hex(digest: &[u8]) -> String {
+    digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+pub fn manifest_file(path: &str, contents: &[u8]) -> ManifestFile {
+    ManifestFile {
+        path: path.to_string(),
+        size: contents.len() as u64,
+        sha256: sha256_hex(&Sha256::digest(contents)),
+    }
+}
+
+/// Paths that stay inside the install directory
+pub fn is_build_file_path(path: &str) -> bool {
+    !path.is_empty()
+        && !path.starts_with('/')
+        && path
+            .split('/')
+            .all(|segment| !matches!(segment, "" | "." | ".."))
+}
+
+/// Hashes the object a chunk at a time, so builds don't have to fit in memory
+async fn hash_object(s3_client: &S3Client, key: &str) -> Result<(u64, String), String> {
+    let mut body = s3_client
+        .get_object()
+        .bucket(GAME_BUCKET_NAME)
+        .key(key)
+        .send()
+        .await
+        .map_err(|e| e.to_string())?
+        .body;
+    let mut hasher = Sha256::new();
+    let mut size = 0;
+    while let Some(chunk) = body.try_next().await.map_err(|e| e.to_string())? {
+        size += chunk.len() as u64;
+        hasher.update(&chunk);
+    }
+    Ok((size, sha256_hex(&hasher.finalize())))
+}
+
+/// Hashes every file uploaded for the build and stores the manifest next to it. `None` if
+/// nothing was uploaded for it.
+pub async fn generate_manifest(
+    s3_client: &S3Client,
+    game: &str,
+    platform: &str,
+    version: &str,
+) -> Result<Option<BuildManifest>, String> {
+    let prefix = build_files_prefix(game, platform, version);
+    let mut keys = vec![];
+    let mut continuation_token = None;
+    loop {
+        let page = s3_client
+            .list_objects_v2()
+            .bucket(GAME_BUCKET_NAME)
+            .prefix(&prefix)
+            .set_continuation_token(continuation_token)
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+        keys.extend(
+            page.contents()
+                .iter()
+                .filter_map(|object| object.key())
+                .map(str::to_string),
+        );
+        continuation_token = page.next_continuation_token().map(str::to_string);
+        if continuation_token.is_none() {
+            break;
+        }
+    }
+
+    let mut files = vec![];
+    for key in keys {
+        let path = &key[prefix.len()..];
+        if !is_build_file_path(path) {
+            log::error!("Left {} out of the manifest", key);
+            continue;
+        }
+        let (size, sha256) = hash_object(s3_client, &key).await?;
+        files.push(ManifestFile {
+            path: path.to_string(),
+            size,
+            sha256,
+        });
+    }
+    if files.is_empty() {
+        return Ok(None);
+    }
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+    let manifest = BuildManifest {
+        game: game.to_string(),
+        platform: platform.to_string(),
+        version: version.to_string(),
+        files,
+    };
+
+    let json = serde_json::to_vec_pretty(&manifest).map_err(|e| e.to_string())?;
+    s3_client
+        .put_object()
+        .bucket(GAME_BUCKET_NAME)
+        .key(manifest_key(game, platform, version))
+        .content_type("application/json")
+        .body(ByteStream::from(json))
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(Some(manifest))
+}
+
+/// Admin only. Generates the manifest of a build whose files were uploaded, returning how many
+/// files it lists.
+pub async fn publish_build_manifest(
+    game: String,
+    platform: String,
+    version: String,
+) -> Result<usize, ServerFnError<NexusError>> {
+    let admin = current_admin().await?;
+    let s3_client = s3_client()?;
+    let manifest = generate_manifest(&s3_client, &game, &platform, &version)
+        .await
+        .map_err(|error| {
+            log::error!(
+                "Could not generate the manifest of {} {} {} {}",
+                game,
+                platform,
+                version,
+                error
+            );
+            UNHANDLED
+        })?
+        .ok_or(NexusError::BuildNotFound)?;
+    log::info!(
+        "{} published the manifest of {} {} {}",
+        admin,
+        game,
+        platform,
+        version
+    );
+    Ok(manifest.files.len())
+}
+
+/// `None` if no manifest was generated for the build
+pub async fn read_manifest(
+    s3_client: &S3Client,
+    game: &str,
+    platform: &str,
+    version: &str,
+) -> Result<Option<BuildManifest>, String> {
+    let json = read_object(
+        s3_client,
+        GAME_BUCKET_NAME,
+        &manifest_key(game, platform, version),
+    )
+    .await?;
+    json.map(|json| serde_json::from_slice(&json).map_err(|e| e.to_string()))
+        .transpose()
+}
+
+/// The manifest of a build of a game the session's account owns. The launcher fetches it to
+/// install or update the game. It isn't counted for the refund policy, as it holds none of the
+/// game.
+pub async fn get_build_manifest(
+    Path((game, platform, version)): Path<(String, String, String)>,
+    State(state): State<AppState>,
+    session_id: SessionId,
+) -> Result<Json<BuildManifest>, HttpResponse> {
+    authorize_download(&state, session_id.session_id, &game).await?;
+
+    let version = if version == "latest" {
+        find_latest_version(&state.s3_client, &platform, &game)
+            .await
+            .map_err(|error| (StatusCode::INTERNAL_SERVER_ERROR, error).into_response())?
+    } else {
+        version
+    };
+    match read_manifest(&state.s3_client, &game, &platform, &version).await {
+        Ok(Some(manifest)) => Ok(Json(manifest)),
+        Ok(None) => Err((StatusCode::NOT_FOUND, "No manifest for that build").into_response()),
+        Err(error) => {
+            log::error!(
+                "Could not read the manifest of {} {} {}",
+                game,
+                platform,
+                version
+            );
+            Err((StatusCode::INTERNAL_SERVER_ERROR, error).into_response())
+        }
+    }
+}
+
+/// One file of a build of a game the session's account owns, for installs missing only some
+pub async fn download_build_file(
+    Path((game, platform, version, path)): Path<(String, String, String, String)>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    session_id: SessionId,
+) -> Result<HttpResponse, HttpResponse> {
+    let path = path.strip_prefix('/').unwrap_or(&path);
+    if !is_build_file_path(path) {
+        return Err((StatusCode::BAD_REQUEST, "Invalid file path").into_response());
+    }
+    authorize_download(&state, session_id.session_id, &game).await?;
+
+    let download = Download {
+        bucket: GAME_BUCKET_NAME,
+        key: format!("{}{}", build_files_prefix(&game, &platform, &version), path),
+        file_name: path.rsplit('/').next().unwrap_or(path).to_string(),
+        content_type: "application/octet-stream",
+    };
+    Ok(serve_download(&state.s3_client, &headers, download).await)
+}
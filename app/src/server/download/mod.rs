@@ -1,3 +1,5 @@
 pub mod download_game_version;
 pub mod download_launcher;
 pub mod download_utils;
+pub mod manifest;
+pub mod patch;
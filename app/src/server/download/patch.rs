@@ -0,0 +1,597 @@
+use super::{
+    super::{admin::current_admin, globals::app_state::AppState, utilities::s3_client},
+    download_utils::{
+        authorize_download, open_object, read_object, serve_download, Download, SessionId,
+        GAME_BUCKET_NAME,
+    },
+    manifest::{
+        build_files_prefix, is_build_file_path, read_manifest, sha256_hex, BuildManifest,
+        ManifestFile,
+    },
+};
+use crate::errors::{NexusError, UNHANDLED};
+use aws_sdk_s3::{primitives::ByteStream, Client as S3Client};
+use axum::{
+    extract::{Path, State},
+    response::{IntoResponse, Response as HttpResponse},
+    Json,
+};
+use http::{HeaderMap, StatusCode};
+use leptos::ServerFnError;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    num::NonZeroUsize,
+};
+
+/// How much of the installed file each block of a delta covers
+pub const DEFAULT_BLOCK_SIZE: NonZeroUsize = NonZeroUsize::new(64 * 1024).unwrap();
+
+/// Deltas are built in memory, so files that would need a bigger one are downloaded whole
+pub const MAX_DELTA_DATA: u64 = 64 * 1024 * 1024;
+
+const DELTA_MAGIC: &[u8; 4] = b"NXD1";
+const COPY_OP: u8 = 0;
+const DATA_OP: u8 = 1;
+
+/// rsync's weak checksum of a window of bytes, cheap to roll forward a byte at a time
+#[derive(Debug, Clone, Copy)]
+struct RollingChecksum {
+    a: u32,
+    b: u32,
+    length: u32,
+}
+
+impl RollingChecksum {
+    fn new(window: &[u8]) -> Self {
+        let length = window.len() as u32;
+        let (a, b) = window
+            .iter()
+            .enumerate()
+            .fold((0u32, 0u32), |(a, b), (i, &byte)| {
+                (
+                    a.wrapping_add(byte as u32),
+                    b.wrapping_add((length - i as u32).wrapping_mul(byte as u32)),
+                )
+            });
+        Self { a, b, length }
+    }
+
+    /// Slides the window one byte, dropping `out` and taking in `into`
+    fn roll(&mut self, out: u8, into: u8) {
+        self.a = self.a.wrapping_sub(out as u32).wrapping_add(into as u32);
+        self.b = self
+            .b
+            .wrapping_sub(self.length.wrapping_mul(out as u32))
+            .wrapping_add(self.a);
+    }
+
+    fn digest(&self) -> u32 {
+        (self.a & 0xffff) | (self.b << 16)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeltaOp {
+    /// `count` blocks of the installed file, starting at block `block`
+    Copy { block: u64, count: u64 },
+    /// Bytes the installed file doesn't have
+    Data(Vec<u8>),
+}
+
+/// How to rebuild a file out of the blocks of another version of it and new bytes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Delta {
+    pub block_size: NonZeroUsize,
+    pub ops: Vec<DeltaOp>,
+}
+
+/// The blocks of the file a delta is made from, read a chunk at a time. Only their checksums
+/// are kept.
+pub struct BlockSignatures {
+    block_size: NonZeroUsize,
+    /// Full blocks by weak checksum, with their index and SHA-256
+    blocks: HashMap<u32, Vec<(u64, [u8; 32])>>,
+    full_blocks: u64,
+    /// The block being read. Once the whole file is, the last block if it's shorter.
+    partial: Vec<u8>,
+}
+
+impl BlockSignatures {
+    pub fn new(block_size: NonZeroUsize) -> Self {
+        Self {
+            block_size,
+            blocks: HashMap::new(),
+            full_blocks: 0,
+            partial: Vec::with_capacity(block_size.get()),
+        }
+    }
+
+    pub fn update(&mut self, mut bytes: &[u8]) {
+        while !bytes.is_empty() {
+            let taken = bytes.len().min(self.block_size.get() - self.partial.len());
+            self.partial.extend_from_slice(&bytes[..taken]);
+            bytes = &bytes[taken..];
+            if self.partial.len() == self.block_size.get() {
+                self.blocks
+                    .entry(RollingChecksum::new(&self.partial).digest())
+                    .or_default()
+                    .push((self.full_blocks, Sha256::digest(&self.partial).into()));
+                self.full_blocks += 1;
+                self.partial.clear();
+            }
+        }
+    }
+
+    /// The full block `window` is a copy of. It's only made contiguous when the weak checksum
+    /// matches one.
+    fn find(&self, weak: u32, window: &mut VecDeque<u8>) -> Option<u64> {
+        let candidates = self.blocks.get(&weak)?;
+        let strong: [u8; 32] = Sha256::digest(window.make_contiguous()).into();
+        candidates
+            .iter()
+            .find(|(_, candidate)| *candidate == strong)
+            .map(|(index, _)| *index)
+    }
+}
+
+/// Finds the blocks of the old file anywhere in the new one, like rsync does, reading the new
+/// file a chunk at a time
+pub struct DeltaBuilder<'a> {
+    signatures: &'a BlockSignatures,
+    delta: Delta,
+    /// The last bytes read, up to a block of them, that haven't matched yet
+    window: VecDeque<u8>,
+    checksum: Option<RollingChecksum>,
+    literal: Vec<u8>,
+    data_len: u64,
+}
+
+impl<'a> DeltaBuilder<'a> {
+    pub fn new(signatures: &'a BlockSignatures) -> Self {
+        Self {
+            signatures,
+            delta: Delta {
+                block_size: signatures.block_size,
+                ops: vec![],
+            },
+            window: VecDeque::with_capacity(signatures.block_size.get()),
+            checksum: None,
+            literal: vec![],
+            data_len: 0,
+        }
+    }
+
+    pub fn update(&mut self, bytes: &[u8]) {
+        let block_size = self.signatures.block_size.get();
+        for &byte in bytes {
+            match self.checksum.as_mut() {
+                // The window didn't match, so slide it a byte
+                Some(checksum) if self.window.len() == block_size => {
+                    let out = self.window.pop_front().unwrap_or_default();
+                    self.window.push_back(byte);
+                    checksum.roll(out, byte);
+                    self.literal.push(out);
+                    self.data_len += 1;
+                }
+                _ => {
+                    self.window.push_back(byte);
+                    if self.window.len() < block_size {
+                        continue;
+                    }
+                    self.checksum = Some(RollingChecksum::new(self.window.make_contiguous()));
+                }
+            }
+
+            let weak = self.checksum.map(|checksum| checksum.digest());
+            let matched = weak.and_then(|weak| self.signatures.find(weak, &mut self.window));
+            if let Some(index) = matched {
+                self.delta.push_data(&mut self.literal);
+                self.delta.push_copy(index);
+                self.window.clear();
+                self.checksum = None;
+            }
+        }
+    }
+
+    /// How many bytes of the new file the delta carries so far
+    pub fn data_len(&self) -> u64 {
+        self.data_len
+    }
+
+    pub fn finish(mut self) -> Delta {
+        self.literal.extend(self.window.drain(..));
+        // The last block of the old file is usually shorter, so it can only match the end of
+        // the new one
+        let tail = &self.signatures.partial;
+        if !tail.is_empty() && self.literal.ends_with(tail) {
+            self.literal.truncate(self.literal.len() - tail.len());
+            self.delta.push_data(&mut self.literal);
+            self.delta.push_copy(self.signatures.full_blocks);
+        } else {
+            self.delta.push_data(&mut self.literal);
+        }
+        self.delta
+    }
+}
+
+impl Delta {
+    pub fn between(old: &[u8], new: &[u8], block_size: NonZeroUsize) -> Delta {
+        let mut signatures = BlockSignatures::new(block_size);
+        signatures.update(old);
+        let mut builder = DeltaBuilder::new(&signatures);
+        builder.update(new);
+        builder.finish()
+    }
+
+    fn push_copy(&mut self, block: u64) {
+        match self.ops.last_mut() {
+            Some(DeltaOp::Copy {
+                block: start,
+                count,
+            }) if *start + *count == block => *count += 1,
+            _ => self.ops.push(DeltaOp::Copy { block, count: 1 }),
+        }
+    }
+
+    fn push_data(&mut self, literal: &mut Vec<u8>) {
+        if !literal.is_empty() {
+            self.ops.push(DeltaOp::Data(std::mem::take(literal)));
+        }
+    }
+
+    /// `None` if the delta refers to blocks `old` doesn't have. Whether `old` is the file the
+    /// delta was made from is up to the caller to check, e.g. against its manifest.
+    pub fn apply(&self, old: &[u8]) -> Option<Vec<u8>> {
+        let mut new = vec![];
+        for op in &self.ops {
+            match op {
+                DeltaOp::Copy { block, count } => {
+                    let block_start = |block: u64| {
+                        usize::try_from(block)
+                            .ok()?
+                            .checked_mul(self.block_size.get())
+                    };
+                    let start = block_start(*block)?;
+                    let last_start = block_start((block + count).checked_sub(1)?)?;
+                    // Only the last block of `old` can be short
+                    if last_start >= old.len() {
+                        return None;
+                    }
+                    let end = block_start(block + count)?.min(old.len());
+                    new.extend_from_slice(old.get(start..end)?);
+                }
+                DeltaOp::Data(data) => new.extend_from_slice(data),
+            }
+        }
+        Some(new)
+    }
+
+    /// `NXD1`, the block size, then each op: `0` with the first block and how many, or `1` with
+    /// the length of the data and the data. Numbers are little endian `u64`s.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut encoded = DELTA_MAGIC.to_vec();
+        encoded.extend_from_slice(&(self.block_size.get() as u64).to_le_bytes());
+        for op in &self.ops {
+            match op {
+                DeltaOp::Copy { block, count } => {
+                    encoded.push(COPY_OP);
+                    encoded.extend_from_slice(&block.to_le_bytes());
+                    encoded.extend_from_slice(&count.to_le_bytes());
+                }
+                DeltaOp::Data(data) => {
+                    encoded.push(DATA_OP);
+                    encoded.extend_from_slice(&(data.len() as u64).to_le_bytes());
+                    encoded.extend_from_slice(data);
+                }
+            }
+        }
+        encoded
+    }
+
+    pub fn decode(encoded: &[u8]) -> Option<Delta> {
+        fn number(bytes: &mut &[u8]) -> Option<u64> {
+            let (number, rest) = bytes.split_first_chunk::<8>()?;
+            *bytes = rest;
+            Some(u64::from_le_bytes(*number))
+        }
+
+        let mut bytes = encoded.strip_prefix(DELTA_MAGIC)?;
+        let block_size = NonZeroUsize::new(usize::try_from(number(&mut bytes)?).ok()?)?;
+        let mut ops = vec![];
+        while let Some((&op, rest)) = bytes.split_first() {
+            bytes = rest;
+            ops.push(match op {
+                COPY_OP => DeltaOp::Copy {
+                    block: number(&mut bytes)?,
+                    count: number(&mut bytes)?,
+                },
+                DATA_OP => {
+                    let length = usize::try_from(number(&mut bytes)?).ok()?;
+                    let data = bytes.get(..length)?.to_vec();
+                    bytes = &bytes[length..];
+                    DeltaOp::Data(data)
+                }
+                _ => return None,
+            });
+        }
+        Some(Delta { block_size, ops })
+    }
+}
+
+/// What updating does with one file of the new build
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum PatchAction {
+    /// Installed already, with the same contents
+    Keep,
+    /// New, or not worth a delta: downloaded whole from the new build's files
+    Download,
+    /// Rebuilt from the installed file and the delta stored with the patch
+    Patch {
+        delta_size: u64,
+        delta_sha256: String,
+    },
+}
+
+/// A file of the new build. `size` and `sha256` are those of the file once updated.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PatchedFile {
+    pub path: String,
+    pub size: u64,
+    pub sha256: String,
+    #[serde(flatten)]
+    pub action: PatchAction,
+}
+
+/// How to update an install of one version to another
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PatchPlan {
+    pub game: String,
+    pub platform: String,
+    pub from: String,
+    pub to: String,
+    pub block_size: NonZeroUsize,
+    pub files: Vec<PatchedFile>,
+    /// Files of the old build the new one doesn't have
+    pub removed: Vec<String>,
+}
+
+impl PatchPlan {
+    /// How much updating downloads, against [`BuildManifest::total_size`] for a fresh install
+    pub fn download_size(&self) -> u64 {
+        self.files
+            .iter()
+            .map(|file| match &file.action {
+                PatchAction::Keep => 0,
+                PatchAction::Download => file.size,
+                PatchAction::Patch { delta_size, .. } => *delta_size,
+            })
+            .sum()
+    }
+}
+
+/// Compares two manifests. Files that changed are downloaded whole until a delta is stored for
+/// them.
+pub fn plan_patch(from: &BuildManifest, to: &BuildManifest, block_size: NonZeroUsize) -> PatchPlan {
+    let installed: HashMap<&str, &ManifestFile> = from
+        .files
+        .iter()
+        .map(|file| (file.path.as_str(), file))
+        .collect();
+    let files = to
+        .files
+        .iter()
+        .map(|file| PatchedFile {
+            path: file.path.clone(),
+            size: file.size,
+            sha256: file.sha256.clone(),
+            action: match installed.get(file.path.as_str()) {
+                Some(installed) if installed.sha256 == file.sha256 => PatchAction::Keep,
+                _ => PatchAction::Download,
+            },
+        })
+        .collect();
+    let kept: HashSet<&str> = to.files.iter().map(|file| file.path.as_str()).collect();
+    PatchPlan {
+        game: to.game.clone(),
+        platform: to.platform.clone(),
+        from: from.version.clone(),
+        to: to.version.clone(),
+        block_size,
+        files,
+        removed: from
+            .files
+            .iter()
+            .filter(|file| !kept.contains(file.path.as_str()))
+            .map(|file| file.path.clone())
+            .collect(),
+    }
+}
+
+/// Patches are stored next to the build they update to
+pub fn patch_prefix(game: &str, platform: &str, from: &str, to: &str) -> String {
+    format!("{}/{}/{}/patches/{}/", game, platform, to, from)
+}
+
+pub fn patch_plan_key(game: &str, platform: &str, from: &str, to: &str) -> String {
+    format!("{}plan.json", patch_prefix(game, platform, from, to))
+}
+
+pub fn delta_key(game: &str, platform: &str, from: &str, to: &str, path: &str) -> String {
+    format!("{}files/{}", patch_prefix(game, platform, from, to), path)
+}
+
+/// Diffs every file that changed between the builds and stores the deltas worth it along with
+/// the plan. Both builds need a manifest. Files are read a chunk at a time, so only the
+/// checksums of the old file's blocks and the delta are kept in memory, and a delta is given up
+/// on once it's no smaller than the file or reaches [`MAX_DELTA_DATA`].
+pub async fn generate_patch(
+    s3_client: &S3Client,
+    game: &str,
+    platform: &str,
+    from: &str,
+    to: &str,
+) -> Result<Option<PatchPlan>, String> {
+    let (Some(from_manifest), Some(to_manifest)) = (
+        read_manifest(s3_client, game, platform, from).await?,
+        read_manifest(s3_client, game, platform, to).await?,
+    ) else {
+        return Ok(None);
+    };
+    let mut plan = plan_patch(&from_manifest, &to_manifest, DEFAULT_BLOCK_SIZE);
+    let installed: HashMap<&str, &ManifestFile> = from_manifest
+        .files
+        .iter()
+        .map(|file| (file.path.as_str(), file))
+        .collect();
+    for file in plan.files.iter_mut() {
+        if file.action != PatchAction::Download || !installed.contains_key(file.path.as_str()) {
+            continue;
+        }
+        let open = |version: &str| {
+            let key = format!(
+                "{}{}",
+                build_files_prefix(game, platform, version),
+                file.path
+            );
+            async move {
+                open_object(s3_client, GAME_BUCKET_NAME, &key)
+                    .await?
+                    .ok_or_else(|| format!("{} is in the manifest but wasn't uploaded", key))
+            }
+        };
+
+        let mut signatures = BlockSignatures::new(plan.block_size);
+        let mut old = open(from).await?;
+        while let Some(chunk) = old.try_next().await.map_err(|e| e.to_string())? {
+            signatures.update(&chunk);
+        }
+        let mut builder = DeltaBuilder::new(&signatures);
+        let mut new = open(to).await?;
+        let max_data = file.size.min(MAX_DELTA_DATA);
+        while let Some(chunk) = new.try_next().await.map_err(|e| e.to_string())? {
+            builder.update(&chunk);
+            if builder.data_len() >= max_data {
+                break;
+            }
+        }
+        if builder.data_len() >= max_data {
+            continue;
+        }
+        let delta = builder.finish().encode();
+        if delta.len() as u64 >= file.size {
+            continue;
+        }
+        let delta_sha256 = sha256_hex(&Sha256::digest(&delta));
+        let delta_size = delta.len() as u64;
+        s3_client
+            .put_object()
+            .bucket(GAME_BUCKET_NAME)
+            .key(delta_key(game, platform, from, to, &file.path))
+            .body(ByteStream::from(delta))
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+        file.action = PatchAction::Patch {
+            delta_size,
+            delta_sha256,
+        };
+    }
+
+    let json = serde_json::to_vec_pretty(&plan).map_err(|e| e.to_string())?;
+    s3_client
+        .put_object()
+        .bucket(GAME_BUCKET_NAME)
+        .key(patch_plan_key(game, platform, from, to))
+        .content_type("application/json")
+        .body(ByteStream::from(json))
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(Some(plan))
+}
+
+/// Admin only. Generates the patch from one published build to another, returning how many
+/// bytes updating downloads.
+pub async fn publish_patch(
+    game: String,
+    platform: String,
+    from: String,
+    to: String,
+) -> Result<u64, ServerFnError<NexusError>> {
+    let admin = current_admin().await?;
+    let s3_client = s3_client()?;
+    let plan = generate_patch(&s3_client, &game, &platform, &from, &to)
+        .await
+        .map_err(|error| {
+            log::error!(
+                "Could not generate the patch of {} {} from {} to {} {}",
+                game,
+                platform,
+                from,
+                to,
+                error
+            );
+            UNHANDLED
+        })?
+        .ok_or(NexusError::BuildNotFound)?;
+    log::info!(
+        "{} published the patch of {} {} from {} to {}",
+        admin,
+        game,
+        platform,
+        from,
+        to
+    );
+    Ok(plan.download_size())
+}
+
+/// The patch plan from one build of a game the session's account owns to another. The launcher
+/// fetches it to update the game. Like the manifest, it isn't counted for the refund policy.
+pub async fn get_patch_plan(
+    Path((game, platform, from, to)): Path<(String, String, String, String)>,
+    State(state): State<AppState>,
+    session_id: SessionId,
+) -> Result<Json<PatchPlan>, HttpResponse> {
+    authorize_download(&state, session_id.session_id, &game).await?;
+
+    let key = patch_plan_key(&game, &platform, &from, &to);
+    let plan = read_object(&state.s3_client, GAME_BUCKET_NAME, &key)
+        .await
+        .and_then(|json| {
+            json.map(|json| serde_json::from_slice(&json).map_err(|e| e.to_string()))
+                .transpose()
+        });
+    match plan {
+        Ok(Some(plan)) => Ok(Json(plan)),
+        Ok(None) => Err((StatusCode::NOT_FOUND, "No patch between those builds").into_response()),
+        Err(error) => {
+            log::error!("Could not read {}", key);
+            Err((StatusCode::INTERNAL_SERVER_ERROR, error).into_response())
+        }
+    }
+}
+
+/// The delta of one file of a patch
+pub async fn download_patch_delta(
+    Path((game, platform, from, to, path)): Path<(String, String, String, String, String)>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    session_id: SessionId,
+) -> Result<HttpResponse, HttpResponse> {
+    let path = path.strip_prefix('/').unwrap_or(&path);
+    if !is_build_file_path(path) {
+        return Err((StatusCode::BAD_REQUEST, "Invalid file path").into_response());
+    }
+    authorize_download(&state, session_id.session_id, &game).await?;
+
+    let download = Download {
+        bucket: GAME_BUCKET_NAME,
+        key: delta_key(&game, &platform, &from, &to, path),
+        file_name: format!("{}.delta", path.rsplit('/').next().unwrap_or(path)),
+        content_type: "application/octet-stream",
+    };
+    Ok(serve_download(&state.s3_client, &headers, download).await)
+}
@@ -0,0 +1,65 @@
+use crate::{
+    errors::{NexusError, UNHANDLED},
+    site::constants::SITE_EMAIL_ADDRESS,
+};
+use aws_sdk_ses::{
+    types::{Body, Content, Destination, Message},
+    Client as SesClient,
+};
+use leptos::ServerFnError;
+
+/// Sends an html email from the site's address
+pub async fn send_email(
+    ses_client: &SesClient,
+    to: &[String],
+    subject: &str,
+    html_body: &str,
+) -> Result<(), ServerFnError<NexusError>> {
+    let email_body_html = Content::builder().data(html_body).build().map_err(|e| {
+        log::error!("Could not build email body html {:?}", e);
+        UNHANDLED
+    })?;
+    let email_subject_content = Content::builder().data(subject).build().map_err(|e| {
+        log::error!("Could not build email subject content {:?}", e);
+        UNHANDLED
+    })?;
+    let email_message = Message::builder()
+        .subject(email_subject_content)
+        .body(Body::builder().html(email_body_html).build())
+        .build();
+    let email_send_resp = ses_client
+        .send_email()
+        .source(SITE_EMAIL_ADDRESS)
+        .destination(
+            Destination::builder()
+                .set_to_addresses(Some(to.to_vec()))
+                .build(),
+        )
+        .message(email_message)
+        .send()
+        .await;
+    match email_send_resp {
+        Ok(_) => Ok(()),
+        Err(e) => {
+            log::error!("Could not send \"{}\" to {:?} {:?}", subject, to, e);
+            Err(UNHANDLED)
+        }
+    }
+}
+
+/// Escapes text someone typed (e.g. a gift message) for putting into an html email
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            '\n' => escaped.push_str("<br>"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
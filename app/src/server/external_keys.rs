@@ -0,0 +1,241 @@
+use super::{
+    admin::{current_admin, notify_admins},
+    catalog::get_product,
+    product_keys::hash_key,
+    repository::external_keys::{
+        assign_external_key, available_external_keys, count_available_external_keys,
+        external_keys_for_purchase, external_keys_for_user, import_external_key,
+        mark_external_key_revoked, pool_id, ExternalKey, KeyAssignment,
+    },
+    utilities::{dynamo_client, logged_in_user, user_repository},
+};
+use crate::{
+    errors::NexusError,
+    orders::{KeyImportSummary, OwnedExternalKey},
+};
+use aws_sdk_dynamodb::Client as DynamoClient;
+use aws_sdk_ses::Client as SesClient;
+use chrono::Utc;
+use leptos::ServerFnError;
+
+/// Admins are told when a pool gets this low, and again every five keys after that
+pub const LOW_STOCK_THRESHOLD: i64 = 20;
+/// Unassigned keys fetched per attempt at handing one out, so a purchase that loses the race
+/// for one can try the next
+const ASSIGNMENT_CANDIDATES: i32 = 5;
+/// Rounds of candidates tried before giving up on a pool that keeps being emptied under us
+const ASSIGNMENT_ROUNDS: usize = 3;
+
+/// Whether handing out a key that left `remaining` in its pool deserves an alert
+pub fn should_alert_low_stock(remaining: i64) -> bool {
+    remaining <= LOW_STOCK_THRESHOLD && remaining % 5 == 0
+}
+
+/// The keys of a CSV export from a storefront, one per line in the first column. A header row
+/// and blank lines are skipped, and lines that can't be keys are reported by number.
+pub fn parse_key_csv(csv: &str) -> (Vec<String>, Vec<usize>) {
+    let mut keys = Vec::new();
+    let mut rejected_lines = Vec::new();
+    for (index, line) in csv.lines().enumerate() {
+        let key = line
+            .split(',')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .trim_matches('"')
+            .trim();
+        if key.is_empty() || (index == 0 && key.eq_ignore_ascii_case("key")) {
+            continue;
+        }
+        if key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+            keys.push(key.to_string());
+        } else {
+            rejected_lines.push(index + 1);
+        }
+    }
+    (keys, rejected_lines)
+}
+
+/// Adds the keys of a CSV to the pool of `platform` keys for `product_id`
+pub async fn import_external_keys(
+    product_id: String,
+    platform: String,
+    csv: String,
+) -> Result<KeyImportSummary, ServerFnError<NexusError>> {
+    let admin = current_admin().await?;
+    let product = get_product(&product_id)?;
+    let platform = platform.trim().to_lowercase();
+    if !product.key_platforms.contains(&platform) {
+        log::error!("{} doesn't hand out {} keys", product_id, platform);
+        return Err(ServerFnError::from(NexusError::KeyPlatformNotOffered));
+    }
+    let dynamodb_client = dynamo_client()?;
+    let (keys, rejected_lines) = parse_key_csv(&csv);
+    let now = Utc::now().timestamp();
+    let mut imported = 0;
+    let mut duplicates = 0;
+    for key_value in keys {
+        let key = ExternalKey {
+            product_id: product_id.clone(),
+            platform: platform.clone(),
+            key_hash: hash_key(&key_value),
+            key_value,
+            imported_by: admin.clone(),
+            imported_at: now,
+            assignment: None,
+        };
+        match import_external_key(&dynamodb_client, &key).await? {
+            true => imported += 1,
+            false => duplicates += 1,
+        }
+    }
+    log::info!(
+        "{} imported {} {} keys for {} ({} duplicates, {} rejected lines)",
+        admin,
+        imported,
+        platform,
+        product_id,
+        duplicates,
+        rejected_lines.len()
+    );
+    Ok(KeyImportSummary {
+        imported,
+        duplicates,
+        rejected_lines,
+        available: count_available_external_keys(
+            &dynamodb_client,
+            &pool_id(&product_id, &platform),
+        )
+        .await?,
+    })
+}
+
+/// Takes one unassigned key from the pool for `assignment`, or `None` if the pool is empty
+async fn take_key(
+    dynamodb_client: &DynamoClient,
+    pool: &str,
+    assignment: &KeyAssignment,
+) -> Result<Option<ExternalKey>, ServerFnError<NexusError>> {
+    for _ in 0..ASSIGNMENT_ROUNDS {
+        let candidates =
+            available_external_keys(dynamodb_client, pool, ASSIGNMENT_CANDIDATES).await?;
+        if candidates.is_empty() {
+            return Ok(None);
+        }
+        for candidate in candidates {
+            if assign_external_key(dynamodb_client, &candidate, assignment).await? {
+                return Ok(Some(candidate));
+            }
+        }
+    }
+    log::error!("Kept losing races for the keys of {}", pool);
+    Err(ServerFnError::from(NexusError::ConcurrentModification))
+}
+
+/// Hands out one key per third-party platform of the product bought by `purchase_id`. Safe to
+/// retry: platforms the purchase already has a key for are skipped. Running out of keys
+/// doesn't fail the purchase, it alerts the admins, who can hand one out once they restock.
+pub async fn assign_external_keys(
+    dynamodb_client: &DynamoClient,
+    ses_client: &SesClient,
+    purchase_id: &str,
+    user_uuid: &str,
+    product_id: &str,
+) -> Result<(), ServerFnError<NexusError>> {
+    let product = get_product(product_id)?;
+    if product.key_platforms.is_empty() {
+        return Ok(());
+    }
+    let assigned = external_keys_for_purchase(dynamodb_client, purchase_id).await?;
+    for platform in &product.key_platforms {
+        if assigned.iter().any(|key| &key.platform == platform) {
+            continue;
+        }
+        let pool = pool_id(product_id, platform);
+        let assignment = KeyAssignment {
+            purchase_id: purchase_id.to_string(),
+            user_uuid: user_uuid.to_string(),
+            assigned_at: Utc::now().timestamp(),
+            revoked_at: None,
+            revoked_reason: None,
+        };
+        if take_key(dynamodb_client, &pool, &assignment)
+            .await?
+            .is_none()
+        {
+            log::error!("Out of {} keys for purchase {}", pool, purchase_id);
+            notify_admins(
+                ses_client,
+                &format!("Out of {} keys for {}", platform, product.title),
+                &format!(
+                    "Purchase {} by {} didn't get a {} key because the pool is empty. \
+                     Import more keys, then replay the checkout's webhook event to hand one out.",
+                    purchase_id, user_uuid, platform
+                ),
+            )
+            .await;
+            continue;
+        }
+        log::info!("Gave purchase {} a {} key", purchase_id, platform);
+        let remaining = count_available_external_keys(dynamodb_client, &pool).await?;
+        if should_alert_low_stock(remaining) {
+            notify_admins(
+                ses_client,
+                &format!("Running low on {} keys for {}", platform, product.title),
+                &format!(
+                    "Only {} {} keys are left for {}. Import more before they run out.",
+                    remaining, platform, product.title
+                ),
+            )
+            .await;
+        }
+    }
+    Ok(())
+}
+
+/// Marks the keys handed out for a purchase as to be revoked, returning them so an admin can
+/// revoke them on their storefronts
+pub async fn revoke_external_keys(
+    dynamodb_client: &DynamoClient,
+    purchase_id: &str,
+    reason: &str,
+) -> Result<Vec<ExternalKey>, ServerFnError<NexusError>> {
+    let keys = external_keys_for_purchase(dynamodb_client, purchase_id).await?;
+    let now = Utc::now().timestamp();
+    for key in &keys {
+        mark_external_key_revoked(dynamodb_client, key, reason, now).await?;
+    }
+    Ok(keys)
+}
+
+/// The keys a library should show: handed out to the account, and not revoked
+pub fn owned_external_keys(keys: Vec<ExternalKey>) -> Vec<OwnedExternalKey> {
+    let mut owned: Vec<OwnedExternalKey> = keys
+        .into_iter()
+        .filter(|key| {
+            key.assignment
+                .as_ref()
+                .is_some_and(|assignment| assignment.revoked_at.is_none())
+        })
+        .map(|key| OwnedExternalKey {
+            product_title: get_product(&key.product_id)
+                .map(|product| product.title)
+                .unwrap_or_else(|_| key.product_id.clone()),
+            product_id: key.product_id,
+            platform: key.platform,
+            key: key.key_value,
+        })
+        .collect();
+    owned.sort_by(|a, b| (&a.product_title, &a.platform).cmp(&(&b.product_title, &b.platform)));
+    owned
+}
+
+/// The third-party keys of the logged in user
+pub async fn list_external_keys() -> Result<Vec<OwnedExternalKey>, ServerFnError<NexusError>> {
+    let repository = user_repository()?;
+    let user = logged_in_user(repository.as_ref()).await?;
+    let dynamodb_client = dynamo_client()?;
+    Ok(owned_external_keys(
+        external_keys_for_user(&dynamodb_client, &user.user_uuid).await?,
+    ))
+}
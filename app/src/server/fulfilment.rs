@@ -0,0 +1,67 @@
+use super::catalog::entitlements_for;
+use super::repository::{
+    purchases::{record_purchase, Purchase},
+    UserRepository,
+};
+use crate::errors::NexusError;
+use aws_sdk_dynamodb::Client as DynamoClient;
+use leptos::ServerFnError;
+
+/// Adds the purchase to the ledger. A purchase that's already there is left as it is.
+pub async fn record(
+    dynamodb_client: &DynamoClient,
+    purchase: &Purchase,
+) -> Result<(), ServerFnError<NexusError>> {
+    if !record_purchase(dynamodb_client, purchase).await? {
+        // Every step after this is idempotent, so a retry of a failed fulfilment still goes
+        // through
+        log::info!(
+            "Purchase {} was already recorded",
+            purchase.checkout_session_id
+        );
+    }
+    Ok(())
+}
+
+/// Puts the product of a recorded purchase (every item of it, for a bundle) in its buyer's
+/// library
+pub async fn grant(
+    repository: &dyn UserRepository,
+    purchase: &Purchase,
+) -> Result<(), ServerFnError<NexusError>> {
+    grant_to(repository, &purchase.email, &purchase.product_id)
+        .await
+        .map_err(|e| {
+            log::error!(
+                "Could not grant entitlement for purchase {}!!!! This is really important!!! {:?}",
+                purchase.checkout_session_id,
+                e
+            );
+            e
+        })
+}
+
+/// Grants the entitlements of `product_id` to the account with `email`
+pub async fn grant_to(
+    repository: &dyn UserRepository,
+    email: &str,
+    product_id: &str,
+) -> Result<(), ServerFnError<NexusError>> {
+    for entitlement in entitlements_for(product_id) {
+        repository.grant_entitlement(email, &entitlement).await?;
+    }
+    Ok(())
+}
+
+/// Records the purchase and grants its product, for purchases that are paid for (or free) as
+/// soon as they're made, like redeemed product keys. Checkouts record their purchases first and
+/// grant them once the ledger has them as paid, and reconciliation only grants purchases the
+/// ledger already has, so nothing is ever granted without a purchase explaining it.
+pub async fn fulfil(
+    dynamodb_client: &DynamoClient,
+    repository: &dyn UserRepository,
+    purchase: &Purchase,
+) -> Result<(), ServerFnError<NexusError>> {
+    record(dynamodb_client, purchase).await?;
+    grant(repository, purchase).await
+}
@@ -0,0 +1,339 @@
+use super::{
+    catalog::get_product,
+    email::{escape_html, send_email},
+    external_keys::assign_external_keys,
+    fulfilment::grant_to,
+    login::login,
+    repository::{
+        gifts::{
+            claim_gift as claim, create_gift, find_gift_by_claim_token, get_gift,
+            list_gifts_for_buyer, mark_gift_notified, Gift,
+        },
+        purchases::{get_purchase, list_purchases_for_user, Purchase, PurchaseStatus},
+        UserRepository,
+    },
+    signup::new_account,
+    utilities::{dynamo_client, logged_in_user, ses_client, user_repository, LoggedInUser},
+};
+use crate::{
+    errors::NexusError,
+    orders::{GiftPreview, GiftStatus, SentGift, GIFT_MESSAGE_MAX_LENGTH},
+    site::constants::{SITE_DOMAIN, SITE_FULL_DOMAIN},
+};
+use aws_sdk_dynamodb::Client as DynamoClient;
+use aws_sdk_ses::Client as SesClient;
+use chrono::Utc;
+use email_address::EmailAddress;
+use leptos::ServerFnError;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+/// Key of the checkout session metadata entry holding the email of the gift's recipient
+pub const GIFT_RECIPIENT_METADATA_KEY: &str = "gift_recipient_email";
+/// Key of the checkout session metadata entry holding the buyer's message to the recipient
+pub const GIFT_MESSAGE_METADATA_KEY: &str = "gift_message";
+
+/// Who a checkout is a gift for, as entered at checkout
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GiftRequest {
+    pub recipient_email: String,
+    pub message: String,
+}
+
+impl GiftRequest {
+    /// Checks the gift fields of a checkout. No recipient means it isn't a gift.
+    pub fn from_checkout(
+        recipient_email: String,
+        message: String,
+    ) -> Result<Option<Self>, ServerFnError<NexusError>> {
+        let recipient_email = recipient_email.trim().to_string();
+        if recipient_email.is_empty() {
+            return Ok(None);
+        }
+        if !EmailAddress::is_valid(&recipient_email) {
+            log::error!("Gift recipient {} is not a valid email", recipient_email);
+            return Err(ServerFnError::from(NexusError::BadEmailAddress));
+        }
+        let message = message.trim().to_string();
+        if message.chars().count() > GIFT_MESSAGE_MAX_LENGTH {
+            return Err(ServerFnError::from(NexusError::GiftMessageTooLong));
+        }
+        Ok(Some(Self {
+            recipient_email,
+            message,
+        }))
+    }
+
+    pub fn to_metadata(&self) -> [(String, String); 2] {
+        [
+            (
+                GIFT_RECIPIENT_METADATA_KEY.to_string(),
+                self.recipient_email.clone(),
+            ),
+            (GIFT_MESSAGE_METADATA_KEY.to_string(), self.message.clone()),
+        ]
+    }
+
+    pub fn from_metadata(metadata: Option<&HashMap<String, String>>) -> Option<Self> {
+        let metadata = metadata?;
+        let recipient_email = metadata.get(GIFT_RECIPIENT_METADATA_KEY)?;
+        Some(Self {
+            recipient_email: recipient_email.clone(),
+            message: metadata
+                .get(GIFT_MESSAGE_METADATA_KEY)
+                .cloned()
+                .unwrap_or_default(),
+        })
+    }
+}
+
+/// Where a gift stands, given the status of the purchase that paid for it
+pub fn gift_status(gift: &Gift, purchase_status: PurchaseStatus) -> GiftStatus {
+    if !purchase_status.grants_access() {
+        GiftStatus::Revoked
+    } else if gift.claimed_by_user_uuid.is_some() {
+        GiftStatus::Claimed
+    } else {
+        GiftStatus::Unclaimed
+    }
+}
+
+fn product_title(product_id: &str) -> String {
+    get_product(product_id)
+        .map(|product| product.title)
+        .unwrap_or_else(|_| product_id.to_string())
+}
+
+/// Records the gift paid for by `purchase` and emails its recipient the claim link. Safe to
+/// retry: the gift is only created once, and the email is sent until it has gone out once.
+pub async fn deliver_gift(
+    dynamodb_client: &DynamoClient,
+    repository: &dyn UserRepository,
+    ses_client: &SesClient,
+    purchase: &Purchase,
+    request: &GiftRequest,
+) -> Result<(), ServerFnError<NexusError>> {
+    let sender_name = repository
+        .find_billing_profile(&purchase.email)
+        .await?
+        .map(|profile| profile.display_name)
+        .unwrap_or_else(|| "A friend".to_string());
+    let new_gift = Gift {
+        gift_id: purchase.checkout_session_id.clone(),
+        claim_token: Uuid::new_v4().to_string(),
+        product_id: purchase.product_id.clone(),
+        buyer_user_uuid: purchase.user_uuid.clone(),
+        sender_name,
+        recipient_email: request.recipient_email.clone(),
+        message: request.message.clone(),
+        created_at: Utc::now().timestamp(),
+        notified_at: None,
+        claimed_by_user_uuid: None,
+        claimed_at: None,
+    };
+    let gift = if create_gift(dynamodb_client, &new_gift).await? {
+        new_gift
+    } else {
+        get_gift(dynamodb_client, &purchase.checkout_session_id)
+            .await?
+            .ok_or_else(|| {
+                log::error!(
+                    "Gift {} exists but could not be read back",
+                    purchase.checkout_session_id
+                );
+                ServerFnError::from(NexusError::GiftNotFound)
+            })?
+    };
+    if gift.notified_at.is_some() {
+        return Ok(());
+    }
+    let message = match gift.message.is_empty() {
+        true => String::new(),
+        false => format!("<blockquote>{}</blockquote>", escape_html(&gift.message)),
+    };
+    let body = format!(
+        "Hello,
+{} bought you {} on {}!
+{}
+Claim it here:
+
+https://{}/gift/{}
+
+If you don't have an account yet, you can create one from that page.",
+        escape_html(&gift.sender_name),
+        escape_html(&product_title(&gift.product_id)),
+        SITE_DOMAIN,
+        message,
+        SITE_FULL_DOMAIN,
+        gift.claim_token
+    );
+    send_email(
+        ses_client,
+        std::slice::from_ref(&gift.recipient_email),
+        &format!("[{}] You've been sent a gift", SITE_DOMAIN),
+        &body,
+    )
+    .await?;
+    mark_gift_notified(dynamodb_client, &gift.gift_id, Utc::now().timestamp()).await
+}
+
+/// The gift behind a claim link, along with the status of the purchase that paid for it
+async fn find_gift(
+    dynamodb_client: &DynamoClient,
+    claim_token: &str,
+) -> Result<(Gift, PurchaseStatus), ServerFnError<NexusError>> {
+    let gift = find_gift_by_claim_token(dynamodb_client, claim_token)
+        .await?
+        .ok_or_else(|| ServerFnError::from(NexusError::GiftNotFound))?;
+    let purchase = get_purchase(dynamodb_client, &gift.gift_id)
+        .await?
+        .ok_or_else(|| {
+            log::error!("Gift {} has no purchase", gift.gift_id);
+            ServerFnError::from(NexusError::PurchaseNotFound)
+        })?;
+    Ok((gift, purchase.status))
+}
+
+pub async fn get_gift_preview(
+    claim_token: String,
+) -> Result<GiftPreview, ServerFnError<NexusError>> {
+    let dynamodb_client = dynamo_client()?;
+    let (gift, purchase_status) = find_gift(&dynamodb_client, &claim_token).await?;
+    Ok(GiftPreview {
+        product_title: product_title(&gift.product_id),
+        status: gift_status(&gift, purchase_status),
+        product_id: gift.product_id,
+        sender_name: gift.sender_name,
+        message: gift.message,
+        recipient_email: gift.recipient_email,
+    })
+}
+
+/// Checks that nobody else has the gift, and that it's still paid for
+fn check_claimable(
+    gift: &Gift,
+    purchase_status: PurchaseStatus,
+    user_uuid: Option<&str>,
+) -> Result<(), ServerFnError<NexusError>> {
+    match gift_status(gift, purchase_status) {
+        GiftStatus::Revoked => Err(ServerFnError::from(NexusError::GiftNotClaimable)),
+        GiftStatus::Claimed if gift.claimed_by_user_uuid.as_deref() != user_uuid => {
+            Err(ServerFnError::from(NexusError::GiftAlreadyClaimed))
+        }
+        _ => Ok(()),
+    }
+}
+
+async fn redeem(
+    dynamodb_client: &DynamoClient,
+    repository: &dyn UserRepository,
+    ses_client: &SesClient,
+    gift: &Gift,
+    user: &LoggedInUser,
+) -> Result<(), ServerFnError<NexusError>> {
+    if !claim(
+        dynamodb_client,
+        &gift.gift_id,
+        &user.user_uuid,
+        Utc::now().timestamp(),
+    )
+    .await?
+    {
+        return Err(ServerFnError::from(NexusError::GiftAlreadyClaimed));
+    }
+    log::info!("Gift {} was claimed by {}", gift.gift_id, user.user_uuid);
+    grant_to(repository, &user.email, &gift.product_id).await?;
+    assign_external_keys(
+        dynamodb_client,
+        ses_client,
+        &gift.gift_id,
+        &user.user_uuid,
+        &gift.product_id,
+    )
+    .await
+}
+
+/// Gives the gift to the logged in user, whichever email it was sent to
+pub async fn claim_gift(claim_token: String) -> Result<(), ServerFnError<NexusError>> {
+    let repository = user_repository()?;
+    let user = logged_in_user(repository.as_ref()).await?;
+    let dynamodb_client = dynamo_client()?;
+    let (gift, purchase_status) = find_gift(&dynamodb_client, &claim_token).await?;
+    check_claimable(&gift, purchase_status, Some(&user.user_uuid))?;
+    let ses_client = ses_client()?;
+    redeem(
+        &dynamodb_client,
+        repository.as_ref(),
+        &ses_client,
+        &gift,
+        &user,
+    )
+    .await
+}
+
+/// Creates an account for the gift's recipient, gives them the gift and logs them in. Having
+/// the claim link proves they own the email it was sent to, so the account starts verified.
+pub async fn claim_gift_with_new_account(
+    claim_token: String,
+    display_name: String,
+    password: String,
+    password_confirmation: String,
+) -> Result<(), ServerFnError<NexusError>> {
+    let repository = user_repository()?;
+    let dynamodb_client = dynamo_client()?;
+    let (gift, purchase_status) = find_gift(&dynamodb_client, &claim_token).await?;
+    check_claimable(&gift, purchase_status, None)?;
+    let new_user = new_account(
+        display_name,
+        gift.recipient_email.clone(),
+        password.clone(),
+        password_confirmation,
+    )?;
+    let user = LoggedInUser {
+        email: new_user.email.clone(),
+        user_uuid: new_user.user_uuid.clone(),
+    };
+    repository.create_user(new_user).await?;
+    repository.mark_email_verified(&user.email).await?;
+    let ses_client = ses_client()?;
+    redeem(
+        &dynamodb_client,
+        repository.as_ref(),
+        &ses_client,
+        &gift,
+        &user,
+    )
+    .await?;
+    login(user.email, password, false).await
+}
+
+/// The gifts the logged in user has bought, and whether they've been claimed
+pub async fn list_sent_gifts() -> Result<Vec<SentGift>, ServerFnError<NexusError>> {
+    let repository = user_repository()?;
+    let user = logged_in_user(repository.as_ref()).await?;
+    let dynamodb_client = dynamo_client()?;
+    let gifts = list_gifts_for_buyer(&dynamodb_client, &user.user_uuid).await?;
+    let purchase_statuses: HashMap<String, PurchaseStatus> =
+        list_purchases_for_user(&dynamodb_client, &user.user_uuid)
+            .await?
+            .into_iter()
+            .map(|purchase| (purchase.checkout_session_id, purchase.status))
+            .collect();
+    Ok(gifts
+        .into_iter()
+        .map(|gift| {
+            let status = match purchase_statuses.get(&gift.gift_id) {
+                Some(purchase_status) => gift_status(&gift, *purchase_status),
+                None => GiftStatus::Revoked,
+            };
+            SentGift {
+                product_title: product_title(&gift.product_id),
+                product_id: gift.product_id,
+                recipient_email: gift.recipient_email,
+                sent_at: gift.created_at,
+                claimed_at: gift.claimed_at,
+                status,
+            }
+        })
+        .collect())
+}
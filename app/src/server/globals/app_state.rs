@@ -1,3 +1,4 @@
+use crate::server::repository::UserRepository;
 use aws_sdk_dynamodb::Client as DynamoClient;
 use aws_sdk_kms::Client as KeyClient;
 use aws_sdk_s3::Client as S3Client;
@@ -13,6 +14,7 @@ use stripe::Client as StripeClient;
 pub struct AppState {
     pub leptos_options: LeptosOptions,
     pub dynamodb_client: Arc<DynamoClient>,
+    pub user_repository: Arc<dyn UserRepository>,
     pub ses_client: Arc<SesClient>,
     pub stripe_client: Arc<StripeClient>,
     pub s3_client: Arc<S3Client>,
@@ -10,7 +10,6 @@ use std::collections::HashMap;
 
 #[cfg(feature = "ssr")]
 pub mod constants {
-    pub const GAME_NAME_1: &str = "game_1";
     pub mod table_attributes {
         pub const DISPLAY_NAME: &str = "display_name";
         pub const EMAIL: &str = "email";
@@ -23,10 +22,171 @@ pub mod constants {
         pub const SESSION_EXPIRY: &str = "session_expiry";
         pub const EMAIL_VERIFICATION_UUID: &str = "email_verification_uuid";
         pub const EMAIL_VERIFICATION_REQUEST_TIME: &str = "email_verification_request_time";
+        pub const VERSION: &str = "version";
+        pub const STRIPE_CUSTOMER_ID: &str = "stripe_customer_id";
+    }
+    pub mod webhook_event_attributes {
+        pub const EVENT_ID: &str = "event_id";
+        pub const EVENT_TYPE: &str = "event_type";
+        pub const STATUS: &str = "status";
+        pub const PAYLOAD: &str = "payload";
+        pub const RECEIVED_AT: &str = "received_at";
+        pub const UPDATED_AT: &str = "updated_at";
+        pub const ATTEMPTS: &str = "attempts";
+        pub const LAST_ERROR: &str = "last_error";
+    }
+    pub mod purchase_attributes {
+        pub const CHECKOUT_SESSION_ID: &str = "checkout_session_id";
+        pub const USER_UUID: &str = "user_uuid";
+        pub const EMAIL: &str = "email";
+        pub const PRODUCT_ID: &str = "product_id";
+        pub const PAYMENT_INTENT_ID: &str = "payment_intent_id";
+        pub const AMOUNT_TOTAL: &str = "amount_total";
+        pub const CURRENCY: &str = "currency";
+        pub const STATUS: &str = "status";
+        pub const CREATED_AT: &str = "created_at";
+        pub const UPDATED_AT: &str = "updated_at";
+        pub const GIFT: &str = "gift";
+        pub const AMOUNT_DISCOUNT: &str = "amount_discount";
+        pub const SALE: &str = "sale";
+        pub const DISCOUNT_CODE: &str = "discount_code";
+        pub const AMOUNT_TAX: &str = "amount_tax";
+        pub const RECEIPT_SENT_AT: &str = "receipt_sent_at";
+        pub const COUNTRY: &str = "country";
+    }
+    pub mod gift_attributes {
+        /// The id of the checkout session that paid for the gift
+        pub const GIFT_ID: &str = "gift_id";
+        pub const CLAIM_TOKEN: &str = "claim_token";
+        pub const PRODUCT_ID: &str = "product_id";
+        pub const BUYER_USER_UUID: &str = "buyer_user_uuid";
+        pub const SENDER_NAME: &str = "sender_name";
+        pub const RECIPIENT_EMAIL: &str = "recipient_email";
+        pub const MESSAGE: &str = "message";
+        pub const CREATED_AT: &str = "created_at";
+        pub const NOTIFIED_AT: &str = "notified_at";
+        pub const CLAIMED_BY_USER_UUID: &str = "claimed_by_user_uuid";
+        pub const CLAIMED_AT: &str = "claimed_at";
+    }
+    pub mod product_key_attributes {
+        /// Hex SHA-256 of the normalized key. The key itself is never stored.
+        pub const KEY_HASH: &str = "key_hash";
+        pub const BATCH_ID: &str = "batch_id";
+        pub const PRODUCT_ID: &str = "product_id";
+        /// The key's last group, so admins can tell keys apart
+        pub const KEY_HINT: &str = "key_hint";
+        pub const CREATED_AT: &str = "created_at";
+        pub const REDEEMED_BY_USER_UUID: &str = "redeemed_by_user_uuid";
+        pub const REDEEMED_AT: &str = "redeemed_at";
+    }
+    pub mod key_batch_attributes {
+        pub const BATCH_ID: &str = "batch_id";
+        pub const PRODUCT_ID: &str = "product_id";
+        pub const LABEL: &str = "label";
+        pub const CREATED_BY: &str = "created_by";
+        pub const CREATED_AT: &str = "created_at";
+        pub const KEY_COUNT: &str = "key_count";
+    }
+    pub mod rate_limit_attributes {
+        pub const BUCKET: &str = "bucket";
+        pub const ATTEMPTS: &str = "attempts";
+        pub const EXPIRES_AT: &str = "expires_at";
+    }
+    pub mod cart_attributes {
+        /// Hex SHA-256 of the session id
+        pub const CART_ID: &str = "cart_id";
+        /// String set of the ids of the products in the cart
+        pub const PRODUCT_IDS: &str = "product_ids";
+        pub const UPDATED_AT: &str = "updated_at";
+        pub const EXPIRES_AT: &str = "expires_at";
+    }
+    pub mod external_key_attributes {
+        /// `{product_id}#{platform}`
+        pub const POOL: &str = "pool";
+        /// Hex SHA-256 of the key, so importing the same key twice is caught
+        pub const KEY_HASH: &str = "key_hash";
+        pub const PRODUCT_ID: &str = "product_id";
+        pub const PLATFORM: &str = "platform";
+        /// The key itself, which buyers have to be shown
+        pub const KEY_VALUE: &str = "key_value";
+        pub const IMPORTED_BY: &str = "imported_by";
+        pub const IMPORTED_AT: &str = "imported_at";
+        /// Copy of `pool` that only exists while the key is unassigned, for a sparse index of
+        /// the keys left
+        pub const AVAILABLE_POOL: &str = "available_pool";
+        /// The purchase (checkout session or gift) the key was handed out for
+        pub const PURCHASE_ID: &str = "purchase_id";
+        pub const ASSIGNED_USER_UUID: &str = "assigned_user_uuid";
+        pub const ASSIGNED_AT: &str = "assigned_at";
+        pub const REVOKED_AT: &str = "revoked_at";
+        pub const REVOKED_REASON: &str = "revoked_reason";
+    }
+    pub mod refund_request_attributes {
+        /// Ledger id of the purchase to refund
+        pub const PURCHASE_ID: &str = "purchase_id";
+        pub const USER_UUID: &str = "user_uuid";
+        pub const EMAIL: &str = "email";
+        pub const PRODUCT_ID: &str = "product_id";
+        pub const AMOUNT: &str = "amount";
+        pub const CURRENCY: &str = "currency";
+        pub const REASON: &str = "reason";
+        pub const INELIGIBILITY: &str = "ineligibility";
+        pub const STATUS: &str = "status";
+        pub const REQUESTED_AT: &str = "requested_at";
+        pub const REVIEWED_BY: &str = "reviewed_by";
+        pub const REVIEWED_AT: &str = "reviewed_at";
+        pub const STRIPE_REFUND_ID: &str = "stripe_refund_id";
+    }
+    pub mod download_attributes {
+        /// `{user_uuid}#{product_id}`
+        pub const DOWNLOAD_ID: &str = "download_id";
+        pub const DOWNLOAD_COUNT: &str = "download_count";
+        pub const FIRST_DOWNLOADED_AT: &str = "first_downloaded_at";
+        pub const LAST_DOWNLOADED_AT: &str = "last_downloaded_at";
+    }
+    pub mod collaborator_attributes {
+        pub const COLLABORATOR_ID: &str = "collaborator_id";
+        pub const NAME: &str = "name";
+        pub const EMAIL: &str = "email";
+        pub const STRIPE_ACCOUNT_ID: &str = "stripe_account_id";
+        pub const TRANSFERS_ENABLED: &str = "transfers_enabled";
+        pub const CREATED_AT: &str = "created_at";
+    }
+    pub mod revenue_transfer_attributes {
+        /// `{purchase_id}#{collaborator_id}`
+        pub const TRANSFER_ID: &str = "transfer_id";
+        pub const PURCHASE_ID: &str = "purchase_id";
+        pub const COLLABORATOR_ID: &str = "collaborator_id";
+        pub const PRODUCT_ID: &str = "product_id";
+        pub const AMOUNT: &str = "amount";
+        pub const CURRENCY: &str = "currency";
+        pub const STATUS: &str = "status";
+        pub const AVAILABLE_AT: &str = "available_at";
+        pub const STRIPE_TRANSFER_ID: &str = "stripe_transfer_id";
+        pub const STRIPE_REVERSAL_ID: &str = "stripe_reversal_id";
+        pub const CREATED_AT: &str = "created_at";
+        pub const UPDATED_AT: &str = "updated_at";
     }
     pub mod index {
         pub const SESSION_ID_INDEX: &str = "session_id-index";
         pub const EMAIL_VERIFICATION_UUID_INDEX: &str = "email_verification_uuid-index";
+        pub const WEBHOOK_EVENT_STATUS_INDEX: &str = "status-index";
+        pub const REFUND_REQUEST_STATUS_INDEX: &str = "status-index";
+        pub const REVENUE_TRANSFER_STATUS_INDEX: &str = "status-index";
+        /// On the Users, Purchases and RefundRequests tables
+        pub const USER_UUID_INDEX: &str = "user_uuid-index";
+        pub const PAYMENT_INTENT_ID_INDEX: &str = "payment_intent_id-index";
+        pub const CLAIM_TOKEN_INDEX: &str = "claim_token-index";
+        pub const BUYER_USER_UUID_INDEX: &str = "buyer_user_uuid-index";
+        /// Sparse, only claimed gifts have the attribute
+        pub const CLAIMED_BY_USER_UUID_INDEX: &str = "claimed_by_user_uuid-index";
+        /// On the ProductKeys table
+        pub const BATCH_ID_INDEX: &str = "batch_id-index";
+        /// On the ExternalKeys table
+        pub const AVAILABLE_POOL_INDEX: &str = "available_pool-index";
+        pub const ASSIGNED_USER_UUID_INDEX: &str = "assigned_user_uuid-index";
+        /// On the ExternalKeys and RevenueTransfers tables
+        pub const PURCHASE_ID_INDEX: &str = "purchase_id-index";
     }
 }
 
@@ -59,6 +219,8 @@ pub enum TableAttributeType {
     SessionId,
     SessionExpiry,
     EmailVerificationUUID,
+    Version,
+    StripeCustomerId,
 }
 
 fn attribute_type_to_string_name(o: &TableAttributeType) -> String {
@@ -73,6 +235,8 @@ fn attribute_type_to_string_name(o: &TableAttributeType) -> String {
         TableAttributeType::SessionId => "session_id",
         TableAttributeType::SessionExpiry => "session_expiry",
         TableAttributeType::EmailVerificationUUID => "email_verification_uuid",
+        TableAttributeType::Version => "version",
+        TableAttributeType::StripeCustomerId => "stripe_customer_id",
     }
     .to_string()
 }
@@ -81,7 +245,15 @@ pub fn parse_string_attribute(
     item: &HashMap<String, AttributeValue>,
     key: &TableAttributeType,
 ) -> Result<Option<String>, NexusError> {
-    item.get(&attribute_type_to_string_name(key))
+    string_attribute(item, &attribute_type_to_string_name(key))
+}
+
+/// Reads a string attribute by name, for tables other than Users
+pub fn string_attribute(
+    item: &HashMap<String, AttributeValue>,
+    name: &str,
+) -> Result<Option<String>, NexusError> {
+    item.get(name)
         .map(|attr| attr.as_s().map(ToString::to_string))
         .transpose()
         .map_err(|e| {
@@ -90,23 +262,28 @@ pub fn parse_string_attribute(
         })
 }
 
+/// Accepts both lists and string sets, since `games_bought` used to be a list
 pub fn parse_list_of_strings_attribute(
     item: &HashMap<String, AttributeValue>,
     key: &TableAttributeType,
 ) -> Result<Option<Vec<String>>, NexusError> {
     item.get(&attribute_type_to_string_name(key))
-        .map(|attr| {
-            attr.as_l().map(|l| {
-                l.iter()
-                    .map(|game| game.as_s().unwrap().clone())
-                    .collect::<Vec<String>>()
-            })
+        .map(|attr| match attr {
+            AttributeValue::L(l) => l
+                .iter()
+                .map(|game| game.as_s().cloned())
+                .collect::<Result<Vec<String>, _>>()
+                .map_err(|e| {
+                    log::error!("Couldn't get list element as string {:?}", e);
+                    NexusError::Unhandled
+                }),
+            AttributeValue::Ss(ss) => Ok(ss.clone()),
+            other => {
+                log::error!("Couldn't get attribute value as list {:?}", other);
+                Err(NexusError::Unhandled)
+            }
         })
         .transpose()
-        .map_err(|e| {
-            log::error!("Couldn't get attribute value as list {:?}", e);
-            NexusError::Unhandled
-        })
 }
 
 pub fn parse_bool_attribute(
@@ -126,7 +303,15 @@ pub fn parse_number_attribute(
     item: &HashMap<String, AttributeValue>,
     key: &TableAttributeType,
 ) -> Result<Option<i64>, NexusError> {
-    item.get(&attribute_type_to_string_name(key))
+    number_attribute(item, &attribute_type_to_string_name(key))
+}
+
+/// Reads a number attribute by name, for tables other than Users
+pub fn number_attribute(
+    item: &HashMap<String, AttributeValue>,
+    name: &str,
+) -> Result<Option<i64>, NexusError> {
+    item.get(name)
         .map(|attr| attr.as_n().map(|s| s.parse::<i64>()))
         .transpose()
         .map_err(|e| {
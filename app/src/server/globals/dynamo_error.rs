@@ -0,0 +1,275 @@
+use crate::errors::NexusError;
+use aws_sdk_dynamodb::error::{ProvideErrorMetadata, SdkError};
+use leptos::ServerFnError;
+use rand::Rng;
+use std::{
+    future::Future,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// Attempts made for a single DynamoDB call before the last error is returned to the caller.
+/// The SDK's own retries are disabled (see `server/src/main.rs`) so this is the only retry layer.
+pub const MAX_ATTEMPTS: u32 = 3;
+const BASE_BACKOFF: Duration = Duration::from_millis(25);
+const MAX_BACKOFF: Duration = Duration::from_millis(400);
+/// Lambda gives us 3 seconds in total, so a single attempt can't be allowed to eat all of it.
+const READ_TIMEOUT: Duration = Duration::from_millis(500);
+const WRITE_TIMEOUT: Duration = Duration::from_millis(800);
+/// How long a call may spend across all its attempts and backoffs, unless the caller has a
+/// tighter deadline of its own (see [`DynamoOperation::until`]).
+const CALL_BUDGET: Duration = Duration::from_millis(1500);
+const METRIC_NAMESPACE: &str = "Nexus";
+
+/// What went wrong with a DynamoDB call, independent of which operation it was.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DynamoErrorKind {
+    /// Provisioned throughput or account request limits were exceeded
+    Throttled,
+    /// A condition expression evaluated to false
+    ConditionalCheckFailed,
+    /// The request itself was malformed (bad expression, wrong attribute type, missing index...)
+    Validation,
+    /// The table or index doesn't exist
+    ResourceNotFound,
+    /// The attempt didn't complete within the operation's timeout
+    Timeout,
+    /// Network failures, unparseable responses and 5xx errors from DynamoDB
+    Transient,
+    Other,
+}
+
+impl DynamoErrorKind {
+    pub fn is_retryable(self) -> bool {
+        matches!(
+            self,
+            DynamoErrorKind::Throttled | DynamoErrorKind::Timeout | DynamoErrorKind::Transient
+        )
+    }
+
+    pub fn nexus_error(self) -> NexusError {
+        match self {
+            DynamoErrorKind::Throttled => NexusError::DynamoThrottled,
+            DynamoErrorKind::ConditionalCheckFailed => NexusError::DynamoConditionalCheckFailed,
+            DynamoErrorKind::Validation => NexusError::DynamoValidationError,
+            DynamoErrorKind::Timeout => NexusError::DynamoTimeout,
+            DynamoErrorKind::ResourceNotFound
+            | DynamoErrorKind::Transient
+            | DynamoErrorKind::Other => NexusError::GenericDynamoServiceError,
+        }
+    }
+}
+
+/// Classifies an error returned by the service. Errors that never reached the service
+/// (timeouts, dispatch failures) are classified by [`classify_sdk_error`] instead.
+pub fn classify(e: &aws_sdk_dynamodb::Error) -> DynamoErrorKind {
+    use aws_sdk_dynamodb::Error;
+    match e {
+        Error::ConditionalCheckFailedException(_) => DynamoErrorKind::ConditionalCheckFailed,
+        Error::ProvisionedThroughputExceededException(_) | Error::RequestLimitExceeded(_) => {
+            DynamoErrorKind::Throttled
+        }
+        Error::ResourceNotFoundException(_)
+        | Error::TableNotFoundException(_)
+        | Error::IndexNotFoundException(_) => DynamoErrorKind::ResourceNotFound,
+        Error::InternalServerError(_) => DynamoErrorKind::Transient,
+        e => classify_error_code(e.code()),
+    }
+}
+
+/// Errors that aren't modelled by the SDK come through as `Unhandled`, so we fall back to the
+/// error code DynamoDB sent back.
+fn classify_error_code(code: Option<&str>) -> DynamoErrorKind {
+    match code {
+        Some("ThrottlingException") | Some("TooManyRequestsException") => {
+            DynamoErrorKind::Throttled
+        }
+        Some("ValidationException") | Some("SerializationException") => DynamoErrorKind::Validation,
+        Some("ServiceUnavailable") | Some("InternalFailure") | Some("InternalServerError") => {
+            DynamoErrorKind::Transient
+        }
+        _ => DynamoErrorKind::Other,
+    }
+}
+
+pub fn classify_sdk_error<E>(e: SdkError<E>) -> DynamoError
+where
+    aws_sdk_dynamodb::Error: From<SdkError<E>>,
+{
+    let kind = match &e {
+        SdkError::TimeoutError(_) => Some(DynamoErrorKind::Timeout),
+        SdkError::DispatchFailure(d) if d.is_timeout() => Some(DynamoErrorKind::Timeout),
+        SdkError::DispatchFailure(_) | SdkError::ResponseError(_) => {
+            Some(DynamoErrorKind::Transient)
+        }
+        _ => None,
+    };
+    let source = aws_sdk_dynamodb::Error::from(e);
+    DynamoError {
+        kind: kind.unwrap_or_else(|| classify(&source)),
+        source: Some(source),
+    }
+}
+
+/// A failed DynamoDB call, after retries
+#[derive(Debug)]
+pub struct DynamoError {
+    pub kind: DynamoErrorKind,
+    /// `None` when the final attempt was cut off by our own timeout
+    pub source: Option<aws_sdk_dynamodb::Error>,
+}
+
+impl From<DynamoError> for ServerFnError<NexusError> {
+    fn from(e: DynamoError) -> Self {
+        ServerFnError::from(e.kind.nexus_error())
+    }
+}
+
+/// Names a DynamoDB call for logging and sets how long a single attempt may take.
+#[derive(Debug, Clone, Copy)]
+pub struct DynamoOperation {
+    pub name: &'static str,
+    pub table: &'static str,
+    pub timeout: Duration,
+    /// When the call has to give up, retries included. Defaults to [`CALL_BUDGET`] from the
+    /// first attempt.
+    pub deadline: Option<Instant>,
+}
+
+impl DynamoOperation {
+    pub fn read(name: &'static str, table: &'static str) -> Self {
+        Self {
+            name,
+            table,
+            timeout: READ_TIMEOUT,
+            deadline: None,
+        }
+    }
+
+    pub fn write(name: &'static str, table: &'static str) -> Self {
+        Self {
+            name,
+            table,
+            timeout: WRITE_TIMEOUT,
+            deadline: None,
+        }
+    }
+
+    /// Shares a deadline between several calls, e.g. the reads and writes of one
+    /// read-modify-write cycle, so that together they can't outlast the request.
+    pub fn until(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+}
+
+/// How long the next attempt may take: the operation's own timeout, cut short by the deadline.
+/// `None` once the deadline has passed.
+pub fn attempt_timeout(timeout: Duration, deadline: Instant, now: Instant) -> Option<Duration> {
+    let remaining = deadline.checked_duration_since(now)?;
+    (!remaining.is_zero()).then(|| timeout.min(remaining))
+}
+
+/// A CloudWatch embedded metric format document counting one failed attempt. Lambda turns these
+/// log lines into the `DynamoErrors` metric, per operation and error kind.
+pub fn error_metric(
+    operation: &DynamoOperation,
+    kind: DynamoErrorKind,
+    timestamp_millis: u128,
+) -> serde_json::Value {
+    serde_json::json!({
+        "_aws": {
+            "Timestamp": timestamp_millis,
+            "CloudWatchMetrics": [{
+                "Namespace": METRIC_NAMESPACE,
+                "Dimensions": [["Operation", "Kind"]],
+                "Metrics": [{ "Name": "DynamoErrors", "Unit": "Count" }],
+            }],
+        },
+        "Operation": operation.name,
+        "Table": operation.table,
+        "Kind": format!("{:?}", kind),
+        "DynamoErrors": 1,
+    })
+}
+
+/// Written straight to stdout: the logger's prefix would stop CloudWatch from parsing the line.
+fn record_error_metric(operation: &DynamoOperation, kind: DynamoErrorKind) {
+    let timestamp_millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    println!("{}", error_metric(operation, kind, timestamp_millis));
+}
+
+/// Full jitter: a uniformly random delay between zero and the exponential backoff for this attempt
+pub fn backoff_with_jitter(attempt: u32) -> Duration {
+    let ceiling = BASE_BACKOFF
+        .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+        .min(MAX_BACKOFF);
+    let millis = rand::thread_rng().gen_range(0..=ceiling.as_millis() as u64);
+    Duration::from_millis(millis)
+}
+
+/// Sends a DynamoDB request, retrying throttling, timeouts and transient failures with jittered
+/// exponential backoff until the operation's deadline. Every failure is logged with the operation
+/// and table it happened on, and counted in the `DynamoErrors` metric.
+///
+/// `send` is called once per attempt, usually as `|| builder.clone().send()`.
+pub async fn send_with_retry<T, E, F, Fut>(
+    operation: DynamoOperation,
+    mut send: F,
+) -> Result<T, DynamoError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, SdkError<E>>>,
+    aws_sdk_dynamodb::Error: From<SdkError<E>>,
+{
+    let deadline = operation
+        .deadline
+        .unwrap_or_else(|| Instant::now() + CALL_BUDGET);
+    let mut attempt = 1;
+    loop {
+        let timed_out = DynamoError {
+            kind: DynamoErrorKind::Timeout,
+            source: None,
+        };
+        let error = match attempt_timeout(operation.timeout, deadline, Instant::now()) {
+            Some(timeout) => match tokio::time::timeout(timeout, send()).await {
+                Ok(Ok(output)) => return Ok(output),
+                Ok(Err(e)) => classify_sdk_error(e),
+                Err(_) => timed_out,
+            },
+            None => timed_out,
+        };
+        record_error_metric(&operation, error.kind);
+        let backoff = backoff_with_jitter(attempt);
+        let retrying = error.kind.is_retryable()
+            && attempt < MAX_ATTEMPTS
+            && Instant::now() + backoff < deadline;
+        // Failed condition checks are an expected outcome callers branch on, not a fault
+        if error.kind == DynamoErrorKind::ConditionalCheckFailed {
+            log::info!(
+                "dynamo operation={} table={} attempt={} kind={:?}",
+                operation.name,
+                operation.table,
+                attempt,
+                error.kind
+            );
+        } else {
+            log::warn!(
+                "dynamo operation={} table={} attempt={} kind={:?} retrying={} error={:?}",
+                operation.name,
+                operation.table,
+                attempt,
+                error.kind,
+                retrying,
+                error.source
+            );
+        }
+        if !retrying {
+            return Err(error);
+        }
+        tokio::time::sleep(backoff).await;
+        attempt += 1;
+    }
+}
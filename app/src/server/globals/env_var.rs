@@ -7,6 +7,129 @@ pub fn get_table_name() -> &'static str {
     }
 }
 
+/// Ledger of every Stripe webhook event we've received, keyed by event id
+pub fn get_webhook_events_table_name() -> &'static str {
+    match std::env!("STAGE") {
+        "prod" => "WebhookEvents",
+        "staging" => "WebhookEvents-staging",
+        "dev" => "WebhookEvents-dev",
+        _ => panic!("STAGE environment variable was not set to 'prod', 'staging', or 'dev' at compile-time.")
+    }
+}
+
+/// One row per checkout session, keyed by the checkout session id
+pub fn get_purchases_table_name() -> &'static str {
+    match std::env!("STAGE") {
+        "prod" => "Purchases",
+        "staging" => "Purchases-staging",
+        "dev" => "Purchases-dev",
+        _ => panic!("STAGE environment variable was not set to 'prod', 'staging', or 'dev' at compile-time.")
+    }
+}
+
+/// Gifts waiting to be claimed (and those already claimed), keyed by the id of the checkout
+/// session that paid for them
+pub fn get_gifts_table_name() -> &'static str {
+    match std::env!("STAGE") {
+        "prod" => "Gifts",
+        "staging" => "Gifts-staging",
+        "dev" => "Gifts-dev",
+        _ => panic!("STAGE environment variable was not set to 'prod', 'staging', or 'dev' at compile-time.")
+    }
+}
+
+/// Redeemable product keys, keyed by the SHA-256 of the key
+pub fn get_product_keys_table_name() -> &'static str {
+    match std::env!("STAGE") {
+        "prod" => "ProductKeys",
+        "staging" => "ProductKeys-staging",
+        "dev" => "ProductKeys-dev",
+        _ => panic!("STAGE environment variable was not set to 'prod', 'staging', or 'dev' at compile-time.")
+    }
+}
+
+/// One row per batch of product keys generated by an admin, keyed by batch id
+pub fn get_key_batches_table_name() -> &'static str {
+    match std::env!("STAGE") {
+        "prod" => "KeyBatches",
+        "staging" => "KeyBatches-staging",
+        "dev" => "KeyBatches-dev",
+        _ => panic!("STAGE environment variable was not set to 'prod', 'staging', or 'dev' at compile-time.")
+    }
+}
+
+/// Attempt counters for rate limited actions, expired by DynamoDB's TTL on `expires_at`
+pub fn get_rate_limits_table_name() -> &'static str {
+    match std::env!("STAGE") {
+        "prod" => "RateLimits",
+        "staging" => "RateLimits-staging",
+        "dev" => "RateLimits-dev",
+        _ => panic!("STAGE environment variable was not set to 'prod', 'staging', or 'dev' at compile-time.")
+    }
+}
+
+/// Keys for third-party storefronts (e.g. Steam) imported by admins, partitioned by pool
+/// (`{product_id}#{platform}`) and sorted by the SHA-256 of the key
+pub fn get_external_keys_table_name() -> &'static str {
+    match std::env!("STAGE") {
+        "prod" => "ExternalKeys",
+        "staging" => "ExternalKeys-staging",
+        "dev" => "ExternalKeys-dev",
+        _ => panic!("STAGE environment variable was not set to 'prod', 'staging', or 'dev' at compile-time.")
+    }
+}
+
+/// Session carts, keyed by a hash of the session id and expired by DynamoDB's TTL on
+/// `expires_at`
+pub fn get_carts_table_name() -> &'static str {
+    match std::env!("STAGE") {
+        "prod" => "Carts",
+        "staging" => "Carts-staging",
+        "dev" => "Carts-dev",
+        _ => panic!("STAGE environment variable was not set to 'prod', 'staging', or 'dev' at compile-time.")
+    }
+}
+
+/// Refund requests, keyed by the ledger id of the purchase they're for
+pub fn get_refund_requests_table_name() -> &'static str {
+    match std::env!("STAGE") {
+        "prod" => "RefundRequests",
+        "staging" => "RefundRequests-staging",
+        "dev" => "RefundRequests-dev",
+        _ => panic!("STAGE environment variable was not set to 'prod', 'staging', or 'dev' at compile-time.")
+    }
+}
+
+/// How often each account downloaded each game, keyed by `{user_uuid}#{product_id}`
+pub fn get_downloads_table_name() -> &'static str {
+    match std::env!("STAGE") {
+        "prod" => "Downloads",
+        "staging" => "Downloads-staging",
+        "dev" => "Downloads-dev",
+        _ => panic!("STAGE environment variable was not set to 'prod', 'staging', or 'dev' at compile-time.")
+    }
+}
+
+/// Collaborators paid a share of sales, keyed by the id the catalog refers to them by
+pub fn get_collaborators_table_name() -> &'static str {
+    match std::env!("STAGE") {
+        "prod" => "Collaborators",
+        "staging" => "Collaborators-staging",
+        "dev" => "Collaborators-dev",
+        _ => panic!("STAGE environment variable was not set to 'prod', 'staging', or 'dev' at compile-time.")
+    }
+}
+
+/// The ledger of collaborators' shares of purchases, keyed by `{purchase_id}#{collaborator_id}`
+pub fn get_revenue_transfers_table_name() -> &'static str {
+    match std::env!("STAGE") {
+        "prod" => "RevenueTransfers",
+        "staging" => "RevenueTransfers-staging",
+        "dev" => "RevenueTransfers-dev",
+        _ => panic!("STAGE environment variable was not set to 'prod', 'staging', or 'dev' at compile-time.")
+    }
+}
+
 pub fn get_host_prefix() -> &'static str {
     if cfg!(debug_assertions) {
         ""
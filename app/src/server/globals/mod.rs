@@ -1,3 +1,4 @@
 pub mod app_state;
 pub mod dynamo;
+pub mod dynamo_error;
 pub mod env_var;
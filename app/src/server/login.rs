@@ -1,24 +1,15 @@
 use super::csrf::{generate_csrf_token, generate_random_bytes};
-use super::globals::dynamo::{query_setup, update_setup, TableKeyType};
-use super::utilities::{
-    dynamo_client, handle_dynamo_generic_error, session_lifespan, verify_password,
-};
-use super::{
-    globals::{
-        dynamo::constants::table_attributes::{
-            EMAIL, EMAIL_VERIFIED, PASSWORD, SESSION_EXPIRY, SESSION_ID,
-        },
-        env_var::get_host_prefix,
-    },
-    utilities::kms_client,
-};
+use super::globals::{dynamo::constants::table_attributes::SESSION_ID, env_var::get_host_prefix};
+use super::repository::UserRepository;
+use super::session_cache::SESSION_CACHE;
+use super::utilities::{kms_client, session_lifespan, user_repository, verify_password};
 use crate::errors::{NexusError, UNHANDLED};
-use aws_sdk_dynamodb::{operation::query::QueryOutput, types::AttributeValue};
 use aws_sdk_kms::Client as KeyClient;
 use chrono::Utc;
 use http::{header, HeaderValue};
 use leptos::{expect_context, ServerFnError};
 use leptos_axum::ResponseOptions;
+use std::sync::Arc;
 use uuid::Uuid;
 
 pub async fn login(
@@ -26,28 +17,30 @@ pub async fn login(
     password: String,
     remember: bool,
 ) -> Result<(), ServerFnError<NexusError>> {
-    let client = dynamo_client()?;
+    let repository = user_repository()?;
     let kms_client = kms_client()?;
-    let columns_to_query = [EMAIL, PASSWORD, EMAIL_VERIFIED];
-    let check_if_password_exists_filter_expression = format!("attribute_exists({})", PASSWORD);
-    let key_condition = format!("{} = :email_val", EMAIL);
-    let db_result = query_setup(&client, email.clone(), TableKeyType::Email)
-        .key_condition_expression(key_condition)
-        .projection_expression(columns_to_query.join(", "))
-        .filter_expression(check_if_password_exists_filter_expression)
-        .send()
-        .await
-        .map_err(aws_sdk_dynamodb::Error::from);
-    let (password_database_hash, verified) = match db_result {
-        Ok(val) => Ok(get_hash_and_verified_status_from_query(val)?),
-        Err(e) => Err(handle_dynamo_generic_error(e)),
-    }?;
+    let credentials = repository.find_credentials(&email).await?.ok_or_else(|| {
+        #[cfg(debug_assertions)]
+        log::error!("Could not find a row with a password for that email");
+        ServerFnError::from(NexusError::CouldNotFindRowWithThatEmail)
+    })?;
+    let (password_database_hash, verified) =
+        (credentials.hashed_password, credentials.email_verified);
     if !verified {
         log::error!("Not email verified");
         return Err(ServerFnError::from(NexusError::AccountNotVerified));
     }
     match verify_password(&password, &password_database_hash) {
-        true => update_session_and_set_cookie(remember, kms_client, client, email).await,
+        true => {
+            update_session_and_set_cookie(
+                remember,
+                kms_client,
+                repository,
+                email,
+                password_database_hash,
+            )
+            .await
+        }
         // https://security.stackexchange.com/questions/227524/password-reset-giving-clues-of-possible-valid-email-addresses/227566#227566
         // TL;DR it is fine from a UX standpoint to say specifically they have the incorrect password, yes this does leak the fact
         // that a specific email address is registered (user enumeration attack)
@@ -60,45 +53,38 @@ pub async fn login(
 
 async fn update_session_and_set_cookie(
     remember: bool,
-    kms_client: std::sync::Arc<KeyClient>,
-    dynamo_client: std::sync::Arc<aws_sdk_dynamodb::Client>,
+    kms_client: Arc<KeyClient>,
+    repository: Arc<dyn UserRepository>,
     email: String,
+    password_database_hash: String,
 ) -> Result<(), ServerFnError<NexusError>> {
     let lifespan = session_lifespan(remember);
     let future_time = Utc::now() + lifespan;
     let session_uuid = Uuid::new_v4().to_string();
-    let update_expression = format!(
-        "SET {} = :session_id, {} = :session_expiry",
-        SESSION_ID, SESSION_EXPIRY
-    );
     let kms_client: &aws_sdk_kms::Client = &kms_client;
     let random_bytes = generate_random_bytes();
     let csrf_token = generate_csrf_token(kms_client, session_uuid.clone(), random_bytes).await?;
-    let update_session_expiry_db_result = update_setup(&dynamo_client, email)
-        .update_expression(update_expression)
-        .expression_attribute_values(":session_id", AttributeValue::S(session_uuid.clone()))
-        .expression_attribute_values(
-            ":session_expiry",
-            AttributeValue::N(future_time.timestamp().to_string()),
+    let update_session_expiry_db_result = repository
+        .start_session(
+            &email,
+            &password_database_hash,
+            &session_uuid,
+            future_time.timestamp(),
         )
-        .send()
-        .await
-        .map_err(aws_sdk_dynamodb::Error::from);
+        .await;
 
     match update_session_expiry_db_result {
-        Ok(_) => on_successful_session_update(session_uuid, future_time, csrf_token),
-        Err(e) => Err(ServerFnError::from(match e {
-            aws_sdk_dynamodb::Error::ConditionalCheckFailedException(e) => {
-                #[cfg(debug_assertions)]
-                log::error!("ConditionalCheckFailedException (email not found?) {:?}", e);
-                NexusError::EmailNotFoundLogin
-            }
-            e => {
-                #[cfg(debug_assertions)]
-                log::error!("Generic error {:?}", e);
-                NexusError::GenericDynamoServiceError
-            }
-        })),
+        Ok(_) => {
+            // The previous session id was just overwritten
+            SESSION_CACHE.invalidate_email(&email);
+            on_successful_session_update(session_uuid, future_time, csrf_token)
+        }
+        Err(ServerFnError::WrappedServerError(NexusError::CouldNotFindRowWithThatEmail)) => {
+            #[cfg(debug_assertions)]
+            log::error!("Could not find row while updating session (email not found?)");
+            Err(ServerFnError::from(NexusError::EmailNotFoundLogin))
+        }
+        Err(e) => Err(e),
     }
 }
 
@@ -138,38 +124,3 @@ fn on_successful_session_update(
     );
     Err(UNHANDLED)
 }
-
-fn get_hash_and_verified_status_from_query(
-    val: QueryOutput,
-) -> Result<(String, bool), ServerFnError<NexusError>> {
-    let item = val.items().first().ok_or(ServerFnError::from(
-        NexusError::CouldNotFindRowWithThatEmail,
-    ))?;
-    let hash_string = item
-        .get(PASSWORD)
-        .ok_or_else(|| {
-            log::error!("Was not able to find the password, despite the filter expression");
-            ServerFnError::from(NexusError::GenericDynamoServiceError)
-        })?
-        .as_s()
-        .map_err(|e| {
-            log::error!(
-                "Was not able to get the inner blob from the password {:?}",
-                e
-            );
-            UNHANDLED
-        })?
-        .to_owned();
-    let email_verified = item
-        .get(EMAIL_VERIFIED)
-        .ok_or_else(|| {
-            log::error!("Was not able to get whether or not this email verification status");
-            UNHANDLED
-        })?
-        .as_bool()
-        .map_err(|e| {
-            log::error!("Could not convert EMAIL_VERIFIED to boolean {:?}", e);
-            UNHANDLED
-        })?;
-    Ok((hash_string, *email_verified))
-}
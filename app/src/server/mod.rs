@@ -1,10 +1,33 @@
+pub mod admin;
+pub mod cart;
+pub mod catalog;
 pub mod change_profile;
+pub mod checkout_status;
+pub mod collaborators;
 pub mod create_checkout;
 pub mod csrf;
+pub mod currency;
 pub mod download;
+pub mod email;
+pub mod external_keys;
+pub mod fulfilment;
+pub mod gifts;
 pub mod globals;
 pub mod login;
 pub mod logout;
+pub mod order_history;
+pub mod payment_changes;
+pub mod product_keys;
+pub mod rate_limit;
+pub mod receipts;
+pub mod reconciliation;
+pub mod refunds;
+pub mod reports;
+pub mod repository;
+pub mod revenue_share;
+pub mod session_cache;
 pub mod signup;
+pub mod stripe_customer;
+pub mod stripe_webhook;
 pub mod utilities;
 pub mod verify_email;
@@ -0,0 +1,119 @@
+use super::{
+    catalog::get_product,
+    rate_limit::RateLimit,
+    receipts::send_receipt,
+    repository::{
+        purchases::{
+            checkout_session_of, get_purchase, list_purchases_for_user, Purchase, PurchaseStatus,
+        },
+        refund_requests::list_refund_requests_for_user,
+    },
+    utilities::{dynamo_client, logged_in_user, ses_client, user_repository},
+};
+use crate::{
+    errors::NexusError,
+    orders::{Order, OrderStatus},
+};
+use leptos::ServerFnError;
+use std::cmp::Reverse;
+
+/// Enough for a lost email or two, not enough to turn receipts into spam
+pub const RESEND_RECEIPT_RATE_LIMIT: RateLimit = RateLimit {
+    action: "resend_receipt",
+    max_attempts: 5,
+    window_seconds: 60 * 60,
+};
+
+pub fn order_status_for(status: PurchaseStatus) -> OrderStatus {
+    match status {
+        PurchaseStatus::Pending => OrderStatus::PaymentPending,
+        PurchaseStatus::Failed => OrderStatus::PaymentFailed,
+        PurchaseStatus::Paid => OrderStatus::Paid,
+        PurchaseStatus::Refunded => OrderStatus::Refunded,
+        PurchaseStatus::Disputed | PurchaseStatus::DisputeLost => OrderStatus::Disputed,
+        PurchaseStatus::FraudWarning => OrderStatus::UnderReview,
+    }
+}
+
+/// A user's purchases as their order history shows them, newest first
+pub fn order_history(purchases: Vec<Purchase>) -> Vec<Order> {
+    let mut orders: Vec<Order> = purchases
+        .into_iter()
+        .map(|purchase| Order {
+            product_title: get_product(&purchase.product_id)
+                .map(|product| product.title)
+                .unwrap_or_else(|_| purchase.product_id.clone()),
+            order_id: purchase.checkout_session_id,
+            product_id: purchase.product_id,
+            amount_total: purchase.amount_total,
+            amount_tax: purchase.amount_tax,
+            amount_discount: purchase.amount_discount,
+            currency: purchase.currency,
+            status: order_status_for(purchase.status),
+            created_at: purchase.created_at,
+            gift: purchase.gift,
+            refund_request: None,
+        })
+        .collect();
+    orders.sort_by_key(|order| Reverse(order.created_at));
+    orders
+}
+
+/// Every purchase of the logged in user, with their refund requests
+pub async fn list_purchases() -> Result<Vec<Order>, ServerFnError<NexusError>> {
+    let repository = user_repository()?;
+    let user = logged_in_user(repository.as_ref()).await?;
+    let dynamodb_client = dynamo_client()?;
+    let refund_requests = list_refund_requests_for_user(&dynamodb_client, &user.user_uuid).await?;
+    let mut orders =
+        order_history(list_purchases_for_user(&dynamodb_client, &user.user_uuid).await?);
+    for order in &mut orders {
+        order.refund_request = refund_requests
+            .iter()
+            .find(|request| request.purchase_id == order.order_id)
+            .map(|request| request.status);
+    }
+    Ok(orders)
+}
+
+/// Emails the receipt of one of the logged in user's purchases to them again. The receipt of a
+/// cart checkout covers every product bought with it.
+pub async fn resend_receipt(order_id: String) -> Result<(), ServerFnError<NexusError>> {
+    let repository = user_repository()?;
+    let user = logged_in_user(repository.as_ref()).await?;
+    let dynamodb_client = dynamo_client()?;
+    RESEND_RECEIPT_RATE_LIMIT
+        .check(&dynamodb_client, &user.user_uuid)
+        .await?;
+    let purchase = get_purchase(&dynamodb_client, &order_id)
+        .await?
+        .filter(|purchase| purchase.user_uuid == user.user_uuid)
+        .ok_or_else(|| {
+            log::error!(
+                "{} asked for the receipt of {}, which isn't theirs",
+                user.user_uuid,
+                order_id
+            );
+            ServerFnError::from(NexusError::PurchaseNotFound)
+        })?;
+    // Nothing was paid (yet, or ever for redeemed keys), so there's nothing to give a receipt for
+    if purchase.currency.is_empty()
+        || matches!(
+            purchase.status,
+            PurchaseStatus::Pending | PurchaseStatus::Failed
+        )
+    {
+        return Err(ServerFnError::from(NexusError::PurchaseNotFound));
+    }
+    let checkout_session_id = checkout_session_of(&purchase.checkout_session_id).to_string();
+    let purchases: Vec<Purchase> = match checkout_session_id == purchase.checkout_session_id {
+        true => vec![purchase],
+        false => list_purchases_for_user(&dynamodb_client, &user.user_uuid)
+            .await?
+            .into_iter()
+            .filter(|other| checkout_session_of(&other.checkout_session_id) == checkout_session_id)
+            .collect(),
+    };
+    let ses_client = ses_client()?;
+    send_receipt(&dynamodb_client, &ses_client, &purchases).await
+}
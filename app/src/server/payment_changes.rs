@@ -0,0 +1,298 @@
+use super::{
+    admin::notify_admins,
+    catalog::entitlements_for,
+    external_keys::revoke_external_keys,
+    refunds::PURCHASE_ID_METADATA_KEY,
+    repository::{
+        gifts::{get_gift, list_gifts_claimed_by},
+        purchases::{
+            entitled_through_other_purchase, find_purchases_by_payment_intent, get_purchase,
+            list_purchases_for_user, set_purchase_status, PaymentChange, Purchase, PurchaseStatus,
+        },
+        UserRepository,
+    },
+    revenue_share::reverse_revenue_shares,
+};
+use crate::errors::NexusError;
+use aws_sdk_dynamodb::Client as DynamoClient;
+use aws_sdk_ses::Client as SesClient;
+use chrono::Utc;
+use leptos::ServerFnError;
+use stripe::{Client as StripeClient, Refund};
+
+/// Applies a refund, dispute or fraud warning to the purchases paid for by `payment_intent_id`
+/// (several for a cart checkout): their entitlements are revoked or restored to match, the new
+/// status is recorded on each purchase and the admins are told. `detail` is included in that
+/// notice.
+///
+/// Every step can safely be retried, so an error here should be retried (Stripe does this for
+/// webhooks) rather than worked around.
+pub async fn apply_payment_change(
+    dynamodb_client: &DynamoClient,
+    repository: &dyn UserRepository,
+    ses_client: &SesClient,
+    stripe_client: &StripeClient,
+    payment_intent_id: &str,
+    change: PaymentChange,
+    detail: &str,
+) -> Result<(), ServerFnError<NexusError>> {
+    let purchases = find_purchases_by_payment_intent(dynamodb_client, payment_intent_id).await?;
+    if purchases.is_empty() {
+        // Also happens if this arrives before the checkout's own webhook was processed
+        log::error!("No purchase was paid for by {}", payment_intent_id);
+        return Err(ServerFnError::from(NexusError::PurchaseNotFound));
+    }
+    for purchase in &purchases {
+        apply_purchase_change(
+            dynamodb_client,
+            repository,
+            ses_client,
+            stripe_client,
+            purchase,
+            change,
+            detail,
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+/// Which of a checkout's purchases a partial refund of its charge was for. Refunds made through
+/// [`crate::server::refunds`] name their purchase in their metadata. Otherwise (e.g. a cart line
+/// refunded from the dashboard) it's the one purchase, not already refunded, whose amount is
+/// what was refunded on top of the purchases that were.
+///
+/// Empty when that's ambiguous or nothing matches, e.g. for a goodwill partial refund.
+pub fn partially_refunded_purchases<'a>(
+    purchases: &'a [Purchase],
+    refunds: &[Refund],
+    amount_refunded: i64,
+) -> Vec<&'a Purchase> {
+    let named = purchases
+        .iter()
+        .filter(|purchase| {
+            refunds.iter().any(|refund| {
+                refund
+                    .metadata
+                    .as_ref()
+                    .and_then(|metadata| metadata.get(PURCHASE_ID_METADATA_KEY))
+                    == Some(&purchase.checkout_session_id)
+            })
+        })
+        .collect::<Vec<&Purchase>>();
+    if !named.is_empty() {
+        return named;
+    }
+    let already_refunded = purchases
+        .iter()
+        .filter(|purchase| purchase.status == PurchaseStatus::Refunded)
+        .map(|purchase| purchase.amount_total)
+        .sum::<i64>();
+    let newly_refunded = amount_refunded - already_refunded;
+    let matching = purchases
+        .iter()
+        .filter(|purchase| {
+            purchase.status != PurchaseStatus::Refunded && purchase.amount_total == newly_refunded
+        })
+        .collect::<Vec<&Purchase>>();
+    if matching.len() == 1 {
+        matching
+    } else {
+        Vec::new()
+    }
+}
+
+/// Applies a payment change to one purchase. Refunding a single product of a cart checkout only
+/// touches its purchase, as the payment as a whole wasn't refunded.
+pub async fn apply_purchase_change(
+    dynamodb_client: &DynamoClient,
+    repository: &dyn UserRepository,
+    ses_client: &SesClient,
+    stripe_client: &StripeClient,
+    purchase: &Purchase,
+    change: PaymentChange,
+    detail: &str,
+) -> Result<(), ServerFnError<NexusError>> {
+    let status = match purchase.status.after(change) {
+        Some(status) => status,
+        None => {
+            log::info!(
+                "{:?} doesn't change purchase {}, which is {}",
+                change,
+                purchase.checkout_session_id,
+                purchase.status.as_str()
+            );
+            return Ok(());
+        }
+    };
+    // The entitlement is changed first, so if recording the status fails the retry still sees
+    // the old status and makes the same decision
+    if status.grants_access() != purchase.status.grants_access() {
+        sync_entitlement(
+            dynamodb_client,
+            repository,
+            purchase,
+            status.grants_access(),
+        )
+        .await?;
+    }
+    // The money is gone for good, so third-party keys handed out with it have to be revoked on
+    // their storefronts. They're listed in the notice below.
+    let revoked_keys = match status {
+        PurchaseStatus::Refunded | PurchaseStatus::DisputeLost => {
+            revoke_external_keys(
+                dynamodb_client,
+                &purchase.checkout_session_id,
+                status.as_str(),
+            )
+            .await?
+        }
+        _ => Vec::new(),
+    };
+    let revoked_keys = revoked_keys
+        .iter()
+        .map(|key| format!("<br>Revoke {} key {}", key.platform, key.key_value))
+        .collect::<String>();
+    // Collaborators don't get a share of money that was given back
+    let reversed_shares = match status {
+        PurchaseStatus::Refunded | PurchaseStatus::DisputeLost => {
+            reverse_revenue_shares(
+                dynamodb_client,
+                stripe_client,
+                &purchase.checkout_session_id,
+                Utc::now().timestamp(),
+            )
+            .await?
+        }
+        _ => Vec::new(),
+    };
+    let reversed_shares = reversed_shares
+        .iter()
+        .map(|share| {
+            format!(
+                "<br>Revenue share of {} ({} {}) {}",
+                share.collaborator_id,
+                share.amount,
+                share.currency,
+                share.status.as_str()
+            )
+        })
+        .collect::<String>();
+    let recorded = set_purchase_status(
+        dynamodb_client,
+        &purchase.checkout_session_id,
+        purchase.status,
+        status,
+        Utc::now().timestamp(),
+    )
+    .await?;
+    if !recorded {
+        log::error!(
+            "Purchase {} changed while applying {:?}",
+            purchase.checkout_session_id,
+            change
+        );
+        return Err(ServerFnError::from(NexusError::ConcurrentModification));
+    }
+    notify_admins(
+        ses_client,
+        &format!(
+            "Purchase {} is now {}",
+            purchase.product_id,
+            status.as_str()
+        ),
+        &format!(
+            "Purchase {} of {} by {} ({}) went from {} to {}.<br>\
+             Amount: {} {}<br>Payment intent: {}<br>{}{}{}",
+            purchase.checkout_session_id,
+            purchase.product_id,
+            purchase.email,
+            purchase.user_uuid,
+            purchase.status.as_str(),
+            status.as_str(),
+            purchase.amount_total,
+            purchase.currency,
+            purchase.payment_intent_id.as_deref().unwrap_or_default(),
+            detail,
+            revoked_keys,
+            reversed_shares
+        ),
+    )
+    .await;
+    Ok(())
+}
+
+/// The purchases of every gift `user_uuid` claimed, which are stored under whoever bought them
+pub async fn list_claimed_gift_purchases(
+    dynamodb_client: &DynamoClient,
+    user_uuid: &str,
+) -> Result<Vec<Purchase>, ServerFnError<NexusError>> {
+    let mut purchases = Vec::new();
+    for gift in list_gifts_claimed_by(dynamodb_client, user_uuid).await? {
+        match get_purchase(dynamodb_client, &gift.gift_id).await? {
+            Some(purchase) => purchases.push(purchase),
+            None => log::error!("Claimed gift {} has no purchase", gift.gift_id),
+        }
+    }
+    Ok(purchases)
+}
+
+/// Grants or revokes the product of `purchase` (every item of it, for a bundle). Revoking leaves
+/// alone what the owner also has through another purchase.
+async fn sync_entitlement(
+    dynamodb_client: &DynamoClient,
+    repository: &dyn UserRepository,
+    purchase: &Purchase,
+    grant: bool,
+) -> Result<(), ServerFnError<NexusError>> {
+    // A gift belongs to whoever claimed it, and nobody has it until then
+    let owner_uuid = match purchase.gift {
+        true => match get_gift(dynamodb_client, &purchase.checkout_session_id)
+            .await?
+            .and_then(|gift| gift.claimed_by_user_uuid)
+        {
+            Some(claimed_by) => claimed_by,
+            None => return Ok(()),
+        },
+        false => purchase.user_uuid.clone(),
+    };
+    // The purchase keeps the email it was made with, which may have changed since
+    let email = match repository.find_email_by_user_uuid(&owner_uuid).await? {
+        Some(email) => email,
+        None if !purchase.gift => purchase.email.clone(),
+        None => {
+            log::info!(
+                "The account that claimed gift {} is gone",
+                purchase.checkout_session_id
+            );
+            return Ok(());
+        }
+    };
+    let entitlements = entitlements_for(&purchase.product_id);
+    if grant {
+        for entitlement in &entitlements {
+            repository.grant_entitlement(&email, entitlement).await?;
+        }
+        return Ok(());
+    }
+    let purchases = list_purchases_for_user(dynamodb_client, &owner_uuid).await?;
+    let claimed_gifts = list_claimed_gift_purchases(dynamodb_client, &owner_uuid).await?;
+    for entitlement in &entitlements {
+        if entitled_through_other_purchase(
+            &purchases,
+            &claimed_gifts,
+            purchase,
+            entitlement,
+            entitlements_for,
+        ) {
+            log::info!(
+                "Not revoking {} from {}, who bought it again",
+                entitlement,
+                owner_uuid
+            );
+            continue;
+        }
+        repository.revoke_entitlement(&email, entitlement).await?;
+    }
+    Ok(())
+}
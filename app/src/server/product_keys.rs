@@ -0,0 +1,275 @@
+use super::{
+    admin::current_admin,
+    catalog::get_product,
+    fulfilment::fulfil,
+    rate_limit::RateLimit,
+    repository::{
+        product_keys::{
+            get_key_batch, list_key_batches as list_batches, list_keys_in_batch, put_key_batch,
+            put_product_keys, redeem_product_key, ProductKey, Redemption,
+        },
+        purchases::{Purchase, PurchaseStatus},
+    },
+    utilities::{dynamo_client, logged_in_user, user_repository, LoggedInUser},
+};
+use crate::{
+    errors::NexusError,
+    orders::{KeyBatchReport, KeyBatchSummary, RedeemedKey, KEY_BATCH_MAX_SIZE},
+};
+use chrono::Utc;
+use leptos::ServerFnError;
+use rand::{rngs::OsRng, Rng};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use uuid::Uuid;
+
+/// Crockford's base32: no I, L, O or U, so keys survive being read aloud or off a card
+const KEY_ALPHABET: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+const KEY_GROUP_LENGTH: usize = 5;
+/// Five groups of five, the last two characters being the checksum
+pub const KEY_LENGTH: usize = 25;
+const KEY_CHECKSUM_LENGTH: usize = 2;
+/// Prefix of the ledger id of a purchase made by redeeming a key. The rest is the key's hash.
+pub const KEY_PURCHASE_PREFIX: &str = "key_";
+
+/// Ten attempts an hour is plenty for typos, and makes guessing keys hopeless
+pub const REDEEM_KEY_RATE_LIMIT: RateLimit = RateLimit {
+    action: "redeem_key",
+    max_attempts: 10,
+    window_seconds: 60 * 60,
+};
+
+/// Two checksum characters (10 bits) from the SHA-256 of the key's body, so most typos are
+/// caught before touching the database
+fn checksum(body: &str) -> [char; KEY_CHECKSUM_LENGTH] {
+    let digest = Sha256::digest(body.as_bytes());
+    let bits = u16::from_be_bytes([digest[0], digest[1]]);
+    [
+        KEY_ALPHABET[usize::from(bits >> 11)] as char,
+        KEY_ALPHABET[usize::from((bits >> 6) & 0b11111)] as char,
+    ]
+}
+
+/// Splits a normalized key into dash separated groups, e.g. `ABCDE-FGHJK-...`
+pub fn format_key(normalized: &str) -> String {
+    normalized
+        .as_bytes()
+        .chunks(KEY_GROUP_LENGTH)
+        .map(|group| String::from_utf8_lossy(group).into_owned())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// A new random key, formatted for handing out
+pub fn generate_key<R: Rng>(rng: &mut R) -> String {
+    let mut key: String = (0..KEY_LENGTH - KEY_CHECKSUM_LENGTH)
+        .map(|_| KEY_ALPHABET[rng.gen_range(0..KEY_ALPHABET.len())] as char)
+        .collect();
+    let checksum = checksum(&key);
+    key.extend(checksum);
+    format_key(&key)
+}
+
+/// The key as it's hashed, or `None` if it isn't one of ours. Case, dashes and spaces don't
+/// matter, and the letters people confuse with digits are read as those digits.
+pub fn normalize_key(input: &str) -> Option<String> {
+    let normalized = input
+        .chars()
+        .filter(|c| *c != '-' && !c.is_whitespace())
+        .map(|c| match c.to_ascii_uppercase() {
+            'O' => '0',
+            'I' | 'L' => '1',
+            c => c,
+        })
+        .collect::<String>();
+    if normalized.len() != KEY_LENGTH || !normalized.bytes().all(|b| KEY_ALPHABET.contains(&b)) {
+        return None;
+    }
+    let (body, check) = normalized.split_at(KEY_LENGTH - KEY_CHECKSUM_LENGTH);
+    (check.chars().eq(checksum(body))).then_some(normalized)
+}
+
+/// Hex SHA-256 of a normalized key. Keys are random enough that this needs no salt.
+pub fn hash_key(normalized: &str) -> String {
+    Sha256::digest(normalized.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// The last group of a normalized key
+pub fn key_hint(normalized: &str) -> String {
+    normalized[KEY_LENGTH - KEY_GROUP_LENGTH..].to_string()
+}
+
+/// The ledger entry for a redeemed key. It's free, and its id is derived from the key, so
+/// retrying a redemption records nothing new.
+pub fn key_purchase(key: &ProductKey, user: &LoggedInUser, now: i64) -> Purchase {
+    Purchase {
+        checkout_session_id: format!("{}{}", KEY_PURCHASE_PREFIX, key.key_hash),
+        user_uuid: user.user_uuid.clone(),
+        email: user.email.clone(),
+        product_id: key.product_id.clone(),
+        payment_intent_id: None,
+        amount_total: 0,
+        currency: String::new(),
+        status: PurchaseStatus::Paid,
+        created_at: key.redeemed_at.unwrap_or(now),
+        updated_at: now,
+        gift: false,
+        amount_discount: 0,
+        sale: None,
+        discount_code: None,
+        amount_tax: 0,
+        receipt_sent_at: None,
+        country: None,
+    }
+}
+
+/// Splits a batch's keys into redeemed and unredeemed
+pub fn batch_report(batch: KeyBatchSummary, keys: Vec<ProductKey>) -> KeyBatchReport {
+    let mut redeemed = Vec::new();
+    let mut unredeemed = Vec::new();
+    for key in keys {
+        match (key.redeemed_by_user_uuid, key.redeemed_at) {
+            (Some(redeemed_by_user_uuid), Some(redeemed_at)) => redeemed.push(RedeemedKey {
+                key_hint: key.key_hint,
+                redeemed_by_user_uuid,
+                redeemed_at,
+            }),
+            _ => unredeemed.push(key.key_hint),
+        }
+    }
+    redeemed.sort_by_key(|key| key.redeemed_at);
+    unredeemed.sort();
+    KeyBatchReport {
+        batch,
+        redeemed,
+        unredeemed,
+    }
+}
+
+/// Generates `count` keys for `product_id`. They're returned formatted, and this is the only
+/// time they can be seen: only their hashes are kept.
+pub async fn generate_key_batch(
+    product_id: String,
+    count: usize,
+    label: String,
+) -> Result<Vec<String>, ServerFnError<NexusError>> {
+    let admin = current_admin().await?;
+    if count == 0 || count > KEY_BATCH_MAX_SIZE {
+        return Err(ServerFnError::from(NexusError::KeyBatchSizeInvalid));
+    }
+    get_product(&product_id)?;
+    let dynamodb_client = dynamo_client()?;
+    let now = Utc::now().timestamp();
+    let batch = KeyBatchSummary {
+        batch_id: Uuid::new_v4().to_string(),
+        product_id,
+        label: label.trim().to_string(),
+        created_by: admin,
+        created_at: now,
+        key_count: count as i64,
+    };
+    let mut formatted = HashSet::with_capacity(count);
+    while formatted.len() < count {
+        formatted.insert(generate_key(&mut OsRng));
+    }
+    let formatted: Vec<String> = formatted.into_iter().collect();
+    let keys: Vec<ProductKey> = formatted
+        .iter()
+        .filter_map(|key| normalize_key(key))
+        .map(|normalized| ProductKey {
+            key_hash: hash_key(&normalized),
+            batch_id: batch.batch_id.clone(),
+            product_id: batch.product_id.clone(),
+            key_hint: key_hint(&normalized),
+            created_at: now,
+            redeemed_by_user_uuid: None,
+            redeemed_at: None,
+        })
+        .collect();
+    put_product_keys(&dynamodb_client, &keys).await?;
+    // Last, so a batch only shows up once all of its keys work
+    put_key_batch(&dynamodb_client, &batch).await?;
+    log::info!(
+        "{} generated {} keys for {} in batch {}",
+        batch.created_by,
+        count,
+        batch.product_id,
+        batch.batch_id
+    );
+    Ok(formatted)
+}
+
+/// Redeems a key for the logged in user, returning the id of the product it granted. Redeeming
+/// a key the user already redeemed grants it again, which is how an incomplete redemption is
+/// finished.
+pub async fn redeem_key(key: String) -> Result<String, ServerFnError<NexusError>> {
+    let repository = user_repository()?;
+    let user = logged_in_user(repository.as_ref()).await?;
+    let dynamodb_client = dynamo_client()?;
+    // Counted before anything else, so malformed guesses use up attempts too
+    REDEEM_KEY_RATE_LIMIT
+        .check(&dynamodb_client, &user.user_uuid)
+        .await?;
+    let normalized =
+        normalize_key(&key).ok_or_else(|| ServerFnError::from(NexusError::KeyInvalid))?;
+    let now = Utc::now().timestamp();
+    let key = match redeem_product_key(
+        &dynamodb_client,
+        &hash_key(&normalized),
+        &user.user_uuid,
+        now,
+    )
+    .await?
+    {
+        Redemption::Redeemed(key) => key,
+        Redemption::NotFound => return Err(ServerFnError::from(NexusError::KeyInvalid)),
+        Redemption::AlreadyRedeemed => {
+            return Err(ServerFnError::from(NexusError::KeyAlreadyRedeemed))
+        }
+    };
+    log::info!(
+        "Key {} of batch {} was redeemed by {}",
+        key.key_hint,
+        key.batch_id,
+        user.user_uuid
+    );
+    // The key stays redeemed by this account, and redeeming it again records the same purchase,
+    // so the user is told to retry rather than that the key is gone
+    if let Err(e) = fulfil(
+        &dynamodb_client,
+        repository.as_ref(),
+        &key_purchase(&key, &user, now),
+    )
+    .await
+    {
+        log::error!(
+            "Key {} was redeemed by {} but not fulfilled {:?}",
+            key.key_hint,
+            user.user_uuid,
+            e
+        );
+        return Err(ServerFnError::from(NexusError::KeyRedemptionIncomplete));
+    }
+    Ok(key.product_id)
+}
+
+pub async fn list_key_batches() -> Result<Vec<KeyBatchSummary>, ServerFnError<NexusError>> {
+    current_admin().await?;
+    let dynamodb_client = dynamo_client()?;
+    list_batches(&dynamodb_client).await
+}
+
+pub async fn get_key_batch_report(
+    batch_id: String,
+) -> Result<KeyBatchReport, ServerFnError<NexusError>> {
+    current_admin().await?;
+    let dynamodb_client = dynamo_client()?;
+    let batch = get_key_batch(&dynamodb_client, &batch_id)
+        .await?
+        .ok_or_else(|| ServerFnError::from(NexusError::KeyBatchNotFound))?;
+    let keys = list_keys_in_batch(&dynamodb_client, &batch_id).await?;
+    Ok(batch_report(batch, keys))
+}
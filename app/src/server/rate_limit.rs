@@ -0,0 +1,42 @@
+use super::repository::rate_limits::count_attempt;
+use crate::errors::NexusError;
+use aws_sdk_dynamodb::Client as DynamoClient;
+use chrono::Utc;
+use leptos::ServerFnError;
+
+/// How often something may be attempted, counted in fixed windows
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    /// Names the counters, e.g. "redeem_key"
+    pub action: &'static str,
+    pub max_attempts: i64,
+    pub window_seconds: i64,
+}
+
+impl RateLimit {
+    /// The counter `subject`'s attempts at `now` go in, and when it can be thrown away
+    pub fn bucket(&self, subject: &str, now: i64) -> (String, i64) {
+        let window = now.div_euclid(self.window_seconds);
+        let expires_at = (window + 1) * self.window_seconds;
+        (
+            format!("{}#{}#{}", self.action, subject, window),
+            expires_at,
+        )
+    }
+
+    /// Counts an attempt by `subject`, failing with [`NexusError::TooManyAttempts`] once they've
+    /// made more than allowed in the current window
+    pub async fn check(
+        &self,
+        dynamodb_client: &DynamoClient,
+        subject: &str,
+    ) -> Result<(), ServerFnError<NexusError>> {
+        let (bucket, expires_at) = self.bucket(subject, Utc::now().timestamp());
+        let attempts = count_attempt(dynamodb_client, &bucket, expires_at).await?;
+        if attempts > self.max_attempts {
+            log::warn!("{} exceeded the {} rate limit", subject, self.action);
+            return Err(ServerFnError::from(NexusError::TooManyAttempts));
+        }
+        Ok(())
+    }
+}
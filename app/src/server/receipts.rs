@@ -0,0 +1,162 @@
+use super::{
+    catalog::get_product,
+    email::{escape_html, send_email},
+    repository::purchases::{checkout_session_of, get_purchase, mark_receipt_sent, Purchase},
+};
+use crate::{
+    currency::format_amount,
+    errors::NexusError,
+    site::constants::{SITE_DOMAIN, SITE_FULL_DOMAIN},
+};
+use aws_sdk_dynamodb::Client as DynamoClient;
+use aws_sdk_ses::Client as SesClient;
+use chrono::{DateTime, Utc};
+use leptos::ServerFnError;
+
+fn product_title(product_id: &str) -> String {
+    get_product(product_id)
+        .map(|product| product.title)
+        .unwrap_or_else(|_| product_id.to_string())
+}
+
+/// The subject and html body of the receipt for a paid checkout: its one purchase, or the
+/// purchases of each product of a cart checkout
+pub fn receipt_email(
+    purchases: &[Purchase],
+    product_title: impl Fn(&str) -> String,
+) -> (String, String) {
+    let first = &purchases[0];
+    let amount = |amount: i64| format_amount(amount, &first.currency);
+    let sum = |field: fn(&Purchase) -> i64| purchases.iter().map(field).sum::<i64>();
+    let date = DateTime::from_timestamp(first.created_at, 0)
+        .map(|date| date.format("%B %-d, %Y").to_string())
+        .unwrap_or_default();
+    let products = match purchases {
+        [purchase] => format!(
+            "<tr><td>Product</td><td>{}</td></tr>\n",
+            escape_html(&product_title(&purchase.product_id))
+        ),
+        purchases => purchases
+            .iter()
+            .map(|purchase| {
+                format!(
+                    "<tr><td>{}</td><td>{}</td></tr>\n",
+                    escape_html(&product_title(&purchase.product_id)),
+                    amount(purchase.amount_total)
+                )
+            })
+            .collect(),
+    };
+    let discount = match sum(|purchase| purchase.amount_discount) {
+        0 => String::new(),
+        discount => format!("<tr><td>Discount</td><td>-{}</td></tr>\n", amount(discount)),
+    };
+    let delivered = match first.gift {
+        true => format!(
+            "Your gift has been sent. You can see whether it's been claimed here:
+
+https://{}/gifts",
+            SITE_FULL_DOMAIN
+        ),
+        false if purchases.len() == 1 => format!(
+            "The game is in your library, ready to download:
+
+https://{}/download",
+            SITE_FULL_DOMAIN
+        ),
+        false => format!(
+            "Your games are in your library, ready to download:
+
+https://{}/download",
+            SITE_FULL_DOMAIN
+        ),
+    };
+    let body = format!(
+        "Hello,
+Thank you for your purchase! Here's your receipt.
+
+<table>
+<tr><td>Order</td><td>{}</td></tr>
+<tr><td>Date</td><td>{}</td></tr>
+{}{}<tr><td>Tax</td><td>{}</td></tr>
+<tr><td>Total</td><td>{}</td></tr>
+</table>
+
+{}
+
+Your past orders are here:
+
+https://{}/orders",
+        escape_html(checkout_session_of(&first.checkout_session_id)),
+        date,
+        products,
+        discount,
+        amount(sum(|purchase| purchase.amount_tax)),
+        amount(sum(|purchase| purchase.amount_total)),
+        delivered,
+        SITE_FULL_DOMAIN
+    );
+    let subject = match purchases {
+        [purchase] => product_title(&purchase.product_id),
+        purchases => format!(
+            "{} and {} more",
+            product_title(&first.product_id),
+            purchases.len() - 1
+        ),
+    };
+    (
+        format!("[{}] Your receipt for {}", SITE_DOMAIN, subject),
+        body,
+    )
+}
+
+/// Emails the receipt for the `purchases` of one checkout to their buyer, and notes when it
+/// first went out
+pub async fn send_receipt(
+    dynamodb_client: &DynamoClient,
+    ses_client: &SesClient,
+    purchases: &[Purchase],
+) -> Result<(), ServerFnError<NexusError>> {
+    let Some(first) = purchases.first() else {
+        return Ok(());
+    };
+    let (subject, body) = receipt_email(purchases, product_title);
+    send_email(
+        ses_client,
+        std::slice::from_ref(&first.email),
+        &subject,
+        &body,
+    )
+    .await?;
+    let now = Utc::now().timestamp();
+    for purchase in purchases {
+        mark_receipt_sent(dynamodb_client, &purchase.checkout_session_id, now).await?;
+    }
+    Ok(())
+}
+
+/// Emails the receipt for the recorded purchases of a checkout unless it has gone out already,
+/// so webhook retries don't send it again
+pub async fn send_receipt_once(
+    dynamodb_client: &DynamoClient,
+    ses_client: &SesClient,
+    purchase_ids: &[String],
+) -> Result<(), ServerFnError<NexusError>> {
+    let mut purchases = Vec::with_capacity(purchase_ids.len());
+    for purchase_id in purchase_ids {
+        let purchase = get_purchase(dynamodb_client, purchase_id)
+            .await?
+            .ok_or_else(|| {
+                log::error!("No purchase {} to send a receipt for", purchase_id);
+                ServerFnError::from(NexusError::PurchaseNotFound)
+            })?;
+        purchases.push(purchase);
+    }
+    if purchases
+        .iter()
+        .any(|purchase| purchase.receipt_sent_at.is_some())
+    {
+        return Ok(());
+    }
+    send_receipt(dynamodb_client, ses_client, &purchases).await
+}
@@ -0,0 +1,399 @@
+use super::{
+    admin::{current_admin, notify_admins},
+    catalog::entitlements_for,
+    fulfilment::grant_to,
+    payment_changes::list_claimed_gift_purchases,
+    repository::purchases::{
+        checkout_session_of, entitled_through_other_purchase, get_purchase,
+        list_purchases_created_between, list_purchases_for_user, Purchase, PurchaseStatus,
+    },
+    stripe_webhook::{
+        handlers::{complete_checkout, purchases_from_checkout, settle_checkout},
+        router::WebhookContext,
+    },
+    utilities::{dynamo_client, ses_client, stripe_client, user_repository},
+};
+use crate::{
+    errors::{NexusError, UNHANDLED},
+    orders::{AnomalyKind, ReconciliationAnomaly, ReconciliationReport},
+};
+use chrono::Utc;
+use leptos::ServerFnError;
+use std::collections::HashSet;
+use stripe::{
+    CheckoutSession, CheckoutSessionId, CheckoutSessionPaymentStatus, CheckoutSessionStatus,
+    Client as StripeClient, ListCheckoutSessions, RangeQuery,
+};
+
+/// How many days back reconciliation looks unless told otherwise. Stripe stops retrying a
+/// webhook after three days, so a checkout older than that won't fix itself.
+pub const DEFAULT_RECONCILIATION_DAYS: u32 = 3;
+
+/// Most days one reconciliation may look back. Every checkout in them is checked against the
+/// ledger one by one, so a longer run wouldn't finish within the request's timeout.
+pub const MAX_RECONCILIATION_DAYS: u32 = 30;
+
+/// Most checkouts Stripe lists in one page
+const CHECKOUTS_PER_PAGE: u64 = 100;
+
+/// Completed checkouts created from `since` on, newest first
+pub async fn list_completed_checkouts(
+    stripe_client: &StripeClient,
+    since: i64,
+) -> Result<Vec<CheckoutSession>, ServerFnError<NexusError>> {
+    let mut checkouts = Vec::new();
+    let mut starting_after: Option<CheckoutSessionId> = None;
+    loop {
+        let mut params = ListCheckoutSessions::new();
+        params.created = Some(RangeQuery::gte(since));
+        params.status = Some(CheckoutSessionStatus::Complete);
+        params.limit = Some(CHECKOUTS_PER_PAGE);
+        params.starting_after = starting_after.clone();
+        let page = CheckoutSession::list(stripe_client, &params)
+            .await
+            .map_err(|e| {
+                log::error!("Could not list checkouts since {} {:?}", since, e);
+                UNHANDLED
+            })?;
+        starting_after = page.data.last().map(|checkout| checkout.id.clone());
+        checkouts.extend(page.data);
+        if !page.has_more || starting_after.is_none() {
+            break;
+        }
+    }
+    Ok(checkouts)
+}
+
+/// What's wrong with a purchase of a completed checkout, given the status Stripe says it should
+/// have, what the ledger `recorded`, and its buyer's `library`, `purchases` and the purchases of
+/// gifts they claimed. Gifts are only checked against the ledger, since they're in the library
+/// of whoever claimed them.
+/// `entitlements_of` maps a product to the entitlements it grants.
+pub fn purchase_anomaly(
+    expected_status: PurchaseStatus,
+    recorded: Option<&Purchase>,
+    library: &[String],
+    purchases: &[Purchase],
+    claimed_gifts: &[Purchase],
+    entitlements_of: impl Fn(&str) -> Vec<String>,
+) -> Option<AnomalyKind> {
+    let Some(recorded) = recorded else {
+        return Some(AnomalyKind::MissingPurchase);
+    };
+    match (expected_status, recorded.status) {
+        (PurchaseStatus::Paid, PurchaseStatus::Pending) => {
+            return Some(AnomalyKind::UnsettledPayment)
+        }
+        // Stripe is still waiting for the money, so nothing should have been granted
+        (PurchaseStatus::Pending, status) if status.grants_access() => {
+            return Some(AnomalyKind::EntitlementWithoutPayment)
+        }
+        _ => {}
+    }
+    if recorded.gift {
+        return None;
+    }
+    let entitlements = entitlements_of(&recorded.product_id);
+    match recorded.status {
+        PurchaseStatus::Pending | PurchaseStatus::Failed => None,
+        status if status.grants_access() => entitlements
+            .iter()
+            .any(|entitlement| !library.contains(entitlement))
+            .then_some(AnomalyKind::MissingEntitlement),
+        // Refunded, disputed or flagged, so the product should have been revoked unless it's
+        // owned through another purchase
+        _ => entitlements
+            .iter()
+            .any(|entitlement| {
+                library.contains(entitlement)
+                    && !entitled_through_other_purchase(
+                        purchases,
+                        claimed_gifts,
+                        recorded,
+                        entitlement,
+                        &entitlements_of,
+                    )
+            })
+            .then_some(AnomalyKind::EntitlementWithoutPayment),
+    }
+}
+
+/// Purchases in the ledger that grant access and were charged for, but whose checkout isn't
+/// among `completed_checkouts`
+pub fn unmatched_purchases<'a>(
+    recorded: &'a [Purchase],
+    completed_checkouts: &HashSet<&str>,
+) -> Vec<&'a Purchase> {
+    recorded
+        .iter()
+        .filter(|purchase| {
+            // Redeemed keys have no checkout
+            !purchase.currency.is_empty()
+                && purchase.status.grants_access()
+                && !completed_checkouts.contains(checkout_session_of(&purchase.checkout_session_id))
+        })
+        .collect()
+}
+
+/// What a buyer owns: their current email, entitlements, purchases and the purchases of gifts
+/// they claimed
+struct Buyer {
+    email: String,
+    library: Vec<String>,
+    purchases: Vec<Purchase>,
+    claimed_gifts: Vec<Purchase>,
+}
+
+async fn buyer_of(
+    context: &WebhookContext,
+    purchase: &Purchase,
+) -> Result<Buyer, ServerFnError<NexusError>> {
+    // The purchase keeps the email it was made with, which may have changed since
+    let email = context
+        .user_repository
+        .find_email_by_user_uuid(&purchase.user_uuid)
+        .await?
+        .unwrap_or_else(|| purchase.email.clone());
+    Ok(Buyer {
+        library: context.user_repository.entitlements(&email).await?,
+        purchases: list_purchases_for_user(&context.dynamodb_client, &purchase.user_uuid).await?,
+        claimed_gifts: list_claimed_gift_purchases(&context.dynamodb_client, &purchase.user_uuid)
+            .await?,
+        email,
+    })
+}
+
+/// Compares the purchases of one completed checkout with the ledger, and repairs what the
+/// webhook left undone
+async fn reconcile_checkout(
+    context: &WebhookContext,
+    checkout_session: &CheckoutSession,
+) -> Result<Vec<ReconciliationAnomaly>, ServerFnError<NexusError>> {
+    let expected_status = match checkout_session.payment_status {
+        CheckoutSessionPaymentStatus::Unpaid => PurchaseStatus::Pending,
+        CheckoutSessionPaymentStatus::Paid | CheckoutSessionPaymentStatus::NoPaymentRequired => {
+            PurchaseStatus::Paid
+        }
+    };
+    let mut anomalies = Vec::new();
+    for purchase in purchases_from_checkout(context, checkout_session, expected_status).await? {
+        let recorded =
+            get_purchase(&context.dynamodb_client, &purchase.checkout_session_id).await?;
+        let buyer = match &recorded {
+            Some(recorded) if !recorded.gift => Some(buyer_of(context, recorded).await?),
+            _ => None,
+        };
+        let Some(kind) = purchase_anomaly(
+            expected_status,
+            recorded.as_ref(),
+            buyer.as_ref().map_or(&[], |buyer| &buyer.library),
+            buyer.as_ref().map_or(&[], |buyer| &buyer.purchases),
+            buyer.as_ref().map_or(&[], |buyer| &buyer.claimed_gifts),
+            entitlements_for,
+        ) else {
+            continue;
+        };
+        // Only this purchase's product, since another product of the same checkout may have
+        // been refunded on its own
+        let repaired = match (kind, &buyer) {
+            (AnomalyKind::MissingEntitlement, Some(buyer)) => {
+                match grant_to(
+                    context.user_repository.as_ref(),
+                    &buyer.email,
+                    &purchase.product_id,
+                )
+                .await
+                {
+                    Ok(()) => true,
+                    Err(e) => {
+                        log::error!(
+                            "Could not repair purchase {} {:?}",
+                            purchase.checkout_session_id,
+                            e
+                        );
+                        false
+                    }
+                }
+            }
+            _ => false,
+        };
+        anomalies.push(ReconciliationAnomaly {
+            kind,
+            purchase_id: purchase.checkout_session_id,
+            user_uuid: purchase.user_uuid,
+            product_id: purchase.product_id,
+            repaired,
+        });
+    }
+    // The webhook never got as far as recording (or settling) the checkout, so it's run
+    // through the same idempotent steps the webhook would have. Those only grant the lines the
+    // ledger says are paid, so a line refunded on its own stays revoked.
+    let unprocessed = |kind| anomalies.iter().any(|anomaly| anomaly.kind == kind);
+    let repair = if unprocessed(AnomalyKind::UnsettledPayment) {
+        Some(settle_checkout(context, checkout_session).await)
+    } else if unprocessed(AnomalyKind::MissingPurchase) {
+        Some(complete_checkout(context, checkout_session).await)
+    } else {
+        None
+    };
+    match repair {
+        Some(Ok(())) => {
+            for anomaly in anomalies.iter_mut() {
+                if matches!(
+                    anomaly.kind,
+                    AnomalyKind::UnsettledPayment | AnomalyKind::MissingPurchase
+                ) {
+                    anomaly.repaired = true;
+                }
+            }
+        }
+        Some(Err(e)) => log::error!("Could not repair checkout {} {:?}", checkout_session.id, e),
+        None => {}
+    }
+    Ok(anomalies)
+}
+
+/// Whether Stripe has the checkout of a purchase down as completed and paid for
+async fn checkout_paid(
+    context: &WebhookContext,
+    purchase: &Purchase,
+) -> Result<bool, ServerFnError<NexusError>> {
+    let checkout_session_id = checkout_session_of(&purchase.checkout_session_id)
+        .parse::<CheckoutSessionId>()
+        .map_err(|e| {
+            log::error!(
+                "Purchase {} has no checkout session id {:?}",
+                purchase.checkout_session_id,
+                e
+            );
+            UNHANDLED
+        })?;
+    let checkout_session =
+        CheckoutSession::retrieve(&context.stripe_client, &checkout_session_id, &[])
+            .await
+            .map_err(|e| {
+                log::error!("Could not fetch checkout {} {:?}", checkout_session_id, e);
+                UNHANDLED
+            })?;
+    Ok(
+        checkout_session.status == Some(CheckoutSessionStatus::Complete)
+            && checkout_session.payment_status != CheckoutSessionPaymentStatus::Unpaid,
+    )
+}
+
+/// Compares the checkouts completed from `since` on with the purchase ledger and entitlements,
+/// repairing what a missed or failed webhook left undone. A checkout that can't be checked is
+/// counted as failed rather than failing the rest.
+pub async fn reconcile(
+    context: &WebhookContext,
+    since: i64,
+    now: i64,
+) -> Result<ReconciliationReport, ServerFnError<NexusError>> {
+    let checkouts = list_completed_checkouts(&context.stripe_client, since).await?;
+    let mut report = ReconciliationReport {
+        since,
+        checkouts: checkouts.len(),
+        failed: 0,
+        anomalies: Vec::new(),
+    };
+    for checkout_session in &checkouts {
+        match reconcile_checkout(context, checkout_session).await {
+            Ok(anomalies) => report.anomalies.extend(anomalies),
+            Err(e) => {
+                log::error!(
+                    "Could not reconcile checkout {} {:?}",
+                    checkout_session.id,
+                    e
+                );
+                report.failed += 1;
+            }
+        }
+    }
+
+    // The other way round: purchases that don't come from any of those checkouts
+    let completed: HashSet<&str> = checkouts
+        .iter()
+        .map(|checkout_session| checkout_session.id.as_str())
+        .collect();
+    let recorded = list_purchases_created_between(&context.dynamodb_client, since, now).await?;
+    for purchase in unmatched_purchases(&recorded, &completed) {
+        // It may have completed after the checkouts were listed
+        match checkout_paid(context, purchase).await {
+            Ok(true) => {}
+            Ok(false) => report.anomalies.push(ReconciliationAnomaly {
+                kind: AnomalyKind::EntitlementWithoutPayment,
+                purchase_id: purchase.checkout_session_id.clone(),
+                user_uuid: purchase.user_uuid.clone(),
+                product_id: purchase.product_id.clone(),
+                repaired: false,
+            }),
+            Err(_) => report.failed += 1,
+        }
+    }
+    Ok(report)
+}
+
+/// How many days a reconciliation asked to look back `days` covers:
+/// [`DEFAULT_RECONCILIATION_DAYS`] for 0, and at most [`MAX_RECONCILIATION_DAYS`]
+pub fn reconciliation_days(days: u32) -> Result<u32, NexusError> {
+    match days {
+        0 => Ok(DEFAULT_RECONCILIATION_DAYS),
+        days if days > MAX_RECONCILIATION_DAYS => Err(NexusError::ReconciliationRangeInvalid),
+        days => Ok(days),
+    }
+}
+
+/// Runs a reconciliation of the last `days` days (see [`reconciliation_days`]), and emails the
+/// admins about anything it found
+pub async fn reconcile_checkouts(
+    days: u32,
+) -> Result<ReconciliationReport, ServerFnError<NexusError>> {
+    let admin = current_admin().await?;
+    let days = reconciliation_days(days)?;
+    let context = WebhookContext {
+        dynamodb_client: dynamo_client()?,
+        user_repository: user_repository()?,
+        stripe_client: stripe_client()?,
+        ses_client: ses_client()?,
+    };
+    let now = Utc::now().timestamp();
+    log::info!(
+        "{} is reconciling the last {} days of checkouts",
+        admin,
+        days
+    );
+    let report = reconcile(&context, now - i64::from(days) * 60 * 60 * 24, now).await?;
+    if !report.anomalies.is_empty() || report.failed > 0 {
+        let anomalies: Vec<String> = report
+            .anomalies
+            .iter()
+            .map(|anomaly| {
+                format!(
+                    "{:?}: purchase {} of {} by {} ({})",
+                    anomaly.kind,
+                    anomaly.purchase_id,
+                    anomaly.product_id,
+                    anomaly.user_uuid,
+                    match anomaly.repaired {
+                        true => "repaired",
+                        false => "needs a look",
+                    }
+                )
+            })
+            .collect();
+        notify_admins(
+            &context.ses_client,
+            "Reconciliation found problems",
+            &format!(
+                "Reconciling {} checkouts from the last {} days found:<br>{}<br>{} checkouts \
+                 couldn't be checked.",
+                report.checkouts,
+                days,
+                anomalies.join("<br>"),
+                report.failed
+            ),
+        )
+        .await;
+    }
+    Ok(report)
+}
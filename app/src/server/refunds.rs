@@ -0,0 +1,455 @@
+use super::{
+    admin::{current_admin, notify_admins},
+    catalog::{entitlements_for, get_product},
+    email::{escape_html, send_email},
+    payment_changes::apply_purchase_change,
+    rate_limit::RateLimit,
+    repository::{
+        downloads::{get_download_stats, DownloadStats},
+        purchases::{get_purchase, PaymentChange, Purchase, PurchaseStatus},
+        refund_requests::{
+            create_refund_request, get_refund_request, list_refund_requests_by_status,
+            set_refund_request_status, RefundRequestUpdate,
+        },
+    },
+    utilities::{dynamo_client, logged_in_user, ses_client, stripe_client, user_repository},
+};
+use crate::{
+    currency::format_amount,
+    errors::{NexusError, UNHANDLED},
+    orders::{RefundOutcome, RefundRequest, RefundRequestStatus, REFUND_REASON_MAX_LENGTH},
+    site::constants::SITE_DOMAIN,
+};
+use chrono::Utc;
+use leptos::ServerFnError;
+use std::collections::HashMap;
+use stripe::{CreateRefund, PaymentIntentId, Refund, RefundReasonFilter, RequestStrategy};
+
+/// Key of the refund metadata entry holding the ledger id of the purchase refunded
+pub const PURCHASE_ID_METADATA_KEY: &str = "purchase_id";
+
+/// Asking for a refund doesn't get anyone anything they couldn't get by asking once
+pub const REQUEST_REFUND_RATE_LIMIT: RateLimit = RateLimit {
+    action: "request_refund",
+    max_attempts: 5,
+    window_seconds: 60 * 60 * 24,
+};
+
+/// Which refund requests are granted without anyone looking at them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RefundPolicy {
+    /// How long after the purchase a refund can be asked for
+    pub window_seconds: i64,
+    pub max_downloads: i64,
+}
+
+/// Fourteen days, and downloaded no more than five times. Playtime isn't tracked (the launcher
+/// doesn't report it), so how long the game was played doesn't count.
+pub const REFUND_POLICY: RefundPolicy = RefundPolicy {
+    window_seconds: 60 * 60 * 24 * 14,
+    max_downloads: 5,
+};
+
+/// Why a refund request needs an admin to look at it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RefundIneligibility {
+    /// The gift may have been claimed and played by someone else
+    Gift,
+    TooLate {
+        days_since_purchase: i64,
+    },
+    TooManyDownloads {
+        download_count: i64,
+    },
+}
+
+impl RefundIneligibility {
+    /// For the admins reviewing the request
+    pub fn describe(&self) -> String {
+        match self {
+            RefundIneligibility::Gift => "It was bought as a gift".to_string(),
+            RefundIneligibility::TooLate {
+                days_since_purchase,
+            } => format!("It was bought {} days ago", days_since_purchase),
+            RefundIneligibility::TooManyDownloads { download_count } => {
+                format!("It was downloaded {} times", download_count)
+            }
+        }
+    }
+}
+
+impl RefundPolicy {
+    /// Why refunding `purchase` at `now` needs a review, or `None` if it can be refunded
+    /// right away. `usage` is how much the buyer used what they bought.
+    pub fn check(
+        &self,
+        purchase: &Purchase,
+        usage: &DownloadStats,
+        now: i64,
+    ) -> Option<RefundIneligibility> {
+        if purchase.gift {
+            return Some(RefundIneligibility::Gift);
+        }
+        if now - purchase.created_at > self.window_seconds {
+            return Some(RefundIneligibility::TooLate {
+                days_since_purchase: (now - purchase.created_at) / (60 * 60 * 24),
+            });
+        }
+        if usage.download_count > self.max_downloads {
+            return Some(RefundIneligibility::TooManyDownloads {
+                download_count: usage.download_count,
+            });
+        }
+        None
+    }
+}
+
+/// Whether there's money to give back for `purchase`: it was paid for, not redeemed with a
+/// key or fully discounted, and hasn't been refunded or disputed already
+pub fn is_refundable(purchase: &Purchase) -> bool {
+    purchase.status == PurchaseStatus::Paid
+        && !purchase.currency.is_empty()
+        && purchase.amount_total > 0
+        && purchase.payment_intent_id.is_some()
+}
+
+async fn email_buyer(email: &str, subject: &str, body: &str) {
+    let ses_client = match ses_client() {
+        Ok(ses_client) => ses_client,
+        Err(_) => return,
+    };
+    let subject = format!("[{}] {}", SITE_DOMAIN, subject);
+    if let Err(e) = send_email(&ses_client, &[email.to_string()], &subject, body).await {
+        log::error!("Could not email {} about their refund {:?}", email, e);
+    }
+}
+
+fn product_title(product_id: &str) -> String {
+    get_product(product_id)
+        .map(|product| product.title)
+        .unwrap_or_else(|_| product_id.to_string())
+}
+
+/// Whether the buyer still has to be given their money back through Stripe: not if the purchase
+/// was refunded already (e.g. from the dashboard), or a refund was made for the request before
+/// it failed partway. Stripe's idempotency keys expire after a day, so they can't be relied on
+/// to catch a request retried later than that.
+pub fn needs_stripe_refund(purchase: &Purchase, request: &RefundRequest) -> bool {
+    request.stripe_refund_id.is_none() && purchase.status != PurchaseStatus::Refunded
+}
+
+/// Refunds this purchase's share of its payment through Stripe
+async fn create_stripe_refund(purchase: &Purchase) -> Result<Refund, ServerFnError<NexusError>> {
+    let payment_intent_id = purchase.payment_intent_id.as_deref().ok_or_else(|| {
+        log::error!(
+            "Purchase {} has no payment to refund",
+            purchase.checkout_session_id
+        );
+        ServerFnError::from(NexusError::NotRefundable)
+    })?;
+    let mut params = CreateRefund::new();
+    params.payment_intent = Some(payment_intent_id.parse::<PaymentIntentId>().map_err(|e| {
+        log::error!("Invalid payment intent id {} {:?}", payment_intent_id, e);
+        UNHANDLED
+    })?);
+    // Only this purchase's share, for a product of a cart checkout
+    params.amount = Some(purchase.amount_total);
+    params.reason = Some(RefundReasonFilter::RequestedByCustomer);
+    params.metadata = Some(HashMap::from([(
+        PURCHASE_ID_METADATA_KEY.to_string(),
+        purchase.checkout_session_id.clone(),
+    )]));
+    // Retrying after Stripe made the refund, but before it was saved on the request, mustn't
+    // refund twice
+    let stripe_client = stripe_client()?;
+    let idempotent_client = (*stripe_client)
+        .clone()
+        .with_strategy(RequestStrategy::Idempotent(format!(
+            "refund-{}",
+            purchase.checkout_session_id
+        )));
+    Refund::create(&idempotent_client, params)
+        .await
+        .map_err(|e| {
+            log::error!(
+                "Could not refund purchase {} {:?}",
+                purchase.checkout_session_id,
+                e
+            );
+            UNHANDLED
+        })
+}
+
+/// Gives the buyer their money back through Stripe, then takes the purchase away through the
+/// same path as refunds made on the dashboard. The request has to be
+/// [`RefundRequestStatus::Refunding`], and finishing one that failed partway never refunds twice.
+async fn refund(
+    purchase: &Purchase,
+    request: &RefundRequest,
+) -> Result<(), ServerFnError<NexusError>> {
+    let dynamodb_client = dynamo_client()?;
+    let stripe_refund_id = if needs_stripe_refund(purchase, request) {
+        let stripe_refund = create_stripe_refund(purchase).await?;
+        // Saved before anything else can fail, so a retry knows the money was given back
+        set_refund_request_status(
+            &dynamodb_client,
+            &purchase.checkout_session_id,
+            RefundRequestStatus::Refunding,
+            RefundRequestStatus::Refunding,
+            RefundRequestUpdate {
+                stripe_refund_id: Some(stripe_refund.id.as_str()),
+                ..Default::default()
+            },
+        )
+        .await?;
+        Some(stripe_refund.id.to_string())
+    } else {
+        request.stripe_refund_id.clone()
+    };
+    let stripe_client = stripe_client()?;
+    let repository = user_repository()?;
+    let ses_client = ses_client()?;
+    let detail = match &stripe_refund_id {
+        Some(stripe_refund_id) => format!(
+            "Refund {} of {} was requested by the buyer",
+            stripe_refund_id,
+            format_amount(purchase.amount_total, &purchase.currency)
+        ),
+        None => "It was refunded before the buyer's refund request was granted".to_string(),
+    };
+    let applied = apply_purchase_change(
+        &dynamodb_client,
+        repository.as_ref(),
+        &ses_client,
+        &stripe_client,
+        purchase,
+        PaymentChange::Refunded,
+        &detail,
+    )
+    .await;
+    if let Err(ServerFnError::WrappedServerError(NexusError::ConcurrentModification)) = applied {
+        // Stripe's webhook for the refund got there first. Going again from where it left the
+        // purchase finishes whatever it didn't.
+        let current = get_purchase(&dynamodb_client, &purchase.checkout_session_id)
+            .await?
+            .ok_or_else(|| ServerFnError::from(NexusError::PurchaseNotFound))?;
+        apply_purchase_change(
+            &dynamodb_client,
+            repository.as_ref(),
+            &ses_client,
+            &stripe_client,
+            &current,
+            PaymentChange::Refunded,
+            &detail,
+        )
+        .await?;
+    } else {
+        applied?;
+    }
+    set_refund_request_status(
+        &dynamodb_client,
+        &purchase.checkout_session_id,
+        RefundRequestStatus::Refunding,
+        RefundRequestStatus::Refunded,
+        RefundRequestUpdate {
+            stripe_refund_id: stripe_refund_id.as_deref(),
+            ..Default::default()
+        },
+    )
+    .await?;
+    email_buyer(
+        &purchase.email,
+        "Your refund is on its way",
+        &format!(
+            "Hello,
+We've refunded {} for {}. It usually takes 5 to 10 days to show up on your statement.",
+            format_amount(purchase.amount_total, &purchase.currency),
+            escape_html(&product_title(&purchase.product_id))
+        ),
+    )
+    .await;
+    log::info!(
+        "Refunded purchase {} with {:?}",
+        purchase.checkout_session_id,
+        stripe_refund_id
+    );
+    Ok(())
+}
+
+/// Asks for the money back for one of the logged in user's purchases. Requests within the
+/// [`REFUND_POLICY`] are refunded right away, the rest wait for an admin.
+pub async fn request_refund(
+    order_id: String,
+    reason: String,
+) -> Result<RefundOutcome, ServerFnError<NexusError>> {
+    let repository = user_repository()?;
+    let user = logged_in_user(repository.as_ref()).await?;
+    let dynamodb_client = dynamo_client()?;
+    REQUEST_REFUND_RATE_LIMIT
+        .check(&dynamodb_client, &user.user_uuid)
+        .await?;
+    let purchase = get_purchase(&dynamodb_client, &order_id)
+        .await?
+        .filter(|purchase| purchase.user_uuid == user.user_uuid)
+        .ok_or_else(|| {
+            log::error!(
+                "{} asked for a refund of {}, which isn't theirs",
+                user.user_uuid,
+                order_id
+            );
+            ServerFnError::from(NexusError::PurchaseNotFound)
+        })?;
+    if let Some(existing) = get_refund_request(&dynamodb_client, &order_id).await? {
+        // A refund that failed partway is finished, anything else has been dealt with
+        if existing.status != RefundRequestStatus::Refunding {
+            return Err(ServerFnError::from(NexusError::RefundAlreadyRequested));
+        }
+        refund(&purchase, &existing).await?;
+        return Ok(RefundOutcome::Refunded);
+    }
+    if !is_refundable(&purchase) {
+        return Err(ServerFnError::from(NexusError::NotRefundable));
+    }
+    let mut usage = Vec::new();
+    for entitlement in entitlements_for(&purchase.product_id) {
+        usage.push(get_download_stats(&dynamodb_client, &user.user_uuid, &entitlement).await?);
+    }
+    let now = Utc::now().timestamp();
+    let ineligibility = REFUND_POLICY.check(&purchase, &DownloadStats::combine(usage), now);
+    let request = RefundRequest {
+        purchase_id: purchase.checkout_session_id.clone(),
+        user_uuid: user.user_uuid.clone(),
+        email: user.email.clone(),
+        product_id: purchase.product_id.clone(),
+        amount: purchase.amount_total,
+        currency: purchase.currency.clone(),
+        reason: reason
+            .trim()
+            .chars()
+            .take(REFUND_REASON_MAX_LENGTH)
+            .collect(),
+        ineligibility: ineligibility.map(|ineligibility| ineligibility.describe()),
+        status: match ineligibility {
+            Some(_) => RefundRequestStatus::PendingReview,
+            None => RefundRequestStatus::Refunding,
+        },
+        requested_at: now,
+        reviewed_by: None,
+        reviewed_at: None,
+        stripe_refund_id: None,
+    };
+    if !create_refund_request(&dynamodb_client, &request).await? {
+        return Err(ServerFnError::from(NexusError::RefundAlreadyRequested));
+    }
+    let Some(ineligibility) = ineligibility else {
+        refund(&purchase, &request).await?;
+        return Ok(RefundOutcome::Refunded);
+    };
+    let ses_client = ses_client()?;
+    notify_admins(
+        &ses_client,
+        &format!("Refund request for {}", product_title(&purchase.product_id)),
+        &format!(
+            "{} asked for a refund of purchase {} ({}), which needs a review: {}.<br>\
+             Their reason: {}",
+            user.email,
+            purchase.checkout_session_id,
+            format_amount(purchase.amount_total, &purchase.currency),
+            ineligibility.describe(),
+            escape_html(&request.reason)
+        ),
+    )
+    .await;
+    Ok(RefundOutcome::QueuedForReview)
+}
+
+/// Refund requests waiting for an admin, oldest first
+pub async fn list_refund_requests() -> Result<Vec<RefundRequest>, ServerFnError<NexusError>> {
+    current_admin().await?;
+    let dynamodb_client = dynamo_client()?;
+    list_refund_requests_by_status(&dynamodb_client, RefundRequestStatus::PendingReview).await
+}
+
+/// Grants or turns down a refund request waiting for review. Approving a request whose refund
+/// failed partway finishes it.
+pub async fn review_refund_request(
+    purchase_id: String,
+    approve: bool,
+) -> Result<RefundRequestStatus, ServerFnError<NexusError>> {
+    let admin = current_admin().await?;
+    let dynamodb_client = dynamo_client()?;
+    let request = get_refund_request(&dynamodb_client, &purchase_id)
+        .await?
+        .ok_or_else(|| ServerFnError::from(NexusError::RefundRequestNotFound))?;
+    let purchase = get_purchase(&dynamodb_client, &purchase_id)
+        .await?
+        .ok_or_else(|| ServerFnError::from(NexusError::PurchaseNotFound))?;
+    let review = RefundRequestUpdate {
+        reviewed_by: Some(&admin),
+        reviewed_at: Some(Utc::now().timestamp()),
+        ..Default::default()
+    };
+    match (request.status, approve) {
+        (RefundRequestStatus::PendingReview, true) => {
+            if !is_refundable(&purchase) {
+                return Err(ServerFnError::from(NexusError::NotRefundable));
+            }
+            if !set_refund_request_status(
+                &dynamodb_client,
+                &purchase_id,
+                RefundRequestStatus::PendingReview,
+                RefundRequestStatus::Refunding,
+                review,
+            )
+            .await?
+            {
+                return Err(ServerFnError::from(NexusError::ConcurrentModification));
+            }
+            refund(&purchase, &request).await?;
+        }
+        (RefundRequestStatus::Refunding, true) => refund(&purchase, &request).await?,
+        (RefundRequestStatus::PendingReview, false) => {
+            if !set_refund_request_status(
+                &dynamodb_client,
+                &purchase_id,
+                RefundRequestStatus::PendingReview,
+                RefundRequestStatus::Denied,
+                review,
+            )
+            .await?
+            {
+                return Err(ServerFnError::from(NexusError::ConcurrentModification));
+            }
+            email_buyer(
+                &purchase.email,
+                "About your refund request",
+                &format!(
+                    "Hello,
+We've looked at your request for a refund of {}, and unfortunately it falls outside our \
+refund policy. If you think we got this wrong, reply to this email.",
+                    escape_html(&product_title(&purchase.product_id))
+                ),
+            )
+            .await;
+        }
+        (status, _) => {
+            log::error!(
+                "{} tried to review refund request {}, which is {}",
+                admin,
+                purchase_id,
+                status.as_str()
+            );
+            return Err(ServerFnError::from(NexusError::RefundRequestNotFound));
+        }
+    }
+    log::info!(
+        "{} {} the refund request for {}",
+        admin,
+        if approve { "approved" } else { "denied" },
+        purchase_id
+    );
+    Ok(match approve {
+        true => RefundRequestStatus::Refunded,
+        false => RefundRequestStatus::Denied,
+    })
+}
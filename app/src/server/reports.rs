@@ -0,0 +1,222 @@
+use super::{
+    admin::current_admin,
+    repository::purchases::{list_purchases_created_between, Purchase, PurchaseStatus},
+    utilities::dynamo_client,
+};
+use crate::{
+    errors::{NexusError, UNHANDLED},
+    orders::{CurrencySales, ReportPeriod, SalesFigures, SalesReport, SalesReportRow},
+};
+use chrono::{DateTime, Days, NaiveDate, Utc};
+use leptos::ServerFnError;
+use std::collections::BTreeMap;
+
+const DAY_SECONDS: i64 = 60 * 60 * 24;
+
+/// How many days a report covers when no start date is given
+pub const DEFAULT_REPORT_DAYS: u64 = 30;
+
+/// The start of the day or week `timestamp` falls in
+pub fn period_start(period: ReportPeriod, timestamp: i64) -> i64 {
+    let day = timestamp.div_euclid(DAY_SECONDS);
+    let first_day = match period {
+        ReportPeriod::Daily => day,
+        // 1970-01-01 was a Thursday, three days after a Monday
+        ReportPeriod::Weekly => day - (day + 3).rem_euclid(7),
+    };
+    first_day * DAY_SECONDS
+}
+
+/// The timestamps a report from one `YYYY-MM-DD` date to another (both included) starts at and
+/// ends before. Without an end date the report runs to the end of `today`, and without a start
+/// date it covers [`DEFAULT_REPORT_DAYS`].
+pub fn report_range(from: &str, to: &str, today: NaiveDate) -> Result<(i64, i64), NexusError> {
+    let parse = |date: &str| {
+        NaiveDate::parse_from_str(date.trim(), "%Y-%m-%d").map_err(|_| {
+            log::error!("Report date {} is not a YYYY-MM-DD date", date);
+            NexusError::ReportRangeInvalid
+        })
+    };
+    let last_day = match to.trim() {
+        "" => today,
+        to => parse(to)?,
+    };
+    let first_day = match from.trim() {
+        "" => last_day
+            .checked_sub_days(Days::new(DEFAULT_REPORT_DAYS - 1))
+            .ok_or(NexusError::ReportRangeInvalid)?,
+        from => parse(from)?,
+    };
+    if first_day > last_day {
+        return Err(NexusError::ReportRangeInvalid);
+    }
+    let midnight = |date: NaiveDate| {
+        date.and_hms_opt(0, 0, 0)
+            .map(|time| time.and_utc().timestamp())
+    };
+    let start = midnight(first_day).ok_or(NexusError::ReportRangeInvalid)?;
+    let end = last_day
+        .checked_add_days(Days::new(1))
+        .and_then(midnight)
+        .ok_or(NexusError::ReportRangeInvalid)?;
+    Ok((start, end))
+}
+
+/// Adds a purchase that was paid for to the figures
+fn add_purchase(figures: &mut SalesFigures, purchase: &Purchase) {
+    figures.purchases += 1;
+    figures.gross += purchase.amount_total;
+    figures.tax += purchase.amount_tax;
+    figures.discounts += purchase.amount_discount;
+    match purchase.status {
+        PurchaseStatus::Refunded => figures.refunds += 1,
+        PurchaseStatus::Disputed | PurchaseStatus::DisputeLost => figures.disputes += 1,
+        PurchaseStatus::Paid | PurchaseStatus::FraudWarning => {
+            figures.net += purchase.amount_total - purchase.amount_tax
+        }
+        PurchaseStatus::Pending | PurchaseStatus::Failed => {}
+    }
+}
+
+/// Sums up the purchases made from `from` up to (not including) `to`. Purchases whose payment
+/// never arrived aren't sales, and neither are redeemed keys, which were never charged for.
+pub fn sales_report(
+    purchases: &[Purchase],
+    period: ReportPeriod,
+    from: i64,
+    to: i64,
+) -> SalesReport {
+    let mut rows: BTreeMap<(i64, &str, &str, &str), SalesFigures> = BTreeMap::new();
+    let mut totals: BTreeMap<&str, SalesFigures> = BTreeMap::new();
+    let sales = purchases.iter().filter(|purchase| {
+        (from..to).contains(&purchase.created_at)
+            && !purchase.currency.is_empty()
+            && !matches!(
+                purchase.status,
+                PurchaseStatus::Pending | PurchaseStatus::Failed
+            )
+    });
+    for purchase in sales {
+        let key = (
+            period_start(period, purchase.created_at),
+            purchase.product_id.as_str(),
+            purchase.currency.as_str(),
+            purchase.country.as_deref().unwrap_or_default(),
+        );
+        add_purchase(rows.entry(key).or_default(), purchase);
+        add_purchase(
+            totals.entry(purchase.currency.as_str()).or_default(),
+            purchase,
+        );
+    }
+    SalesReport {
+        period,
+        from,
+        to,
+        rows: rows
+            .into_iter()
+            .map(
+                |((period_start, product_id, currency, country), figures)| SalesReportRow {
+                    period_start,
+                    product_id: product_id.to_string(),
+                    currency: currency.to_string(),
+                    country: country.to_string(),
+                    figures,
+                },
+            )
+            .collect(),
+        totals: totals
+            .into_iter()
+            .map(|(currency, figures)| CurrencySales {
+                currency: currency.to_string(),
+                figures,
+            })
+            .collect(),
+    }
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// One line per report row. Amounts are in the currency's smallest unit, like in the ledger.
+pub fn sales_report_csv(report: &SalesReport) -> String {
+    let mut csv = "period_start,product_id,currency,country,purchases,gross,tax,discounts,net,\
+                   refunds,refund_rate,disputes,dispute_rate\n"
+        .to_string();
+    for row in &report.rows {
+        let figures = &row.figures;
+        let period_start = DateTime::from_timestamp(row.period_start, 0)
+            .map(|date| date.format("%Y-%m-%d").to_string())
+            .unwrap_or_default();
+        csv.push_str(&format!(
+            "{},{},{},{},{},{},{},{},{},{},{:.4},{},{:.4}\n",
+            period_start,
+            csv_field(&row.product_id),
+            csv_field(&row.currency),
+            csv_field(&row.country),
+            figures.purchases,
+            figures.gross,
+            figures.tax,
+            figures.discounts,
+            figures.net,
+            figures.refunds,
+            figures.refund_rate(),
+            figures.disputes,
+            figures.dispute_rate(),
+        ));
+    }
+    csv
+}
+
+/// A report of the sales from one `YYYY-MM-DD` date to another, grouped `daily` (if empty) or
+/// `weekly`
+pub async fn get_sales_report(
+    from: String,
+    to: String,
+    period: String,
+) -> Result<SalesReport, ServerFnError<NexusError>> {
+    let admin = current_admin().await?;
+    let period = match period.as_str() {
+        "" => ReportPeriod::default(),
+        period => ReportPeriod::parse(period).ok_or_else(|| {
+            log::error!("Unknown report period {}", period);
+            UNHANDLED
+        })?,
+    };
+    let (from, to) = report_range(&from, &to, Utc::now().date_naive())?;
+    log::info!(
+        "{} is running a sales report from {} to {}",
+        admin,
+        from,
+        to
+    );
+    let dynamodb_client = dynamo_client()?;
+    let purchases = list_purchases_created_between(&dynamodb_client, from, to).await?;
+    Ok(sales_report(&purchases, period, from, to))
+}
+
+/// [`get_sales_report`] as a `csv` (if `format` is empty) or `json` document
+pub async fn export_sales_report(
+    from: String,
+    to: String,
+    period: String,
+    format: String,
+) -> Result<String, ServerFnError<NexusError>> {
+    let report = get_sales_report(from, to, period).await?;
+    match format.as_str() {
+        "" | "csv" => Ok(sales_report_csv(&report)),
+        "json" => serde_json::to_string_pretty(&report).map_err(|e| {
+            log::error!("Could not serialize sales report {:?}", e);
+            UNHANDLED
+        }),
+        format => {
+            log::error!("Unknown report format {}", format);
+            Err(UNHANDLED)
+        }
+    }
+}
@@ -0,0 +1,107 @@
+use super::super::{
+    globals::{
+        dynamo::constants::cart_attributes::{CART_ID, EXPIRES_AT, PRODUCT_IDS, UPDATED_AT},
+        dynamo_error::{send_with_retry, DynamoErrorKind, DynamoOperation},
+        env_var::get_carts_table_name,
+    },
+    utilities::handle_dynamo_generic_error,
+};
+use crate::errors::NexusError;
+use aws_sdk_dynamodb::{types::AttributeValue, Client as DynamoClient};
+use leptos::ServerFnError;
+
+/// The ids of the products in a cart, sorted. A cart nobody has added to is empty.
+pub async fn get_cart(
+    client: &DynamoClient,
+    cart_id: &str,
+) -> Result<Vec<String>, ServerFnError<NexusError>> {
+    let get_item = client
+        .get_item()
+        .table_name(get_carts_table_name())
+        .key(CART_ID, AttributeValue::S(cart_id.to_string()))
+        .consistent_read(true);
+    let db_result = send_with_retry(
+        DynamoOperation::read("get_cart", get_carts_table_name()),
+        || get_item.clone().send(),
+    )
+    .await;
+
+    match db_result {
+        Ok(o) => {
+            let mut product_ids = match o.item.as_ref().and_then(|item| item.get(PRODUCT_IDS)) {
+                Some(AttributeValue::Ss(product_ids)) => product_ids.clone(),
+                _ => Vec::new(),
+            };
+            product_ids.sort();
+            Ok(product_ids)
+        }
+        Err(e) => Err(handle_dynamo_generic_error(e)),
+    }
+}
+
+/// Adds products to a cart, creating it if need be. The cart is deleted by the table's TTL some
+/// time after `expires_at`, which every change pushes back.
+pub async fn add_to_cart(
+    client: &DynamoClient,
+    cart_id: &str,
+    product_ids: &[String],
+    now: i64,
+    expires_at: i64,
+) -> Result<(), ServerFnError<NexusError>> {
+    let update = client
+        .update_item()
+        .table_name(get_carts_table_name())
+        .key(CART_ID, AttributeValue::S(cart_id.to_string()))
+        .update_expression(
+            "ADD #product_ids :product_ids SET #updated_at = :now, #expires_at = :expires_at",
+        )
+        .expression_attribute_names("#product_ids", PRODUCT_IDS)
+        .expression_attribute_names("#updated_at", UPDATED_AT)
+        .expression_attribute_names("#expires_at", EXPIRES_AT)
+        .expression_attribute_values(":product_ids", AttributeValue::Ss(product_ids.to_vec()))
+        .expression_attribute_values(":now", AttributeValue::N(now.to_string()))
+        .expression_attribute_values(":expires_at", AttributeValue::N(expires_at.to_string()));
+    let update_result = send_with_retry(
+        DynamoOperation::write("add_to_cart", get_carts_table_name()),
+        || update.clone().send(),
+    )
+    .await;
+
+    match update_result {
+        Ok(_) => Ok(()),
+        Err(e) => Err(handle_dynamo_generic_error(e)),
+    }
+}
+
+/// Takes products out of a cart. Ones that aren't in it are ignored, and a cart left empty
+/// loses its product set (DynamoDB doesn't keep empty sets).
+pub async fn remove_from_cart(
+    client: &DynamoClient,
+    cart_id: &str,
+    product_ids: &[String],
+    now: i64,
+) -> Result<(), ServerFnError<NexusError>> {
+    let update = client
+        .update_item()
+        .table_name(get_carts_table_name())
+        .key(CART_ID, AttributeValue::S(cart_id.to_string()))
+        .update_expression("DELETE #product_ids :product_ids SET #updated_at = :now")
+        .condition_expression("attribute_exists(#cart_id)")
+        .expression_attribute_names("#product_ids", PRODUCT_IDS)
+        .expression_attribute_names("#updated_at", UPDATED_AT)
+        .expression_attribute_names("#cart_id", CART_ID)
+        .expression_attribute_values(":product_ids", AttributeValue::Ss(product_ids.to_vec()))
+        .expression_attribute_values(":now", AttributeValue::N(now.to_string()));
+    let update_result = send_with_retry(
+        DynamoOperation::write("remove_from_cart", get_carts_table_name()),
+        || update.clone().send(),
+    )
+    .await;
+
+    match update_result {
+        Ok(_) => Ok(()),
+        // There's no cart, so nothing to take out of it
+        Err(e) if e.kind == DynamoErrorKind::ConditionalCheckFailed => Ok(()),
+        Err(e) => Err(handle_dynamo_generic_error(e)),
+    }
+}
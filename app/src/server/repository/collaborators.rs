@@ -0,0 +1,183 @@
+use super::super::{
+    globals::{
+        dynamo::{
+            constants::collaborator_attributes::{
+                COLLABORATOR_ID, CREATED_AT, EMAIL, NAME, STRIPE_ACCOUNT_ID, TRANSFERS_ENABLED,
+            },
+            number_attribute, string_attribute,
+        },
+        dynamo_error::{send_with_retry, DynamoErrorKind, DynamoOperation},
+        env_var::get_collaborators_table_name,
+    },
+    utilities::handle_dynamo_generic_error,
+};
+use crate::{errors::NexusError, orders::Collaborator};
+use aws_sdk_dynamodb::{types::AttributeValue, Client as DynamoClient};
+use leptos::ServerFnError;
+use std::collections::HashMap;
+
+pub fn collaborator_to_item(collaborator: &Collaborator) -> HashMap<String, AttributeValue> {
+    HashMap::from([
+        (
+            COLLABORATOR_ID.to_string(),
+            AttributeValue::S(collaborator.collaborator_id.clone()),
+        ),
+        (
+            NAME.to_string(),
+            AttributeValue::S(collaborator.name.clone()),
+        ),
+        (
+            EMAIL.to_string(),
+            AttributeValue::S(collaborator.email.clone()),
+        ),
+        (
+            STRIPE_ACCOUNT_ID.to_string(),
+            AttributeValue::S(collaborator.stripe_account_id.clone()),
+        ),
+        (
+            TRANSFERS_ENABLED.to_string(),
+            AttributeValue::Bool(collaborator.transfers_enabled),
+        ),
+        (
+            CREATED_AT.to_string(),
+            AttributeValue::N(collaborator.created_at.to_string()),
+        ),
+    ])
+}
+
+pub fn parse_collaborator(
+    item: &HashMap<String, AttributeValue>,
+) -> Result<Collaborator, ServerFnError<NexusError>> {
+    let required_string = |name: &str| {
+        string_attribute(item, name)?.ok_or_else(|| {
+            log::error!("Collaborator is missing {}", name);
+            NexusError::Unhandled
+        })
+    };
+    Ok(Collaborator {
+        collaborator_id: required_string(COLLABORATOR_ID)?,
+        name: required_string(NAME)?,
+        email: required_string(EMAIL)?,
+        stripe_account_id: required_string(STRIPE_ACCOUNT_ID)?,
+        transfers_enabled: matches!(
+            item.get(TRANSFERS_ENABLED),
+            Some(AttributeValue::Bool(true))
+        ),
+        created_at: number_attribute(item, CREATED_AT)?.unwrap_or(0),
+    })
+}
+
+/// Adds a collaborator unless one with the same id exists, returning whether it was added
+pub async fn create_collaborator(
+    client: &DynamoClient,
+    collaborator: &Collaborator,
+) -> Result<bool, ServerFnError<NexusError>> {
+    let put = client
+        .put_item()
+        .table_name(get_collaborators_table_name())
+        .set_item(Some(collaborator_to_item(collaborator)))
+        .condition_expression(format!("attribute_not_exists({})", COLLABORATOR_ID));
+    let put_result = send_with_retry(
+        DynamoOperation::write("create_collaborator", get_collaborators_table_name()),
+        || put.clone().send(),
+    )
+    .await;
+
+    match put_result {
+        Ok(_) => Ok(true),
+        Err(e) if e.kind == DynamoErrorKind::ConditionalCheckFailed => Ok(false),
+        Err(e) => Err(handle_dynamo_generic_error(e)),
+    }
+}
+
+pub async fn get_collaborator(
+    client: &DynamoClient,
+    collaborator_id: &str,
+) -> Result<Option<Collaborator>, ServerFnError<NexusError>> {
+    let get_item = client
+        .get_item()
+        .table_name(get_collaborators_table_name())
+        .key(
+            COLLABORATOR_ID,
+            AttributeValue::S(collaborator_id.to_string()),
+        )
+        .consistent_read(true);
+    let db_result = send_with_retry(
+        DynamoOperation::read("get_collaborator", get_collaborators_table_name()),
+        || get_item.clone().send(),
+    )
+    .await;
+
+    match db_result {
+        Ok(o) => o.item.as_ref().map(parse_collaborator).transpose(),
+        Err(e) => Err(handle_dynamo_generic_error(e)),
+    }
+}
+
+/// Every collaborator, by id. There are few enough that a scan is fine.
+pub async fn list_collaborators(
+    client: &DynamoClient,
+) -> Result<Vec<Collaborator>, ServerFnError<NexusError>> {
+    let mut collaborators = Vec::new();
+    let mut exclusive_start_key = None;
+    loop {
+        let scan = client
+            .scan()
+            .table_name(get_collaborators_table_name())
+            .set_exclusive_start_key(exclusive_start_key.clone());
+        let db_result = send_with_retry(
+            DynamoOperation::read("list_collaborators", get_collaborators_table_name()),
+            || scan.clone().send(),
+        )
+        .await;
+        let output = match db_result {
+            Ok(o) => o,
+            Err(e) => return Err(handle_dynamo_generic_error(e)),
+        };
+        for item in output.items() {
+            collaborators.push(parse_collaborator(item)?);
+        }
+        exclusive_start_key = output.last_evaluated_key;
+        if exclusive_start_key.is_none() {
+            break;
+        }
+    }
+    collaborators.sort_by(|a, b| a.collaborator_id.cmp(&b.collaborator_id));
+    Ok(collaborators)
+}
+
+/// Records whether the collaborator's connected account can be transferred to
+pub async fn set_transfers_enabled(
+    client: &DynamoClient,
+    collaborator_id: &str,
+    transfers_enabled: bool,
+) -> Result<(), ServerFnError<NexusError>> {
+    let update = client
+        .update_item()
+        .table_name(get_collaborators_table_name())
+        .key(
+            COLLABORATOR_ID,
+            AttributeValue::S(collaborator_id.to_string()),
+        )
+        .update_expression("SET #transfers_enabled = :transfers_enabled")
+        .condition_expression("attribute_exists(#collaborator_id)")
+        .expression_attribute_names("#transfers_enabled", TRANSFERS_ENABLED)
+        .expression_attribute_names("#collaborator_id", COLLABORATOR_ID)
+        .expression_attribute_values(
+            ":transfers_enabled",
+            AttributeValue::Bool(transfers_enabled),
+        );
+    let update_result = send_with_retry(
+        DynamoOperation::write("set_transfers_enabled", get_collaborators_table_name()),
+        || update.clone().send(),
+    )
+    .await;
+
+    match update_result {
+        Ok(_) => Ok(()),
+        Err(e) if e.kind == DynamoErrorKind::ConditionalCheckFailed => {
+            Err(ServerFnError::from(NexusError::CollaboratorNotFound))
+        }
+        Err(e) => Err(handle_dynamo_generic_error(e)),
+    }
+}
@@ -0,0 +1,111 @@
+use super::super::{
+    globals::{
+        dynamo::{
+            constants::download_attributes::{
+                DOWNLOAD_COUNT, DOWNLOAD_ID, FIRST_DOWNLOADED_AT, LAST_DOWNLOADED_AT,
+            },
+            number_attribute,
+        },
+        dynamo_error::{send_with_retry, DynamoOperation},
+        env_var::get_downloads_table_name,
+    },
+    utilities::handle_dynamo_generic_error,
+};
+use crate::errors::NexusError;
+use aws_sdk_dynamodb::{types::AttributeValue, Client as DynamoClient};
+use leptos::ServerFnError;
+
+/// How much an account has used a game. Downloads are all we know about: the launcher doesn't
+/// report playtime.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DownloadStats {
+    pub download_count: i64,
+    pub first_downloaded_at: Option<i64>,
+}
+
+impl DownloadStats {
+    /// The stats of several games together, e.g. the items of a bundle
+    pub fn combine(stats: impl IntoIterator<Item = DownloadStats>) -> DownloadStats {
+        stats
+            .into_iter()
+            .fold(DownloadStats::default(), |total, stats| DownloadStats {
+                download_count: total.download_count + stats.download_count,
+                first_downloaded_at: total
+                    .first_downloaded_at
+                    .into_iter()
+                    .chain(stats.first_downloaded_at)
+                    .min(),
+            })
+    }
+}
+
+fn download_id(user_uuid: &str, product_id: &str) -> String {
+    format!("{}#{}", user_uuid, product_id)
+}
+
+/// Counts a download of `product_id` by `user_uuid`
+pub async fn record_download(
+    client: &DynamoClient,
+    user_uuid: &str,
+    product_id: &str,
+    now: i64,
+) -> Result<(), ServerFnError<NexusError>> {
+    let update = client
+        .update_item()
+        .table_name(get_downloads_table_name())
+        .key(
+            DOWNLOAD_ID,
+            AttributeValue::S(download_id(user_uuid, product_id)),
+        )
+        .update_expression(
+            "ADD #download_count :one SET #first = if_not_exists(#first, :now), #last = :now",
+        )
+        .expression_attribute_names("#download_count", DOWNLOAD_COUNT)
+        .expression_attribute_names("#first", FIRST_DOWNLOADED_AT)
+        .expression_attribute_names("#last", LAST_DOWNLOADED_AT)
+        .expression_attribute_values(":one", AttributeValue::N("1".to_string()))
+        .expression_attribute_values(":now", AttributeValue::N(now.to_string()));
+    let update_result = send_with_retry(
+        DynamoOperation::write("record_download", get_downloads_table_name()),
+        || update.clone().send(),
+    )
+    .await;
+
+    match update_result {
+        Ok(_) => Ok(()),
+        Err(e) => Err(handle_dynamo_generic_error(e)),
+    }
+}
+
+/// How much `user_uuid` has used `product_id`. Nothing recorded means it was never downloaded.
+pub async fn get_download_stats(
+    client: &DynamoClient,
+    user_uuid: &str,
+    product_id: &str,
+) -> Result<DownloadStats, ServerFnError<NexusError>> {
+    let get_item = client
+        .get_item()
+        .table_name(get_downloads_table_name())
+        .key(
+            DOWNLOAD_ID,
+            AttributeValue::S(download_id(user_uuid, product_id)),
+        )
+        .consistent_read(true);
+    let db_result = send_with_retry(
+        DynamoOperation::read("get_download_stats", get_downloads_table_name()),
+        || get_item.clone().send(),
+    )
+    .await;
+
+    let item = match db_result {
+        Ok(o) => o.item,
+        Err(e) => return Err(handle_dynamo_generic_error(e)),
+    };
+    let Some(item) = item else {
+        return Ok(DownloadStats::default());
+    };
+    Ok(DownloadStats {
+        download_count: number_attribute(&item, DOWNLOAD_COUNT)?.unwrap_or(0),
+        first_downloaded_at: number_attribute(&item, FIRST_DOWNLOADED_AT)?,
+    })
+}
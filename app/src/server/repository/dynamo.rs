@@ -0,0 +1,472 @@
+use super::{
+    super::{
+        globals::{
+            dynamo::{
+                constants::{
+                    index::USER_UUID_INDEX,
+                    table_attributes::{
+                        ACCOUNT_CREATION_TIME, DISPLAY_NAME, EMAIL,
+                        EMAIL_VERIFICATION_REQUEST_TIME, EMAIL_VERIFICATION_UUID, EMAIL_VERIFIED,
+                        GAMES_BOUGHT, PASSWORD, SESSION_EXPIRY, SESSION_ID, STRIPE_CUSTOMER_ID,
+                        USER_UUID, VERSION,
+                    },
+                },
+                parse_bool_attribute, parse_list_of_strings_attribute, parse_number_attribute,
+                parse_string_attribute, query_setup, TableAttributeType, TableKeyType,
+            },
+            dynamo_error::{send_with_retry, DynamoErrorKind, DynamoOperation},
+            env_var::get_table_name,
+        },
+        utilities::{
+            extract_email_from_query, extract_email_verification_request_time_from_query,
+            handle_dynamo_generic_error,
+        },
+    },
+    users::{get_user, update_user, update_user_with_retry, UserItem, UserUpdate},
+    BillingProfile, Credentials, NewUser, Session, UserRepository, VerificationToken,
+};
+use crate::errors::{NexusError, UNHANDLED};
+use async_trait::async_trait;
+use aws_sdk_dynamodb::{types::AttributeValue, Client as DynamoClient};
+use leptos::ServerFnError;
+use std::sync::Arc;
+
+/// Users, sessions, entitlements and verification tokens all live on a single row of the
+/// Users table, keyed by email.
+pub struct DynamoUserRepository {
+    client: Arc<DynamoClient>,
+}
+
+impl DynamoUserRepository {
+    pub fn new(client: Arc<DynamoClient>) -> Self {
+        Self { client }
+    }
+
+    /// Every row with this uuid, of which there's more than one while an email change is
+    /// unfinished
+    async fn rows_of_account(
+        &self,
+        user_uuid: &str,
+    ) -> Result<Vec<UserItem>, ServerFnError<NexusError>> {
+        let query = self
+            .client
+            .query()
+            .table_name(get_table_name())
+            .index_name(USER_UUID_INDEX)
+            .key_condition_expression("#user_uuid = :user_uuid")
+            .expression_attribute_names("#user_uuid", USER_UUID)
+            .expression_attribute_values(":user_uuid", AttributeValue::S(user_uuid.to_string()));
+        let db_result = send_with_retry(
+            DynamoOperation::read("rows_of_account", get_table_name()),
+            || query.clone().send(),
+        )
+        .await;
+
+        match db_result {
+            Ok(o) => Ok(o.items.unwrap_or_default()),
+            Err(e) => Err(handle_dynamo_generic_error(e)),
+        }
+    }
+}
+
+#[async_trait]
+impl UserRepository for DynamoUserRepository {
+    async fn create_user(&self, user: NewUser) -> Result<(), ServerFnError<NexusError>> {
+        let check_email_not_exists_expression = format!("attribute_not_exists({})", EMAIL);
+        let put = self
+            .client
+            .put_item()
+            .table_name(get_table_name())
+            .item(DISPLAY_NAME, AttributeValue::S(user.display_name))
+            .item(EMAIL, AttributeValue::S(user.email))
+            .item(PASSWORD, AttributeValue::S(user.hashed_password))
+            .item(USER_UUID, AttributeValue::S(user.user_uuid))
+            .item(VERSION, AttributeValue::N("0".to_string()))
+            .item(EMAIL_VERIFIED, AttributeValue::Bool(false))
+            .item(
+                EMAIL_VERIFICATION_UUID,
+                AttributeValue::S(user.email_verification_uuid),
+            )
+            .item(
+                EMAIL_VERIFICATION_REQUEST_TIME,
+                AttributeValue::N(user.account_creation_time.to_string()),
+            )
+            .item(
+                ACCOUNT_CREATION_TIME,
+                AttributeValue::N(user.account_creation_time.to_string()),
+            )
+            .condition_expression(check_email_not_exists_expression);
+        let db_result = send_with_retry(
+            DynamoOperation::write("create_user", get_table_name()),
+            || put.clone().send(),
+        )
+        .await;
+
+        match db_result {
+            Ok(_) => Ok(()),
+            Err(e) if e.kind == DynamoErrorKind::ConditionalCheckFailed => {
+                Err(ServerFnError::from(NexusError::BadUsernameEmailCombination))
+            }
+            Err(e) => Err(handle_dynamo_generic_error(e)),
+        }
+    }
+
+    async fn find_credentials(
+        &self,
+        email: &str,
+    ) -> Result<Option<Credentials>, ServerFnError<NexusError>> {
+        let item = match get_user(&self.client, email).await? {
+            Some(item) => item,
+            None => return Ok(None),
+        };
+        let hashed_password = match item.get(PASSWORD) {
+            Some(hash) => hash.as_s().map_err(|e| {
+                log::error!("Could not get password hash as string {:?}", e);
+                UNHANDLED
+            })?,
+            // Rows without a password can't be logged into
+            None => return Ok(None),
+        };
+        let email_verified = parse_bool_attribute(&item, &TableAttributeType::EmailVerified)?
+            .ok_or_else(|| {
+                log::error!("Was not able to get whether or not this email is verified");
+                UNHANDLED
+            })?;
+        Ok(Some(Credentials {
+            hashed_password: hashed_password.to_owned(),
+            email_verified,
+        }))
+    }
+
+    async fn start_session(
+        &self,
+        email: &str,
+        expected_hashed_password: &str,
+        session_id: &str,
+        session_expiry: i64,
+    ) -> Result<(), ServerFnError<NexusError>> {
+        update_user_with_retry(&self.client, email, |item| {
+            // If the password was changed after we verified it, this login must not go through
+            let current_hash = item.get(PASSWORD).and_then(|hash| hash.as_s().ok());
+            if current_hash.map(String::as_str) != Some(expected_hashed_password) {
+                log::error!("Password changed while logging in");
+                return Err(ServerFnError::from(NexusError::IncorrectPassword));
+            }
+            Ok(Some(
+                UserUpdate::new()
+                    .set(SESSION_ID, AttributeValue::S(session_id.to_string()))
+                    .set(
+                        SESSION_EXPIRY,
+                        AttributeValue::N(session_expiry.to_string()),
+                    ),
+            ))
+        })
+        .await
+    }
+
+    async fn find_session(
+        &self,
+        session_id: &str,
+    ) -> Result<Option<Session>, ServerFnError<NexusError>> {
+        let query = query_setup(
+            &self.client,
+            session_id.to_string(),
+            TableKeyType::SessionId,
+        )
+        .projection_expression([SESSION_ID, SESSION_EXPIRY, EMAIL].join(", "));
+        let db_result = send_with_retry(
+            DynamoOperation::read("find_session", get_table_name()),
+            || query.clone().send(),
+        )
+        .await;
+
+        let output = match db_result {
+            Ok(o) => o,
+            Err(e) => return Err(handle_dynamo_generic_error(e)),
+        };
+        let item = match output.items().first() {
+            Some(item) => item,
+            None => return Ok(None),
+        };
+        if parse_string_attribute(item, &TableAttributeType::SessionId)?.as_deref()
+            != Some(session_id)
+        {
+            return Ok(None);
+        }
+        let session_expiry = parse_number_attribute(item, &TableAttributeType::SessionExpiry)?
+            .ok_or_else(|| {
+                log::error!("Unable to get session_expiry in find_session query");
+                UNHANDLED
+            })?;
+        let email = parse_string_attribute(item, &TableAttributeType::Email)?.ok_or_else(|| {
+            log::error!("Unable to get email in find_session query");
+            UNHANDLED
+        })?;
+        Ok(Some(Session {
+            email,
+            session_expiry,
+        }))
+    }
+
+    async fn expire_session(&self, email: &str) -> Result<(), ServerFnError<NexusError>> {
+        let update = UserUpdate::new().set(SESSION_EXPIRY, AttributeValue::N("0".to_string()));
+        update_user(&self.client, email, update).await
+    }
+
+    async fn find_verification_token(
+        &self,
+        token: &str,
+    ) -> Result<Option<VerificationToken>, ServerFnError<NexusError>> {
+        let query = query_setup(
+            &self.client,
+            token.to_string(),
+            TableKeyType::EmailVerificationUUID,
+        );
+        let db_result = send_with_retry(
+            DynamoOperation::read("find_verification_token", get_table_name()),
+            || query.clone().send(),
+        )
+        .await;
+
+        match db_result {
+            Ok(o) if o.items().is_empty() => Ok(None),
+            Ok(o) => Ok(Some(VerificationToken {
+                email: extract_email_from_query(&o)?,
+                request_time: extract_email_verification_request_time_from_query(&o)?,
+            })),
+            Err(e) => Err(handle_dynamo_generic_error(e)),
+        }
+    }
+
+    async fn mark_email_verified(&self, email: &str) -> Result<(), ServerFnError<NexusError>> {
+        let update = UserUpdate::new().set(EMAIL_VERIFIED, AttributeValue::Bool(true));
+        update_user(&self.client, email, update).await
+    }
+
+    async fn start_email_change(
+        &self,
+        old_email: &str,
+        new_email: &str,
+        token: &str,
+        request_time: i64,
+    ) -> Result<(), ServerFnError<NexusError>> {
+        let attributes = get_user(&self.client, old_email).await?.ok_or_else(|| {
+            log::error!("Could not find the row of the user changing their email");
+            ServerFnError::from(NexusError::CouldNotFindRowWithThatEmail)
+        })?;
+        let check_email_not_already_exists_expression = format!("attribute_not_exists({})", EMAIL);
+        let mut put = self
+            .client
+            .put_item()
+            .table_name(get_table_name())
+            .condition_expression(check_email_not_already_exists_expression);
+        for (name, attribute) in attributes.into_iter() {
+            // The session stays with the old row, otherwise two rows would share a session id
+            if name != SESSION_ID && name != SESSION_EXPIRY {
+                put = put.item(name, attribute);
+            }
+        }
+        put = put
+            .item(EMAIL, AttributeValue::S(new_email.to_string()))
+            .item(VERSION, AttributeValue::N("0".to_string()))
+            .item(
+                EMAIL_VERIFICATION_UUID,
+                AttributeValue::S(token.to_string()),
+            )
+            .item(
+                EMAIL_VERIFICATION_REQUEST_TIME,
+                AttributeValue::N(request_time.to_string()),
+            )
+            .item(EMAIL_VERIFIED, AttributeValue::Bool(false));
+        let put_resp = send_with_retry(
+            DynamoOperation::write("start_email_change", get_table_name()),
+            || put.clone().send(),
+        )
+        .await;
+
+        match put_resp {
+            Ok(_) => Ok(()),
+            Err(e) if e.kind == DynamoErrorKind::ConditionalCheckFailed => {
+                Err(ServerFnError::from(NexusError::EmailAlreadyInUse))
+            }
+            Err(e) => Err(handle_dynamo_generic_error(e)),
+        }
+    }
+
+    async fn set_display_name(
+        &self,
+        email: &str,
+        display_name: &str,
+    ) -> Result<(), ServerFnError<NexusError>> {
+        let update =
+            UserUpdate::new().set(DISPLAY_NAME, AttributeValue::S(display_name.to_string()));
+        update_user(&self.client, email, update).await
+    }
+
+    async fn set_hashed_password(
+        &self,
+        email: &str,
+        hashed_password: &str,
+    ) -> Result<(), ServerFnError<NexusError>> {
+        let update =
+            UserUpdate::new().set(PASSWORD, AttributeValue::S(hashed_password.to_string()));
+        update_user(&self.client, email, update).await
+    }
+
+    async fn find_user_uuid(
+        &self,
+        email: &str,
+    ) -> Result<Option<String>, ServerFnError<NexusError>> {
+        match get_user(&self.client, email).await? {
+            Some(item) => parse_string_attribute(&item, &TableAttributeType::UserUUID)
+                .map_err(ServerFnError::from),
+            None => Ok(None),
+        }
+    }
+
+    async fn find_email_by_user_uuid(
+        &self,
+        user_uuid: &str,
+    ) -> Result<Option<String>, ServerFnError<NexusError>> {
+        let items = self.rows_of_account(user_uuid).await?;
+        // An unfinished email change leaves a second, unverified row with the same uuid, so the
+        // verified row wins if there is one
+        let mut fallback = None;
+        for item in items.iter() {
+            let email = parse_string_attribute(item, &TableAttributeType::Email)?;
+            if parse_bool_attribute(item, &TableAttributeType::EmailVerified)?.unwrap_or(false) {
+                return Ok(email);
+            }
+            fallback = fallback.or(email);
+        }
+        Ok(fallback)
+    }
+
+    async fn entitlements(&self, email: &str) -> Result<Vec<String>, ServerFnError<NexusError>> {
+        let item = get_user(&self.client, email).await?.ok_or_else(|| {
+            log::error!("Could not find the row of the user whose entitlements we wanted");
+            ServerFnError::from(NexusError::CouldNotFindRowWithThatEmail)
+        })?;
+        Ok(
+            parse_list_of_strings_attribute(&item, &TableAttributeType::GamesBought)?
+                .unwrap_or_default(),
+        )
+    }
+
+    async fn grant_entitlement(
+        &self,
+        email: &str,
+        game_id: &str,
+    ) -> Result<(), ServerFnError<NexusError>> {
+        // Reloading the row on conflict means two webhooks for the same user can't clobber
+        // each other's entitlements. They're written back as a string set, which also converts
+        // rows still holding the old list.
+        update_user_with_retry(&self.client, email, |item| {
+            let mut games_bought =
+                parse_list_of_strings_attribute(item, &TableAttributeType::GamesBought)?
+                    .unwrap_or_default();
+            if games_bought.iter().any(|game| game == game_id) {
+                return Ok(None);
+            }
+            games_bought.push(game_id.to_string());
+            games_bought.sort();
+            games_bought.dedup();
+            Ok(Some(
+                UserUpdate::new().set(GAMES_BOUGHT, AttributeValue::Ss(games_bought)),
+            ))
+        })
+        .await
+    }
+
+    async fn revoke_entitlement(
+        &self,
+        email: &str,
+        game_id: &str,
+    ) -> Result<(), ServerFnError<NexusError>> {
+        update_user_with_retry(&self.client, email, |item| {
+            let games_bought =
+                parse_list_of_strings_attribute(item, &TableAttributeType::GamesBought)?
+                    .unwrap_or_default();
+            if !games_bought.iter().any(|game| game == game_id) {
+                return Ok(None);
+            }
+            let remaining: Vec<String> = games_bought
+                .into_iter()
+                .filter(|game| game != game_id)
+                .collect();
+            // String sets can't be empty, so the last entitlement takes the attribute with it
+            Ok(Some(if remaining.is_empty() {
+                UserUpdate::new().remove(GAMES_BOUGHT)
+            } else {
+                UserUpdate::new().set(GAMES_BOUGHT, AttributeValue::Ss(remaining))
+            }))
+        })
+        .await
+    }
+
+    async fn find_billing_profile(
+        &self,
+        email: &str,
+    ) -> Result<Option<BillingProfile>, ServerFnError<NexusError>> {
+        let item = match get_user(&self.client, email).await? {
+            Some(item) => item,
+            None => return Ok(None),
+        };
+        let display_name = parse_string_attribute(&item, &TableAttributeType::DisplayName)?
+            .ok_or_else(|| {
+                log::error!("User row has no display name");
+                UNHANDLED
+            })?;
+        Ok(Some(BillingProfile {
+            display_name,
+            stripe_customer_id: parse_string_attribute(
+                &item,
+                &TableAttributeType::StripeCustomerId,
+            )?,
+        }))
+    }
+
+    async fn link_stripe_customer(
+        &self,
+        email: &str,
+        customer_id: &str,
+    ) -> Result<String, ServerFnError<NexusError>> {
+        let mut linked = None;
+        update_user_with_retry(&self.client, email, |item| {
+            linked = parse_string_attribute(item, &TableAttributeType::StripeCustomerId)?;
+            if linked.is_some() {
+                return Ok(None);
+            }
+            Ok(Some(UserUpdate::new().set(
+                STRIPE_CUSTOMER_ID,
+                AttributeValue::S(customer_id.to_string()),
+            )))
+        })
+        .await?;
+        Ok(linked.unwrap_or_else(|| customer_id.to_string()))
+    }
+
+    async fn delete_account(&self, user_uuid: &str) -> Result<(), ServerFnError<NexusError>> {
+        for item in self.rows_of_account(user_uuid).await? {
+            let email =
+                parse_string_attribute(&item, &TableAttributeType::Email)?.ok_or_else(|| {
+                    log::error!("User row has no email");
+                    UNHANDLED
+                })?;
+            let delete = self
+                .client
+                .delete_item()
+                .table_name(get_table_name())
+                .key(EMAIL, AttributeValue::S(email));
+            let db_result = send_with_retry(
+                DynamoOperation::write("delete_account", get_table_name()),
+                || delete.clone().send(),
+            )
+            .await;
+            if let Err(e) = db_result {
+                return Err(handle_dynamo_generic_error(e));
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,392 @@
+use super::super::{
+    globals::{
+        dynamo::{
+            constants::{
+                external_key_attributes::{
+                    ASSIGNED_AT, ASSIGNED_USER_UUID, AVAILABLE_POOL, IMPORTED_AT, IMPORTED_BY,
+                    KEY_HASH, KEY_VALUE, PLATFORM, POOL, PRODUCT_ID, PURCHASE_ID, REVOKED_AT,
+                    REVOKED_REASON,
+                },
+                index::{ASSIGNED_USER_UUID_INDEX, AVAILABLE_POOL_INDEX, PURCHASE_ID_INDEX},
+            },
+            number_attribute, string_attribute,
+        },
+        dynamo_error::{send_with_retry, DynamoErrorKind, DynamoOperation},
+        env_var::get_external_keys_table_name,
+    },
+    utilities::handle_dynamo_generic_error,
+};
+use crate::errors::NexusError;
+use aws_sdk_dynamodb::{
+    types::{AttributeValue, Select},
+    Client as DynamoClient,
+};
+use leptos::ServerFnError;
+use std::collections::HashMap;
+
+/// The pool keys for `platform` copies of `product_id` are drawn from
+pub fn pool_id(product_id: &str, platform: &str) -> String {
+    format!("{}#{}", product_id, platform)
+}
+
+/// A key for a third-party storefront, handed out with a purchase
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExternalKey {
+    pub product_id: String,
+    pub platform: String,
+    pub key_hash: String,
+    pub key_value: String,
+    pub imported_by: String,
+    pub imported_at: i64,
+    pub assignment: Option<KeyAssignment>,
+}
+
+/// Who a key was handed out to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyAssignment {
+    pub purchase_id: String,
+    pub user_uuid: String,
+    pub assigned_at: i64,
+    /// When the purchase was refunded (or lost to a dispute). The key can't be taken back
+    /// automatically, so this is bookkeeping for revoking it on the storefront.
+    pub revoked_at: Option<i64>,
+    pub revoked_reason: Option<String>,
+}
+
+impl ExternalKey {
+    pub fn pool(&self) -> String {
+        pool_id(&self.product_id, &self.platform)
+    }
+}
+
+pub fn external_key_to_item(key: &ExternalKey) -> HashMap<String, AttributeValue> {
+    let mut item = HashMap::from([
+        (POOL.to_string(), AttributeValue::S(key.pool())),
+        (
+            KEY_HASH.to_string(),
+            AttributeValue::S(key.key_hash.clone()),
+        ),
+        (
+            PRODUCT_ID.to_string(),
+            AttributeValue::S(key.product_id.clone()),
+        ),
+        (
+            PLATFORM.to_string(),
+            AttributeValue::S(key.platform.clone()),
+        ),
+        (
+            KEY_VALUE.to_string(),
+            AttributeValue::S(key.key_value.clone()),
+        ),
+        (
+            IMPORTED_BY.to_string(),
+            AttributeValue::S(key.imported_by.clone()),
+        ),
+        (
+            IMPORTED_AT.to_string(),
+            AttributeValue::N(key.imported_at.to_string()),
+        ),
+    ]);
+    match &key.assignment {
+        None => {
+            item.insert(AVAILABLE_POOL.to_string(), AttributeValue::S(key.pool()));
+        }
+        Some(assignment) => {
+            item.insert(
+                PURCHASE_ID.to_string(),
+                AttributeValue::S(assignment.purchase_id.clone()),
+            );
+            item.insert(
+                ASSIGNED_USER_UUID.to_string(),
+                AttributeValue::S(assignment.user_uuid.clone()),
+            );
+            item.insert(
+                ASSIGNED_AT.to_string(),
+                AttributeValue::N(assignment.assigned_at.to_string()),
+            );
+            if let Some(revoked_at) = assignment.revoked_at {
+                item.insert(
+                    REVOKED_AT.to_string(),
+                    AttributeValue::N(revoked_at.to_string()),
+                );
+            }
+            if let Some(revoked_reason) = &assignment.revoked_reason {
+                item.insert(
+                    REVOKED_REASON.to_string(),
+                    AttributeValue::S(revoked_reason.clone()),
+                );
+            }
+        }
+    }
+    item
+}
+
+pub fn parse_external_key(
+    item: &HashMap<String, AttributeValue>,
+) -> Result<ExternalKey, ServerFnError<NexusError>> {
+    let required_string = |name: &str| {
+        string_attribute(item, name)?.ok_or_else(|| {
+            log::error!("External key is missing {}", name);
+            NexusError::Unhandled
+        })
+    };
+    let required_number = |name: &str| {
+        number_attribute(item, name)?.ok_or_else(|| {
+            log::error!("External key is missing {}", name);
+            NexusError::Unhandled
+        })
+    };
+    let assignment = match string_attribute(item, PURCHASE_ID)? {
+        None => None,
+        Some(purchase_id) => Some(KeyAssignment {
+            purchase_id,
+            user_uuid: required_string(ASSIGNED_USER_UUID)?,
+            assigned_at: required_number(ASSIGNED_AT)?,
+            revoked_at: number_attribute(item, REVOKED_AT)?,
+            revoked_reason: string_attribute(item, REVOKED_REASON)?,
+        }),
+    };
+    Ok(ExternalKey {
+        product_id: required_string(PRODUCT_ID)?,
+        platform: required_string(PLATFORM)?,
+        key_hash: required_string(KEY_HASH)?,
+        key_value: required_string(KEY_VALUE)?,
+        imported_by: required_string(IMPORTED_BY)?,
+        imported_at: required_number(IMPORTED_AT)?,
+        assignment,
+    })
+}
+
+/// Adds a key to its pool, returning false if it was already imported
+pub async fn import_external_key(
+    client: &DynamoClient,
+    key: &ExternalKey,
+) -> Result<bool, ServerFnError<NexusError>> {
+    let put = client
+        .put_item()
+        .table_name(get_external_keys_table_name())
+        .set_item(Some(external_key_to_item(key)))
+        .condition_expression(format!("attribute_not_exists({})", KEY_HASH));
+    let put_result = send_with_retry(
+        DynamoOperation::write("import_external_key", get_external_keys_table_name()),
+        || put.clone().send(),
+    )
+    .await;
+
+    match put_result {
+        Ok(_) => Ok(true),
+        Err(e) if e.kind == DynamoErrorKind::ConditionalCheckFailed => Ok(false),
+        Err(e) => Err(handle_dynamo_generic_error(e)),
+    }
+}
+
+/// A few unassigned keys of the pool. The index is eventually consistent, so some of them may
+/// have just been handed out.
+pub async fn available_external_keys(
+    client: &DynamoClient,
+    pool: &str,
+    limit: i32,
+) -> Result<Vec<ExternalKey>, ServerFnError<NexusError>> {
+    let query = client
+        .query()
+        .table_name(get_external_keys_table_name())
+        .index_name(AVAILABLE_POOL_INDEX)
+        .key_condition_expression("#available_pool = :pool")
+        .expression_attribute_names("#available_pool", AVAILABLE_POOL)
+        .expression_attribute_values(":pool", AttributeValue::S(pool.to_string()))
+        .limit(limit);
+    let db_result = send_with_retry(
+        DynamoOperation::read("available_external_keys", get_external_keys_table_name()),
+        || query.clone().send(),
+    )
+    .await;
+
+    match db_result {
+        Ok(o) => o.items().iter().map(parse_external_key).collect(),
+        Err(e) => Err(handle_dynamo_generic_error(e)),
+    }
+}
+
+/// How many keys of the pool haven't been handed out
+pub async fn count_available_external_keys(
+    client: &DynamoClient,
+    pool: &str,
+) -> Result<i64, ServerFnError<NexusError>> {
+    let mut count = 0;
+    let mut exclusive_start_key = None;
+    loop {
+        let query = client
+            .query()
+            .table_name(get_external_keys_table_name())
+            .index_name(AVAILABLE_POOL_INDEX)
+            .key_condition_expression("#available_pool = :pool")
+            .expression_attribute_names("#available_pool", AVAILABLE_POOL)
+            .expression_attribute_values(":pool", AttributeValue::S(pool.to_string()))
+            .select(Select::Count)
+            .set_exclusive_start_key(exclusive_start_key.clone());
+        let db_result = send_with_retry(
+            DynamoOperation::read(
+                "count_available_external_keys",
+                get_external_keys_table_name(),
+            ),
+            || query.clone().send(),
+        )
+        .await;
+        let output = match db_result {
+            Ok(o) => o,
+            Err(e) => return Err(handle_dynamo_generic_error(e)),
+        };
+        count += i64::from(output.count);
+        exclusive_start_key = output.last_evaluated_key;
+        if exclusive_start_key.is_none() {
+            break;
+        }
+    }
+    Ok(count)
+}
+
+/// Hands the key out for `assignment`, unless another purchase got it first
+pub async fn assign_external_key(
+    client: &DynamoClient,
+    key: &ExternalKey,
+    assignment: &KeyAssignment,
+) -> Result<bool, ServerFnError<NexusError>> {
+    let update = client
+        .update_item()
+        .table_name(get_external_keys_table_name())
+        .key(POOL, AttributeValue::S(key.pool()))
+        .key(KEY_HASH, AttributeValue::S(key.key_hash.clone()))
+        .update_expression(
+            "SET #purchase_id = :purchase_id, #user_uuid = :user_uuid, #assigned_at = :assigned_at REMOVE #available_pool",
+        )
+        .condition_expression("attribute_exists(#available_pool)")
+        .expression_attribute_names("#purchase_id", PURCHASE_ID)
+        .expression_attribute_names("#user_uuid", ASSIGNED_USER_UUID)
+        .expression_attribute_names("#assigned_at", ASSIGNED_AT)
+        .expression_attribute_names("#available_pool", AVAILABLE_POOL)
+        .expression_attribute_values(
+            ":purchase_id",
+            AttributeValue::S(assignment.purchase_id.clone()),
+        )
+        .expression_attribute_values(
+            ":user_uuid",
+            AttributeValue::S(assignment.user_uuid.clone()),
+        )
+        .expression_attribute_values(
+            ":assigned_at",
+            AttributeValue::N(assignment.assigned_at.to_string()),
+        );
+    let update_result = send_with_retry(
+        DynamoOperation::write("assign_external_key", get_external_keys_table_name()),
+        || update.clone().send(),
+    )
+    .await;
+
+    match update_result {
+        Ok(_) => Ok(true),
+        Err(e) if e.kind == DynamoErrorKind::ConditionalCheckFailed => Ok(false),
+        Err(e) => Err(handle_dynamo_generic_error(e)),
+    }
+}
+
+async fn query_external_keys(
+    client: &DynamoClient,
+    operation: &'static str,
+    index_name: &str,
+    attribute: &str,
+    value: &str,
+) -> Result<Vec<ExternalKey>, ServerFnError<NexusError>> {
+    let mut keys = Vec::new();
+    let mut exclusive_start_key = None;
+    loop {
+        let query = client
+            .query()
+            .table_name(get_external_keys_table_name())
+            .index_name(index_name)
+            .key_condition_expression("#attribute = :value")
+            .expression_attribute_names("#attribute", attribute)
+            .expression_attribute_values(":value", AttributeValue::S(value.to_string()))
+            .set_exclusive_start_key(exclusive_start_key.clone());
+        let db_result = send_with_retry(
+            DynamoOperation::read(operation, get_external_keys_table_name()),
+            || query.clone().send(),
+        )
+        .await;
+        let output = match db_result {
+            Ok(o) => o,
+            Err(e) => return Err(handle_dynamo_generic_error(e)),
+        };
+        for item in output.items() {
+            keys.push(parse_external_key(item)?);
+        }
+        exclusive_start_key = output.last_evaluated_key;
+        if exclusive_start_key.is_none() {
+            break;
+        }
+    }
+    Ok(keys)
+}
+
+/// The keys handed out for a purchase
+pub async fn external_keys_for_purchase(
+    client: &DynamoClient,
+    purchase_id: &str,
+) -> Result<Vec<ExternalKey>, ServerFnError<NexusError>> {
+    query_external_keys(
+        client,
+        "external_keys_for_purchase",
+        PURCHASE_ID_INDEX,
+        PURCHASE_ID,
+        purchase_id,
+    )
+    .await
+}
+
+/// The keys handed out to an account, including revoked ones
+pub async fn external_keys_for_user(
+    client: &DynamoClient,
+    user_uuid: &str,
+) -> Result<Vec<ExternalKey>, ServerFnError<NexusError>> {
+    query_external_keys(
+        client,
+        "external_keys_for_user",
+        ASSIGNED_USER_UUID_INDEX,
+        ASSIGNED_USER_UUID,
+        user_uuid,
+    )
+    .await
+}
+
+/// Records that a handed out key should be revoked on its storefront. Revoking it again keeps
+/// the first time and reason.
+pub async fn mark_external_key_revoked(
+    client: &DynamoClient,
+    key: &ExternalKey,
+    reason: &str,
+    now: i64,
+) -> Result<(), ServerFnError<NexusError>> {
+    let update = client
+        .update_item()
+        .table_name(get_external_keys_table_name())
+        .key(POOL, AttributeValue::S(key.pool()))
+        .key(KEY_HASH, AttributeValue::S(key.key_hash.clone()))
+        .update_expression(
+            "SET #revoked_at = if_not_exists(#revoked_at, :now), #revoked_reason = if_not_exists(#revoked_reason, :reason)",
+        )
+        .condition_expression("attribute_exists(#purchase_id)")
+        .expression_attribute_names("#revoked_at", REVOKED_AT)
+        .expression_attribute_names("#revoked_reason", REVOKED_REASON)
+        .expression_attribute_names("#purchase_id", PURCHASE_ID)
+        .expression_attribute_values(":now", AttributeValue::N(now.to_string()))
+        .expression_attribute_values(":reason", AttributeValue::S(reason.to_string()));
+    let update_result = send_with_retry(
+        DynamoOperation::write("mark_external_key_revoked", get_external_keys_table_name()),
+        || update.clone().send(),
+    )
+    .await;
+
+    match update_result {
+        Ok(_) => Ok(()),
+        Err(e) => Err(handle_dynamo_generic_error(e)),
+    }
+}
@@ -0,0 +1,328 @@
+use super::super::{
+    globals::{
+        dynamo::{
+            constants::{
+                gift_attributes::{
+                    BUYER_USER_UUID, CLAIMED_AT, CLAIMED_BY_USER_UUID, CLAIM_TOKEN, CREATED_AT,
+                    GIFT_ID, MESSAGE, NOTIFIED_AT, PRODUCT_ID, RECIPIENT_EMAIL, SENDER_NAME,
+                },
+                index::{BUYER_USER_UUID_INDEX, CLAIMED_BY_USER_UUID_INDEX, CLAIM_TOKEN_INDEX},
+            },
+            number_attribute, string_attribute,
+        },
+        dynamo_error::{send_with_retry, DynamoErrorKind, DynamoOperation},
+        env_var::get_gifts_table_name,
+    },
+    utilities::handle_dynamo_generic_error,
+};
+use crate::errors::NexusError;
+use aws_sdk_dynamodb::{types::AttributeValue, Client as DynamoClient};
+use leptos::ServerFnError;
+use std::collections::HashMap;
+
+/// A purchase made for someone else, claimable by whoever has its claim token
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Gift {
+    /// The id of the checkout session that paid for it
+    pub gift_id: String,
+    /// Secret, it's the only thing the claim link contains
+    pub claim_token: String,
+    pub product_id: String,
+    pub buyer_user_uuid: String,
+    /// The buyer's display name when they bought it
+    pub sender_name: String,
+    pub recipient_email: String,
+    pub message: String,
+    pub created_at: i64,
+    /// When the recipient was emailed the claim link
+    pub notified_at: Option<i64>,
+    pub claimed_by_user_uuid: Option<String>,
+    pub claimed_at: Option<i64>,
+}
+
+pub fn gift_to_item(gift: &Gift) -> HashMap<String, AttributeValue> {
+    let mut item = HashMap::from([
+        (GIFT_ID.to_string(), AttributeValue::S(gift.gift_id.clone())),
+        (
+            CLAIM_TOKEN.to_string(),
+            AttributeValue::S(gift.claim_token.clone()),
+        ),
+        (
+            PRODUCT_ID.to_string(),
+            AttributeValue::S(gift.product_id.clone()),
+        ),
+        (
+            BUYER_USER_UUID.to_string(),
+            AttributeValue::S(gift.buyer_user_uuid.clone()),
+        ),
+        (
+            SENDER_NAME.to_string(),
+            AttributeValue::S(gift.sender_name.clone()),
+        ),
+        (
+            RECIPIENT_EMAIL.to_string(),
+            AttributeValue::S(gift.recipient_email.clone()),
+        ),
+        (MESSAGE.to_string(), AttributeValue::S(gift.message.clone())),
+        (
+            CREATED_AT.to_string(),
+            AttributeValue::N(gift.created_at.to_string()),
+        ),
+    ]);
+    if let Some(notified_at) = gift.notified_at {
+        item.insert(
+            NOTIFIED_AT.to_string(),
+            AttributeValue::N(notified_at.to_string()),
+        );
+    }
+    if let Some(claimed_by_user_uuid) = &gift.claimed_by_user_uuid {
+        item.insert(
+            CLAIMED_BY_USER_UUID.to_string(),
+            AttributeValue::S(claimed_by_user_uuid.clone()),
+        );
+    }
+    if let Some(claimed_at) = gift.claimed_at {
+        item.insert(
+            CLAIMED_AT.to_string(),
+            AttributeValue::N(claimed_at.to_string()),
+        );
+    }
+    item
+}
+
+pub fn parse_gift(
+    item: &HashMap<String, AttributeValue>,
+) -> Result<Gift, ServerFnError<NexusError>> {
+    let required_string = |name: &str| {
+        string_attribute(item, name)?.ok_or_else(|| {
+            log::error!("Gift is missing {}", name);
+            NexusError::Unhandled
+        })
+    };
+    Ok(Gift {
+        gift_id: required_string(GIFT_ID)?,
+        claim_token: required_string(CLAIM_TOKEN)?,
+        product_id: required_string(PRODUCT_ID)?,
+        buyer_user_uuid: required_string(BUYER_USER_UUID)?,
+        sender_name: required_string(SENDER_NAME)?,
+        recipient_email: required_string(RECIPIENT_EMAIL)?,
+        message: string_attribute(item, MESSAGE)?.unwrap_or_default(),
+        created_at: number_attribute(item, CREATED_AT)?.ok_or_else(|| {
+            log::error!("Gift is missing {}", CREATED_AT);
+            NexusError::Unhandled
+        })?,
+        notified_at: number_attribute(item, NOTIFIED_AT)?,
+        claimed_by_user_uuid: string_attribute(item, CLAIMED_BY_USER_UUID)?,
+        claimed_at: number_attribute(item, CLAIMED_AT)?,
+    })
+}
+
+/// Records a gift unless its checkout session already has one, returning whether it was newly
+/// recorded
+pub async fn create_gift(
+    client: &DynamoClient,
+    gift: &Gift,
+) -> Result<bool, ServerFnError<NexusError>> {
+    let put = client
+        .put_item()
+        .table_name(get_gifts_table_name())
+        .set_item(Some(gift_to_item(gift)))
+        .condition_expression(format!("attribute_not_exists({})", GIFT_ID));
+    let put_result = send_with_retry(
+        DynamoOperation::write("create_gift", get_gifts_table_name()),
+        || put.clone().send(),
+    )
+    .await;
+
+    match put_result {
+        Ok(_) => Ok(true),
+        Err(e) if e.kind == DynamoErrorKind::ConditionalCheckFailed => Ok(false),
+        Err(e) => Err(handle_dynamo_generic_error(e)),
+    }
+}
+
+pub async fn get_gift(
+    client: &DynamoClient,
+    gift_id: &str,
+) -> Result<Option<Gift>, ServerFnError<NexusError>> {
+    let get_item = client
+        .get_item()
+        .table_name(get_gifts_table_name())
+        .key(GIFT_ID, AttributeValue::S(gift_id.to_string()))
+        .consistent_read(true);
+    let db_result = send_with_retry(
+        DynamoOperation::read("get_gift", get_gifts_table_name()),
+        || get_item.clone().send(),
+    )
+    .await;
+
+    match db_result {
+        Ok(o) => o.item.as_ref().map(parse_gift).transpose(),
+        Err(e) => Err(handle_dynamo_generic_error(e)),
+    }
+}
+
+pub async fn find_gift_by_claim_token(
+    client: &DynamoClient,
+    claim_token: &str,
+) -> Result<Option<Gift>, ServerFnError<NexusError>> {
+    let query = client
+        .query()
+        .table_name(get_gifts_table_name())
+        .index_name(CLAIM_TOKEN_INDEX)
+        .key_condition_expression("#claim_token = :claim_token")
+        .expression_attribute_names("#claim_token", CLAIM_TOKEN)
+        .expression_attribute_values(":claim_token", AttributeValue::S(claim_token.to_string()));
+    let db_result = send_with_retry(
+        DynamoOperation::read("find_gift_by_claim_token", get_gifts_table_name()),
+        || query.clone().send(),
+    )
+    .await;
+
+    match db_result {
+        Ok(o) => o.items().first().map(parse_gift).transpose(),
+        Err(e) => Err(handle_dynamo_generic_error(e)),
+    }
+}
+
+/// Every gift an account bought, oldest first
+pub async fn list_gifts_for_buyer(
+    client: &DynamoClient,
+    buyer_user_uuid: &str,
+) -> Result<Vec<Gift>, ServerFnError<NexusError>> {
+    let mut gifts = Vec::new();
+    let mut exclusive_start_key = None;
+    loop {
+        let query = client
+            .query()
+            .table_name(get_gifts_table_name())
+            .index_name(BUYER_USER_UUID_INDEX)
+            .key_condition_expression("#buyer_user_uuid = :buyer_user_uuid")
+            .expression_attribute_names("#buyer_user_uuid", BUYER_USER_UUID)
+            .expression_attribute_values(
+                ":buyer_user_uuid",
+                AttributeValue::S(buyer_user_uuid.to_string()),
+            )
+            .set_exclusive_start_key(exclusive_start_key.clone());
+        let db_result = send_with_retry(
+            DynamoOperation::read("list_gifts_for_buyer", get_gifts_table_name()),
+            || query.clone().send(),
+        )
+        .await;
+        let output = match db_result {
+            Ok(o) => o,
+            Err(e) => return Err(handle_dynamo_generic_error(e)),
+        };
+        for item in output.items() {
+            gifts.push(parse_gift(item)?);
+        }
+        exclusive_start_key = output.last_evaluated_key;
+        if exclusive_start_key.is_none() {
+            break;
+        }
+    }
+    gifts.sort_by_key(|gift| gift.created_at);
+    Ok(gifts)
+}
+
+/// Every gift an account claimed, in no particular order. Their purchases are stored under the
+/// buyer, so this is how the products an account owns through gifts are found.
+pub async fn list_gifts_claimed_by(
+    client: &DynamoClient,
+    user_uuid: &str,
+) -> Result<Vec<Gift>, ServerFnError<NexusError>> {
+    let mut gifts = Vec::new();
+    let mut exclusive_start_key = None;
+    loop {
+        let query = client
+            .query()
+            .table_name(get_gifts_table_name())
+            .index_name(CLAIMED_BY_USER_UUID_INDEX)
+            .key_condition_expression("#claimed_by_user_uuid = :claimed_by_user_uuid")
+            .expression_attribute_names("#claimed_by_user_uuid", CLAIMED_BY_USER_UUID)
+            .expression_attribute_values(
+                ":claimed_by_user_uuid",
+                AttributeValue::S(user_uuid.to_string()),
+            )
+            .set_exclusive_start_key(exclusive_start_key.clone());
+        let db_result = send_with_retry(
+            DynamoOperation::read("list_gifts_claimed_by", get_gifts_table_name()),
+            || query.clone().send(),
+        )
+        .await;
+        let output = match db_result {
+            Ok(o) => o,
+            Err(e) => return Err(handle_dynamo_generic_error(e)),
+        };
+        for item in output.items() {
+            gifts.push(parse_gift(item)?);
+        }
+        exclusive_start_key = output.last_evaluated_key;
+        if exclusive_start_key.is_none() {
+            break;
+        }
+    }
+    Ok(gifts)
+}
+
+pub async fn mark_gift_notified(
+    client: &DynamoClient,
+    gift_id: &str,
+    now: i64,
+) -> Result<(), ServerFnError<NexusError>> {
+    let update = client
+        .update_item()
+        .table_name(get_gifts_table_name())
+        .key(GIFT_ID, AttributeValue::S(gift_id.to_string()))
+        .update_expression("SET #notified_at = :now")
+        .condition_expression("attribute_exists(#gift_id)")
+        .expression_attribute_names("#notified_at", NOTIFIED_AT)
+        .expression_attribute_names("#gift_id", GIFT_ID)
+        .expression_attribute_values(":now", AttributeValue::N(now.to_string()));
+    let update_result = send_with_retry(
+        DynamoOperation::write("mark_gift_notified", get_gifts_table_name()),
+        || update.clone().send(),
+    )
+    .await;
+
+    match update_result {
+        Ok(_) => Ok(()),
+        Err(e) => Err(handle_dynamo_generic_error(e)),
+    }
+}
+
+/// Gives the gift to `user_uuid`, unless someone else claimed it first. Claiming a gift again
+/// with the account that has it succeeds, so a claim that failed halfway can be retried.
+pub async fn claim_gift(
+    client: &DynamoClient,
+    gift_id: &str,
+    user_uuid: &str,
+    now: i64,
+) -> Result<bool, ServerFnError<NexusError>> {
+    let update = client
+        .update_item()
+        .table_name(get_gifts_table_name())
+        .key(GIFT_ID, AttributeValue::S(gift_id.to_string()))
+        .update_expression(
+            "SET #claimed_by = :user_uuid, #claimed_at = if_not_exists(#claimed_at, :now)",
+        )
+        .condition_expression(
+            "attribute_exists(#gift_id) AND (attribute_not_exists(#claimed_by) OR #claimed_by = :user_uuid)",
+        )
+        .expression_attribute_names("#gift_id", GIFT_ID)
+        .expression_attribute_names("#claimed_by", CLAIMED_BY_USER_UUID)
+        .expression_attribute_names("#claimed_at", CLAIMED_AT)
+        .expression_attribute_values(":user_uuid", AttributeValue::S(user_uuid.to_string()))
+        .expression_attribute_values(":now", AttributeValue::N(now.to_string()));
+    let update_result = send_with_retry(
+        DynamoOperation::write("claim_gift", get_gifts_table_name()),
+        || update.clone().send(),
+    )
+    .await;
+
+    match update_result {
+        Ok(_) => Ok(true),
+        Err(e) if e.kind == DynamoErrorKind::ConditionalCheckFailed => Ok(false),
+        Err(e) => Err(handle_dynamo_generic_error(e)),
+    }
+}
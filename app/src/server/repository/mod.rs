@@ -0,0 +1,169 @@
+pub mod carts;
+pub mod collaborators;
+pub mod downloads;
+pub mod dynamo;
+pub mod external_keys;
+pub mod gifts;
+pub mod product_keys;
+pub mod purchases;
+pub mod rate_limits;
+pub mod refund_requests;
+pub mod revenue_transfers;
+#[cfg(feature = "sql")]
+pub mod sql;
+pub mod users;
+pub mod webhook_events;
+
+use crate::errors::NexusError;
+use async_trait::async_trait;
+use leptos::ServerFnError;
+
+/// Everything needed to create an account. The account starts unverified, with
+/// `email_verification_uuid` as its outstanding verification token.
+#[derive(Debug, Clone)]
+pub struct NewUser {
+    pub display_name: String,
+    pub email: String,
+    pub hashed_password: String,
+    pub user_uuid: String,
+    pub email_verification_uuid: String,
+    pub account_creation_time: i64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Credentials {
+    pub hashed_password: String,
+    pub email_verified: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Session {
+    pub email: String,
+    pub session_expiry: i64,
+}
+
+#[derive(Debug, Clone)]
+pub struct VerificationToken {
+    pub email: String,
+    pub request_time: i64,
+}
+
+/// What an account's Stripe Customer is kept in sync with
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BillingProfile {
+    pub display_name: String,
+    /// Set at the account's first checkout
+    pub stripe_customer_id: Option<String>,
+}
+
+/// Storage for users, sessions, entitlements and email verification tokens.
+///
+/// Server functions get this from context (see `utilities::user_repository`) and the Axum
+/// handlers from `AppState`, so the backend is picked once in `server/src/main.rs`.
+///
+/// This is the only storage with a SQL backend. Built with the `sql` feature, accounts live in
+/// SQLite or Postgres but everything else (purchases, gifts, keys, carts, refunds, downloads,
+/// revenue shares, webhook events) stays in DynamoDB, see `sql::dynamo_table_names`.
+#[async_trait]
+pub trait UserRepository: Send + Sync {
+    /// Fails with [`NexusError::BadUsernameEmailCombination`] if the email is taken
+    async fn create_user(&self, user: NewUser) -> Result<(), ServerFnError<NexusError>>;
+
+    async fn find_credentials(
+        &self,
+        email: &str,
+    ) -> Result<Option<Credentials>, ServerFnError<NexusError>>;
+
+    /// Replaces the user's session, but only if their password hash is still
+    /// `expected_hashed_password` (i.e. it wasn't changed while they were logging in)
+    async fn start_session(
+        &self,
+        email: &str,
+        expected_hashed_password: &str,
+        session_id: &str,
+        session_expiry: i64,
+    ) -> Result<(), ServerFnError<NexusError>>;
+
+    async fn find_session(
+        &self,
+        session_id: &str,
+    ) -> Result<Option<Session>, ServerFnError<NexusError>>;
+
+    async fn expire_session(&self, email: &str) -> Result<(), ServerFnError<NexusError>>;
+
+    async fn find_verification_token(
+        &self,
+        token: &str,
+    ) -> Result<Option<VerificationToken>, ServerFnError<NexusError>>;
+
+    async fn mark_email_verified(&self, email: &str) -> Result<(), ServerFnError<NexusError>>;
+
+    /// Creates an unverified copy of the account under `new_email`, verified by `token`.
+    /// Fails with [`NexusError::EmailAlreadyInUse`] if `new_email` is taken.
+    async fn start_email_change(
+        &self,
+        old_email: &str,
+        new_email: &str,
+        token: &str,
+        request_time: i64,
+    ) -> Result<(), ServerFnError<NexusError>>;
+
+    async fn set_display_name(
+        &self,
+        email: &str,
+        display_name: &str,
+    ) -> Result<(), ServerFnError<NexusError>>;
+
+    async fn set_hashed_password(
+        &self,
+        email: &str,
+        hashed_password: &str,
+    ) -> Result<(), ServerFnError<NexusError>>;
+
+    /// The account's permanent id, which checkouts are tagged with
+    async fn find_user_uuid(
+        &self,
+        email: &str,
+    ) -> Result<Option<String>, ServerFnError<NexusError>>;
+
+    /// The current email of the account with this uuid. Accounts keep their uuid across email
+    /// changes, so this is how purchases find their way back to the buyer.
+    async fn find_email_by_user_uuid(
+        &self,
+        user_uuid: &str,
+    ) -> Result<Option<String>, ServerFnError<NexusError>>;
+
+    /// Ids of the games the user owns
+    async fn entitlements(&self, email: &str) -> Result<Vec<String>, ServerFnError<NexusError>>;
+
+    /// Granting something the user already owns is a no-op
+    async fn grant_entitlement(
+        &self,
+        email: &str,
+        game_id: &str,
+    ) -> Result<(), ServerFnError<NexusError>>;
+
+    /// Revoking something the user doesn't own is a no-op
+    async fn revoke_entitlement(
+        &self,
+        email: &str,
+        game_id: &str,
+    ) -> Result<(), ServerFnError<NexusError>>;
+
+    async fn find_billing_profile(
+        &self,
+        email: &str,
+    ) -> Result<Option<BillingProfile>, ServerFnError<NexusError>>;
+
+    /// Links the account to `customer_id` unless it's already linked to a Stripe Customer,
+    /// and returns the id of the customer it ends up linked to
+    async fn link_stripe_customer(
+        &self,
+        email: &str,
+        customer_id: &str,
+    ) -> Result<String, ServerFnError<NexusError>>;
+
+    /// Deletes the account along with its session and entitlements, including the row of an
+    /// unfinished email change. Purchases stay in the ledger under the uuid.
+    async fn delete_account(&self, user_uuid: &str) -> Result<(), ServerFnError<NexusError>>;
+}
@@ -0,0 +1,409 @@
+use super::super::{
+    globals::{
+        dynamo::{
+            constants::{
+                index::BATCH_ID_INDEX,
+                key_batch_attributes,
+                product_key_attributes::{
+                    BATCH_ID, CREATED_AT, KEY_HASH, KEY_HINT, PRODUCT_ID, REDEEMED_AT,
+                    REDEEMED_BY_USER_UUID,
+                },
+            },
+            number_attribute, string_attribute,
+        },
+        dynamo_error::{backoff_with_jitter, send_with_retry, DynamoErrorKind, DynamoOperation},
+        env_var::{get_key_batches_table_name, get_product_keys_table_name},
+    },
+    utilities::handle_dynamo_generic_error,
+};
+use crate::{errors::NexusError, orders::KeyBatchSummary};
+use aws_sdk_dynamodb::{
+    types::{AttributeValue, PutRequest, ReturnValue, WriteRequest},
+    Client as DynamoClient,
+};
+use leptos::ServerFnError;
+use std::collections::HashMap;
+
+/// Most items DynamoDB accepts in one BatchWriteItem
+const BATCH_WRITE_LIMIT: usize = 25;
+/// How many times unprocessed items of a batch write are resent before giving up
+const MAX_UNPROCESSED_RETRIES: u32 = 5;
+
+/// A key that grants a product when redeemed. Only its hash is stored, so a leaked table
+/// doesn't leak usable keys.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProductKey {
+    pub key_hash: String,
+    pub batch_id: String,
+    pub product_id: String,
+    pub key_hint: String,
+    pub created_at: i64,
+    pub redeemed_by_user_uuid: Option<String>,
+    pub redeemed_at: Option<i64>,
+}
+
+/// What happened when someone tried to redeem a key
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Redemption {
+    /// The key is now theirs (or already was)
+    Redeemed(ProductKey),
+    NotFound,
+    /// Someone else redeemed it first
+    AlreadyRedeemed,
+}
+
+pub fn product_key_to_item(key: &ProductKey) -> HashMap<String, AttributeValue> {
+    let mut item = HashMap::from([
+        (
+            KEY_HASH.to_string(),
+            AttributeValue::S(key.key_hash.clone()),
+        ),
+        (
+            BATCH_ID.to_string(),
+            AttributeValue::S(key.batch_id.clone()),
+        ),
+        (
+            PRODUCT_ID.to_string(),
+            AttributeValue::S(key.product_id.clone()),
+        ),
+        (
+            KEY_HINT.to_string(),
+            AttributeValue::S(key.key_hint.clone()),
+        ),
+        (
+            CREATED_AT.to_string(),
+            AttributeValue::N(key.created_at.to_string()),
+        ),
+    ]);
+    if let Some(redeemed_by_user_uuid) = &key.redeemed_by_user_uuid {
+        item.insert(
+            REDEEMED_BY_USER_UUID.to_string(),
+            AttributeValue::S(redeemed_by_user_uuid.clone()),
+        );
+    }
+    if let Some(redeemed_at) = key.redeemed_at {
+        item.insert(
+            REDEEMED_AT.to_string(),
+            AttributeValue::N(redeemed_at.to_string()),
+        );
+    }
+    item
+}
+
+pub fn parse_product_key(
+    item: &HashMap<String, AttributeValue>,
+) -> Result<ProductKey, ServerFnError<NexusError>> {
+    let required_string = |name: &str| {
+        string_attribute(item, name)?.ok_or_else(|| {
+            log::error!("Product key is missing {}", name);
+            NexusError::Unhandled
+        })
+    };
+    Ok(ProductKey {
+        key_hash: required_string(KEY_HASH)?,
+        batch_id: required_string(BATCH_ID)?,
+        product_id: required_string(PRODUCT_ID)?,
+        key_hint: required_string(KEY_HINT)?,
+        created_at: number_attribute(item, CREATED_AT)?.ok_or_else(|| {
+            log::error!("Product key is missing {}", CREATED_AT);
+            NexusError::Unhandled
+        })?,
+        redeemed_by_user_uuid: string_attribute(item, REDEEMED_BY_USER_UUID)?,
+        redeemed_at: number_attribute(item, REDEEMED_AT)?,
+    })
+}
+
+pub fn key_batch_to_item(batch: &KeyBatchSummary) -> HashMap<String, AttributeValue> {
+    HashMap::from([
+        (
+            key_batch_attributes::BATCH_ID.to_string(),
+            AttributeValue::S(batch.batch_id.clone()),
+        ),
+        (
+            key_batch_attributes::PRODUCT_ID.to_string(),
+            AttributeValue::S(batch.product_id.clone()),
+        ),
+        (
+            key_batch_attributes::LABEL.to_string(),
+            AttributeValue::S(batch.label.clone()),
+        ),
+        (
+            key_batch_attributes::CREATED_BY.to_string(),
+            AttributeValue::S(batch.created_by.clone()),
+        ),
+        (
+            key_batch_attributes::CREATED_AT.to_string(),
+            AttributeValue::N(batch.created_at.to_string()),
+        ),
+        (
+            key_batch_attributes::KEY_COUNT.to_string(),
+            AttributeValue::N(batch.key_count.to_string()),
+        ),
+    ])
+}
+
+pub fn parse_key_batch(
+    item: &HashMap<String, AttributeValue>,
+) -> Result<KeyBatchSummary, ServerFnError<NexusError>> {
+    let required_string = |name: &str| {
+        string_attribute(item, name)?.ok_or_else(|| {
+            log::error!("Key batch is missing {}", name);
+            NexusError::Unhandled
+        })
+    };
+    let required_number = |name: &str| {
+        number_attribute(item, name)?.ok_or_else(|| {
+            log::error!("Key batch is missing {}", name);
+            NexusError::Unhandled
+        })
+    };
+    Ok(KeyBatchSummary {
+        batch_id: required_string(key_batch_attributes::BATCH_ID)?,
+        product_id: required_string(key_batch_attributes::PRODUCT_ID)?,
+        label: string_attribute(item, key_batch_attributes::LABEL)?.unwrap_or_default(),
+        created_by: required_string(key_batch_attributes::CREATED_BY)?,
+        created_at: required_number(key_batch_attributes::CREATED_AT)?,
+        key_count: required_number(key_batch_attributes::KEY_COUNT)?,
+    })
+}
+
+pub async fn put_key_batch(
+    client: &DynamoClient,
+    batch: &KeyBatchSummary,
+) -> Result<(), ServerFnError<NexusError>> {
+    let put = client
+        .put_item()
+        .table_name(get_key_batches_table_name())
+        .set_item(Some(key_batch_to_item(batch)))
+        .condition_expression(format!(
+            "attribute_not_exists({})",
+            key_batch_attributes::BATCH_ID
+        ));
+    let put_result = send_with_retry(
+        DynamoOperation::write("put_key_batch", get_key_batches_table_name()),
+        || put.clone().send(),
+    )
+    .await;
+
+    match put_result {
+        Ok(_) => Ok(()),
+        Err(e) => Err(handle_dynamo_generic_error(e)),
+    }
+}
+
+pub async fn get_key_batch(
+    client: &DynamoClient,
+    batch_id: &str,
+) -> Result<Option<KeyBatchSummary>, ServerFnError<NexusError>> {
+    let get_item = client
+        .get_item()
+        .table_name(get_key_batches_table_name())
+        .key(
+            key_batch_attributes::BATCH_ID,
+            AttributeValue::S(batch_id.to_string()),
+        )
+        .consistent_read(true);
+    let db_result = send_with_retry(
+        DynamoOperation::read("get_key_batch", get_key_batches_table_name()),
+        || get_item.clone().send(),
+    )
+    .await;
+
+    match db_result {
+        Ok(o) => o.item.as_ref().map(parse_key_batch).transpose(),
+        Err(e) => Err(handle_dynamo_generic_error(e)),
+    }
+}
+
+/// Every batch ever generated, newest first. There are few enough that a scan is fine.
+pub async fn list_key_batches(
+    client: &DynamoClient,
+) -> Result<Vec<KeyBatchSummary>, ServerFnError<NexusError>> {
+    let mut batches = Vec::new();
+    let mut exclusive_start_key = None;
+    loop {
+        let scan = client
+            .scan()
+            .table_name(get_key_batches_table_name())
+            .set_exclusive_start_key(exclusive_start_key.clone());
+        let db_result = send_with_retry(
+            DynamoOperation::read("list_key_batches", get_key_batches_table_name()),
+            || scan.clone().send(),
+        )
+        .await;
+        let output = match db_result {
+            Ok(o) => o,
+            Err(e) => return Err(handle_dynamo_generic_error(e)),
+        };
+        for item in output.items() {
+            batches.push(parse_key_batch(item)?);
+        }
+        exclusive_start_key = output.last_evaluated_key;
+        if exclusive_start_key.is_none() {
+            break;
+        }
+    }
+    batches.sort_by_key(|batch| std::cmp::Reverse(batch.created_at));
+    Ok(batches)
+}
+
+/// Stores freshly generated keys, 25 to a request. Keys DynamoDB leaves unprocessed are resent
+/// with backoff.
+pub async fn put_product_keys(
+    client: &DynamoClient,
+    keys: &[ProductKey],
+) -> Result<(), ServerFnError<NexusError>> {
+    for chunk in keys.chunks(BATCH_WRITE_LIMIT) {
+        let mut requests = chunk
+            .iter()
+            .map(|key| {
+                PutRequest::builder()
+                    .set_item(Some(product_key_to_item(key)))
+                    .build()
+                    .map(|put| WriteRequest::builder().put_request(put).build())
+                    .map_err(|e| {
+                        log::error!("Could not build put request for product key {:?}", e);
+                        ServerFnError::from(NexusError::Unhandled)
+                    })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut attempt = 0;
+        while !requests.is_empty() {
+            if attempt > 0 {
+                if attempt > MAX_UNPROCESSED_RETRIES {
+                    log::error!(
+                        "{} product keys were still unprocessed after {} retries",
+                        requests.len(),
+                        MAX_UNPROCESSED_RETRIES
+                    );
+                    return Err(ServerFnError::from(NexusError::DynamoThrottled));
+                }
+                tokio::time::sleep(backoff_with_jitter(attempt)).await;
+            }
+            let batch_write = client
+                .batch_write_item()
+                .request_items(get_product_keys_table_name(), requests);
+            let write_result = send_with_retry(
+                DynamoOperation::write("put_product_keys", get_product_keys_table_name()),
+                || batch_write.clone().send(),
+            )
+            .await;
+            requests = match write_result {
+                Ok(o) => o
+                    .unprocessed_items
+                    .and_then(|mut unprocessed| unprocessed.remove(get_product_keys_table_name()))
+                    .unwrap_or_default(),
+                Err(e) => return Err(handle_dynamo_generic_error(e)),
+            };
+            attempt += 1;
+        }
+    }
+    Ok(())
+}
+
+pub async fn get_product_key(
+    client: &DynamoClient,
+    key_hash: &str,
+) -> Result<Option<ProductKey>, ServerFnError<NexusError>> {
+    let get_item = client
+        .get_item()
+        .table_name(get_product_keys_table_name())
+        .key(KEY_HASH, AttributeValue::S(key_hash.to_string()))
+        .consistent_read(true);
+    let db_result = send_with_retry(
+        DynamoOperation::read("get_product_key", get_product_keys_table_name()),
+        || get_item.clone().send(),
+    )
+    .await;
+
+    match db_result {
+        Ok(o) => o.item.as_ref().map(parse_product_key).transpose(),
+        Err(e) => Err(handle_dynamo_generic_error(e)),
+    }
+}
+
+/// Every key of a batch
+pub async fn list_keys_in_batch(
+    client: &DynamoClient,
+    batch_id: &str,
+) -> Result<Vec<ProductKey>, ServerFnError<NexusError>> {
+    let mut keys = Vec::new();
+    let mut exclusive_start_key = None;
+    loop {
+        let query = client
+            .query()
+            .table_name(get_product_keys_table_name())
+            .index_name(BATCH_ID_INDEX)
+            .key_condition_expression("#batch_id = :batch_id")
+            .expression_attribute_names("#batch_id", BATCH_ID)
+            .expression_attribute_values(":batch_id", AttributeValue::S(batch_id.to_string()))
+            .set_exclusive_start_key(exclusive_start_key.clone());
+        let db_result = send_with_retry(
+            DynamoOperation::read("list_keys_in_batch", get_product_keys_table_name()),
+            || query.clone().send(),
+        )
+        .await;
+        let output = match db_result {
+            Ok(o) => o,
+            Err(e) => return Err(handle_dynamo_generic_error(e)),
+        };
+        for item in output.items() {
+            keys.push(parse_product_key(item)?);
+        }
+        exclusive_start_key = output.last_evaluated_key;
+        if exclusive_start_key.is_none() {
+            break;
+        }
+    }
+    Ok(keys)
+}
+
+/// Marks the key as redeemed by `user_uuid`, unless someone else redeemed it first. Redeeming a
+/// key again with the account that has it succeeds, so a redemption that failed halfway can be
+/// retried.
+pub async fn redeem_product_key(
+    client: &DynamoClient,
+    key_hash: &str,
+    user_uuid: &str,
+    now: i64,
+) -> Result<Redemption, ServerFnError<NexusError>> {
+    let update = client
+        .update_item()
+        .table_name(get_product_keys_table_name())
+        .key(KEY_HASH, AttributeValue::S(key_hash.to_string()))
+        .update_expression(
+            "SET #redeemed_by = :user_uuid, #redeemed_at = if_not_exists(#redeemed_at, :now)",
+        )
+        .condition_expression(
+            "attribute_exists(#key_hash) AND (attribute_not_exists(#redeemed_by) OR #redeemed_by = :user_uuid)",
+        )
+        .expression_attribute_names("#key_hash", KEY_HASH)
+        .expression_attribute_names("#redeemed_by", REDEEMED_BY_USER_UUID)
+        .expression_attribute_names("#redeemed_at", REDEEMED_AT)
+        .expression_attribute_values(":user_uuid", AttributeValue::S(user_uuid.to_string()))
+        .expression_attribute_values(":now", AttributeValue::N(now.to_string()))
+        .return_values(ReturnValue::AllNew);
+    let update_result = send_with_retry(
+        DynamoOperation::write("redeem_product_key", get_product_keys_table_name()),
+        || update.clone().send(),
+    )
+    .await;
+
+    match update_result {
+        Ok(o) => match o.attributes.as_ref() {
+            Some(item) => Ok(Redemption::Redeemed(parse_product_key(item)?)),
+            None => {
+                log::error!("Redeeming product key {} returned no attributes", key_hash);
+                Err(ServerFnError::from(NexusError::Unhandled))
+            }
+        },
+        Err(e) if e.kind == DynamoErrorKind::ConditionalCheckFailed => {
+            // The condition doesn't say which half failed
+            match get_product_key(client, key_hash).await? {
+                Some(_) => Ok(Redemption::AlreadyRedeemed),
+                None => Ok(Redemption::NotFound),
+            }
+        }
+        Err(e) => Err(handle_dynamo_generic_error(e)),
+    }
+}
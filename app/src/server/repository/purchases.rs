@@ -0,0 +1,523 @@
+use super::super::{
+    globals::{
+        dynamo::{
+            constants::index::{PAYMENT_INTENT_ID_INDEX, USER_UUID_INDEX},
+            constants::purchase_attributes::{
+                AMOUNT_DISCOUNT, AMOUNT_TAX, AMOUNT_TOTAL, CHECKOUT_SESSION_ID, COUNTRY,
+                CREATED_AT, CURRENCY, DISCOUNT_CODE, EMAIL, GIFT, PAYMENT_INTENT_ID, PRODUCT_ID,
+                RECEIPT_SENT_AT, SALE, STATUS, UPDATED_AT, USER_UUID,
+            },
+            number_attribute, string_attribute,
+        },
+        dynamo_error::{send_with_retry, DynamoErrorKind, DynamoOperation},
+        env_var::get_purchases_table_name,
+    },
+    utilities::handle_dynamo_generic_error,
+};
+use crate::errors::{NexusError, UNHANDLED};
+use aws_sdk_dynamodb::{types::AttributeValue, Client as DynamoClient};
+use leptos::ServerFnError;
+use std::collections::HashMap;
+
+/// Ledger id of one product of a cart checkout. Checkouts of a single product are recorded
+/// under the checkout session id itself.
+pub fn line_purchase_id(checkout_session_id: &str, product_id: &str) -> String {
+    format!("{}#{}", checkout_session_id, product_id)
+}
+
+/// The checkout session a ledger id belongs to
+pub fn checkout_session_of(purchase_id: &str) -> &str {
+    purchase_id
+        .split_once('#')
+        .map_or(purchase_id, |(checkout_session_id, _)| checkout_session_id)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PurchaseStatus {
+    /// Checked out with a delayed payment method (e.g. a bank debit) whose money hasn't arrived
+    Pending,
+    /// The delayed payment never arrived
+    Failed,
+    Paid,
+    /// Fully refunded
+    Refunded,
+    /// The buyer opened a dispute with their bank, and it hasn't been decided yet
+    Disputed,
+    /// The dispute was decided in the buyer's favour, so the money is gone
+    DisputeLost,
+    /// The card issuer reported the payment as likely fraudulent (a Radar early fraud warning)
+    FraudWarning,
+}
+
+impl PurchaseStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PurchaseStatus::Pending => "pending",
+            PurchaseStatus::Failed => "failed",
+            PurchaseStatus::Paid => "paid",
+            PurchaseStatus::Refunded => "refunded",
+            PurchaseStatus::Disputed => "disputed",
+            PurchaseStatus::DisputeLost => "dispute_lost",
+            PurchaseStatus::FraudWarning => "fraud_warning",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "pending" => Some(PurchaseStatus::Pending),
+            "failed" => Some(PurchaseStatus::Failed),
+            "paid" => Some(PurchaseStatus::Paid),
+            "refunded" => Some(PurchaseStatus::Refunded),
+            "disputed" => Some(PurchaseStatus::Disputed),
+            "dispute_lost" => Some(PurchaseStatus::DisputeLost),
+            "fraud_warning" => Some(PurchaseStatus::FraudWarning),
+            _ => None,
+        }
+    }
+
+    /// Whether the buyer should be able to download what they bought. Disputed and flagged
+    /// purchases are suspended until they're resolved.
+    pub fn grants_access(self) -> bool {
+        matches!(self, PurchaseStatus::Paid)
+    }
+
+    /// The status a purchase moves to when something happens to its payment, or `None` if it
+    /// stays as it is
+    pub fn after(self, change: PaymentChange) -> Option<PurchaseStatus> {
+        use PurchaseStatus::*;
+        let next = match (self, change) {
+            // There's no money to refund or dispute yet (or ever)
+            (Pending | Failed, _) => return None,
+            (_, PaymentChange::Refunded) => Refunded,
+            // A dispute over money we already gave back doesn't change anything
+            (Refunded, _) => return None,
+            (_, PaymentChange::DisputeOpened) => Disputed,
+            (Disputed, PaymentChange::DisputeWon) => Paid,
+            (_, PaymentChange::DisputeWon) => return None,
+            (_, PaymentChange::DisputeLost) => DisputeLost,
+            (Paid, PaymentChange::FraudWarning) => FraudWarning,
+            (_, PaymentChange::FraudWarning) => return None,
+        };
+        (next != self).then_some(next)
+    }
+}
+
+/// Something that happened to the payment of a purchase after checkout
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PaymentChange {
+    Refunded,
+    DisputeOpened,
+    DisputeWon,
+    DisputeLost,
+    FraudWarning,
+}
+
+/// A completed checkout session, as recorded when its webhook arrived
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Purchase {
+    /// The ledger id: the checkout session id, or for a product of a cart checkout
+    /// [`line_purchase_id`]
+    pub checkout_session_id: String,
+    pub user_uuid: String,
+    /// The buyer's account email at the time of purchase
+    pub email: String,
+    pub product_id: String,
+    pub payment_intent_id: Option<String>,
+    /// In the currency's smallest unit
+    pub amount_total: i64,
+    pub currency: String,
+    pub status: PurchaseStatus,
+    pub created_at: i64,
+    pub updated_at: i64,
+    /// Bought for someone else. The product goes to whoever claims the gift (see
+    /// [`super::gifts`]) rather than to the buyer.
+    pub gift: bool,
+    /// Taken off the regular price, in the same unit as `amount_total`: what Stripe discounted
+    /// (sale coupons and promotion codes) plus the markdown of a fixed price sale
+    pub amount_discount: i64,
+    /// The sale running at checkout
+    pub sale: Option<String>,
+    /// The Stripe promotion code (or coupon) the discount came from
+    pub discount_code: Option<String>,
+    /// Tax included in `amount_total`
+    pub amount_tax: i64,
+    /// Unix timestamp (seconds) the receipt was first emailed
+    pub receipt_sent_at: Option<i64>,
+    /// ISO 3166-1 alpha-2 code of the buyer's billing address, if Stripe collected one
+    pub country: Option<String>,
+}
+
+pub fn purchase_to_item(purchase: &Purchase) -> HashMap<String, AttributeValue> {
+    let mut item = HashMap::from([
+        (
+            CHECKOUT_SESSION_ID.to_string(),
+            AttributeValue::S(purchase.checkout_session_id.clone()),
+        ),
+        (
+            USER_UUID.to_string(),
+            AttributeValue::S(purchase.user_uuid.clone()),
+        ),
+        (EMAIL.to_string(), AttributeValue::S(purchase.email.clone())),
+        (
+            PRODUCT_ID.to_string(),
+            AttributeValue::S(purchase.product_id.clone()),
+        ),
+        (
+            AMOUNT_TOTAL.to_string(),
+            AttributeValue::N(purchase.amount_total.to_string()),
+        ),
+        (
+            CURRENCY.to_string(),
+            AttributeValue::S(purchase.currency.clone()),
+        ),
+        (
+            STATUS.to_string(),
+            AttributeValue::S(purchase.status.as_str().to_string()),
+        ),
+        (
+            CREATED_AT.to_string(),
+            AttributeValue::N(purchase.created_at.to_string()),
+        ),
+        (
+            UPDATED_AT.to_string(),
+            AttributeValue::N(purchase.updated_at.to_string()),
+        ),
+    ]);
+    // Index key attributes can't be empty strings, so this one is left out entirely instead
+    if let Some(payment_intent_id) = &purchase.payment_intent_id {
+        item.insert(
+            PAYMENT_INTENT_ID.to_string(),
+            AttributeValue::S(payment_intent_id.clone()),
+        );
+    }
+    if purchase.gift {
+        item.insert(GIFT.to_string(), AttributeValue::Bool(true));
+    }
+    if purchase.amount_discount != 0 {
+        item.insert(
+            AMOUNT_DISCOUNT.to_string(),
+            AttributeValue::N(purchase.amount_discount.to_string()),
+        );
+    }
+    if let Some(sale) = &purchase.sale {
+        item.insert(SALE.to_string(), AttributeValue::S(sale.clone()));
+    }
+    if let Some(discount_code) = &purchase.discount_code {
+        item.insert(
+            DISCOUNT_CODE.to_string(),
+            AttributeValue::S(discount_code.clone()),
+        );
+    }
+    if purchase.amount_tax != 0 {
+        item.insert(
+            AMOUNT_TAX.to_string(),
+            AttributeValue::N(purchase.amount_tax.to_string()),
+        );
+    }
+    if let Some(receipt_sent_at) = purchase.receipt_sent_at {
+        item.insert(
+            RECEIPT_SENT_AT.to_string(),
+            AttributeValue::N(receipt_sent_at.to_string()),
+        );
+    }
+    if let Some(country) = &purchase.country {
+        item.insert(COUNTRY.to_string(), AttributeValue::S(country.clone()));
+    }
+    item
+}
+
+pub fn parse_purchase(
+    item: &HashMap<String, AttributeValue>,
+) -> Result<Purchase, ServerFnError<NexusError>> {
+    let required_string = |name: &str| {
+        string_attribute(item, name)?.ok_or_else(|| {
+            log::error!("Purchase is missing {}", name);
+            NexusError::Unhandled
+        })
+    };
+    let required_number = |name: &str| {
+        number_attribute(item, name)?.ok_or_else(|| {
+            log::error!("Purchase is missing {}", name);
+            NexusError::Unhandled
+        })
+    };
+    let status = required_string(STATUS)?;
+    Ok(Purchase {
+        checkout_session_id: required_string(CHECKOUT_SESSION_ID)?,
+        user_uuid: required_string(USER_UUID)?,
+        email: required_string(EMAIL)?,
+        product_id: required_string(PRODUCT_ID)?,
+        payment_intent_id: string_attribute(item, PAYMENT_INTENT_ID)?,
+        amount_total: required_number(AMOUNT_TOTAL)?,
+        currency: required_string(CURRENCY)?,
+        status: PurchaseStatus::parse(&status).ok_or_else(|| {
+            log::error!("Unknown purchase status {}", status);
+            UNHANDLED
+        })?,
+        created_at: required_number(CREATED_AT)?,
+        updated_at: required_number(UPDATED_AT)?,
+        gift: matches!(item.get(GIFT), Some(AttributeValue::Bool(true))),
+        amount_discount: number_attribute(item, AMOUNT_DISCOUNT)?.unwrap_or(0),
+        sale: string_attribute(item, SALE)?,
+        discount_code: string_attribute(item, DISCOUNT_CODE)?,
+        amount_tax: number_attribute(item, AMOUNT_TAX)?.unwrap_or(0),
+        receipt_sent_at: number_attribute(item, RECEIPT_SENT_AT)?,
+        country: string_attribute(item, COUNTRY)?,
+    })
+}
+
+/// Records a purchase unless its checkout session was recorded already, returning whether it
+/// was newly recorded
+pub async fn record_purchase(
+    client: &DynamoClient,
+    purchase: &Purchase,
+) -> Result<bool, ServerFnError<NexusError>> {
+    let put = client
+        .put_item()
+        .table_name(get_purchases_table_name())
+        .set_item(Some(purchase_to_item(purchase)))
+        .condition_expression(format!("attribute_not_exists({})", CHECKOUT_SESSION_ID));
+    let put_result = send_with_retry(
+        DynamoOperation::write("record_purchase", get_purchases_table_name()),
+        || put.clone().send(),
+    )
+    .await;
+
+    match put_result {
+        Ok(_) => Ok(true),
+        Err(e) if e.kind == DynamoErrorKind::ConditionalCheckFailed => Ok(false),
+        Err(e) => Err(handle_dynamo_generic_error(e)),
+    }
+}
+
+pub async fn get_purchase(
+    client: &DynamoClient,
+    checkout_session_id: &str,
+) -> Result<Option<Purchase>, ServerFnError<NexusError>> {
+    let get_item = client
+        .get_item()
+        .table_name(get_purchases_table_name())
+        .key(
+            CHECKOUT_SESSION_ID,
+            AttributeValue::S(checkout_session_id.to_string()),
+        )
+        .consistent_read(true);
+    let db_result = send_with_retry(
+        DynamoOperation::read("get_purchase", get_purchases_table_name()),
+        || get_item.clone().send(),
+    )
+    .await;
+
+    match db_result {
+        Ok(o) => o.item.as_ref().map(parse_purchase).transpose(),
+        Err(e) => Err(handle_dynamo_generic_error(e)),
+    }
+}
+
+/// The purchases paid for by a payment intent (one per product for a cart checkout), which is
+/// how refunds, disputes and fraud warnings are traced back to what was bought
+pub async fn find_purchases_by_payment_intent(
+    client: &DynamoClient,
+    payment_intent_id: &str,
+) -> Result<Vec<Purchase>, ServerFnError<NexusError>> {
+    let query = client
+        .query()
+        .table_name(get_purchases_table_name())
+        .index_name(PAYMENT_INTENT_ID_INDEX)
+        .key_condition_expression("#payment_intent_id = :payment_intent_id")
+        .expression_attribute_names("#payment_intent_id", PAYMENT_INTENT_ID)
+        .expression_attribute_values(
+            ":payment_intent_id",
+            AttributeValue::S(payment_intent_id.to_string()),
+        );
+    let db_result = send_with_retry(
+        DynamoOperation::read(
+            "find_purchases_by_payment_intent",
+            get_purchases_table_name(),
+        ),
+        || query.clone().send(),
+    )
+    .await;
+
+    match db_result {
+        Ok(o) => o.items().iter().map(parse_purchase).collect(),
+        Err(e) => Err(handle_dynamo_generic_error(e)),
+    }
+}
+
+/// Every purchase made by an account, oldest first
+pub async fn list_purchases_for_user(
+    client: &DynamoClient,
+    user_uuid: &str,
+) -> Result<Vec<Purchase>, ServerFnError<NexusError>> {
+    let mut purchases = Vec::new();
+    let mut exclusive_start_key = None;
+    loop {
+        let query = client
+            .query()
+            .table_name(get_purchases_table_name())
+            .index_name(USER_UUID_INDEX)
+            .key_condition_expression("#user_uuid = :user_uuid")
+            .expression_attribute_names("#user_uuid", USER_UUID)
+            .expression_attribute_values(":user_uuid", AttributeValue::S(user_uuid.to_string()))
+            .set_exclusive_start_key(exclusive_start_key.clone());
+        let db_result = send_with_retry(
+            DynamoOperation::read("list_purchases_for_user", get_purchases_table_name()),
+            || query.clone().send(),
+        )
+        .await;
+        let output = match db_result {
+            Ok(o) => o,
+            Err(e) => return Err(handle_dynamo_generic_error(e)),
+        };
+        for item in output.items() {
+            purchases.push(parse_purchase(item)?);
+        }
+        exclusive_start_key = output.last_evaluated_key;
+        if exclusive_start_key.is_none() {
+            break;
+        }
+    }
+    purchases.sort_by_key(|purchase| purchase.created_at);
+    Ok(purchases)
+}
+
+/// Every purchase made from `from` up to (not including) `to`, oldest first. This scans the
+/// whole ledger, which is fine for reports an admin runs now and then.
+pub async fn list_purchases_created_between(
+    client: &DynamoClient,
+    from: i64,
+    to: i64,
+) -> Result<Vec<Purchase>, ServerFnError<NexusError>> {
+    let mut purchases = Vec::new();
+    let mut exclusive_start_key = None;
+    loop {
+        let scan = client
+            .scan()
+            .table_name(get_purchases_table_name())
+            .filter_expression("#created_at >= :from AND #created_at < :to")
+            .expression_attribute_names("#created_at", CREATED_AT)
+            .expression_attribute_values(":from", AttributeValue::N(from.to_string()))
+            .expression_attribute_values(":to", AttributeValue::N(to.to_string()))
+            .set_exclusive_start_key(exclusive_start_key.clone());
+        let db_result = send_with_retry(
+            DynamoOperation::read("list_purchases_created_between", get_purchases_table_name()),
+            || scan.clone().send(),
+        )
+        .await;
+        let output = match db_result {
+            Ok(o) => o,
+            Err(e) => return Err(handle_dynamo_generic_error(e)),
+        };
+        for item in output.items() {
+            purchases.push(parse_purchase(item)?);
+        }
+        exclusive_start_key = output.last_evaluated_key;
+        if exclusive_start_key.is_none() {
+            break;
+        }
+    }
+    purchases.sort_by_key(|purchase| purchase.created_at);
+    Ok(purchases)
+}
+
+/// Moves a purchase from `previous` to `status`. Returns `false` without changing anything if
+/// the purchase is no longer `previous`, i.e. something else changed it since it was read.
+pub async fn set_purchase_status(
+    client: &DynamoClient,
+    checkout_session_id: &str,
+    previous: PurchaseStatus,
+    status: PurchaseStatus,
+    now: i64,
+) -> Result<bool, ServerFnError<NexusError>> {
+    let update = client
+        .update_item()
+        .table_name(get_purchases_table_name())
+        .key(
+            CHECKOUT_SESSION_ID,
+            AttributeValue::S(checkout_session_id.to_string()),
+        )
+        .update_expression("SET #status = :status, #updated_at = :now")
+        .condition_expression("#status = :previous")
+        .expression_attribute_names("#status", STATUS)
+        .expression_attribute_names("#updated_at", UPDATED_AT)
+        .expression_attribute_values(":status", AttributeValue::S(status.as_str().to_string()))
+        .expression_attribute_values(
+            ":previous",
+            AttributeValue::S(previous.as_str().to_string()),
+        )
+        .expression_attribute_values(":now", AttributeValue::N(now.to_string()));
+    let update_result = send_with_retry(
+        DynamoOperation::write("set_purchase_status", get_purchases_table_name()),
+        || update.clone().send(),
+    )
+    .await;
+
+    match update_result {
+        Ok(_) => Ok(true),
+        Err(e) if e.kind == DynamoErrorKind::ConditionalCheckFailed => Ok(false),
+        Err(e) => Err(handle_dynamo_generic_error(e)),
+    }
+}
+
+pub async fn mark_receipt_sent(
+    client: &DynamoClient,
+    checkout_session_id: &str,
+    now: i64,
+) -> Result<(), ServerFnError<NexusError>> {
+    let update = client
+        .update_item()
+        .table_name(get_purchases_table_name())
+        .key(
+            CHECKOUT_SESSION_ID,
+            AttributeValue::S(checkout_session_id.to_string()),
+        )
+        .update_expression("SET #receipt_sent_at = if_not_exists(#receipt_sent_at, :now)")
+        .condition_expression("attribute_exists(#checkout_session_id)")
+        .expression_attribute_names("#receipt_sent_at", RECEIPT_SENT_AT)
+        .expression_attribute_names("#checkout_session_id", CHECKOUT_SESSION_ID)
+        .expression_attribute_values(":now", AttributeValue::N(now.to_string()));
+    let update_result = send_with_retry(
+        DynamoOperation::write("mark_receipt_sent", get_purchases_table_name()),
+        || update.clone().send(),
+    )
+    .await;
+
+    match update_result {
+        Ok(_) => Ok(()),
+        Err(e) => Err(handle_dynamo_generic_error(e)),
+    }
+}
+
+/// Whether the buyer of `purchase` owns its product through some other purchase that still
+/// grants access, in which case taking this one away mustn't revoke the product. Gifts they
+/// bought don't count, those belong to whoever claimed them.
+pub fn owned_through_other_purchase(purchases: &[Purchase], purchase: &Purchase) -> bool {
+    purchases.iter().any(|other| {
+        other.checkout_session_id != purchase.checkout_session_id
+            && !other.gift
+            && other.product_id == purchase.product_id
+            && other.status.grants_access()
+    })
+}
+
+/// Whether the owner of `purchase` has `entitlement` through some other purchase that still
+/// grants access, e.g. a bundle that includes it. `purchases` are the ones the owner made, and
+/// `claimed_gifts` those of gifts they claimed (which are stored under whoever bought them).
+/// `entitlements_of` maps a product to the entitlements it grants.
+pub fn entitled_through_other_purchase(
+    purchases: &[Purchase],
+    claimed_gifts: &[Purchase],
+    purchase: &Purchase,
+    entitlement: &str,
+    entitlements_of: impl Fn(&str) -> Vec<String>,
+) -> bool {
+    let grants = |other: &Purchase| {
+        other.checkout_session_id != purchase.checkout_session_id
+            && other.status.grants_access()
+            && entitlements_of(&other.product_id)
+                .iter()
+                .any(|other_entitlement| other_entitlement == entitlement)
+    };
+    purchases.iter().any(|other| !other.gift && grants(other)) || claimed_gifts.iter().any(grants)
+}
@@ -0,0 +1,49 @@
+use super::super::{
+    globals::{
+        dynamo::{
+            constants::rate_limit_attributes::{ATTEMPTS, BUCKET, EXPIRES_AT},
+            number_attribute,
+        },
+        dynamo_error::{send_with_retry, DynamoOperation},
+        env_var::get_rate_limits_table_name,
+    },
+    utilities::handle_dynamo_generic_error,
+};
+use crate::errors::NexusError;
+use aws_sdk_dynamodb::{
+    types::{AttributeValue, ReturnValue},
+    Client as DynamoClient,
+};
+use leptos::ServerFnError;
+
+/// Counts one more attempt in `bucket`, returning how many there have been in it so far. The
+/// bucket is deleted by the table's TTL some time after `expires_at`.
+pub async fn count_attempt(
+    client: &DynamoClient,
+    bucket: &str,
+    expires_at: i64,
+) -> Result<i64, ServerFnError<NexusError>> {
+    let update = client
+        .update_item()
+        .table_name(get_rate_limits_table_name())
+        .key(BUCKET, AttributeValue::S(bucket.to_string()))
+        .update_expression("ADD #attempts :one SET #expires_at = :expires_at")
+        .expression_attribute_names("#attempts", ATTEMPTS)
+        .expression_attribute_names("#expires_at", EXPIRES_AT)
+        .expression_attribute_values(":one", AttributeValue::N("1".to_string()))
+        .expression_attribute_values(":expires_at", AttributeValue::N(expires_at.to_string()))
+        .return_values(ReturnValue::UpdatedNew);
+    let update_result = send_with_retry(
+        DynamoOperation::write("count_attempt", get_rate_limits_table_name()),
+        || update.clone().send(),
+    )
+    .await;
+
+    match update_result {
+        Ok(o) => {
+            let attributes = o.attributes.unwrap_or_default();
+            Ok(number_attribute(&attributes, ATTEMPTS)?.unwrap_or(1))
+        }
+        Err(e) => Err(handle_dynamo_generic_error(e)),
+    }
+}
@@ -0,0 +1,313 @@
+use super::super::{
+    globals::{
+        dynamo::{
+            constants::{
+                index::{REFUND_REQUEST_STATUS_INDEX, USER_UUID_INDEX},
+                refund_request_attributes::{
+                    AMOUNT, CURRENCY, EMAIL, INELIGIBILITY, PRODUCT_ID, PURCHASE_ID, REASON,
+                    REQUESTED_AT, REVIEWED_AT, REVIEWED_BY, STATUS, STRIPE_REFUND_ID, USER_UUID,
+                },
+            },
+            number_attribute, string_attribute,
+        },
+        dynamo_error::{send_with_retry, DynamoErrorKind, DynamoOperation},
+        env_var::get_refund_requests_table_name,
+    },
+    utilities::handle_dynamo_generic_error,
+};
+use crate::{
+    errors::NexusError,
+    orders::{RefundRequest, RefundRequestStatus},
+};
+use aws_sdk_dynamodb::{types::AttributeValue, Client as DynamoClient};
+use leptos::ServerFnError;
+use std::collections::HashMap;
+
+pub fn refund_request_to_item(request: &RefundRequest) -> HashMap<String, AttributeValue> {
+    let mut item = HashMap::from([
+        (
+            PURCHASE_ID.to_string(),
+            AttributeValue::S(request.purchase_id.clone()),
+        ),
+        (
+            USER_UUID.to_string(),
+            AttributeValue::S(request.user_uuid.clone()),
+        ),
+        (EMAIL.to_string(), AttributeValue::S(request.email.clone())),
+        (
+            PRODUCT_ID.to_string(),
+            AttributeValue::S(request.product_id.clone()),
+        ),
+        (
+            AMOUNT.to_string(),
+            AttributeValue::N(request.amount.to_string()),
+        ),
+        (
+            CURRENCY.to_string(),
+            AttributeValue::S(request.currency.clone()),
+        ),
+        (
+            REASON.to_string(),
+            AttributeValue::S(request.reason.clone()),
+        ),
+        (
+            STATUS.to_string(),
+            AttributeValue::S(request.status.as_str().to_string()),
+        ),
+        (
+            REQUESTED_AT.to_string(),
+            AttributeValue::N(request.requested_at.to_string()),
+        ),
+    ]);
+    if let Some(ineligibility) = &request.ineligibility {
+        item.insert(
+            INELIGIBILITY.to_string(),
+            AttributeValue::S(ineligibility.clone()),
+        );
+    }
+    if let Some(reviewed_by) = &request.reviewed_by {
+        item.insert(
+            REVIEWED_BY.to_string(),
+            AttributeValue::S(reviewed_by.clone()),
+        );
+    }
+    if let Some(reviewed_at) = request.reviewed_at {
+        item.insert(
+            REVIEWED_AT.to_string(),
+            AttributeValue::N(reviewed_at.to_string()),
+        );
+    }
+    if let Some(stripe_refund_id) = &request.stripe_refund_id {
+        item.insert(
+            STRIPE_REFUND_ID.to_string(),
+            AttributeValue::S(stripe_refund_id.clone()),
+        );
+    }
+    item
+}
+
+pub fn parse_refund_request(
+    item: &HashMap<String, AttributeValue>,
+) -> Result<RefundRequest, ServerFnError<NexusError>> {
+    let required_string = |name: &str| {
+        string_attribute(item, name)?.ok_or_else(|| {
+            log::error!("Refund request is missing {}", name);
+            NexusError::Unhandled
+        })
+    };
+    let required_number = |name: &str| {
+        number_attribute(item, name)?.ok_or_else(|| {
+            log::error!("Refund request is missing {}", name);
+            NexusError::Unhandled
+        })
+    };
+    let status = required_string(STATUS)?;
+    Ok(RefundRequest {
+        purchase_id: required_string(PURCHASE_ID)?,
+        user_uuid: required_string(USER_UUID)?,
+        email: required_string(EMAIL)?,
+        product_id: required_string(PRODUCT_ID)?,
+        amount: required_number(AMOUNT)?,
+        currency: required_string(CURRENCY)?,
+        reason: string_attribute(item, REASON)?.unwrap_or_default(),
+        ineligibility: string_attribute(item, INELIGIBILITY)?,
+        status: RefundRequestStatus::parse(&status).ok_or_else(|| {
+            log::error!("Refund request has unknown status {}", status);
+            NexusError::Unhandled
+        })?,
+        requested_at: required_number(REQUESTED_AT)?,
+        reviewed_by: string_attribute(item, REVIEWED_BY)?,
+        reviewed_at: number_attribute(item, REVIEWED_AT)?,
+        stripe_refund_id: string_attribute(item, STRIPE_REFUND_ID)?,
+    })
+}
+
+/// Records a refund request unless its purchase already has one, returning whether it was newly
+/// recorded
+pub async fn create_refund_request(
+    client: &DynamoClient,
+    request: &RefundRequest,
+) -> Result<bool, ServerFnError<NexusError>> {
+    let put = client
+        .put_item()
+        .table_name(get_refund_requests_table_name())
+        .set_item(Some(refund_request_to_item(request)))
+        .condition_expression(format!("attribute_not_exists({})", PURCHASE_ID));
+    let put_result = send_with_retry(
+        DynamoOperation::write("create_refund_request", get_refund_requests_table_name()),
+        || put.clone().send(),
+    )
+    .await;
+
+    match put_result {
+        Ok(_) => Ok(true),
+        Err(e) if e.kind == DynamoErrorKind::ConditionalCheckFailed => Ok(false),
+        Err(e) => Err(handle_dynamo_generic_error(e)),
+    }
+}
+
+pub async fn get_refund_request(
+    client: &DynamoClient,
+    purchase_id: &str,
+) -> Result<Option<RefundRequest>, ServerFnError<NexusError>> {
+    let get_item = client
+        .get_item()
+        .table_name(get_refund_requests_table_name())
+        .key(PURCHASE_ID, AttributeValue::S(purchase_id.to_string()))
+        .consistent_read(true);
+    let db_result = send_with_retry(
+        DynamoOperation::read("get_refund_request", get_refund_requests_table_name()),
+        || get_item.clone().send(),
+    )
+    .await;
+
+    match db_result {
+        Ok(o) => o.item.as_ref().map(parse_refund_request).transpose(),
+        Err(e) => Err(handle_dynamo_generic_error(e)),
+    }
+}
+
+/// What changes about a refund request along with its status
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RefundRequestUpdate<'a> {
+    pub reviewed_by: Option<&'a str>,
+    pub reviewed_at: Option<i64>,
+    pub stripe_refund_id: Option<&'a str>,
+}
+
+/// Moves a refund request from `previous` to `status`, returning false if it wasn't `previous`
+/// any more
+pub async fn set_refund_request_status(
+    client: &DynamoClient,
+    purchase_id: &str,
+    previous: RefundRequestStatus,
+    status: RefundRequestStatus,
+    update: RefundRequestUpdate<'_>,
+) -> Result<bool, ServerFnError<NexusError>> {
+    let mut expression = "SET #status = :status".to_string();
+    let mut values = HashMap::from([
+        (
+            ":status".to_string(),
+            AttributeValue::S(status.as_str().to_string()),
+        ),
+        (
+            ":previous".to_string(),
+            AttributeValue::S(previous.as_str().to_string()),
+        ),
+    ]);
+    let mut names = HashMap::from([("#status".to_string(), STATUS.to_string())]);
+    let fields = [
+        (
+            REVIEWED_BY,
+            update.reviewed_by.map(|s| AttributeValue::S(s.to_string())),
+        ),
+        (
+            REVIEWED_AT,
+            update.reviewed_at.map(|n| AttributeValue::N(n.to_string())),
+        ),
+        (
+            STRIPE_REFUND_ID,
+            update
+                .stripe_refund_id
+                .map(|s| AttributeValue::S(s.to_string())),
+        ),
+    ];
+    for (name, value) in fields {
+        if let Some(value) = value {
+            expression.push_str(&format!(", #{name} = :{name}"));
+            names.insert(format!("#{}", name), name.to_string());
+            values.insert(format!(":{}", name), value);
+        }
+    }
+    let update = client
+        .update_item()
+        .table_name(get_refund_requests_table_name())
+        .key(PURCHASE_ID, AttributeValue::S(purchase_id.to_string()))
+        .update_expression(expression)
+        .condition_expression("#status = :previous")
+        .set_expression_attribute_names(Some(names))
+        .set_expression_attribute_values(Some(values));
+    let update_result = send_with_retry(
+        DynamoOperation::write(
+            "set_refund_request_status",
+            get_refund_requests_table_name(),
+        ),
+        || update.clone().send(),
+    )
+    .await;
+
+    match update_result {
+        Ok(_) => Ok(true),
+        Err(e) if e.kind == DynamoErrorKind::ConditionalCheckFailed => Ok(false),
+        Err(e) => Err(handle_dynamo_generic_error(e)),
+    }
+}
+
+async fn query_refund_requests(
+    client: &DynamoClient,
+    operation: &'static str,
+    index: &str,
+    attribute: &str,
+    value: &str,
+) -> Result<Vec<RefundRequest>, ServerFnError<NexusError>> {
+    let mut requests = Vec::new();
+    let mut exclusive_start_key = None;
+    loop {
+        let query = client
+            .query()
+            .table_name(get_refund_requests_table_name())
+            .index_name(index)
+            .key_condition_expression("#attribute = :value")
+            .expression_attribute_names("#attribute", attribute)
+            .expression_attribute_values(":value", AttributeValue::S(value.to_string()))
+            .set_exclusive_start_key(exclusive_start_key.clone());
+        let db_result = send_with_retry(
+            DynamoOperation::read(operation, get_refund_requests_table_name()),
+            || query.clone().send(),
+        )
+        .await;
+        let output = match db_result {
+            Ok(o) => o,
+            Err(e) => return Err(handle_dynamo_generic_error(e)),
+        };
+        for item in output.items() {
+            requests.push(parse_refund_request(item)?);
+        }
+        exclusive_start_key = output.last_evaluated_key;
+        if exclusive_start_key.is_none() {
+            break;
+        }
+    }
+    requests.sort_by_key(|request| request.requested_at);
+    Ok(requests)
+}
+
+/// Every refund request with `status`, oldest first
+pub async fn list_refund_requests_by_status(
+    client: &DynamoClient,
+    status: RefundRequestStatus,
+) -> Result<Vec<RefundRequest>, ServerFnError<NexusError>> {
+    query_refund_requests(
+        client,
+        "list_refund_requests_by_status",
+        REFUND_REQUEST_STATUS_INDEX,
+        STATUS,
+        status.as_str(),
+    )
+    .await
+}
+
+/// Every refund request an account made, oldest first
+pub async fn list_refund_requests_for_user(
+    client: &DynamoClient,
+    user_uuid: &str,
+) -> Result<Vec<RefundRequest>, ServerFnError<NexusError>> {
+    query_refund_requests(
+        client,
+        "list_refund_requests_for_user",
+        USER_UUID_INDEX,
+        USER_UUID,
+        user_uuid,
+    )
+    .await
+}
@@ -0,0 +1,288 @@
+use super::super::{
+    globals::{
+        dynamo::{
+            constants::{
+                index::{PURCHASE_ID_INDEX, REVENUE_TRANSFER_STATUS_INDEX},
+                revenue_transfer_attributes::{
+                    AMOUNT, AVAILABLE_AT, COLLABORATOR_ID, CREATED_AT, CURRENCY, PRODUCT_ID,
+                    PURCHASE_ID, STATUS, STRIPE_REVERSAL_ID, STRIPE_TRANSFER_ID, TRANSFER_ID,
+                    UPDATED_AT,
+                },
+            },
+            number_attribute, string_attribute,
+        },
+        dynamo_error::{send_with_retry, DynamoErrorKind, DynamoOperation},
+        env_var::get_revenue_transfers_table_name,
+    },
+    utilities::handle_dynamo_generic_error,
+};
+use crate::{
+    errors::NexusError,
+    orders::{RevenueTransfer, RevenueTransferStatus},
+};
+use aws_sdk_dynamodb::{types::AttributeValue, Client as DynamoClient};
+use leptos::ServerFnError;
+use std::collections::HashMap;
+
+/// The ledger id of a collaborator's share of a purchase
+pub fn revenue_transfer_id(purchase_id: &str, collaborator_id: &str) -> String {
+    format!("{}#{}", purchase_id, collaborator_id)
+}
+
+pub fn revenue_transfer_to_item(transfer: &RevenueTransfer) -> HashMap<String, AttributeValue> {
+    let mut item = HashMap::from([
+        (
+            TRANSFER_ID.to_string(),
+            AttributeValue::S(transfer.transfer_id.clone()),
+        ),
+        (
+            PURCHASE_ID.to_string(),
+            AttributeValue::S(transfer.purchase_id.clone()),
+        ),
+        (
+            COLLABORATOR_ID.to_string(),
+            AttributeValue::S(transfer.collaborator_id.clone()),
+        ),
+        (
+            PRODUCT_ID.to_string(),
+            AttributeValue::S(transfer.product_id.clone()),
+        ),
+        (
+            AMOUNT.to_string(),
+            AttributeValue::N(transfer.amount.to_string()),
+        ),
+        (
+            CURRENCY.to_string(),
+            AttributeValue::S(transfer.currency.clone()),
+        ),
+        (
+            STATUS.to_string(),
+            AttributeValue::S(transfer.status.as_str().to_string()),
+        ),
+        (
+            AVAILABLE_AT.to_string(),
+            AttributeValue::N(transfer.available_at.to_string()),
+        ),
+        (
+            CREATED_AT.to_string(),
+            AttributeValue::N(transfer.created_at.to_string()),
+        ),
+        (
+            UPDATED_AT.to_string(),
+            AttributeValue::N(transfer.updated_at.to_string()),
+        ),
+    ]);
+    if let Some(stripe_transfer_id) = &transfer.stripe_transfer_id {
+        item.insert(
+            STRIPE_TRANSFER_ID.to_string(),
+            AttributeValue::S(stripe_transfer_id.clone()),
+        );
+    }
+    if let Some(stripe_reversal_id) = &transfer.stripe_reversal_id {
+        item.insert(
+            STRIPE_REVERSAL_ID.to_string(),
+            AttributeValue::S(stripe_reversal_id.clone()),
+        );
+    }
+    item
+}
+
+pub fn parse_revenue_transfer(
+    item: &HashMap<String, AttributeValue>,
+) -> Result<RevenueTransfer, ServerFnError<NexusError>> {
+    let required_string = |name: &str| {
+        string_attribute(item, name)?.ok_or_else(|| {
+            log::error!("Revenue transfer is missing {}", name);
+            NexusError::Unhandled
+        })
+    };
+    let required_number = |name: &str| {
+        number_attribute(item, name)?.ok_or_else(|| {
+            log::error!("Revenue transfer is missing {}", name);
+            NexusError::Unhandled
+        })
+    };
+    let status = required_string(STATUS)?;
+    Ok(RevenueTransfer {
+        transfer_id: required_string(TRANSFER_ID)?,
+        purchase_id: required_string(PURCHASE_ID)?,
+        collaborator_id: required_string(COLLABORATOR_ID)?,
+        product_id: required_string(PRODUCT_ID)?,
+        amount: required_number(AMOUNT)?,
+        currency: required_string(CURRENCY)?,
+        status: RevenueTransferStatus::parse(&status).ok_or_else(|| {
+            log::error!("Revenue transfer has unknown status {}", status);
+            NexusError::Unhandled
+        })?,
+        available_at: required_number(AVAILABLE_AT)?,
+        stripe_transfer_id: string_attribute(item, STRIPE_TRANSFER_ID)?,
+        stripe_reversal_id: string_attribute(item, STRIPE_REVERSAL_ID)?,
+        created_at: required_number(CREATED_AT)?,
+        updated_at: required_number(UPDATED_AT)?,
+    })
+}
+
+/// Adds a share to the ledger unless it's already there, returning whether it was added
+pub async fn create_revenue_transfer(
+    client: &DynamoClient,
+    transfer: &RevenueTransfer,
+) -> Result<bool, ServerFnError<NexusError>> {
+    let put = client
+        .put_item()
+        .table_name(get_revenue_transfers_table_name())
+        .set_item(Some(revenue_transfer_to_item(transfer)))
+        .condition_expression(format!("attribute_not_exists({})", TRANSFER_ID));
+    let put_result = send_with_retry(
+        DynamoOperation::write(
+            "create_revenue_transfer",
+            get_revenue_transfers_table_name(),
+        ),
+        || put.clone().send(),
+    )
+    .await;
+
+    match put_result {
+        Ok(_) => Ok(true),
+        Err(e) if e.kind == DynamoErrorKind::ConditionalCheckFailed => Ok(false),
+        Err(e) => Err(handle_dynamo_generic_error(e)),
+    }
+}
+
+/// The Stripe objects a share gained along with its status
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RevenueTransferUpdate<'a> {
+    pub stripe_transfer_id: Option<&'a str>,
+    pub stripe_reversal_id: Option<&'a str>,
+}
+
+/// Moves a share from `previous` to `status`, returning false if it wasn't `previous` any more
+pub async fn set_revenue_transfer_status(
+    client: &DynamoClient,
+    transfer_id: &str,
+    previous: RevenueTransferStatus,
+    status: RevenueTransferStatus,
+    update: RevenueTransferUpdate<'_>,
+    now: i64,
+) -> Result<bool, ServerFnError<NexusError>> {
+    let mut expression = "SET #status = :status, #updated_at = :updated_at".to_string();
+    let mut values = HashMap::from([
+        (
+            ":status".to_string(),
+            AttributeValue::S(status.as_str().to_string()),
+        ),
+        (
+            ":previous".to_string(),
+            AttributeValue::S(previous.as_str().to_string()),
+        ),
+        (
+            ":updated_at".to_string(),
+            AttributeValue::N(now.to_string()),
+        ),
+    ]);
+    let mut names = HashMap::from([
+        ("#status".to_string(), STATUS.to_string()),
+        ("#updated_at".to_string(), UPDATED_AT.to_string()),
+    ]);
+    let fields = [
+        (STRIPE_TRANSFER_ID, update.stripe_transfer_id),
+        (STRIPE_REVERSAL_ID, update.stripe_reversal_id),
+    ];
+    for (name, value) in fields {
+        if let Some(value) = value {
+            expression.push_str(&format!(", #{name} = :{name}"));
+            names.insert(format!("#{}", name), name.to_string());
+            values.insert(format!(":{}", name), AttributeValue::S(value.to_string()));
+        }
+    }
+    let update = client
+        .update_item()
+        .table_name(get_revenue_transfers_table_name())
+        .key(TRANSFER_ID, AttributeValue::S(transfer_id.to_string()))
+        .update_expression(expression)
+        .condition_expression("#status = :previous")
+        .set_expression_attribute_names(Some(names))
+        .set_expression_attribute_values(Some(values));
+    let update_result = send_with_retry(
+        DynamoOperation::write(
+            "set_revenue_transfer_status",
+            get_revenue_transfers_table_name(),
+        ),
+        || update.clone().send(),
+    )
+    .await;
+
+    match update_result {
+        Ok(_) => Ok(true),
+        Err(e) if e.kind == DynamoErrorKind::ConditionalCheckFailed => Ok(false),
+        Err(e) => Err(handle_dynamo_generic_error(e)),
+    }
+}
+
+async fn query_revenue_transfers(
+    client: &DynamoClient,
+    operation: &'static str,
+    index: &str,
+    attribute: &str,
+    value: &str,
+) -> Result<Vec<RevenueTransfer>, ServerFnError<NexusError>> {
+    let mut transfers = Vec::new();
+    let mut exclusive_start_key = None;
+    loop {
+        let query = client
+            .query()
+            .table_name(get_revenue_transfers_table_name())
+            .index_name(index)
+            .key_condition_expression("#attribute = :value")
+            .expression_attribute_names("#attribute", attribute)
+            .expression_attribute_values(":value", AttributeValue::S(value.to_string()))
+            .set_exclusive_start_key(exclusive_start_key.clone());
+        let db_result = send_with_retry(
+            DynamoOperation::read(operation, get_revenue_transfers_table_name()),
+            || query.clone().send(),
+        )
+        .await;
+        let output = match db_result {
+            Ok(o) => o,
+            Err(e) => return Err(handle_dynamo_generic_error(e)),
+        };
+        for item in output.items() {
+            transfers.push(parse_revenue_transfer(item)?);
+        }
+        exclusive_start_key = output.last_evaluated_key;
+        if exclusive_start_key.is_none() {
+            break;
+        }
+    }
+    transfers.sort_by_key(|transfer| transfer.created_at);
+    Ok(transfers)
+}
+
+/// Every share with `status`, oldest first
+pub async fn list_revenue_transfers_by_status(
+    client: &DynamoClient,
+    status: RevenueTransferStatus,
+) -> Result<Vec<RevenueTransfer>, ServerFnError<NexusError>> {
+    query_revenue_transfers(
+        client,
+        "list_revenue_transfers_by_status",
+        REVENUE_TRANSFER_STATUS_INDEX,
+        STATUS,
+        status.as_str(),
+    )
+    .await
+}
+
+/// The collaborators' shares of one purchase
+pub async fn list_revenue_transfers_for_purchase(
+    client: &DynamoClient,
+    purchase_id: &str,
+) -> Result<Vec<RevenueTransfer>, ServerFnError<NexusError>> {
+    query_revenue_transfers(
+        client,
+        "list_revenue_transfers_for_purchase",
+        PURCHASE_ID_INDEX,
+        PURCHASE_ID,
+        purchase_id,
+    )
+    .await
+}
@@ -0,0 +1,206 @@
+use super::super::{
+    globals::{
+        dynamo::{
+            constants::table_attributes::{EMAIL, VERSION},
+            parse_number_attribute, update_setup, TableAttributeType,
+        },
+        dynamo_error::{send_with_retry, DynamoErrorKind, DynamoOperation},
+        env_var::get_table_name,
+    },
+    utilities::handle_dynamo_generic_error,
+};
+use crate::errors::NexusError;
+use aws_sdk_dynamodb::{
+    operation::update_item::builders::UpdateItemFluentBuilder, types::AttributeValue,
+    Client as DynamoClient,
+};
+use leptos::ServerFnError;
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+/// How many times a write is re-attempted against a freshly loaded row after losing a race
+/// with another writer before we give up with [`NexusError::ConcurrentModification`].
+const MAX_WRITE_ATTEMPTS: usize = 5;
+/// How long [`update_user_with_retry`] may spend in total, reloads and DynamoDB retries
+/// included, so a contended row can't hold a request past the Lambda timeout.
+const UPDATE_BUDGET: Duration = Duration::from_secs(2);
+
+pub type UserItem = HashMap<String, AttributeValue>;
+
+/// The changes to make to a single user row. Every write through [`update_user_with_retry`]
+/// also bumps the row's `version` attribute, so callers never touch it themselves.
+#[derive(Default, Debug, Clone)]
+pub struct UserUpdate {
+    set: Vec<(String, AttributeValue)>,
+    remove: Vec<String>,
+}
+
+impl UserUpdate {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set(mut self, attribute: &str, value: AttributeValue) -> Self {
+        self.set.push((attribute.to_string(), value));
+        self
+    }
+
+    pub fn remove(mut self, attribute: &str) -> Self {
+        self.remove.push(attribute.to_string());
+        self
+    }
+
+    /// Builds the update expression, e.g. `SET #a0 = :v0, #version = :next_version REMOVE #r0`
+    pub fn update_expression(&self) -> String {
+        let mut set_clauses = (0..self.set.len())
+            .map(|i| format!("#a{} = :v{}", i, i))
+            .collect::<Vec<String>>();
+        set_clauses.push("#version = :next_version".to_string());
+        let mut expression = format!("SET {}", set_clauses.join(", "));
+        if !self.remove.is_empty() {
+            let remove_clauses = (0..self.remove.len())
+                .map(|i| format!("#r{}", i))
+                .collect::<Vec<String>>();
+            expression = format!("{} REMOVE {}", expression, remove_clauses.join(", "));
+        }
+        expression
+    }
+
+    /// The row must still exist, and must still be at the version we read it at.
+    /// Rows written before versioning was introduced have no version attribute at all.
+    pub fn condition_expression(expected_version: Option<i64>) -> String {
+        match expected_version {
+            Some(_) => "attribute_exists(#email) AND #version = :expected_version".to_string(),
+            None => "attribute_exists(#email) AND attribute_not_exists(#version)".to_string(),
+        }
+    }
+
+    fn apply(
+        self,
+        builder: UpdateItemFluentBuilder,
+        expected_version: Option<i64>,
+    ) -> UpdateItemFluentBuilder {
+        let mut b = builder
+            .update_expression(self.update_expression())
+            .condition_expression(Self::condition_expression(expected_version))
+            .expression_attribute_names("#email", EMAIL)
+            .expression_attribute_names("#version", VERSION)
+            .expression_attribute_values(
+                ":next_version",
+                AttributeValue::N((expected_version.unwrap_or(0) + 1).to_string()),
+            );
+        if let Some(expected_version) = expected_version {
+            b = b.expression_attribute_values(
+                ":expected_version",
+                AttributeValue::N(expected_version.to_string()),
+            );
+        }
+        for (i, (name, value)) in self.set.into_iter().enumerate() {
+            b = b
+                .expression_attribute_names(format!("#a{}", i), name)
+                .expression_attribute_values(format!(":v{}", i), value);
+        }
+        for (i, name) in self.remove.into_iter().enumerate() {
+            b = b.expression_attribute_names(format!("#r{}", i), name);
+        }
+        b
+    }
+}
+
+/// Reads a user row with a strongly consistent read, so that the version we get back is the
+/// one any conditional write will be compared against.
+pub async fn get_user(
+    client: &DynamoClient,
+    email: &str,
+) -> Result<Option<UserItem>, ServerFnError<NexusError>> {
+    read_user(
+        client,
+        email,
+        DynamoOperation::read("get_user", get_table_name()),
+    )
+    .await
+}
+
+async fn read_user(
+    client: &DynamoClient,
+    email: &str,
+    operation: DynamoOperation,
+) -> Result<Option<UserItem>, ServerFnError<NexusError>> {
+    let get_item = client
+        .get_item()
+        .table_name(get_table_name())
+        .key(EMAIL, AttributeValue::S(email.to_string()))
+        .consistent_read(true);
+    let db_result = send_with_retry(operation, || get_item.clone().send()).await;
+
+    match db_result {
+        Ok(o) => Ok(o.item),
+        Err(e) => Err(handle_dynamo_generic_error(e)),
+    }
+}
+
+/// Loads the user row, asks `build` what to change given its current contents, and writes the
+/// change conditioned on the row not having been modified in between. If another writer got
+/// there first the row is reloaded and `build` is run again against the new contents.
+///
+/// `build` returning `Ok(None)` means there is nothing left to do (e.g. the change has already
+/// been applied by a concurrent request), and is treated as success.
+///
+/// Every read and write shares one deadline, so the whole cycle gives up with
+/// [`NexusError::DynamoTimeout`] rather than outlasting the request.
+pub async fn update_user_with_retry<F>(
+    client: &DynamoClient,
+    email: &str,
+    mut build: F,
+) -> Result<(), ServerFnError<NexusError>>
+where
+    F: FnMut(&UserItem) -> Result<Option<UserUpdate>, ServerFnError<NexusError>>,
+{
+    let deadline = Instant::now() + UPDATE_BUDGET;
+    for attempt in 1..=MAX_WRITE_ATTEMPTS {
+        let read = DynamoOperation::read("get_user", get_table_name()).until(deadline);
+        let item = read_user(client, email, read).await?.ok_or_else(|| {
+            log::error!("Tried to update a user row that doesn't exist");
+            ServerFnError::from(NexusError::CouldNotFindRowWithThatEmail)
+        })?;
+        let expected_version = parse_number_attribute(&item, &TableAttributeType::Version)?;
+        let update = match build(&item)? {
+            Some(update) => update,
+            None => return Ok(()),
+        };
+        let update = update.apply(update_setup(client, email.to_string()), expected_version);
+        let update_result = send_with_retry(
+            DynamoOperation::write("update_user", get_table_name()).until(deadline),
+            || update.clone().send(),
+        )
+        .await;
+
+        match update_result {
+            Ok(_) => return Ok(()),
+            Err(e) if e.kind == DynamoErrorKind::ConditionalCheckFailed => {
+                log::warn!(
+                    "User row was modified concurrently (attempt {} of {}), reloading",
+                    attempt,
+                    MAX_WRITE_ATTEMPTS
+                );
+            }
+            Err(e) => return Err(handle_dynamo_generic_error(e)),
+        }
+    }
+    log::error!(
+        "Gave up writing user row after {} conflicting attempts",
+        MAX_WRITE_ATTEMPTS
+    );
+    Err(ServerFnError::from(NexusError::ConcurrentModification))
+}
+
+/// Convenience wrapper for updates that don't depend on the current contents of the row.
+pub async fn update_user(
+    client: &DynamoClient,
+    email: &str,
+    update: UserUpdate,
+) -> Result<(), ServerFnError<NexusError>> {
+    update_user_with_retry(client, email, |_| Ok(Some(update.clone()))).await
+}
@@ -0,0 +1,334 @@
+use super::super::{
+    globals::{
+        dynamo::{
+            constants::{
+                index::WEBHOOK_EVENT_STATUS_INDEX,
+                webhook_event_attributes::{
+                    ATTEMPTS, EVENT_ID, EVENT_TYPE, LAST_ERROR, PAYLOAD, RECEIVED_AT, STATUS,
+                    UPDATED_AT,
+                },
+            },
+            number_attribute, string_attribute,
+        },
+        dynamo_error::{send_with_retry, DynamoErrorKind, DynamoOperation},
+        env_var::get_webhook_events_table_name,
+    },
+    utilities::handle_dynamo_generic_error,
+};
+use crate::errors::{NexusError, UNHANDLED};
+use aws_sdk_dynamodb::{types::AttributeValue, Client as DynamoClient};
+use leptos::ServerFnError;
+use std::collections::HashMap;
+
+/// A `received` event that hasn't finished processing within this long is assumed to have died
+/// with the instance processing it (e.g. a Lambda timeout), so a redelivery may take it over.
+pub const PROCESSING_LEASE_SECS: i64 = 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WebhookEventStatus {
+    Received,
+    Processed,
+    Failed,
+}
+
+impl WebhookEventStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            WebhookEventStatus::Received => "received",
+            WebhookEventStatus::Processed => "processed",
+            WebhookEventStatus::Failed => "failed",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "received" => Some(WebhookEventStatus::Received),
+            "processed" => Some(WebhookEventStatus::Processed),
+            "failed" => Some(WebhookEventStatus::Failed),
+            _ => None,
+        }
+    }
+}
+
+/// One Stripe event as recorded in the ledger, with the raw (already verified) payload so it
+/// can be processed again later.
+#[derive(Debug, Clone)]
+pub struct WebhookEventRecord {
+    pub event_id: String,
+    pub event_type: String,
+    pub status: WebhookEventStatus,
+    pub payload: String,
+    pub received_at: i64,
+    pub updated_at: i64,
+    pub attempts: i64,
+    pub last_error: Option<String>,
+}
+
+/// What to do with a delivery of an event, given what the ledger already knows about it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BeginOutcome {
+    /// We own this event now and should process it
+    Process,
+    /// It was already handled, acknowledge it without doing anything
+    AlreadyProcessed,
+    /// Another delivery of it is being processed right now
+    InProgress,
+}
+
+/// Decides what a new delivery of an event the ledger already has should do
+pub fn outcome_for_existing(status: WebhookEventStatus, updated_at: i64, now: i64) -> BeginOutcome {
+    match status {
+        WebhookEventStatus::Processed => BeginOutcome::AlreadyProcessed,
+        WebhookEventStatus::Failed => BeginOutcome::Process,
+        WebhookEventStatus::Received if now - updated_at < PROCESSING_LEASE_SECS => {
+            BeginOutcome::InProgress
+        }
+        WebhookEventStatus::Received => BeginOutcome::Process,
+    }
+}
+
+/// Records a verified event as `received`. Only the delivery that gets [`BeginOutcome::Process`]
+/// back may run the event's handlers, and must then call [`mark_processed`] or [`mark_failed`].
+pub async fn begin_processing(
+    client: &DynamoClient,
+    event_id: &str,
+    event_type: &str,
+    payload: &str,
+    now: i64,
+) -> Result<BeginOutcome, ServerFnError<NexusError>> {
+    let put = client
+        .put_item()
+        .table_name(get_webhook_events_table_name())
+        .item(EVENT_ID, AttributeValue::S(event_id.to_string()))
+        .item(EVENT_TYPE, AttributeValue::S(event_type.to_string()))
+        .item(
+            STATUS,
+            AttributeValue::S(WebhookEventStatus::Received.as_str().to_string()),
+        )
+        .item(PAYLOAD, AttributeValue::S(payload.to_string()))
+        .item(RECEIVED_AT, AttributeValue::N(now.to_string()))
+        .item(UPDATED_AT, AttributeValue::N(now.to_string()))
+        .item(ATTEMPTS, AttributeValue::N("1".to_string()))
+        .condition_expression(format!("attribute_not_exists({})", EVENT_ID));
+    let put_result = send_with_retry(
+        DynamoOperation::write("record_webhook_event", get_webhook_events_table_name()),
+        || put.clone().send(),
+    )
+    .await;
+
+    match put_result {
+        Ok(_) => return Ok(BeginOutcome::Process),
+        Err(e) if e.kind == DynamoErrorKind::ConditionalCheckFailed => {}
+        Err(e) => return Err(handle_dynamo_generic_error(e)),
+    }
+    let existing = get_event(client, event_id).await?.ok_or_else(|| {
+        log::error!(
+            "Webhook event {} exists but could not be read back",
+            event_id
+        );
+        UNHANDLED
+    })?;
+    match outcome_for_existing(existing.status, existing.updated_at, now) {
+        BeginOutcome::Process => claim(client, &existing, now).await,
+        outcome => Ok(outcome),
+    }
+}
+
+/// Takes over an event that failed (or whose processing was abandoned), provided nobody else
+/// took it over since `record` was read.
+pub async fn claim(
+    client: &DynamoClient,
+    record: &WebhookEventRecord,
+    now: i64,
+) -> Result<BeginOutcome, ServerFnError<NexusError>> {
+    let update = client
+        .update_item()
+        .table_name(get_webhook_events_table_name())
+        .key(EVENT_ID, AttributeValue::S(record.event_id.clone()))
+        .update_expression(
+            "SET #status = :received, #updated_at = :now, #attempts = #attempts + :one",
+        )
+        .condition_expression("#status = :previous_status AND #updated_at = :previous_updated_at")
+        .expression_attribute_names("#status", STATUS)
+        .expression_attribute_names("#updated_at", UPDATED_AT)
+        .expression_attribute_names("#attempts", ATTEMPTS)
+        .expression_attribute_values(
+            ":received",
+            AttributeValue::S(WebhookEventStatus::Received.as_str().to_string()),
+        )
+        .expression_attribute_values(":now", AttributeValue::N(now.to_string()))
+        .expression_attribute_values(":one", AttributeValue::N("1".to_string()))
+        .expression_attribute_values(
+            ":previous_status",
+            AttributeValue::S(record.status.as_str().to_string()),
+        )
+        .expression_attribute_values(
+            ":previous_updated_at",
+            AttributeValue::N(record.updated_at.to_string()),
+        );
+    let update_result = send_with_retry(
+        DynamoOperation::write("claim_webhook_event", get_webhook_events_table_name()),
+        || update.clone().send(),
+    )
+    .await;
+
+    match update_result {
+        Ok(_) => Ok(BeginOutcome::Process),
+        Err(e) if e.kind == DynamoErrorKind::ConditionalCheckFailed => Ok(BeginOutcome::InProgress),
+        Err(e) => Err(handle_dynamo_generic_error(e)),
+    }
+}
+
+pub async fn mark_processed(
+    client: &DynamoClient,
+    event_id: &str,
+    now: i64,
+) -> Result<(), ServerFnError<NexusError>> {
+    set_status(client, event_id, WebhookEventStatus::Processed, None, now).await
+}
+
+pub async fn mark_failed(
+    client: &DynamoClient,
+    event_id: &str,
+    error: &str,
+    now: i64,
+) -> Result<(), ServerFnError<NexusError>> {
+    set_status(
+        client,
+        event_id,
+        WebhookEventStatus::Failed,
+        Some(error),
+        now,
+    )
+    .await
+}
+
+async fn set_status(
+    client: &DynamoClient,
+    event_id: &str,
+    status: WebhookEventStatus,
+    error: Option<&str>,
+    now: i64,
+) -> Result<(), ServerFnError<NexusError>> {
+    let mut update = client
+        .update_item()
+        .table_name(get_webhook_events_table_name())
+        .key(EVENT_ID, AttributeValue::S(event_id.to_string()))
+        .expression_attribute_names("#status", STATUS)
+        .expression_attribute_names("#updated_at", UPDATED_AT)
+        .expression_attribute_names("#last_error", LAST_ERROR)
+        .expression_attribute_values(":status", AttributeValue::S(status.as_str().to_string()))
+        .expression_attribute_values(":now", AttributeValue::N(now.to_string()));
+    update = match error {
+        Some(error) => update
+            .update_expression("SET #status = :status, #updated_at = :now, #last_error = :error")
+            .expression_attribute_values(":error", AttributeValue::S(error.to_string())),
+        None => {
+            update.update_expression("SET #status = :status, #updated_at = :now REMOVE #last_error")
+        }
+    };
+    let update_result = send_with_retry(
+        DynamoOperation::write("set_webhook_event_status", get_webhook_events_table_name()),
+        || update.clone().send(),
+    )
+    .await;
+
+    match update_result {
+        Ok(_) => Ok(()),
+        Err(e) => Err(handle_dynamo_generic_error(e)),
+    }
+}
+
+pub async fn get_event(
+    client: &DynamoClient,
+    event_id: &str,
+) -> Result<Option<WebhookEventRecord>, ServerFnError<NexusError>> {
+    let get_item = client
+        .get_item()
+        .table_name(get_webhook_events_table_name())
+        .key(EVENT_ID, AttributeValue::S(event_id.to_string()))
+        .consistent_read(true);
+    let db_result = send_with_retry(
+        DynamoOperation::read("get_webhook_event", get_webhook_events_table_name()),
+        || get_item.clone().send(),
+    )
+    .await;
+
+    match db_result {
+        Ok(o) => o.item.as_ref().map(parse_record).transpose(),
+        Err(e) => Err(handle_dynamo_generic_error(e)),
+    }
+}
+
+/// Every event whose processing failed, oldest first, for replaying
+pub async fn list_failed_events(
+    client: &DynamoClient,
+) -> Result<Vec<WebhookEventRecord>, ServerFnError<NexusError>> {
+    let mut records = Vec::new();
+    let mut exclusive_start_key = None;
+    loop {
+        let query = client
+            .query()
+            .table_name(get_webhook_events_table_name())
+            .index_name(WEBHOOK_EVENT_STATUS_INDEX)
+            .key_condition_expression("#status = :failed")
+            .expression_attribute_names("#status", STATUS)
+            .expression_attribute_values(
+                ":failed",
+                AttributeValue::S(WebhookEventStatus::Failed.as_str().to_string()),
+            )
+            .set_exclusive_start_key(exclusive_start_key.clone());
+        let db_result = send_with_retry(
+            DynamoOperation::read(
+                "list_failed_webhook_events",
+                get_webhook_events_table_name(),
+            ),
+            || query.clone().send(),
+        )
+        .await;
+        let output = match db_result {
+            Ok(o) => o,
+            Err(e) => return Err(handle_dynamo_generic_error(e)),
+        };
+        for item in output.items() {
+            records.push(parse_record(item)?);
+        }
+        exclusive_start_key = output.last_evaluated_key;
+        if exclusive_start_key.is_none() {
+            break;
+        }
+    }
+    records.sort_by_key(|record| record.received_at);
+    Ok(records)
+}
+
+fn parse_record(
+    item: &HashMap<String, AttributeValue>,
+) -> Result<WebhookEventRecord, ServerFnError<NexusError>> {
+    let required_string = |name: &str| {
+        string_attribute(item, name)?.ok_or_else(|| {
+            log::error!("Webhook event is missing {}", name);
+            NexusError::Unhandled
+        })
+    };
+    let required_number = |name: &str| {
+        number_attribute(item, name)?.ok_or_else(|| {
+            log::error!("Webhook event is missing {}", name);
+            NexusError::Unhandled
+        })
+    };
+    let status = required_string(STATUS)?;
+    Ok(WebhookEventRecord {
+        event_id: required_string(EVENT_ID)?,
+        event_type: required_string(EVENT_TYPE)?,
+        status: WebhookEventStatus::parse(&status).ok_or_else(|| {
+            log::error!("Unknown webhook event status {}", status);
+            UNHANDLED
+        })?,
+        payload: required_string(PAYLOAD)?,
+        received_at: required_number(RECEIVED_AT)?,
+        updated_at: required_number(UPDATED_AT)?,
+        attempts: required_number(ATTEMPTS)?,
+        last_error: string_attribute(item, LAST_ERROR)?,
+    })
+}
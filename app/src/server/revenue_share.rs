@@ -0,0 +1,458 @@
+use super::{
+    catalog::{all_products, find_product},
+    refunds::REFUND_POLICY,
+    repository::{
+        collaborators::{get_collaborator, set_transfers_enabled},
+        purchases::{checkout_session_of, get_purchase, Purchase, PurchaseStatus},
+        revenue_transfers::{
+            create_revenue_transfer, list_revenue_transfers_by_status,
+            list_revenue_transfers_for_purchase, revenue_transfer_id, set_revenue_transfer_status,
+            RevenueTransferUpdate,
+        },
+    },
+};
+use crate::{
+    catalog::RevenueShare,
+    errors::{NexusError, UNHANDLED},
+    orders::{Collaborator, RevenueSharePayouts, RevenueTransfer, RevenueTransferStatus},
+};
+use aws_sdk_dynamodb::Client as DynamoClient;
+use leptos::ServerFnError;
+use std::collections::HashMap;
+use stripe::{
+    Account, AccountId, CapabilityStatus, Client as StripeClient, CreateTransfer,
+    CreateTransferReversal, Currency, PaymentIntent, PaymentIntentId, RequestStrategy, Transfer,
+    TransferId, TransferReversal,
+};
+
+/// Shares are held for as long as refunds are granted without a review, so most refunds cancel
+/// a share rather than take it back from the collaborator
+pub const REVENUE_SHARE_HOLD_SECONDS: i64 = REFUND_POLICY.window_seconds;
+
+/// Key of the transfer metadata entry holding the ledger id of the share paid
+pub const REVENUE_TRANSFER_ID_METADATA_KEY: &str = "revenue_transfer_id";
+
+/// The collaborators' shares of `purchase`, each a percentage of what was paid less tax.
+/// Purchases nothing was paid for (redeemed keys, fully discounted ones) have none.
+pub fn revenue_transfers_for(
+    purchase: &Purchase,
+    shares: &[RevenueShare],
+    now: i64,
+) -> Vec<RevenueTransfer> {
+    let net = purchase.amount_total - purchase.amount_tax;
+    if purchase.currency.is_empty() || net <= 0 {
+        return Vec::new();
+    }
+    shares
+        .iter()
+        .map(|share| RevenueTransfer {
+            transfer_id: revenue_transfer_id(&purchase.checkout_session_id, &share.collaborator_id),
+            purchase_id: purchase.checkout_session_id.clone(),
+            collaborator_id: share.collaborator_id.clone(),
+            product_id: purchase.product_id.clone(),
+            amount: share.amount_of(net),
+            currency: purchase.currency.clone(),
+            status: RevenueTransferStatus::Pending,
+            available_at: purchase.created_at + REVENUE_SHARE_HOLD_SECONDS,
+            stripe_transfer_id: None,
+            stripe_reversal_id: None,
+            created_at: now,
+            updated_at: now,
+        })
+        .filter(|transfer| transfer.amount > 0)
+        .collect()
+}
+
+/// Adds the collaborators' shares of a paid purchase to the ledger, to be transferred once it
+/// clears. Shares already there are left as they are, so this can be retried.
+pub async fn record_revenue_shares(
+    dynamodb_client: &DynamoClient,
+    purchase: &Purchase,
+    now: i64,
+) -> Result<(), ServerFnError<NexusError>> {
+    let shares = match find_product(all_products(), &purchase.product_id) {
+        Some(product) => &product.revenue_shares,
+        None => return Ok(()),
+    };
+    for transfer in revenue_transfers_for(purchase, shares, now) {
+        if create_revenue_transfer(dynamodb_client, &transfer).await? {
+            log::info!(
+                "{} is owed {} {} for purchase {}",
+                transfer.collaborator_id,
+                transfer.amount,
+                transfer.currency,
+                transfer.purchase_id
+            );
+        }
+    }
+    Ok(())
+}
+
+fn stripe_error(what: &str, id: &str, e: stripe::StripeError) -> ServerFnError<NexusError> {
+    log::error!("Could not {} {} {:?}", what, id, e);
+    UNHANDLED
+}
+
+/// Checks with Stripe whether a collaborator finished onboarding, recording it if that changed.
+/// Connected accounts' own events only go to Connect webhook endpoints, so this is asked for
+/// instead of waiting for `account.updated`.
+pub async fn refresh_transfers_enabled(
+    dynamodb_client: &DynamoClient,
+    stripe_client: &StripeClient,
+    collaborator: &mut Collaborator,
+) -> Result<(), ServerFnError<NexusError>> {
+    let account_id = collaborator
+        .stripe_account_id
+        .parse::<AccountId>()
+        .map_err(|e| {
+            log::error!(
+                "Collaborator {} has an invalid account id {:?}",
+                collaborator.collaborator_id,
+                e
+            );
+            UNHANDLED
+        })?;
+    let account = Account::retrieve(stripe_client, &account_id, &[])
+        .await
+        .map_err(|e| stripe_error("fetch account", &collaborator.stripe_account_id, e))?;
+    let transfers_enabled = account
+        .capabilities
+        .and_then(|capabilities| capabilities.transfers)
+        == Some(CapabilityStatus::Active);
+    if transfers_enabled != collaborator.transfers_enabled {
+        set_transfers_enabled(
+            dynamodb_client,
+            &collaborator.collaborator_id,
+            transfers_enabled,
+        )
+        .await?;
+        collaborator.transfers_enabled = transfers_enabled;
+    }
+    Ok(())
+}
+
+/// Takes a transferred share back from the collaborator and records it as `Reversed`
+async fn reverse(
+    dynamodb_client: &DynamoClient,
+    stripe_client: &StripeClient,
+    transfer: &RevenueTransfer,
+    stripe_transfer_id: &str,
+    now: i64,
+) -> Result<(), ServerFnError<NexusError>> {
+    let transfer_id = stripe_transfer_id.parse::<TransferId>().map_err(|e| {
+        log::error!("Invalid transfer id {} {:?}", stripe_transfer_id, e);
+        UNHANDLED
+    })?;
+    let stripe_client = stripe_client
+        .clone()
+        .with_strategy(RequestStrategy::Idempotent(format!(
+            "revenue-share-reversal-{}",
+            transfer.transfer_id
+        )));
+    let reversal = TransferReversal::create(
+        &stripe_client,
+        &transfer_id,
+        CreateTransferReversal {
+            metadata: Some(HashMap::from([(
+                REVENUE_TRANSFER_ID_METADATA_KEY.to_string(),
+                transfer.transfer_id.clone(),
+            )])),
+            ..Default::default()
+        },
+    )
+    .await
+    .map_err(|e| stripe_error("reverse transfer", stripe_transfer_id, e))?;
+    let reversed = set_revenue_transfer_status(
+        dynamodb_client,
+        &transfer.transfer_id,
+        transfer.status,
+        RevenueTransferStatus::Reversed,
+        RevenueTransferUpdate {
+            stripe_transfer_id: Some(stripe_transfer_id),
+            stripe_reversal_id: Some(reversal.id.as_str()),
+        },
+        now,
+    )
+    .await?;
+    if !reversed {
+        log::error!(
+            "Revenue share {} changed while being reversed",
+            transfer.transfer_id
+        );
+        return Err(ServerFnError::from(NexusError::ConcurrentModification));
+    }
+    log::info!(
+        "Reversed transfer {} of revenue share {}",
+        stripe_transfer_id,
+        transfer.transfer_id
+    );
+    Ok(())
+}
+
+/// Takes back the collaborators' shares of a purchase that was refunded, or whose dispute was
+/// lost: pending ones are cancelled and transferred ones reversed. Returns the shares that
+/// changed, as they are now.
+pub async fn reverse_revenue_shares(
+    dynamodb_client: &DynamoClient,
+    stripe_client: &StripeClient,
+    purchase_id: &str,
+    now: i64,
+) -> Result<Vec<RevenueTransfer>, ServerFnError<NexusError>> {
+    let mut changed = Vec::new();
+    for transfer in list_revenue_transfers_for_purchase(dynamodb_client, purchase_id).await? {
+        let status = match (transfer.status, transfer.stripe_transfer_id.as_deref()) {
+            (RevenueTransferStatus::Pending, _) => {
+                let cancelled = set_revenue_transfer_status(
+                    dynamodb_client,
+                    &transfer.transfer_id,
+                    RevenueTransferStatus::Pending,
+                    RevenueTransferStatus::Cancelled,
+                    RevenueTransferUpdate::default(),
+                    now,
+                )
+                .await?;
+                if !cancelled {
+                    // It was transferred in the meantime. The retry reverses it.
+                    log::error!(
+                        "Revenue share {} changed while being cancelled",
+                        transfer.transfer_id
+                    );
+                    return Err(ServerFnError::from(NexusError::ConcurrentModification));
+                }
+                RevenueTransferStatus::Cancelled
+            }
+            (RevenueTransferStatus::Transferred, Some(stripe_transfer_id)) => {
+                reverse(
+                    dynamodb_client,
+                    stripe_client,
+                    &transfer,
+                    stripe_transfer_id,
+                    now,
+                )
+                .await?;
+                RevenueTransferStatus::Reversed
+            }
+            _ => continue,
+        };
+        changed.push(RevenueTransfer { status, ..transfer });
+    }
+    Ok(changed)
+}
+
+/// The charge a purchase was paid with, so its share can be transferred out of that charge's
+/// funds even before they're available
+async fn source_charge(
+    stripe_client: &StripeClient,
+    purchase: &Purchase,
+) -> Result<Option<stripe::ChargeId>, ServerFnError<NexusError>> {
+    let Some(payment_intent_id) = purchase.payment_intent_id.as_deref() else {
+        return Ok(None);
+    };
+    let id = payment_intent_id.parse::<PaymentIntentId>().map_err(|e| {
+        log::error!("Invalid payment intent id {} {:?}", payment_intent_id, e);
+        UNHANDLED
+    })?;
+    let payment_intent = PaymentIntent::retrieve(stripe_client, &id, &[])
+        .await
+        .map_err(|e| stripe_error("fetch payment intent", payment_intent_id, e))?;
+    Ok(payment_intent.latest_charge.map(|charge| charge.id()))
+}
+
+/// Transfers one pending share, returning whether it was transferred. Shares of purchases that
+/// aren't simply paid for (e.g. disputed ones) are held until they are.
+async fn pay(
+    dynamodb_client: &DynamoClient,
+    stripe_client: &StripeClient,
+    transfer: &RevenueTransfer,
+    collaborator: &Collaborator,
+    now: i64,
+) -> Result<bool, ServerFnError<NexusError>> {
+    let purchase = get_purchase(dynamodb_client, &transfer.purchase_id)
+        .await?
+        .ok_or_else(|| {
+            log::error!(
+                "Revenue share {} is of a purchase that isn't in the ledger",
+                transfer.transfer_id
+            );
+            ServerFnError::from(NexusError::PurchaseNotFound)
+        })?;
+    if purchase.status != PurchaseStatus::Paid {
+        log::info!(
+            "Holding revenue share {} while purchase {} is {}",
+            transfer.transfer_id,
+            purchase.checkout_session_id,
+            purchase.status.as_str()
+        );
+        return Ok(false);
+    }
+    let currency = transfer.currency.parse::<Currency>().map_err(|e| {
+        log::error!(
+            "Revenue share {} is in unknown currency {} {:?}",
+            transfer.transfer_id,
+            transfer.currency,
+            e
+        );
+        UNHANDLED
+    })?;
+    let description = format!(
+        "Share of {} ({})",
+        transfer.product_id, transfer.purchase_id
+    );
+    let transfer_group = checkout_session_of(&transfer.purchase_id).to_string();
+    let mut params = CreateTransfer::new(currency, collaborator.stripe_account_id.clone());
+    params.amount = Some(transfer.amount);
+    params.description = Some(&description);
+    params.source_transaction = source_charge(stripe_client, &purchase).await?;
+    params.transfer_group = Some(&transfer_group);
+    params.metadata = Some(HashMap::from([(
+        REVENUE_TRANSFER_ID_METADATA_KEY.to_string(),
+        transfer.transfer_id.clone(),
+    )]));
+    // A retry after Stripe made the transfer, but before it was recorded, mustn't pay twice
+    let idempotent_client = stripe_client
+        .clone()
+        .with_strategy(RequestStrategy::Idempotent(format!(
+            "revenue-share-{}",
+            transfer.transfer_id
+        )));
+    let stripe_transfer = Transfer::create(&idempotent_client, params)
+        .await
+        .map_err(|e| stripe_error("transfer revenue share", &transfer.transfer_id, e))?;
+    let recorded = set_revenue_transfer_status(
+        dynamodb_client,
+        &transfer.transfer_id,
+        RevenueTransferStatus::Pending,
+        RevenueTransferStatus::Transferred,
+        RevenueTransferUpdate {
+            stripe_transfer_id: Some(stripe_transfer.id.as_str()),
+            ..Default::default()
+        },
+        now,
+    )
+    .await?;
+    if !recorded {
+        // The purchase was refunded while this was being transferred, which cancelled the share
+        log::error!(
+            "Revenue share {} was cancelled while being transferred",
+            transfer.transfer_id
+        );
+        let cancelled = RevenueTransfer {
+            status: RevenueTransferStatus::Cancelled,
+            ..transfer.clone()
+        };
+        reverse(
+            dynamodb_client,
+            stripe_client,
+            &cancelled,
+            stripe_transfer.id.as_str(),
+            now,
+        )
+        .await?;
+        return Ok(false);
+    }
+    log::info!(
+        "Transferred {} {} to {} for revenue share {}",
+        transfer.amount,
+        transfer.currency,
+        collaborator.collaborator_id,
+        transfer.transfer_id
+    );
+    Ok(true)
+}
+
+/// A collaborator who can be paid, or `None` if they haven't finished onboarding (or are
+/// missing altogether)
+async fn payable_collaborator(
+    dynamodb_client: &DynamoClient,
+    stripe_client: &StripeClient,
+    collaborator_id: &str,
+) -> Result<Option<Collaborator>, ServerFnError<NexusError>> {
+    let Some(mut collaborator) = get_collaborator(dynamodb_client, collaborator_id).await? else {
+        log::error!(
+            "Revenue shares are owed to {}, who isn't a collaborator",
+            collaborator_id
+        );
+        return Ok(None);
+    };
+    if !collaborator.transfers_enabled {
+        refresh_transfers_enabled(dynamodb_client, stripe_client, &mut collaborator).await?;
+    }
+    Ok(collaborator.transfers_enabled.then_some(collaborator))
+}
+
+/// Transfers every pending share whose purchase has cleared. Each share is tried on its own,
+/// so one that fails is left pending for the next run rather than holding up the rest.
+pub async fn pay_revenue_shares(
+    dynamodb_client: &DynamoClient,
+    stripe_client: &StripeClient,
+    now: i64,
+) -> Result<RevenueSharePayouts, ServerFnError<NexusError>> {
+    let pending =
+        list_revenue_transfers_by_status(dynamodb_client, RevenueTransferStatus::Pending).await?;
+    let mut payouts = RevenueSharePayouts::default();
+    let mut collaborators: HashMap<String, Option<Collaborator>> = HashMap::new();
+    for transfer in pending {
+        if transfer.available_at > now {
+            payouts.held += 1;
+            continue;
+        }
+        let collaborator = match collaborators.get(&transfer.collaborator_id) {
+            Some(collaborator) => collaborator.clone(),
+            None => {
+                match payable_collaborator(
+                    dynamodb_client,
+                    stripe_client,
+                    &transfer.collaborator_id,
+                )
+                .await
+                {
+                    Ok(collaborator) => {
+                        collaborators
+                            .insert(transfer.collaborator_id.clone(), collaborator.clone());
+                        collaborator
+                    }
+                    Err(e) => {
+                        log::error!(
+                            "Could not look up collaborator {} {:?}",
+                            transfer.collaborator_id,
+                            e
+                        );
+                        payouts.failed += 1;
+                        continue;
+                    }
+                }
+            }
+        };
+        let Some(collaborator) = collaborator else {
+            payouts.held += 1;
+            continue;
+        };
+        match pay(
+            dynamodb_client,
+            stripe_client,
+            &transfer,
+            &collaborator,
+            now,
+        )
+        .await
+        {
+            Ok(true) => payouts.transferred += 1,
+            Ok(false) => payouts.held += 1,
+            Err(e) => {
+                log::error!(
+                    "Could not pay revenue share {} {:?}",
+                    transfer.transfer_id,
+                    e
+                );
+                payouts.failed += 1;
+            }
+        }
+    }
+    log::info!(
+        "Revenue shares: {} transferred, {} held, {} failed",
+        payouts.transferred,
+        payouts.held,
+        payouts.failed
+    );
+    Ok(payouts)
+}
@@ -0,0 +1,142 @@
+use super::repository::Session;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{LazyLock, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Each Lambda instance (or container) has its own cache and invalidation is only local, so this
+/// is also the longest another instance can keep accepting a session after it was revoked.
+const SESSION_TTL: Duration = Duration::from_secs(30);
+/// Unknown ids are remembered for less time, so a session created on another instance right
+/// after a miss becomes usable here quickly.
+const UNKNOWN_SESSION_TTL: Duration = Duration::from_secs(10);
+const MAX_ENTRIES: usize = 10_000;
+
+pub static SESSION_CACHE: LazyLock<SessionCache> = LazyLock::new(SessionCache::default);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SessionLookup {
+    Known(Session),
+    /// We recently asked the database about this id and it doesn't belong to anyone
+    Unknown,
+}
+
+struct Entry {
+    lookup: SessionLookup,
+    expires_at: Instant,
+}
+
+#[derive(Default)]
+struct Entries {
+    by_session_id: HashMap<String, Entry>,
+    /// Insertion order, used to evict the oldest entries once the cache is full
+    order: VecDeque<String>,
+}
+
+/// A bounded, TTL based cache of session id lookups sitting in front of the session index.
+#[derive(Default)]
+pub struct SessionCache {
+    entries: Mutex<Entries>,
+}
+
+impl SessionCache {
+    pub fn get(&self, session_id: &str) -> Option<SessionLookup> {
+        self.get_at(session_id, Instant::now())
+    }
+
+    pub fn get_at(&self, session_id: &str, now: Instant) -> Option<SessionLookup> {
+        // Expired entries are left in place until they are overwritten or evicted
+        self.lock()
+            .by_session_id
+            .get(session_id)
+            .filter(|entry| entry.expires_at > now)
+            .map(|entry| entry.lookup.clone())
+    }
+
+    pub fn insert_known(&self, session_id: String, session: Session) {
+        self.insert_at(
+            session_id,
+            SessionLookup::Known(session),
+            SESSION_TTL,
+            Instant::now(),
+        );
+    }
+
+    pub fn insert_unknown(&self, session_id: String) {
+        self.insert_at(
+            session_id,
+            SessionLookup::Unknown,
+            UNKNOWN_SESSION_TTL,
+            Instant::now(),
+        );
+    }
+
+    pub fn insert_at(
+        &self,
+        session_id: String,
+        lookup: SessionLookup,
+        ttl: Duration,
+        now: Instant,
+    ) {
+        let mut entries = self.lock();
+        if !entries.by_session_id.contains_key(&session_id) {
+            while entries.by_session_id.len() >= MAX_ENTRIES {
+                match entries.order.pop_front() {
+                    Some(oldest) => {
+                        entries.by_session_id.remove(&oldest);
+                    }
+                    None => break,
+                }
+            }
+            entries.order.push_back(session_id.clone());
+        }
+        entries.by_session_id.insert(
+            session_id,
+            Entry {
+                lookup,
+                expires_at: now + ttl,
+            },
+        );
+    }
+
+    /// Forgets a single session, e.g. on logout
+    pub fn invalidate(&self, session_id: &str) {
+        let mut entries = self.lock();
+        entries.by_session_id.remove(session_id);
+        entries.order.retain(|id| id != session_id);
+    }
+
+    /// Forgets every session belonging to `email`, for when we don't know (or don't trust) which
+    /// session ids the user currently has: password changes, new logins, revocation.
+    pub fn invalidate_email(&self, email: &str) {
+        let mut entries = self.lock();
+        entries
+            .by_session_id
+            .retain(|_, entry| match &entry.lookup {
+                SessionLookup::Known(session) => session.email != email,
+                SessionLookup::Unknown => true,
+            });
+        let Entries {
+            by_session_id,
+            order,
+        } = &mut *entries;
+        order.retain(|id| by_session_id.contains_key(id));
+    }
+
+    pub fn len(&self) -> usize {
+        self.lock().by_session_id.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Entries> {
+        // A panic while holding the lock can't leave the maps in a state worse than stale,
+        // and stale entries expire on their own
+        self.entries
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
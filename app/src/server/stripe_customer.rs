@@ -0,0 +1,132 @@
+use super::{repository::UserRepository, utilities::stripe_client};
+use crate::errors::{NexusError, UNHANDLED};
+use leptos::ServerFnError;
+use std::collections::HashMap;
+use stripe::{
+    Client as StripeClient, CreateCustomer, Customer, CustomerId, StripeError, UpdateCustomer,
+};
+
+/// Key of the customer metadata entry holding the uuid of the account it belongs to
+pub const USER_UUID_METADATA_KEY: &str = "user_uuid";
+
+fn handle_stripe_error(e: StripeError) -> ServerFnError<NexusError> {
+    log::error!("{:?}", e);
+    UNHANDLED
+}
+
+fn parse_customer_id(customer_id: &str) -> Result<CustomerId, ServerFnError<NexusError>> {
+    customer_id.parse().map_err(|e| {
+        log::error!(
+            "Stored Stripe customer id {} is invalid {:?}",
+            customer_id,
+            e
+        );
+        UNHANDLED
+    })
+}
+
+/// The Stripe Customer the account checks out as. It's created at the account's first
+/// checkout and stored on the user row, so every later checkout reuses it.
+pub async fn customer_for(
+    stripe_client: &StripeClient,
+    repository: &dyn UserRepository,
+    email: &str,
+    user_uuid: &str,
+) -> Result<CustomerId, ServerFnError<NexusError>> {
+    let profile = repository
+        .find_billing_profile(email)
+        .await?
+        .ok_or_else(|| {
+            log::error!("Could not find the row of {} to check out", email);
+            ServerFnError::from(NexusError::CouldNotFindRowWithThatEmail)
+        })?;
+    if let Some(customer_id) = profile.stripe_customer_id {
+        return parse_customer_id(&customer_id);
+    }
+    let customer = Customer::create(
+        stripe_client,
+        CreateCustomer {
+            email: Some(email),
+            name: Some(profile.display_name.as_str()),
+            metadata: Some(HashMap::from([(
+                USER_UUID_METADATA_KEY.to_string(),
+                user_uuid.to_string(),
+            )])),
+            ..Default::default()
+        },
+    )
+    .await
+    .map_err(handle_stripe_error)?;
+    let linked = repository
+        .link_stripe_customer(email, customer.id.as_str())
+        .await?;
+    if linked == customer.id.as_str() {
+        log::info!("Created Stripe customer {} for {}", customer.id, user_uuid);
+        return Ok(customer.id);
+    }
+    // A concurrent checkout linked its customer first, so ours is a duplicate
+    if let Err(e) = Customer::delete(stripe_client, &customer.id).await {
+        log::error!(
+            "Could not delete duplicate Stripe customer {} {:?}",
+            customer.id,
+            e
+        );
+    }
+    parse_customer_id(&linked)
+}
+
+/// Copies the account's current email and display name onto its Stripe Customer, if it has one
+pub async fn sync_customer(
+    stripe_client: &StripeClient,
+    repository: &dyn UserRepository,
+    email: &str,
+) -> Result<(), ServerFnError<NexusError>> {
+    let profile = match repository.find_billing_profile(email).await? {
+        Some(profile) => profile,
+        None => return Ok(()),
+    };
+    let customer_id = match profile.stripe_customer_id {
+        Some(customer_id) => parse_customer_id(&customer_id)?,
+        None => return Ok(()),
+    };
+    Customer::update(
+        stripe_client,
+        &customer_id,
+        UpdateCustomer {
+            email: Some(email),
+            name: Some(profile.display_name.as_str()),
+            ..Default::default()
+        },
+    )
+    .await
+    .map_err(handle_stripe_error)?;
+    Ok(())
+}
+
+/// [`sync_customer`] after a change that's saved already. Failing the request then would report
+/// a change that went through as failed, so a Stripe outage is only logged, and the Customer is
+/// caught up by the account's next change.
+pub async fn sync_customer_after_change(repository: &dyn UserRepository, email: &str) {
+    let synced = match stripe_client() {
+        Ok(stripe_client) => sync_customer(&stripe_client, repository, email).await,
+        Err(e) => Err(e),
+    };
+    if let Err(e) = synced {
+        log::error!("Could not sync the Stripe customer of {} {:?}", email, e);
+    }
+}
+
+/// Deletes the account's Stripe Customer, which removes its email, name and payment methods
+/// from Stripe. Payments made by it are kept by Stripe regardless.
+pub async fn delete_customer(
+    stripe_client: &StripeClient,
+    customer_id: &str,
+) -> Result<(), ServerFnError<NexusError>> {
+    let customer_id = parse_customer_id(customer_id)?;
+    match Customer::delete(stripe_client, &customer_id).await {
+        Ok(_) => Ok(()),
+        // Already deleted, e.g. by an earlier attempt at deleting the account
+        Err(StripeError::Stripe(e)) if e.http_status == 404 => Ok(()),
+        Err(e) => Err(handle_stripe_error(e)),
+    }
+}
@@ -0,0 +1,721 @@
+use super::{
+    router::{default_error_status, WebhookContext, WebhookHandler, WebhookRouter},
+    signed_event::StripeEvent,
+};
+use crate::{
+    errors::{NexusError, UNHANDLED},
+    orders::CART_MAX_ITEMS,
+    server::{
+        admin::notify_admins,
+        create_checkout::{
+            line_metadata_key, PRODUCT_IDS_METADATA_KEY, PRODUCT_ID_METADATA_KEY,
+            SALE_DISCOUNT_METADATA_KEY, SALE_METADATA_KEY,
+        },
+        email::send_email,
+        external_keys::assign_external_keys,
+        fulfilment::{self, record},
+        gifts::{deliver_gift, GiftRequest},
+        payment_changes::{
+            apply_payment_change, apply_purchase_change, partially_refunded_purchases,
+        },
+        receipts::send_receipt_once,
+        repository::purchases::{
+            find_purchases_by_payment_intent, get_purchase, line_purchase_id, set_purchase_status,
+            PaymentChange, Purchase, PurchaseStatus,
+        },
+        revenue_share::{pay_revenue_shares, record_revenue_shares},
+    },
+    site::constants::{SITE_DOMAIN, SITE_FULL_DOMAIN},
+};
+use async_trait::async_trait;
+use chrono::Utc;
+use http::StatusCode;
+use leptos::ServerFnError;
+use std::collections::HashMap;
+use stripe::{
+    CheckoutSession, CheckoutSessionItem, CheckoutSessionPaymentStatus, DisputeStatus, EventType,
+    List, PaymentPagesCheckoutSessionTotalDetails,
+};
+
+/// Every handler the store needs
+pub fn nexus_router() -> WebhookRouter {
+    WebhookRouter::new()
+        .on(EventType::CheckoutSessionCompleted, CheckoutCompleted)
+        .on(
+            EventType::CheckoutSessionAsyncPaymentSucceeded,
+            CheckoutAsyncPaymentSucceeded,
+        )
+        .on(
+            EventType::CheckoutSessionAsyncPaymentFailed,
+            CheckoutAsyncPaymentFailed,
+        )
+        .on(EventType::ChargeRefunded, ChargeRefunded)
+        .on(EventType::ChargeDisputeCreated, DisputeChanged)
+        .on(EventType::ChargeDisputeClosed, DisputeChanged)
+        .on(
+            EventType::RadarEarlyFraudWarningCreated,
+            EarlyFraudWarningCreated,
+        )
+        .on(EventType::BalanceAvailable, BalanceAvailable)
+}
+
+fn missing(event: &StripeEvent, what: &str) -> ServerFnError<NexusError> {
+    log::error!("Webhook event {} is missing {}", event.id(), what);
+    ServerFnError::from(NexusError::WebhookEventMalformed)
+}
+
+fn missing_from_checkout(
+    checkout_session: &CheckoutSession,
+    what: &str,
+) -> ServerFnError<NexusError> {
+    log::error!("Checkout {} is missing {}", checkout_session.id, what);
+    ServerFnError::from(NexusError::WebhookEventMalformed)
+}
+
+/// A buyer who can't be found won't appear by retrying, but a purchase might: a refund can
+/// arrive before the checkout it refunds was processed
+fn not_found_status(error: &ServerFnError<NexusError>) -> StatusCode {
+    match error {
+        ServerFnError::WrappedServerError(
+            NexusError::CouldNotFindRowWithThatEmail | NexusError::PurchaseNotFound,
+        ) => StatusCode::NOT_FOUND,
+        error => default_error_status(error),
+    }
+}
+
+/// The promotion code (or, failing that, the coupon) behind the first discount in a breakdown
+fn first_discount_code(total_details: &PaymentPagesCheckoutSessionTotalDetails) -> Option<String> {
+    let discount = &total_details
+        .breakdown
+        .as_ref()?
+        .discounts
+        .first()?
+        .discount;
+    Some(match &discount.promotion_code {
+        Some(promotion_code) => promotion_code.id().to_string(),
+        None => discount.coupon.id.to_string(),
+    })
+}
+
+/// Which promotion code or coupon a checkout was discounted with. Events don't include the
+/// breakdown of discounts, so it's fetched for checkouts that have one. That's only
+/// bookkeeping, so failing to is logged rather than failing the event.
+async fn discount_code(
+    context: &WebhookContext,
+    checkout_session: &CheckoutSession,
+) -> Option<String> {
+    let total_details = checkout_session.total_details.as_ref()?;
+    if total_details.amount_discount == 0 {
+        return None;
+    }
+    if let Some(code) = first_discount_code(total_details) {
+        return Some(code);
+    }
+    match CheckoutSession::retrieve(
+        &context.stripe_client,
+        &checkout_session.id,
+        &["total_details.breakdown"],
+    )
+    .await
+    {
+        Ok(expanded) => expanded
+            .total_details
+            .as_ref()
+            .and_then(first_discount_code),
+        Err(e) => {
+            log::error!(
+                "Could not fetch the discounts of checkout {} {:?}",
+                checkout_session.id,
+                e
+            );
+            None
+        }
+    }
+}
+
+/// The line items of a checkout, in the order they were created. Events don't include them.
+async fn line_items(
+    context: &WebhookContext,
+    checkout_session: &CheckoutSession,
+) -> Result<Vec<CheckoutSessionItem>, ServerFnError<NexusError>> {
+    context
+        .stripe_client
+        .get_query::<List<CheckoutSessionItem>, _>(
+            &format!("/checkout/sessions/{}/line_items", checkout_session.id),
+            HashMap::from([("limit", CART_MAX_ITEMS)]),
+        )
+        .await
+        .map(|line_items| line_items.data)
+        .map_err(|e| {
+            log::error!(
+                "Could not fetch the line items of checkout {} {:?}",
+                checkout_session.id,
+                e
+            );
+            UNHANDLED
+        })
+}
+
+/// The purchases a checkout session is for: one, or one per product of a cart checkout. The
+/// buyer is the account that started the checkout, which `create_checkout` put in
+/// `client_reference_id`: the customer email is only what the buyer typed into Stripe, and
+/// can't be trusted to name an account.
+pub async fn purchases_from_checkout(
+    context: &WebhookContext,
+    checkout_session: &CheckoutSession,
+    status: PurchaseStatus,
+) -> Result<Vec<Purchase>, ServerFnError<NexusError>> {
+    let user_uuid = checkout_session
+        .client_reference_id
+        .as_deref()
+        .ok_or_else(|| missing_from_checkout(checkout_session, "client_reference_id"))?;
+    let metadata = |key: &str| {
+        checkout_session
+            .metadata
+            .as_ref()
+            .and_then(|metadata| metadata.get(key))
+    };
+    let email = context
+        .user_repository
+        .find_email_by_user_uuid(user_uuid)
+        .await?
+        .ok_or_else(|| {
+            log::error!(
+                "Checkout {} was made by unknown user {}",
+                checkout_session.id,
+                user_uuid
+            );
+            ServerFnError::from(NexusError::CouldNotFindRowWithThatEmail)
+        })?;
+    let purchase = Purchase {
+        checkout_session_id: checkout_session.id.to_string(),
+        user_uuid: user_uuid.to_string(),
+        email,
+        product_id: String::new(),
+        payment_intent_id: checkout_session
+            .payment_intent
+            .as_ref()
+            .map(|payment_intent| payment_intent.id().to_string()),
+        amount_total: checkout_session.amount_total.unwrap_or(0),
+        currency: checkout_session
+            .currency
+            .map(|currency| currency.to_string())
+            .unwrap_or_default(),
+        status,
+        created_at: checkout_session.created,
+        updated_at: Utc::now().timestamp(),
+        gift: GiftRequest::from_metadata(checkout_session.metadata.as_ref()).is_some(),
+        amount_discount: checkout_session
+            .total_details
+            .as_ref()
+            .map(|total_details| total_details.amount_discount)
+            .unwrap_or(0)
+            + metadata(SALE_DISCOUNT_METADATA_KEY)
+                .and_then(|discount| discount.parse::<i64>().ok())
+                .unwrap_or(0),
+        sale: metadata(SALE_METADATA_KEY).cloned(),
+        discount_code: discount_code(context, checkout_session).await,
+        amount_tax: checkout_session
+            .total_details
+            .as_ref()
+            .map(|total_details| total_details.amount_tax)
+            .unwrap_or(0),
+        receipt_sent_at: None,
+        country: checkout_session
+            .customer_details
+            .as_ref()
+            .and_then(|customer_details| customer_details.address.as_ref())
+            .and_then(|address| address.country.clone()),
+    };
+    if let Some(product_id) = metadata(PRODUCT_ID_METADATA_KEY) {
+        return Ok(vec![Purchase {
+            product_id: product_id.clone(),
+            ..purchase
+        }]);
+    }
+    let product_ids: Vec<&str> = metadata(PRODUCT_IDS_METADATA_KEY)
+        .ok_or_else(|| missing_from_checkout(checkout_session, "product_id metadata"))?
+        .split(',')
+        .collect();
+    let line_items = line_items(context, checkout_session).await?;
+    if line_items.len() != product_ids.len() {
+        log::error!(
+            "Checkout {} has {} line items for products {:?}",
+            checkout_session.id,
+            line_items.len(),
+            product_ids
+        );
+        return Err(ServerFnError::from(NexusError::WebhookEventMalformed));
+    }
+    Ok(product_ids
+        .into_iter()
+        .zip(line_items)
+        .map(|(product_id, line_item)| Purchase {
+            checkout_session_id: line_purchase_id(&checkout_session.id, product_id),
+            product_id: product_id.to_string(),
+            amount_total: line_item.amount_total,
+            amount_tax: line_item.amount_tax,
+            amount_discount: line_item.amount_discount
+                + metadata(&line_metadata_key(SALE_DISCOUNT_METADATA_KEY, product_id))
+                    .and_then(|discount| discount.parse::<i64>().ok())
+                    .unwrap_or(0),
+            sale: metadata(&line_metadata_key(SALE_METADATA_KEY, product_id)).cloned(),
+            ..purchase.clone()
+        })
+        .collect())
+}
+
+/// Gives the buyer what they paid for, or sends it to the recipient if it's a gift
+async fn grant(
+    context: &WebhookContext,
+    checkout_session: &CheckoutSession,
+    purchase: &Purchase,
+) -> Result<(), ServerFnError<NexusError>> {
+    if let Some(gift) = GiftRequest::from_metadata(checkout_session.metadata.as_ref()) {
+        return deliver_gift(
+            &context.dynamodb_client,
+            context.user_repository.as_ref(),
+            &context.ses_client,
+            purchase,
+            &gift,
+        )
+        .await
+        .map_err(|e| {
+            log::error!(
+                "Could not deliver gift {} {:?}",
+                purchase.checkout_session_id,
+                e
+            );
+            e
+        });
+    }
+    fulfilment::grant(context.user_repository.as_ref(), purchase).await?;
+    assign_external_keys(
+        &context.dynamodb_client,
+        &context.ses_client,
+        &purchase.checkout_session_id,
+        &purchase.user_uuid,
+        &purchase.product_id,
+    )
+    .await
+}
+
+/// Moves a pending purchase on, returning whether this call did it (as opposed to an earlier
+/// delivery of the same event)
+async fn settle_pending(
+    context: &WebhookContext,
+    purchase: &Purchase,
+    status: PurchaseStatus,
+) -> Result<bool, ServerFnError<NexusError>> {
+    let settled = set_purchase_status(
+        &context.dynamodb_client,
+        &purchase.checkout_session_id,
+        PurchaseStatus::Pending,
+        status,
+        Utc::now().timestamp(),
+    )
+    .await?;
+    if !settled {
+        let current = get_purchase(&context.dynamodb_client, &purchase.checkout_session_id)
+            .await?
+            .map(|purchase| purchase.status);
+        if current != Some(status) {
+            log::error!(
+                "Purchase {} was {:?} instead of pending when its payment became {}",
+                purchase.checkout_session_id,
+                current,
+                status.as_str()
+            );
+        }
+    }
+    Ok(settled)
+}
+
+/// Receipts are a courtesy, and can be re-sent from the order history, so failing to send one
+/// doesn't fail the event
+async fn send_receipt(context: &WebhookContext, purchases: &[Purchase]) {
+    let purchase_ids: Vec<String> = purchases
+        .iter()
+        .map(|purchase| purchase.checkout_session_id.clone())
+        .collect();
+    if let Err(e) =
+        send_receipt_once(&context.dynamodb_client, &context.ses_client, &purchase_ids).await
+    {
+        log::error!("Could not send the receipt for {:?} {:?}", purchase_ids, e);
+    }
+}
+
+async fn email_buyer(context: &WebhookContext, purchase: &Purchase, subject: &str, body: &str) {
+    let subject = format!("[{}] {}", SITE_DOMAIN, subject);
+    if let Err(e) = send_email(
+        &context.ses_client,
+        std::slice::from_ref(&purchase.email),
+        &subject,
+        body,
+    )
+    .await
+    {
+        log::error!(
+            "Could not email the buyer of {} {:?}",
+            purchase.checkout_session_id,
+            e
+        );
+    }
+}
+
+/// The checkout's purchases as the ledger has them, leaving out those that don't grant access,
+/// e.g. a cart line refunded on its own before the checkout's events were replayed
+pub fn purchases_to_grant(
+    purchases: &[Purchase],
+    recorded: Vec<Option<Purchase>>,
+) -> Vec<Purchase> {
+    purchases
+        .iter()
+        .zip(recorded)
+        .filter_map(|(purchase, recorded)| match recorded {
+            Some(recorded) if recorded.status.grants_access() => Some(recorded),
+            Some(recorded) => {
+                log::info!(
+                    "Not granting purchase {} as it's {}",
+                    recorded.checkout_session_id,
+                    recorded.status.as_str()
+                );
+                None
+            }
+            None => {
+                log::error!("Purchase {} wasn't recorded", purchase.checkout_session_id);
+                None
+            }
+        })
+        .collect()
+}
+
+async fn grantable_purchases(
+    context: &WebhookContext,
+    purchases: &[Purchase],
+) -> Result<Vec<Purchase>, ServerFnError<NexusError>> {
+    let mut recorded = vec![];
+    for purchase in purchases {
+        recorded.push(get_purchase(&context.dynamodb_client, &purchase.checkout_session_id).await?);
+    }
+    Ok(purchases_to_grant(purchases, recorded))
+}
+
+/// Records the purchases of a completed checkout and, if it's paid for, grants them. Every step
+/// is idempotent, so a checkout that was already fulfilled is left as it is.
+///
+/// Bank debits and vouchers complete the checkout before the money arrives. Those purchases
+/// are recorded as pending, and granted once `checkout.session.async_payment_succeeded` comes.
+pub async fn complete_checkout(
+    context: &WebhookContext,
+    checkout_session: &CheckoutSession,
+) -> Result<(), ServerFnError<NexusError>> {
+    let status = match checkout_session.payment_status {
+        CheckoutSessionPaymentStatus::Unpaid => PurchaseStatus::Pending,
+        CheckoutSessionPaymentStatus::Paid | CheckoutSessionPaymentStatus::NoPaymentRequired => {
+            PurchaseStatus::Paid
+        }
+    };
+    let purchases = purchases_from_checkout(context, checkout_session, status).await?;
+    for purchase in &purchases {
+        record(&context.dynamodb_client, purchase).await?;
+    }
+    if status == PurchaseStatus::Pending {
+        log::info!(
+            "Checkout {} is waiting for its payment",
+            checkout_session.id
+        );
+        return Ok(());
+    }
+    for purchase in &grantable_purchases(context, &purchases).await? {
+        grant(context, checkout_session, purchase).await?;
+        record_revenue_shares(&context.dynamodb_client, purchase, Utc::now().timestamp()).await?;
+    }
+    send_receipt(context, &purchases).await;
+    Ok(())
+}
+
+/// Marks the purchases of a checkout whose delayed payment arrived paid, and grants them
+pub async fn settle_checkout(
+    context: &WebhookContext,
+    checkout_session: &CheckoutSession,
+) -> Result<(), ServerFnError<NexusError>> {
+    let purchases =
+        purchases_from_checkout(context, checkout_session, PurchaseStatus::Pending).await?;
+    let mut settled = false;
+    for purchase in &purchases {
+        // In case the completed event never made it
+        record(&context.dynamodb_client, purchase).await?;
+        settled |= settle_pending(context, purchase, PurchaseStatus::Paid).await?;
+    }
+    for purchase in &grantable_purchases(context, &purchases).await? {
+        grant(context, checkout_session, purchase).await?;
+        record_revenue_shares(&context.dynamodb_client, purchase, Utc::now().timestamp()).await?;
+    }
+    // The receipt doubles as word that the payment arrived
+    if settled {
+        send_receipt(context, &purchases).await;
+    }
+    Ok(())
+}
+
+pub struct CheckoutCompleted;
+
+#[async_trait]
+impl WebhookHandler for CheckoutCompleted {
+    async fn handle(
+        &self,
+        context: &WebhookContext,
+        event: &StripeEvent,
+    ) -> Result<(), ServerFnError<NexusError>> {
+        complete_checkout(context, event.checkout_session()?).await
+    }
+
+    fn error_status(&self, error: &ServerFnError<NexusError>) -> StatusCode {
+        not_found_status(error)
+    }
+}
+
+pub struct CheckoutAsyncPaymentSucceeded;
+
+#[async_trait]
+impl WebhookHandler for CheckoutAsyncPaymentSucceeded {
+    async fn handle(
+        &self,
+        context: &WebhookContext,
+        event: &StripeEvent,
+    ) -> Result<(), ServerFnError<NexusError>> {
+        settle_checkout(context, event.checkout_session()?).await
+    }
+
+    fn error_status(&self, error: &ServerFnError<NexusError>) -> StatusCode {
+        not_found_status(error)
+    }
+}
+
+pub struct CheckoutAsyncPaymentFailed;
+
+#[async_trait]
+impl WebhookHandler for CheckoutAsyncPaymentFailed {
+    async fn handle(
+        &self,
+        context: &WebhookContext,
+        event: &StripeEvent,
+    ) -> Result<(), ServerFnError<NexusError>> {
+        let checkout_session = event.checkout_session()?;
+        let purchases =
+            purchases_from_checkout(context, checkout_session, PurchaseStatus::Pending).await?;
+        let mut settled = false;
+        for purchase in &purchases {
+            record(&context.dynamodb_client, purchase).await?;
+            settled |= settle_pending(context, purchase, PurchaseStatus::Failed).await?;
+        }
+        if settled {
+            let product_ids: Vec<&str> = purchases
+                .iter()
+                .map(|purchase| purchase.product_id.as_str())
+                .collect();
+            // A cart checkout leaves the products in the cart, ready to try again
+            let retry_path = match product_ids.as_slice() {
+                [product_id] => format!("checkout/{}", product_id),
+                _ => "cart".to_string(),
+            };
+            email_buyer(
+                context,
+                &purchases[0],
+                "Your payment didn't go through",
+                &format!(
+                    "Hello,
+Unfortunately your payment for {} failed, so you haven't been charged.
+You can try again with a different payment method here:
+
+https://{}/{}",
+                    product_ids.join(", "),
+                    SITE_FULL_DOMAIN,
+                    retry_path
+                ),
+            )
+            .await;
+        }
+        Ok(())
+    }
+
+    fn error_status(&self, error: &ServerFnError<NexusError>) -> StatusCode {
+        not_found_status(error)
+    }
+}
+
+async fn apply_change(
+    context: &WebhookContext,
+    payment_intent_id: &str,
+    change: PaymentChange,
+    detail: &str,
+) -> Result<(), ServerFnError<NexusError>> {
+    apply_payment_change(
+        &context.dynamodb_client,
+        context.user_repository.as_ref(),
+        &context.ses_client,
+        &context.stripe_client,
+        payment_intent_id,
+        change,
+        detail,
+    )
+    .await
+}
+
+pub struct ChargeRefunded;
+
+#[async_trait]
+impl WebhookHandler for ChargeRefunded {
+    async fn handle(
+        &self,
+        context: &WebhookContext,
+        event: &StripeEvent,
+    ) -> Result<(), ServerFnError<NexusError>> {
+        let charge = event.charge()?;
+        let payment_intent_id = charge
+            .payment_intent
+            .as_ref()
+            .map(|payment_intent| payment_intent.id().to_string())
+            .ok_or_else(|| missing(event, "payment_intent"))?;
+        let detail = format!(
+            "Charge {} refunded {} of {} {}",
+            charge.id, charge.amount_refunded, charge.amount, charge.currency
+        );
+        if !charge.refunded {
+            let purchases =
+                find_purchases_by_payment_intent(&context.dynamodb_client, &payment_intent_id)
+                    .await?;
+            let refunded = partially_refunded_purchases(
+                &purchases,
+                &charge.refunds.data,
+                charge.amount_refunded,
+            );
+            if refunded.is_empty() {
+                // Partial refunds that aren't for a whole product are goodwill gestures, the
+                // buyer keeps the game
+                log::info!("{}", detail);
+                notify_admins(&context.ses_client, "Partial refund", &detail).await;
+                return Ok(());
+            }
+            // A product of a cart checkout was refunded, so only its purchase is taken away
+            for purchase in refunded {
+                apply_purchase_change(
+                    &context.dynamodb_client,
+                    context.user_repository.as_ref(),
+                    &context.ses_client,
+                    &context.stripe_client,
+                    purchase,
+                    PaymentChange::Refunded,
+                    &detail,
+                )
+                .await?;
+            }
+            return Ok(());
+        }
+        apply_change(
+            context,
+            &payment_intent_id,
+            PaymentChange::Refunded,
+            &detail,
+        )
+        .await
+    }
+
+    fn error_status(&self, error: &ServerFnError<NexusError>) -> StatusCode {
+        not_found_status(error)
+    }
+}
+
+/// Suspends access while a dispute is open, and restores it if the dispute is won
+pub struct DisputeChanged;
+
+#[async_trait]
+impl WebhookHandler for DisputeChanged {
+    async fn handle(
+        &self,
+        context: &WebhookContext,
+        event: &StripeEvent,
+    ) -> Result<(), ServerFnError<NexusError>> {
+        let dispute = event.dispute()?;
+        let change = match (event.type_(), dispute.status) {
+            (EventType::ChargeDisputeCreated, _) => PaymentChange::DisputeOpened,
+            (EventType::ChargeDisputeClosed, DisputeStatus::Won | DisputeStatus::WarningClosed) => {
+                PaymentChange::DisputeWon
+            }
+            (EventType::ChargeDisputeClosed, DisputeStatus::Lost) => PaymentChange::DisputeLost,
+            _ => return Ok(()),
+        };
+        let payment_intent_id = dispute
+            .payment_intent
+            .as_ref()
+            .map(|payment_intent| payment_intent.id().to_string())
+            .ok_or_else(|| missing(event, "payment_intent"))?;
+        let detail = format!(
+            "Dispute {} over {} {} ({}) is {}",
+            dispute.id,
+            dispute.amount,
+            dispute.currency,
+            dispute.reason,
+            dispute.status.as_str()
+        );
+        apply_change(context, &payment_intent_id, change, &detail).await
+    }
+
+    fn error_status(&self, error: &ServerFnError<NexusError>) -> StatusCode {
+        not_found_status(error)
+    }
+}
+
+pub struct EarlyFraudWarningCreated;
+
+#[async_trait]
+impl WebhookHandler for EarlyFraudWarningCreated {
+    async fn handle(
+        &self,
+        context: &WebhookContext,
+        event: &StripeEvent,
+    ) -> Result<(), ServerFnError<NexusError>> {
+        let warning = event.early_fraud_warning()?;
+        let payment_intent_id = warning
+            .payment_intent
+            .as_deref()
+            .ok_or_else(|| missing(event, "payment_intent"))?;
+        let detail = format!(
+            "Early fraud warning {} on charge {}: {}. Consider refunding it before it becomes a dispute.",
+            warning.id, warning.charge, warning.fraud_type
+        );
+        apply_change(
+            context,
+            payment_intent_id,
+            PaymentChange::FraudWarning,
+            &detail,
+        )
+        .await
+    }
+
+    fn error_status(&self, error: &ServerFnError<NexusError>) -> StatusCode {
+        not_found_status(error)
+    }
+}
+
+/// Stripe makes funds available a few days after each charge, which is a good time to pay
+/// collaborators the shares that have cleared since
+pub struct BalanceAvailable;
+
+#[async_trait]
+impl WebhookHandler for BalanceAvailable {
+    async fn handle(
+        &self,
+        context: &WebhookContext,
+        _event: &StripeEvent,
+    ) -> Result<(), ServerFnError<NexusError>> {
+        pay_revenue_shares(
+            &context.dynamodb_client,
+            &context.stripe_client,
+            Utc::now().timestamp(),
+        )
+        .await
+        .map(|_| ())
+    }
+}
@@ -0,0 +1,217 @@
+pub mod handlers;
+pub mod router;
+pub mod signed_event;
+
+use super::{
+    admin::require_admin,
+    download::download_utils::SessionId,
+    globals::app_state::AppState,
+    repository::webhook_events::{
+        begin_processing, claim, get_event, list_failed_events, mark_failed, mark_processed,
+        BeginOutcome, WebhookEventRecord, WebhookEventStatus,
+    },
+};
+use crate::errors::NexusError;
+use axum::{
+    extract::{FromRef, Path, State},
+    response::IntoResponse,
+};
+use chrono::Utc;
+use http::StatusCode;
+use leptos::ServerFnError;
+use router::{WebhookContext, WebhookRouter};
+use signed_event::{parse_event, SignedStripeEvent, StripeEvent};
+use std::{fmt::Debug, sync::LazyLock};
+use stripe::EventType;
+
+/// Built once per instance, like the catalog
+pub static WEBHOOK_ROUTER: LazyLock<WebhookRouter> = LazyLock::new(handlers::nexus_router);
+
+pub fn handle_error(err: impl Debug) -> (StatusCode, String) {
+    tracing::error!("{:?}", err);
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "Internal Server Error".into(),
+    )
+}
+
+pub fn not_found<S: AsRef<str>>(item: S) -> (StatusCode, String) {
+    (
+        StatusCode::NOT_FOUND,
+        format!("{} not found.", item.as_ref()),
+    )
+}
+
+/// Stripe delivers events at least once, so every event goes through the ledger first and its
+/// handlers only run for the delivery that gets to process it.
+pub async fn stripe_webhook(
+    State(state): State<AppState>,
+    SignedStripeEvent { event, payload }: SignedStripeEvent,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let event_id = event.id();
+    let outcome = begin_processing(
+        &state.dynamodb_client,
+        &event_id,
+        &event_type_name(event.type_()),
+        &payload,
+        Utc::now().timestamp(),
+    )
+    .await
+    .map_err(handle_error)?;
+    match outcome {
+        BeginOutcome::Process => process_recorded_event(&state, event).await,
+        BeginOutcome::AlreadyProcessed => {
+            log::info!("Webhook event {} was already processed", event_id);
+            Ok(())
+        }
+        // Stripe retries anything that isn't a 2xx, by which point the other delivery has
+        // either finished or given up its lease
+        BeginOutcome::InProgress => Err((
+            StatusCode::CONFLICT,
+            "Event is already being processed".into(),
+        )),
+    }
+}
+
+/// `checkout.session.completed` rather than the Debug or Display (quoted JSON) forms
+fn event_type_name(event_type: EventType) -> String {
+    event_type.to_string().trim_matches('"').to_string()
+}
+
+/// Runs the handlers of an event we own in the ledger, and records how that went
+async fn process_recorded_event(
+    state: &AppState,
+    event: StripeEvent,
+) -> Result<(), (StatusCode, String)> {
+    let event_id = event.id();
+    let result = WEBHOOK_ROUTER
+        .dispatch(&WebhookContext::from_ref(state), &event)
+        .await;
+    let now = Utc::now().timestamp();
+    let recorded = match &result {
+        Ok(()) => mark_processed(&state.dynamodb_client, &event_id, now).await,
+        Err((status, message)) => {
+            let error = format!("{} {}", status, message);
+            mark_failed(&state.dynamodb_client, &event_id, &error, now).await
+        }
+    };
+    if let Err(e) = recorded {
+        // The event stays `received`, so it will be processed again once its lease runs out
+        log::error!(
+            "Could not record the outcome of webhook event {} {:?}",
+            event_id,
+            e
+        );
+    }
+    result
+}
+
+fn admin_error_status(e: ServerFnError<NexusError>) -> (StatusCode, String) {
+    match e {
+        ServerFnError::WrappedServerError(NexusError::NotAuthorized) => {
+            (StatusCode::FORBIDDEN, "Not an admin".into())
+        }
+        _ => (
+            StatusCode::UNAUTHORIZED,
+            "Session expired or otherwise invalid".into(),
+        ),
+    }
+}
+
+/// Processes a failed event from the ledger again. Returns whether it succeeded this time, or
+/// `None` if someone else is processing it already.
+async fn replay(
+    state: &AppState,
+    record: &WebhookEventRecord,
+) -> Result<Option<bool>, (StatusCode, String)> {
+    if claim(&state.dynamodb_client, record, Utc::now().timestamp())
+        .await
+        .map_err(handle_error)?
+        != BeginOutcome::Process
+    {
+        return Ok(None);
+    }
+    // The signature was checked when the event was first received
+    let event = match parse_event(&record.payload) {
+        Ok(event) => event,
+        Err(e) => {
+            let error = format!("Could not parse stored payload {:?}", e);
+            log::error!("{} for webhook event {}", error, record.event_id);
+            mark_failed(
+                &state.dynamodb_client,
+                &record.event_id,
+                &error,
+                Utc::now().timestamp(),
+            )
+            .await
+            .map_err(handle_error)?;
+            return Ok(Some(false));
+        }
+    };
+    Ok(Some(process_recorded_event(state, event).await.is_ok()))
+}
+
+/// Admin only: re-runs every failed event in the ledger
+pub async fn replay_failed_webhook_events(
+    State(state): State<AppState>,
+    session_id: SessionId,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    require_admin(
+        session_id.session_id,
+        state.user_repository.as_ref(),
+        &state.key_client,
+    )
+    .await
+    .map_err(admin_error_status)?;
+    let records = list_failed_events(&state.dynamodb_client)
+        .await
+        .map_err(handle_error)?;
+    let (mut succeeded, mut failed, mut skipped) = (0, 0, 0);
+    for record in &records {
+        match replay(&state, record).await? {
+            Some(true) => succeeded += 1,
+            Some(false) => failed += 1,
+            None => skipped += 1,
+        }
+    }
+    Ok(format!(
+        "{} succeeded, {} failed again, {} already being processed",
+        succeeded, failed, skipped
+    ))
+}
+
+/// Admin only: re-runs a single failed event from the ledger
+pub async fn replay_webhook_event(
+    State(state): State<AppState>,
+    Path(event_id): Path<String>,
+    session_id: SessionId,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    require_admin(
+        session_id.session_id,
+        state.user_repository.as_ref(),
+        &state.key_client,
+    )
+    .await
+    .map_err(admin_error_status)?;
+    let record = get_event(&state.dynamodb_client, &event_id)
+        .await
+        .map_err(handle_error)?
+        .ok_or_else(|| not_found(format!("Webhook event {}", event_id)))?;
+    if record.status != WebhookEventStatus::Failed {
+        return Err((
+            StatusCode::CONFLICT,
+            format!("Webhook event {} is {}", event_id, record.status.as_str()),
+        ));
+    }
+    match replay(&state, &record).await? {
+        Some(true) => Ok("processed".to_string()),
+        Some(false) => Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "failed again".to_string(),
+        )),
+        None => Err((
+            StatusCode::CONFLICT,
+            "Event is already being processed".into(),
+        )),
+    }
+}
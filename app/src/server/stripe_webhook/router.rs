@@ -0,0 +1,111 @@
+use super::signed_event::StripeEvent;
+use crate::{
+    errors::NexusError,
+    server::{globals::app_state::AppState, repository::UserRepository},
+};
+use async_trait::async_trait;
+use aws_sdk_dynamodb::Client as DynamoClient;
+use aws_sdk_ses::Client as SesClient;
+use axum::extract::FromRef;
+use http::StatusCode;
+use leptos::ServerFnError;
+use std::{collections::HashMap, sync::Arc};
+use stripe::{Client as StripeClient, EventType};
+
+/// What webhook handlers get to work with
+#[derive(Clone)]
+pub struct WebhookContext {
+    pub dynamodb_client: Arc<DynamoClient>,
+    pub user_repository: Arc<dyn UserRepository>,
+    pub stripe_client: Arc<StripeClient>,
+    /// For emailing buyers and admins
+    pub ses_client: Arc<SesClient>,
+}
+
+impl FromRef<AppState> for WebhookContext {
+    fn from_ref(state: &AppState) -> Self {
+        Self {
+            dynamodb_client: state.dynamodb_client.clone(),
+            user_repository: state.user_repository.clone(),
+            stripe_client: state.stripe_client.clone(),
+            ses_client: state.ses_client.clone(),
+        }
+    }
+}
+
+#[async_trait]
+pub trait WebhookHandler: Send + Sync {
+    async fn handle(
+        &self,
+        context: &WebhookContext,
+        event: &StripeEvent,
+    ) -> Result<(), ServerFnError<NexusError>>;
+
+    /// The status Stripe is answered with when [`WebhookHandler::handle`] fails. Stripe retries
+    /// anything that isn't a 2xx for a few days, and the ledger marks the event as failed.
+    fn error_status(&self, error: &ServerFnError<NexusError>) -> StatusCode {
+        default_error_status(error)
+    }
+}
+
+pub fn default_error_status(error: &ServerFnError<NexusError>) -> StatusCode {
+    match error {
+        ServerFnError::WrappedServerError(NexusError::WebhookEventMalformed) => {
+            StatusCode::BAD_REQUEST
+        }
+        ServerFnError::WrappedServerError(NexusError::ConcurrentModification) => {
+            StatusCode::CONFLICT
+        }
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+/// Handlers keyed by the type of event they handle. Events of any other type are acknowledged
+/// without doing anything, so subscribing the endpoint to more events than we handle is fine.
+#[derive(Default)]
+pub struct WebhookRouter {
+    handlers: HashMap<EventType, Box<dyn WebhookHandler>>,
+}
+
+impl WebhookRouter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers `handler` for `event_type`, replacing any handler it already had
+    pub fn on(mut self, event_type: EventType, handler: impl WebhookHandler + 'static) -> Self {
+        self.handlers.insert(event_type, Box::new(handler));
+        self
+    }
+
+    pub fn handles(&self, event_type: EventType) -> bool {
+        self.handlers.contains_key(&event_type)
+    }
+
+    pub async fn dispatch(
+        &self,
+        context: &WebhookContext,
+        event: &StripeEvent,
+    ) -> Result<(), (StatusCode, String)> {
+        let handler = match self.handlers.get(&event.type_()) {
+            Some(handler) => handler,
+            None => {
+                log::info!(
+                    "Acknowledging webhook event {} of unhandled type {:?}",
+                    event.id(),
+                    event.type_()
+                );
+                return Ok(());
+            }
+        };
+        handler.handle(context, event).await.map_err(|e| {
+            log::error!(
+                "Handling webhook event {} ({:?}) failed {:?}",
+                event.id(),
+                event.type_(),
+                e
+            );
+            (handler.error_status(&e), e.to_string())
+        })
+    }
+}
@@ -0,0 +1,237 @@
+use super::handle_error;
+use crate::errors::NexusError;
+use axum::{
+    body::{Body, HttpBody},
+    extract::FromRequest,
+    http::Request,
+    response::{IntoResponse, Response},
+};
+use headers::Header;
+use http::{HeaderName, HeaderValue, StatusCode};
+use leptos::ServerFnError;
+use serde::Deserialize;
+use std::env;
+use stripe::{
+    Charge, CheckoutSession, Dispute, Event as WebhookEvent, EventObject, EventType, Webhook,
+    WebhookError,
+};
+
+impl From<(StatusCode, String)> for ServerError {
+    fn from(value: (StatusCode, String)) -> Self {
+        Self(value.0, value.1)
+    }
+}
+
+impl IntoResponse for ServerError {
+    fn into_response(self) -> Response {
+        (self.0, self.1).into_response()
+    }
+}
+
+pub fn get_stripe_webhook_signature() -> String {
+    match env::var("STRIPE_WEBHOOK_SECRET") {
+        Ok(s) => s,
+        Err(_) => {
+            panic!("Cannot get STRIPE_WEBHOOK_SECRET");
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ServerError(pub StatusCode, pub String);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StripeSignatureHeader(pub String);
+
+static STRIPE_SIGNATURE_HEADER: HeaderName = HeaderName::from_static("stripe-signature");
+impl Header for StripeSignatureHeader {
+    fn name() -> &'static HeaderName {
+        &STRIPE_SIGNATURE_HEADER
+    }
+
+    fn decode<'i, I>(values: &mut I) -> Result<Self, headers::Error>
+    where
+        Self: Sized,
+        I: Iterator<Item = &'i HeaderValue>,
+    {
+        let value = values.next().ok_or_else(headers::Error::invalid)?;
+        Ok(Self(
+            value
+                .to_str()
+                .map_err(|_| headers::Error::invalid())?
+                .to_string(),
+        ))
+    }
+
+    fn encode<E: Extend<HeaderValue>>(&self, values: &mut E) {
+        if let Ok(val) = HeaderValue::from_str(&self.0) {
+            values.extend(std::iter::once(val));
+        }
+    }
+}
+
+const MAX_ALLOWED_REQ_SIZE: u64 = 1_000_000;
+/// A webhook event whose signature checked out, along with the exact body it was parsed from
+pub struct SignedStripeEvent {
+    pub event: StripeEvent,
+    pub payload: String,
+}
+
+/// async-stripe's `EventObject` has no variant for Radar early fraud warnings, so events
+/// carrying one can't be parsed as a [`WebhookEvent`] and get their own type.
+pub enum StripeEvent {
+    Modelled(Box<WebhookEvent>),
+    EarlyFraudWarning(EarlyFraudWarningEvent),
+}
+
+impl SignedStripeEvent {
+    /// Checks `payload` against the `Stripe-Signature` header it came with and parses it
+    pub fn verify(payload: &str, signature: &str, secret: &str) -> Result<Self, ServerError> {
+        let event = match Webhook::construct_event(payload, signature, secret) {
+            Ok(event) => StripeEvent::Modelled(Box::new(event)),
+            // The signature is checked before parsing, so this body is genuine
+            Err(WebhookError::BadParse(_)) => parse_event(payload)
+                .map_err(|err| ServerError(StatusCode::BAD_REQUEST, format!("{:?}", err)))?,
+            Err(err) => return Err(ServerError(StatusCode::UNAUTHORIZED, format!("{:?}", err))),
+        };
+        Ok(SignedStripeEvent {
+            event,
+            payload: payload.to_string(),
+        })
+    }
+}
+
+impl StripeEvent {
+    pub fn id(&self) -> String {
+        match self {
+            StripeEvent::Modelled(event) => event.id.to_string(),
+            StripeEvent::EarlyFraudWarning(event) => event.id.clone(),
+        }
+    }
+
+    pub fn type_(&self) -> EventType {
+        match self {
+            StripeEvent::Modelled(event) => event.type_,
+            StripeEvent::EarlyFraudWarning(event) => event.type_,
+        }
+    }
+
+    pub fn object(&self) -> Option<&EventObject> {
+        match self {
+            StripeEvent::Modelled(event) => Some(&event.data.object),
+            StripeEvent::EarlyFraudWarning(_) => None,
+        }
+    }
+
+    pub fn checkout_session(&self) -> Result<&CheckoutSession, ServerFnError<NexusError>> {
+        match self.object() {
+            Some(EventObject::CheckoutSession(checkout_session)) => Ok(checkout_session),
+            _ => Err(self.unexpected_object("checkout session")),
+        }
+    }
+
+    pub fn charge(&self) -> Result<&Charge, ServerFnError<NexusError>> {
+        match self.object() {
+            Some(EventObject::Charge(charge)) => Ok(charge),
+            _ => Err(self.unexpected_object("charge")),
+        }
+    }
+
+    pub fn dispute(&self) -> Result<&Dispute, ServerFnError<NexusError>> {
+        match self.object() {
+            Some(EventObject::Dispute(dispute)) => Ok(dispute),
+            _ => Err(self.unexpected_object("dispute")),
+        }
+    }
+
+    pub fn early_fraud_warning(&self) -> Result<&EarlyFraudWarning, ServerFnError<NexusError>> {
+        match self {
+            StripeEvent::EarlyFraudWarning(event) => Ok(&event.data.object),
+            StripeEvent::Modelled(_) => Err(self.unexpected_object("early fraud warning")),
+        }
+    }
+
+    fn unexpected_object(&self, expected: &str) -> ServerFnError<NexusError> {
+        log::error!(
+            "Webhook event {} ({:?}) doesn't carry a {}",
+            self.id(),
+            self.type_(),
+            expected
+        );
+        ServerFnError::from(NexusError::WebhookEventMalformed)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EarlyFraudWarningEvent {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub type_: EventType,
+    pub data: EarlyFraudWarningData,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EarlyFraudWarningData {
+    pub object: EarlyFraudWarning,
+}
+
+/// The parts of a `radar.early_fraud_warning` we use. Webhooks never expand its references.
+#[derive(Debug, Deserialize)]
+pub struct EarlyFraudWarning {
+    pub id: String,
+    pub charge: String,
+    pub payment_intent: Option<String>,
+    pub fraud_type: String,
+}
+
+/// Parses the body of an event whose signature was already checked
+pub fn parse_event(payload: &str) -> Result<StripeEvent, serde_json::Error> {
+    match serde_json::from_str::<WebhookEvent>(payload) {
+        Ok(event) => Ok(StripeEvent::Modelled(Box::new(event))),
+        Err(e) => serde_json::from_str::<EarlyFraudWarningEvent>(payload)
+            .map(StripeEvent::EarlyFraudWarning)
+            .map_err(|_| e),
+    }
+}
+
+#[async_trait::async_trait]
+impl<S: Sync> FromRequest<S, Body> for SignedStripeEvent {
+    type Rejection = ServerError;
+
+    async fn from_request(req: Request<Body>, _: &S) -> Result<Self, Self::Rejection> {
+        let signature = req
+            .headers()
+            .get("Stripe-Signature")
+            .ok_or_else(|| {
+                ServerError(
+                    StatusCode::BAD_REQUEST,
+                    "Missing Stripe-Signature header".into(),
+                )
+            })
+            .and_then(|value| {
+                value.to_str().map_err(|_| {
+                    ServerError(
+                        StatusCode::BAD_REQUEST,
+                        "Invalid Stripe-Signature header".into(),
+                    )
+                })
+            })
+            .map_err(handle_error)?
+            .to_string();
+        let secret = get_stripe_webhook_signature();
+        let req_content_length = match req.body().size_hint().upper() {
+            Some(v) => v,
+            None => MAX_ALLOWED_REQ_SIZE + 1, // Just to protect ourselves from a malicious response
+        };
+        if req_content_length < MAX_ALLOWED_REQ_SIZE {
+            let body = axum::body::to_bytes(req.into_body(), req_content_length as usize)
+                .await
+                .map_err(handle_error)?;
+            let body_str = std::str::from_utf8(&body).map_err(handle_error)?;
+
+            SignedStripeEvent::verify(body_str, &signature, &secret)
+        } else {
+            Err(ServerError(StatusCode::PAYLOAD_TOO_LARGE, "...".into()))
+        }
+    }
+}
@@ -6,6 +6,7 @@ use aws_sdk_dynamodb::{
     operation::query::QueryOutput, types::AttributeValue, Client as DynamoClient,
 };
 use aws_sdk_kms::Client as KeyClient;
+use aws_sdk_s3::Client as S3Client;
 use aws_sdk_ses::Client as SesClient;
 use axum_extra::extract::CookieJar;
 use chrono::Utc;
@@ -16,13 +17,13 @@ use std::{collections::HashMap, sync::Arc};
 use stripe::Client as StripeClient;
 
 use super::globals::{
-    dynamo::{
-        constants::table_attributes::{self, EMAIL, SESSION_EXPIRY, SESSION_ID},
-        query_setup, TableKeyType,
-    },
+    dynamo::constants::table_attributes::{self, EMAIL, SESSION_ID},
+    dynamo_error::{send_with_retry, DynamoError, DynamoOperation},
     env_var::{get_host_prefix, get_table_name},
 };
 
+use super::repository::{Session, UserRepository};
+use super::session_cache::{SessionLookup, SESSION_CACHE};
 use crate::errors::{NexusError, UNHANDLED};
 
 pub fn dynamo_client() -> Result<Arc<DynamoClient>, ServerFnError<NexusError>> {
@@ -37,6 +38,13 @@ pub fn kms_client() -> Result<Arc<KeyClient>, ServerFnError<NexusError>> {
     use_context::<Arc<KeyClient>>().ok_or(UNHANDLED)
 }
 
+pub fn user_repository() -> Result<Arc<dyn UserRepository>, ServerFnError<NexusError>> {
+    use_context::<Arc<dyn UserRepository>>().ok_or_else(|| {
+        log::error!("Could not retrieve user repository from context");
+        UNHANDLED
+    })
+}
+
 pub fn stripe_client() -> Result<Arc<StripeClient>, ServerFnError<NexusError>> {
     use_context::<Arc<StripeClient>>().ok_or_else(|| {
         log::error!("Could not get Stripe client");
@@ -44,6 +52,13 @@ pub fn stripe_client() -> Result<Arc<StripeClient>, ServerFnError<NexusError>> {
     })
 }
 
+pub fn s3_client() -> Result<Arc<S3Client>, ServerFnError<NexusError>> {
+    use_context::<Arc<S3Client>>().ok_or_else(|| {
+        log::error!("Could not get S3 client");
+        UNHANDLED
+    })
+}
+
 pub fn hash_password(password: &str) -> Result<String, Error> {
     let salt = SaltString::generate(&mut OsRng);
     let argon2 = Argon2::default();
@@ -95,72 +110,64 @@ pub async fn get_session_cookie() -> Result<String, ServerFnError<NexusError>> {
 
 pub async fn check_if_session_is_valid(
     session_id_cookie: String,
-    csrf_cookie: String,
-    dynamo_client: &DynamoClient,
-    kms_client: &KeyClient,
+    _csrf_cookie: String,
+    repository: &dyn UserRepository,
+    _kms_client: &KeyClient,
 ) -> Result<(bool, String), ServerFnError<NexusError>> {
-    let query = query_setup(
-        dynamo_client,
-        session_id_cookie.clone(),
-        TableKeyType::SessionId,
-    )
-    .projection_expression([SESSION_ID, SESSION_EXPIRY, EMAIL].join(", "))
-    .send()
-    .await
-    .map_err(aws_sdk_dynamodb::Error::from);
+    let session = lookup_session(session_id_cookie, repository).await?;
+    let now = Utc::now().timestamp();
+    Ok((now < session.session_expiry, session.email))
+}
 
-    match query {
-        Ok(o) => {
-            let items = o.items.ok_or(UNHANDLED)?;
-            let item_in_query = items.first().ok_or_else(|| {
-                log::error!("Unable to get first item in check_if_session_is_valid query");
-                UNHANDLED
-            })?;
-            let session_id = item_in_query
-                .get(SESSION_ID)
-                .ok_or_else(|| {
-                    log::error!("Unable to get session_id in check_if_session_is_valid query");
-                    UNHANDLED
-                })?
-                .as_s()
-                .map_err(|e| {
-                    log::error!(
-                        "Can't get session_id as string in check_if_session_is_valid query{:?}",
-                        e
-                    );
-                    UNHANDLED
-                })?;
-            let session_expiry = item_in_query.get(SESSION_EXPIRY).ok_or_else(|| {
-                    log::error!("Unable to get session_id in check_if_session_is_valid query");
-                    UNHANDLED
-                })?.as_n().map_err(|e| {
-                    log::error!("Can't get session_expiry as number in check_if_session_is_valid query {:?}", e);
-                    UNHANDLED
-                })?.parse::<i64>().map_err(|e| {
-                    log::error!("Could not parse string as i64 {:?}", e);
-                    UNHANDLED
-                })?;
-            let email = item_in_query
-                .get(EMAIL)
-                .ok_or_else(|| {
-                    log::error!("Unable to get email in check_if_session_is_valid query");
-                    UNHANDLED
-                })?
-                .as_s()
-                .map_err(|e| {
-                    log::error!(
-                        "Can't get email as string in check_if_session_is_valid query {:?}",
-                        e
-                    );
-                    UNHANDLED
-                })?;
-            let now = Utc::now().timestamp();
-            Ok((
-                *session_id == session_id_cookie && now < session_expiry,
-                email.to_owned(),
-            ))
+/// The account behind the request's session cookie
+#[derive(Debug, Clone)]
+pub struct LoggedInUser {
+    pub email: String,
+    pub user_uuid: String,
+}
+
+/// Fails with [`NexusError::InvalidSession`] unless the request comes from someone logged in
+pub async fn logged_in_user(
+    repository: &dyn UserRepository,
+) -> Result<LoggedInUser, ServerFnError<NexusError>> {
+    let session_id_cookie = get_session_cookie().await?;
+    let kms_client = kms_client()?;
+    let (valid, email) =
+        check_if_session_is_valid(session_id_cookie, String::new(), repository, &kms_client)
+            .await?;
+    if !valid {
+        return Err(ServerFnError::from(NexusError::InvalidSession));
+    }
+    let user_uuid = repository.find_user_uuid(&email).await?.ok_or_else(|| {
+        log::error!("Could not find the uuid of {}", email);
+        ServerFnError::from(NexusError::CouldNotFindRowWithThatEmail)
+    })?;
+    Ok(LoggedInUser { email, user_uuid })
+}
+
+/// Finds the session with the given id, going through [`SESSION_CACHE`] first.
+/// Ids that don't belong to any user are remembered too, so guessing session ids
+/// doesn't translate into a query per guess.
+async fn lookup_session(
+    session_id_cookie: String,
+    repository: &dyn UserRepository,
+) -> Result<Session, ServerFnError<NexusError>> {
+    match SESSION_CACHE.get(&session_id_cookie) {
+        Some(SessionLookup::Known(session)) => return Ok(session),
+        Some(SessionLookup::Unknown) => {
+            return Err(ServerFnError::from(NexusError::InvalidSession));
+        }
+        None => {}
+    }
+    match repository.find_session(&session_id_cookie).await? {
+        Some(session) => {
+            SESSION_CACHE.insert_known(session_id_cookie, session.clone());
+            Ok(session)
+        }
+        None => {
+            SESSION_CACHE.insert_unknown(session_id_cookie);
+            Err(ServerFnError::from(NexusError::InvalidSession))
         }
-        Err(e) => Err(handle_dynamo_generic_error(e)),
     }
 }
 
@@ -256,14 +263,16 @@ pub async fn check_email_uniqueness(
     email: String,
     client: &aws_sdk_dynamodb::Client,
 ) -> Result<bool, ServerFnError<NexusError>> {
-    let db_query = client
+    let get_item = client
         .get_item()
         .table_name(get_table_name())
         .key(EMAIL, AttributeValue::S(email))
-        .projection_expression([EMAIL].join(", "))
-        .send()
-        .await
-        .map_err(aws_sdk_dynamodb::Error::from);
+        .projection_expression([EMAIL].join(", "));
+    let db_query = send_with_retry(
+        DynamoOperation::read("check_email_uniqueness", get_table_name()),
+        || get_item.clone().send(),
+    )
+    .await;
 
     match db_query {
         Ok(o) => {
@@ -280,22 +289,13 @@ pub async fn check_email_uniqueness(
 
 pub async fn get_email_from_session_id(
     session_id_cookie: String,
-    client: &aws_sdk_dynamodb::Client,
+    repository: &dyn UserRepository,
 ) -> Result<String, ServerFnError<NexusError>> {
-    let db_query_result = query_setup(client, session_id_cookie, TableKeyType::SessionId)
-        .send()
-        .await
-        .map_err(aws_sdk_dynamodb::Error::from);
-
-    let email = match db_query_result {
-        Ok(o) => Ok(extract_email_from_query(&o)?),
-        Err(e) => Err(handle_dynamo_generic_error(e)),
-    }?;
-
-    Ok(email)
+    Ok(lookup_session(session_id_cookie, repository).await?.email)
 }
 
-pub fn handle_dynamo_generic_error(e: aws_sdk_dynamodb::Error) -> ServerFnError<NexusError> {
-    log::error!("{:?}", e);
-    ServerFnError::from(NexusError::GenericDynamoServiceError)
+/// Maps a failed DynamoDB call to the error the UI gets. The failure itself has already been
+/// logged with its operation and table by `send_with_retry`.
+pub fn handle_dynamo_generic_error(e: DynamoError) -> ServerFnError<NexusError> {
+    ServerFnError::from(e)
 }
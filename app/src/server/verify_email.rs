@@ -1,16 +1,11 @@
 use super::{
-    globals::dynamo::{constants::table_attributes, query_setup, update_setup, TableKeyType},
-    utilities::{
-        dynamo_client, extract_email_from_query,
-        extract_email_verification_request_time_from_query, handle_dynamo_generic_error,
-        ses_client,
-    },
+    stripe_customer::sync_customer_after_change,
+    utilities::{ses_client, user_repository},
 };
 use crate::{
-    errors::NexusError,
+    errors::{NexusError, UNHANDLED},
     site::constants::{SITE_DOMAIN, SITE_EMAIL_ADDRESS, SITE_FULL_DOMAIN},
 };
-use aws_sdk_dynamodb::types::AttributeValue;
 use aws_sdk_ses::types::{Body, Content, Destination, Message};
 use chrono::{DateTime, Utc};
 use leptos::ServerFnError;
@@ -74,21 +69,17 @@ If this was not you, you may ignore this email.",
 
 /// Verifies a given email_uuid
 pub async fn verify_email(email_uuid: String) -> Result<(), ServerFnError<NexusError>> {
-    let client = dynamo_client()?;
+    let repository = user_repository()?;
 
-    // first we have to query to find the email address associated with this verification attempt.
-    let db_query_result = query_setup(&client, email_uuid, TableKeyType::EmailVerificationUUID)
-        .send()
-        .await
-        .map_err(aws_sdk_dynamodb::Error::from);
-
-    let (email, email_verification_request_time) = match db_query_result {
-        Ok(o) => Ok((
-            extract_email_from_query(&o)?,
-            extract_email_verification_request_time_from_query(&o)?,
-        )),
-        Err(e) => Err(handle_dynamo_generic_error(e)),
-    }?;
+    // first we have to find the email address associated with this verification attempt.
+    let token = repository
+        .find_verification_token(&email_uuid)
+        .await?
+        .ok_or_else(|| {
+            log::error!("Could not find the email verification token");
+            UNHANDLED
+        })?;
+    let (email, email_verification_request_time) = (token.email, token.request_time);
 
     // TODO: Evaluate this 24 hour constant to verify email time
     let time_to_verify_email = chrono::Duration::hours(24);
@@ -104,16 +95,8 @@ pub async fn verify_email(email_uuid: String) -> Result<(), ServerFnError<NexusE
     }
 
     // secondly if we can find the email, update its verification field
-    let db_update_result = update_setup(&client, email)
-        .update_expression("SET #e = :r")
-        .expression_attribute_names("#e".to_string(), table_attributes::EMAIL_VERIFIED)
-        .expression_attribute_values(":r", AttributeValue::Bool(true))
-        .send()
-        .await
-        .map_err(aws_sdk_dynamodb::Error::from);
-
-    match db_update_result {
-        Ok(_) => Ok(()),
-        Err(e) => Err(handle_dynamo_generic_error(e)),
-    }
+    repository.mark_email_verified(&email).await?;
+    // If this finished an email change, the account's Stripe Customer gets the new address
+    sync_customer_after_change(repository.as_ref(), &email).await;
+    Ok(())
 }
@@ -4,4 +4,3 @@ pub mod constants {
     pub const SITE_EMAIL_ADDRESS: &str = "andrew@ProjectGlint.com";
     pub const NO_REPLY_EMAIL_ADDRESS: &str = "noreply@ProjectGlint.com";
 }
-
@@ -0,0 +1,174 @@
+mod common;
+
+use app::{
+    catalog::{Product, ProductPrice},
+    server::{
+        cart::{cart_id, price_cart},
+        repository::purchases::{
+            checkout_session_of, entitled_through_other_purchase, line_purchase_id, Purchase,
+            PurchaseStatus,
+        },
+    },
+};
+use common::price;
+
+fn product(id: &str, prices: Vec<ProductPrice>, bundle: &[&str]) -> Product {
+    Product {
+        bundle: bundle.iter().map(|item| item.to_string()).collect(),
+        ..common::product(id, prices)
+    }
+}
+
+/// Two games, one of them not priced in euros, and a bundle of both
+fn catalog() -> Vec<Product> {
+    vec![
+        product("a", vec![price("usd", 1000), price("eur", 900)], &[]),
+        product("b", vec![price("usd", 2000)], &[]),
+        product(
+            "ab",
+            vec![price("usd", 2400), price("eur", 2100)],
+            &["a", "b"],
+        ),
+    ]
+}
+
+fn ids(ids: &[&str]) -> Vec<String> {
+    ids.iter().map(|id| id.to_string()).collect()
+}
+
+fn purchase(checkout_session_id: &str, product_id: &str, status: PurchaseStatus) -> Purchase {
+    Purchase {
+        checkout_session_id: checkout_session_id.to_string(),
+        product_id: product_id.to_string(),
+        status,
+        ..common::purchase()
+    }
+}
+
+#[test]
+fn test_cart_totals_its_lines() {
+    let priced = price_cart(&ids(&["a", "b"]), &catalog(), &[], "usd", 0);
+    assert!(priced.stale.is_empty());
+    let cart = priced.summary();
+    assert_eq!(cart.currency, "usd");
+    assert_eq!(cart.lines.len(), 2);
+    assert_eq!(cart.lines[1].product_title, "B");
+    assert_eq!(cart.total, 3000);
+}
+
+#[test]
+fn test_cart_falls_back_to_the_default_currency_as_a_whole() {
+    let priced = price_cart(&ids(&["a"]), &catalog(), &[], "eur", 0);
+    assert_eq!(priced.summary().total, 900);
+    // b has no euro price, so a is charged in dollars too
+    let priced = price_cart(&ids(&["a", "b"]), &catalog(), &[], "eur", 0);
+    assert_eq!(priced.currency, "usd");
+    assert_eq!(priced.summary().total, 3000);
+}
+
+#[test]
+fn test_cart_drops_what_cant_be_bought() {
+    let priced = price_cart(&ids(&["a", "ab", "gone"]), &catalog(), &[], "usd", 0);
+    let lines: Vec<&str> = priced
+        .lines
+        .iter()
+        .map(|(product, _)| product.id.as_str())
+        .collect();
+    // a comes with the bundle anyway
+    assert_eq!(lines, vec!["ab"]);
+    assert_eq!(priced.stale, ids(&["a", "gone"]));
+
+    let priced = price_cart(&ids(&["a", "ab"]), &catalog(), &ids(&["a", "b"]), "usd", 0);
+    assert!(priced.lines.is_empty());
+    assert_eq!(priced.stale, ids(&["a", "ab"]));
+}
+
+#[test]
+fn test_bundles_are_cheaper_by_what_is_already_owned() {
+    let catalog = catalog();
+    let bundle = &catalog[2];
+    assert!(bundle.is_bundle());
+    assert_eq!(bundle.entitlements(), ids(&["a", "b"]));
+    assert_eq!(catalog[0].entitlements(), ids(&["a"]));
+    assert!(!bundle.is_owned_by(&ids(&["a"])));
+    assert!(bundle.is_owned_by(&ids(&["b", "a"])));
+
+    // a is a third of the items' regular value
+    let offer = bundle
+        .offer_for_owner(&catalog, "usd", 0, &ids(&["a"]))
+        .unwrap();
+    assert_eq!(offer.owned_credit, 800);
+    assert_eq!(offer.price.unit_amount, 1600);
+    assert_eq!(offer.discount(), 800);
+    assert!(!offer.matches_stripe_price());
+
+    let offer = bundle.offer_for_owner(&catalog, "usd", 0, &[]).unwrap();
+    assert_eq!(offer.owned_credit, 0);
+    assert!(offer.matches_stripe_price());
+
+    // Without a euro price for b there's nothing to weigh a against
+    let offer = bundle
+        .offer_for_owner(&catalog, "eur", 0, &ids(&["a"]))
+        .unwrap();
+    assert_eq!(offer.owned_credit, 0);
+    assert_eq!(offer.price.unit_amount, 2100);
+
+    let priced = price_cart(&ids(&["ab"]), &catalog, &ids(&["a"]), "usd", 0);
+    assert_eq!(priced.summary().lines[0].owned_credit, 800);
+    assert_eq!(priced.summary().total, 1600);
+}
+
+#[test]
+fn test_cart_id_hides_the_session_id() {
+    let id = cart_id("session");
+    assert_eq!(id.len(), 64);
+    assert!(id.chars().all(|c| c.is_ascii_hexdigit()));
+    assert!(!id.contains("session"));
+    assert_eq!(id, cart_id("session"));
+    assert_ne!(id, cart_id("other session"));
+}
+
+#[test]
+fn test_cart_lines_share_their_checkout() {
+    let id = line_purchase_id("cs_test_1", "a");
+    assert_eq!(id, "cs_test_1#a");
+    assert_eq!(checkout_session_of(&id), "cs_test_1");
+    assert_eq!(checkout_session_of("cs_test_1"), "cs_test_1");
+}
+
+#[test]
+fn test_bundle_keeps_items_of_a_refunded_purchase() {
+    let catalog = catalog();
+    let entitlements_of = |product_id: &str| {
+        catalog
+            .iter()
+            .find(|product| product.id == product_id)
+            .map(Product::entitlements)
+            .unwrap_or_default()
+    };
+    let refunded = purchase("cs_1", "a", PurchaseStatus::Refunded);
+    let bundle = purchase("cs_2", "ab", PurchaseStatus::Paid);
+    let purchases = vec![refunded.clone(), bundle.clone()];
+    assert!(entitled_through_other_purchase(
+        &purchases,
+        &[],
+        &refunded,
+        "a",
+        entitlements_of
+    ));
+    // Refunding the bundle takes both games, as the refunded purchase no longer grants a
+    assert!(!entitled_through_other_purchase(
+        &purchases,
+        &[],
+        &bundle,
+        "a",
+        entitlements_of
+    ));
+    assert!(!entitled_through_other_purchase(
+        &purchases,
+        &[],
+        &bundle,
+        "b",
+        entitlements_of
+    ));
+}
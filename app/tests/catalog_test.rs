@@ -0,0 +1,189 @@
+mod common;
+
+use app::{
+    catalog::{Product, ProductPrice, Sale, SaleDiscount, DEFAULT_CURRENCY},
+    server::catalog::{find_product, parse_catalog},
+};
+use common::price;
+
+fn product(available_from: Option<i64>, available_until: Option<i64>) -> Product {
+    Product {
+        title: "Game".to_string(),
+        platforms: vec!["linux".to_string()],
+        available_from,
+        available_until,
+        ..common::product("game", vec![price("usd", 1999)])
+    }
+}
+
+#[test]
+fn test_bundled_catalog_parses() {
+    let products = parse_catalog(include_str!("../catalog.json")).unwrap();
+    assert!(!products.is_empty());
+    for product in &products {
+        assert!(
+            product.price_for(DEFAULT_CURRENCY).is_some(),
+            "{} has no default currency price",
+            product.id
+        );
+        let shared: u32 = product
+            .revenue_shares
+            .iter()
+            .map(|share| u32::from(share.percent))
+            .sum();
+        assert!(
+            shared < 100,
+            "{} shares {}% of its sales",
+            product.id,
+            shared
+        );
+    }
+}
+
+#[test]
+fn test_availability_window() {
+    assert!(product(None, None).is_available_at(0));
+    assert!(!product(Some(100), None).is_available_at(99));
+    assert!(product(Some(100), None).is_available_at(100));
+    assert!(product(None, Some(200)).is_available_at(199));
+    assert!(!product(None, Some(200)).is_available_at(200));
+}
+
+#[test]
+fn test_price_lookup() {
+    let product = product(None, None);
+    assert_eq!(
+        product
+            .price_for("USD")
+            .map(|price| price.stripe_price_id.as_str()),
+        Some("price_usd")
+    );
+    assert!(product.price_for("eur").is_none());
+    assert_eq!(product.prices[0].display(), "19.99 USD");
+    let products = vec![product];
+    assert!(find_product(&products, "game").is_some());
+    assert!(find_product(&products, "other").is_none());
+}
+
+fn sale(starts_at: i64, ends_at: i64, discount: SaleDiscount) -> Sale {
+    Sale {
+        name: "Launch discount".to_string(),
+        starts_at,
+        ends_at,
+        discount,
+    }
+}
+
+#[test]
+fn test_percentage_sale_only_applies_in_its_window() {
+    let mut product = product(None, None);
+    product.sales = vec![sale(
+        100,
+        200,
+        SaleDiscount::Percentage {
+            percent_off: 25,
+            stripe_coupon_id: "launch25".to_string(),
+        },
+    )];
+    let before = product.offer_for("usd", 99).unwrap();
+    assert!(before.sale.is_none());
+    assert_eq!(before.price, before.regular);
+    assert_eq!(before.discount(), 0);
+    assert_eq!(before.stripe_coupon_id(), None);
+
+    let during = product.offer_for("usd", 100).unwrap();
+    assert_eq!(during.sale.as_ref().unwrap().name, "Launch discount");
+    assert_eq!(during.price.unit_amount, 1499);
+    assert_eq!(during.price.stripe_price_id, "price_usd");
+    assert_eq!(during.discount(), 500);
+    assert_eq!(during.stripe_coupon_id(), Some("launch25"));
+
+    assert!(product.offer_for("usd", 200).unwrap().sale.is_none());
+    assert!(product.offer_for("eur", 150).is_none());
+}
+
+#[test]
+fn test_fixed_price_sale_swaps_the_price() {
+    let mut product = product(None, None);
+    product.sales = vec![sale(
+        100,
+        200,
+        SaleDiscount::Prices(vec![ProductPrice {
+            currency: "usd".to_string(),
+            stripe_price_id: "price_usd_sale".to_string(),
+            unit_amount: 999,
+        }]),
+    )];
+    let during = product.offer_for("USD", 150).unwrap();
+    assert_eq!(during.price.stripe_price_id, "price_usd_sale");
+    assert_eq!(during.discount(), 1000);
+    assert_eq!(during.stripe_coupon_id(), None);
+}
+
+#[test]
+fn test_sale_without_a_price_in_the_currency_is_skipped() {
+    let mut product = product(None, None);
+    product.sales = vec![
+        sale(
+            100,
+            200,
+            SaleDiscount::Prices(vec![ProductPrice {
+                currency: "eur".to_string(),
+                stripe_price_id: "price_eur_sale".to_string(),
+                unit_amount: 999,
+            }]),
+        ),
+        sale(
+            100,
+            200,
+            SaleDiscount::Percentage {
+                percent_off: 10,
+                stripe_coupon_id: "ten".to_string(),
+            },
+        ),
+    ];
+    assert_eq!(
+        product.offer_for("usd", 150).unwrap().stripe_coupon_id(),
+        Some("ten")
+    );
+}
+
+#[test]
+fn test_sales_parse_from_json() {
+    let products = parse_catalog(
+        r#"[{
+            "id": "game",
+            "title": "Game",
+            "description": "",
+            "prices": [{"currency": "usd", "stripe_price_id": "price_usd", "unit_amount": 1999}],
+            "sales": [
+                {
+                    "name": "Launch discount",
+                    "starts_at": 100,
+                    "ends_at": 200,
+                    "discount": {"percentage": {"percent_off": 20, "stripe_coupon_id": "launch"}}
+                },
+                {
+                    "name": "Winter sale",
+                    "starts_at": 300,
+                    "ends_at": 400,
+                    "discount": {"prices": [{"currency": "usd", "stripe_price_id": "price_winter", "unit_amount": 999}]}
+                }
+            ]
+        }]"#,
+    )
+    .unwrap();
+    assert_eq!(products[0].sales.len(), 2);
+    assert_eq!(
+        products[0].offer_for("usd", 150).unwrap().price.unit_amount,
+        1599
+    );
+    assert_eq!(
+        products[0]
+            .offer_for("usd", 350)
+            .unwrap()
+            .price
+            .stripe_price_id,
+        "price_winter"
+    );
+}
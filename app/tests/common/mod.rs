@@ -0,0 +1,57 @@
+//! Fixtures shared by the integration tests. Each test crate uses only some of them.
+#![allow(dead_code)]
+
+use app::{
+    catalog::{Product, ProductPrice},
+    server::repository::purchases::{Purchase, PurchaseStatus},
+};
+
+pub fn price(currency: &str, unit_amount: i64) -> ProductPrice {
+    ProductPrice {
+        currency: currency.to_string(),
+        stripe_price_id: format!("price_{}", currency),
+        unit_amount,
+    }
+}
+
+/// A product on sale for good, with nothing but its prices
+pub fn product(id: &str, prices: Vec<ProductPrice>) -> Product {
+    Product {
+        id: id.to_string(),
+        title: id.to_uppercase(),
+        description: String::new(),
+        media: Vec::new(),
+        platforms: Vec::new(),
+        key_platforms: Vec::new(),
+        prices,
+        available_from: None,
+        available_until: None,
+        sales: Vec::new(),
+        bundle: Vec::new(),
+        revenue_shares: Vec::new(),
+    }
+}
+
+/// A paid purchase of game_1 in dollars. Tests override what they're about with struct update
+/// syntax.
+pub fn purchase() -> Purchase {
+    Purchase {
+        checkout_session_id: "cs_test_1".to_string(),
+        user_uuid: "8d0c6f3e-3a4f-4d43-9b43-7d1b1b0c9a11".to_string(),
+        email: "buyer@example.com".to_string(),
+        product_id: "game_1".to_string(),
+        payment_intent_id: Some("pi_test_1".to_string()),
+        amount_total: 1999,
+        currency: "usd".to_string(),
+        status: PurchaseStatus::Paid,
+        created_at: 1_700_000_000,
+        updated_at: 1_700_000_000,
+        gift: false,
+        amount_discount: 0,
+        sale: None,
+        discount_code: None,
+        amount_tax: 0,
+        receipt_sent_at: None,
+        country: None,
+    }
+}
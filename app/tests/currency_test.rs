@@ -0,0 +1,86 @@
+mod common;
+
+use app::{
+    currency::{currencies_from_accept_language, currency_for_region, format_amount},
+    server::currency::{available_currencies, resolve_currency},
+};
+use common::{price, product};
+
+fn offered() -> Vec<String> {
+    vec!["eur".to_string(), "gbp".to_string(), "usd".to_string()]
+}
+
+#[test]
+fn test_amounts_respect_minor_units() {
+    assert_eq!(format_amount(1999, "usd"), "19.99 USD");
+    assert_eq!(format_amount(1505, "EUR"), "15.05 EUR");
+    assert_eq!(format_amount(-250, "gbp"), "-2.50 GBP");
+    assert_eq!(format_amount(1999, "jpy"), "1999 JPY");
+    assert_eq!(price("krw", 25000).display(), "25000 KRW");
+}
+
+#[test]
+fn test_regions_map_to_currencies() {
+    assert_eq!(currency_for_region("DE"), Some("eur"));
+    assert_eq!(currency_for_region("gb"), Some("gbp"));
+    assert_eq!(currency_for_region("US"), Some("usd"));
+    assert_eq!(currency_for_region("zz"), None);
+}
+
+#[test]
+fn test_accept_language_is_ordered_by_quality() {
+    assert_eq!(
+        currencies_from_accept_language("en-GB;q=0.8, de-DE, en;q=0.5, ja;q=0.9"),
+        vec!["eur", "jpy", "gbp"]
+    );
+    assert_eq!(
+        currencies_from_accept_language("fr-FR,fr;q=0.9,en-US;q=0.8,en;q=0.7"),
+        vec!["eur", "usd"]
+    );
+    assert_eq!(
+        currencies_from_accept_language("zh-Hant-TW, en-US;q=0"),
+        Vec::<&str>::new()
+    );
+    assert!(currencies_from_accept_language("").is_empty());
+    assert!(currencies_from_accept_language("*").is_empty());
+}
+
+#[test]
+fn test_picked_currency_beats_the_browser_language() {
+    let settings = resolve_currency(Some("GBP"), Some("de-DE"), offered());
+    assert_eq!(settings.selected, "gbp");
+    assert!(settings.overridden);
+    // A pick that's no longer offered is ignored
+    let settings = resolve_currency(Some("jpy"), Some("de-DE"), offered());
+    assert_eq!(settings.selected, "eur");
+    assert!(!settings.overridden);
+}
+
+#[test]
+fn test_unconfigured_regions_fall_back_to_the_default_currency() {
+    let settings = resolve_currency(None, Some("ja-JP, pl;q=0.5"), offered());
+    assert_eq!(settings.selected, "usd");
+    assert_eq!(settings.available, offered());
+    assert_eq!(resolve_currency(None, None, offered()).selected, "usd");
+}
+
+#[test]
+fn test_available_currencies_cover_every_product() {
+    let products = vec![
+        product("game", vec![price("usd", 1999), price("EUR", 1799)]),
+        product("game", vec![price("usd", 999), price("gbp", 899)]),
+    ];
+    assert_eq!(available_currencies(&products), offered());
+}
+
+#[test]
+fn test_offer_falls_back_to_the_default_currency() {
+    let product = product("game", vec![price("usd", 1999), price("eur", 1799)]);
+    let offer = product.offer_with_fallback("eur", 0).unwrap();
+    assert_eq!(offer.price.stripe_price_id, "price_eur");
+    let offer = product.offer_with_fallback("gbp", 0).unwrap();
+    assert_eq!(offer.price.stripe_price_id, "price_usd");
+    assert!(self::product("game", vec![price("eur", 1799)])
+        .offer_with_fallback("gbp", 0)
+        .is_none());
+}
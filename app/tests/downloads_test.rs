@@ -0,0 +1,189 @@
+use app::server::download::{
+    download_game_version::game_key,
+    download_utils::{
+        presigned_download, starts_download, ByteRange, Download, DownloadMode, GAME_BUCKET_NAME,
+        PRESIGNED_URL_SECONDS,
+    },
+    manifest::{
+        build_files_prefix, is_build_file_path, manifest_file, manifest_key, BuildManifest,
+    },
+};
+use aws_sdk_s3::config::{BehaviorVersion, Credentials, Region};
+use http::{header::RANGE, HeaderMap, HeaderValue};
+
+#[test]
+fn test_download_mode_parse() {
+    assert_eq!(
+        DownloadMode::parse("presigned"),
+        Some(DownloadMode::Presigned)
+    );
+    assert_eq!(DownloadMode::parse(" stream "), Some(DownloadMode::Stream));
+    assert_eq!(DownloadMode::parse("buffered"), None);
+}
+
+#[test]
+fn test_byte_range_parse() {
+    let cases = [
+        (
+            "bytes=0-499",
+            Some(ByteRange::From {
+                start: 0,
+                end: Some(499),
+            }),
+        ),
+        (
+            "bytes=500-",
+            Some(ByteRange::From {
+                start: 500,
+                end: None,
+            }),
+        ),
+        ("bytes=-500", Some(ByteRange::Last(500))),
+        // Backwards
+        ("bytes=500-499", None),
+        // S3 serves one range per request
+        ("bytes=0-1,4-5", None),
+        ("items=0-5", None),
+        ("bytes=-", None),
+    ];
+    for (header, expected) in cases {
+        assert_eq!(ByteRange::parse(header), expected, "{}", header);
+        if let Some(range) = expected {
+            assert_eq!(range.header(), header);
+        }
+    }
+}
+
+#[test]
+fn test_only_downloads_from_the_start_are_counted() {
+    let with_range = |range: &'static str| {
+        let mut headers = HeaderMap::new();
+        headers.insert(RANGE, HeaderValue::from_static(range));
+        headers
+    };
+    assert!(starts_download(&HeaderMap::new()));
+    assert!(starts_download(&with_range("bytes=0-")));
+    assert!(starts_download(&with_range("bytes=0-1048575")));
+    // Served whole
+    assert!(starts_download(&with_range("bytes=0-1,4-5")));
+    // Resumed or fetched in parts
+    assert!(!starts_download(&with_range("bytes=1048576-")));
+    assert!(!starts_download(&with_range("bytes=-500")));
+}
+
+#[tokio::test]
+async fn test_presigned_download() {
+    let s3_client = aws_sdk_s3::Client::from_conf(
+        aws_sdk_s3::Config::builder()
+            .behavior_version(BehaviorVersion::latest())
+            .region(Region::new("us-east-1"))
+            .credentials_provider(Credentials::new(
+                "AKIDEXAMPLE",
+                "secret",
+                None,
+                None,
+                "test",
+            ))
+            .build(),
+    );
+    let download = Download {
+        bucket: GAME_BUCKET_NAME,
+        key: game_key("game_1", "linux", "1.2.0"),
+        file_name: "game_1-linux-1.2.0.zip".to_string(),
+        content_type: "application/zip",
+    };
+    let presigned = presigned_download(&s3_client, &download, 1_700_000_000)
+        .await
+        .unwrap();
+    assert!(presigned
+        .url
+        .starts_with("https://games.s3.us-east-1.amazonaws.com/game_1/linux/1.2.0/game.zip?"));
+    assert!(presigned
+        .url
+        .contains(&format!("X-Amz-Expires={}", PRESIGNED_URL_SECONDS)));
+    assert!(presigned.url.contains("response-content-disposition="));
+    assert_eq!(
+        presigned.expires_at,
+        1_700_000_000 + PRESIGNED_URL_SECONDS as i64
+    );
+}
+
+fn build_manifest() -> BuildManifest {
+    BuildManifest {
+        game: "game_1".to_string(),
+        platform: "linux".to_string(),
+        version: "1.2.0".to_string(),
+        files: vec![
+            manifest_file("assets/level_1.pak", b"level one"),
+            manifest_file("game", b"abc"),
+        ],
+    }
+}
+
+#[test]
+fn test_manifest_file() {
+    let file = manifest_file("game", b"abc");
+    assert_eq!(file.size, 3);
+    assert_eq!(
+        file.sha256,
+        "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+    );
+    assert_eq!(
+        manifest_key("game_1", "linux", "1.2.0"),
+        "game_1/linux/1.2.0/manifest.json"
+    );
+    assert_eq!(
+        build_files_prefix("game_1", "linux", "1.2.0"),
+        "game_1/linux/1.2.0/files/"
+    );
+}
+
+#[test]
+fn test_manifest_round_trips_as_json() {
+    let manifest = build_manifest();
+    let json = serde_json::to_value(&manifest).unwrap();
+    assert_eq!(json["files"][1]["path"], "game");
+    assert_eq!(json["files"][1]["size"], 3);
+    assert_eq!(
+        serde_json::from_value::<BuildManifest>(json).unwrap(),
+        manifest
+    );
+    assert_eq!(manifest.total_size(), 12);
+}
+
+#[test]
+fn test_files_to_download() {
+    let manifest = build_manifest();
+    let paths = |installed| {
+        manifest
+            .files_to_download(installed)
+            .into_iter()
+            .map(|file| file.path.as_str())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(paths(&manifest.files), Vec::<&str>::new());
+    assert_eq!(paths(&[]), vec!["assets/level_1.pak", "game"]);
+    // Corrupted
+    assert_eq!(
+        paths(&[
+            manifest_file("assets/level_1.pak", b"level 0ne"),
+            manifest_file("game", b"abc"),
+        ]),
+        vec!["assets/level_1.pak"]
+    );
+}
+
+#[test]
+fn test_is_build_file_path() {
+    assert!(is_build_file_path("game"));
+    assert!(is_build_file_path("assets/level_1.pak"));
+    for path in [
+        "",
+        "/etc/passwd",
+        "../game_2/game",
+        "assets//level_1.pak",
+        "./game",
+    ] {
+        assert!(!is_build_file_path(path), "{}", path);
+    }
+}
@@ -0,0 +1,97 @@
+use app::{
+    errors::NexusError,
+    server::globals::dynamo_error::{
+        attempt_timeout, backoff_with_jitter, classify, error_metric, DynamoErrorKind,
+        DynamoOperation,
+    },
+};
+use aws_sdk_dynamodb::{
+    error::ErrorMetadata,
+    operation::query::QueryError,
+    types::error::{ConditionalCheckFailedException, ProvisionedThroughputExceededException},
+    Error,
+};
+use std::time::{Duration, Instant};
+
+#[test]
+fn test_classify_modelled_errors() {
+    let conditional =
+        Error::ConditionalCheckFailedException(ConditionalCheckFailedException::builder().build());
+    assert_eq!(
+        classify(&conditional),
+        DynamoErrorKind::ConditionalCheckFailed
+    );
+    let throttled = Error::ProvisionedThroughputExceededException(
+        ProvisionedThroughputExceededException::builder().build(),
+    );
+    assert_eq!(classify(&throttled), DynamoErrorKind::Throttled);
+}
+
+#[test]
+fn test_classify_unmodelled_errors_by_code() {
+    let unmodelled = |code: &str| {
+        Error::from(QueryError::generic(
+            ErrorMetadata::builder().code(code).build(),
+        ))
+    };
+    assert_eq!(
+        classify(&unmodelled("ValidationException")),
+        DynamoErrorKind::Validation
+    );
+    assert_eq!(
+        classify(&unmodelled("ThrottlingException")),
+        DynamoErrorKind::Throttled
+    );
+}
+
+#[test]
+fn test_only_transient_failures_are_retried() {
+    assert!(DynamoErrorKind::Throttled.is_retryable());
+    assert!(DynamoErrorKind::Timeout.is_retryable());
+    assert!(DynamoErrorKind::Transient.is_retryable());
+    assert!(!DynamoErrorKind::ConditionalCheckFailed.is_retryable());
+    assert!(!DynamoErrorKind::Validation.is_retryable());
+    assert!(matches!(
+        DynamoErrorKind::Throttled.nexus_error(),
+        NexusError::DynamoThrottled
+    ));
+}
+
+#[test]
+fn test_backoff_is_bounded() {
+    for attempt in 1..20 {
+        assert!(backoff_with_jitter(attempt) <= Duration::from_millis(400));
+    }
+}
+
+#[test]
+fn test_attempts_are_cut_short_by_the_deadline() {
+    let now = Instant::now();
+    let timeout = Duration::from_millis(800);
+    assert_eq!(
+        attempt_timeout(timeout, now + Duration::from_secs(2), now),
+        Some(timeout)
+    );
+    assert_eq!(
+        attempt_timeout(timeout, now + Duration::from_millis(300), now),
+        Some(Duration::from_millis(300))
+    );
+    assert_eq!(attempt_timeout(timeout, now, now), None);
+    assert_eq!(
+        attempt_timeout(timeout, now, now + Duration::from_millis(1)),
+        None
+    );
+}
+
+#[test]
+fn test_error_metric_is_dimensioned_by_operation_and_kind() {
+    let operation = DynamoOperation::write("update_user", "users");
+    let metric = error_metric(&operation, DynamoErrorKind::Throttled, 1_700_000_000_000);
+    assert_eq!(metric["Operation"], "update_user");
+    assert_eq!(metric["Kind"], "Throttled");
+    assert_eq!(metric["DynamoErrors"], 1);
+    let definition = &metric["_aws"]["CloudWatchMetrics"][0];
+    assert_eq!(definition["Dimensions"][0][0], "Operation");
+    assert_eq!(definition["Dimensions"][0][1], "Kind");
+    assert_eq!(definition["Metrics"][0]["Name"], "DynamoErrors");
+}
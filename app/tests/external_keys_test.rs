@@ -0,0 +1,95 @@
+use app::server::{
+    external_keys::{owned_external_keys, parse_key_csv, should_alert_low_stock},
+    globals::dynamo::constants::external_key_attributes::{AVAILABLE_POOL, PURCHASE_ID},
+    repository::external_keys::{
+        external_key_to_item, parse_external_key, pool_id, ExternalKey, KeyAssignment,
+    },
+};
+use aws_sdk_dynamodb::types::AttributeValue;
+
+fn key(key_value: &str, assignment: Option<KeyAssignment>) -> ExternalKey {
+    ExternalKey {
+        product_id: "game_1".to_string(),
+        platform: "steam".to_string(),
+        key_hash: format!("hash_{}", key_value),
+        key_value: key_value.to_string(),
+        imported_by: "admin@example.com".to_string(),
+        imported_at: 1_700_000_000,
+        assignment,
+    }
+}
+
+fn assignment(revoked_at: Option<i64>) -> KeyAssignment {
+    KeyAssignment {
+        purchase_id: "cs_test_1".to_string(),
+        user_uuid: "8d0c6f3e-3a4f-4d43-9b43-7d1b1b0c9a11".to_string(),
+        assigned_at: 1_700_000_100,
+        revoked_at,
+        revoked_reason: revoked_at.map(|_| "refunded".to_string()),
+    }
+}
+
+#[test]
+fn test_unassigned_keys_are_in_the_available_index() {
+    let available = key("AAAAA-BBBBB-CCCCC", None);
+    let item = external_key_to_item(&available);
+    assert_eq!(
+        item.get(AVAILABLE_POOL),
+        Some(&AttributeValue::S(pool_id("game_1", "steam")))
+    );
+    assert!(!item.contains_key(PURCHASE_ID));
+    assert_eq!(parse_external_key(&item).unwrap(), available);
+}
+
+#[test]
+fn test_assigned_keys_leave_the_available_index() {
+    for revoked_at in [None, Some(1_700_000_200)] {
+        let assigned = key("AAAAA-BBBBB-CCCCC", Some(assignment(revoked_at)));
+        let item = external_key_to_item(&assigned);
+        assert!(!item.contains_key(AVAILABLE_POOL));
+        assert_eq!(parse_external_key(&item).unwrap(), assigned);
+    }
+}
+
+#[test]
+fn test_parse_key_csv() {
+    let (keys, rejected_lines) = parse_key_csv(
+        "Key,Notes\n\"AAAAA-BBBBB-CCCCC\",first\n\nDDDDD-EEEEE-FFFFF\nnot a key!\r\nGGGGG\n",
+    );
+    assert_eq!(
+        keys,
+        vec![
+            "AAAAA-BBBBB-CCCCC".to_string(),
+            "DDDDD-EEEEE-FFFFF".to_string(),
+            "GGGGG".to_string()
+        ]
+    );
+    assert_eq!(rejected_lines, vec![5]);
+
+    let (keys, rejected_lines) = parse_key_csv("AAAAA-BBBBB-CCCCC");
+    assert_eq!(keys, vec!["AAAAA-BBBBB-CCCCC".to_string()]);
+    assert!(rejected_lines.is_empty());
+}
+
+#[test]
+fn test_low_stock_alerts() {
+    assert!(!should_alert_low_stock(100));
+    assert!(!should_alert_low_stock(21));
+    assert!(should_alert_low_stock(20));
+    assert!(!should_alert_low_stock(19));
+    assert!(should_alert_low_stock(5));
+    assert!(should_alert_low_stock(0));
+}
+
+#[test]
+fn test_library_only_shows_keys_that_are_still_owned() {
+    let owned = owned_external_keys(vec![
+        key("OWNED", Some(assignment(None))),
+        key("REVOKED", Some(assignment(Some(1_700_000_200)))),
+        key("AVAILABLE", None),
+    ]);
+    assert_eq!(owned.len(), 1);
+    assert_eq!(owned[0].key, "OWNED");
+    assert_eq!(owned[0].platform, "steam");
+    assert_eq!(owned[0].product_title, "Untitled Game");
+}
@@ -0,0 +1,148 @@
+mod common;
+
+use app::{
+    errors::NexusError,
+    orders::{GiftStatus, GIFT_MESSAGE_MAX_LENGTH},
+    server::{
+        email::escape_html,
+        gifts::{gift_status, GiftRequest},
+        globals::dynamo::constants::gift_attributes::{CLAIMED_AT, CLAIMED_BY_USER_UUID},
+        repository::{
+            gifts::{gift_to_item, parse_gift, Gift},
+            purchases::{
+                owned_through_other_purchase, parse_purchase, purchase_to_item, Purchase,
+                PurchaseStatus,
+            },
+        },
+    },
+};
+use leptos::ServerFnError;
+use std::collections::HashMap;
+
+fn gift() -> Gift {
+    Gift {
+        gift_id: "cs_test_gift".to_string(),
+        claim_token: "5b0e5d8e-5c43-4d8c-9a4e-8f0f6d1c2b3a".to_string(),
+        product_id: "game_1".to_string(),
+        buyer_user_uuid: "8d0c6f3e-3a4f-4d43-9b43-7d1b1b0c9a11".to_string(),
+        sender_name: "Buyer".to_string(),
+        recipient_email: "friend@example.com".to_string(),
+        message: "Happy birthday!".to_string(),
+        created_at: 1_700_000_000,
+        notified_at: Some(1_700_000_001),
+        claimed_by_user_uuid: None,
+        claimed_at: None,
+    }
+}
+
+fn purchase(checkout_session_id: &str, gift: bool) -> Purchase {
+    Purchase {
+        checkout_session_id: checkout_session_id.to_string(),
+        payment_intent_id: None,
+        gift,
+        ..common::purchase()
+    }
+}
+
+#[test]
+fn test_gift_round_trips() {
+    let unclaimed = gift();
+    let item = gift_to_item(&unclaimed);
+    assert!(!item.contains_key(CLAIMED_BY_USER_UUID));
+    assert!(!item.contains_key(CLAIMED_AT));
+    assert_eq!(parse_gift(&item).unwrap(), unclaimed);
+
+    let claimed = Gift {
+        claimed_by_user_uuid: Some("0f1e2d3c-4b5a-6978-8796-a5b4c3d2e1f0".to_string()),
+        claimed_at: Some(1_700_000_100),
+        ..gift()
+    };
+    assert_eq!(parse_gift(&gift_to_item(&claimed)).unwrap(), claimed);
+}
+
+#[test]
+fn test_gift_purchases_round_trip() {
+    let gifted = purchase("cs_test_gift", true);
+    assert_eq!(parse_purchase(&purchase_to_item(&gifted)).unwrap(), gifted);
+    let bought = purchase("cs_test_1", false);
+    assert_eq!(parse_purchase(&purchase_to_item(&bought)).unwrap(), bought);
+}
+
+#[test]
+fn test_gift_request_validation() {
+    assert_eq!(
+        GiftRequest::from_checkout(String::new(), "ignored".to_string()).unwrap(),
+        None
+    );
+    let request =
+        GiftRequest::from_checkout(" friend@example.com ".to_string(), "Enjoy!\n".to_string())
+            .unwrap()
+            .unwrap();
+    assert_eq!(request.recipient_email, "friend@example.com");
+    assert_eq!(request.message, "Enjoy!");
+    assert!(matches!(
+        GiftRequest::from_checkout("not an email".to_string(), String::new()),
+        Err(ServerFnError::WrappedServerError(
+            NexusError::BadEmailAddress
+        ))
+    ));
+    assert!(matches!(
+        GiftRequest::from_checkout(
+            "friend@example.com".to_string(),
+            "a".repeat(GIFT_MESSAGE_MAX_LENGTH + 1)
+        ),
+        Err(ServerFnError::WrappedServerError(
+            NexusError::GiftMessageTooLong
+        ))
+    ));
+
+    let metadata: HashMap<String, String> = request
+        .to_metadata()
+        .into_iter()
+        .chain([("product_id".to_string(), "game_1".to_string())])
+        .collect();
+    assert_eq!(GiftRequest::from_metadata(Some(&metadata)), Some(request));
+    let not_a_gift = HashMap::from([("product_id".to_string(), "game_1".to_string())]);
+    assert_eq!(GiftRequest::from_metadata(Some(&not_a_gift)), None);
+    assert_eq!(GiftRequest::from_metadata(None), None);
+}
+
+#[test]
+fn test_gift_status_follows_its_purchase() {
+    let unclaimed = gift();
+    assert_eq!(
+        gift_status(&unclaimed, PurchaseStatus::Paid),
+        GiftStatus::Unclaimed
+    );
+    let claimed = Gift {
+        claimed_by_user_uuid: Some("0f1e2d3c-4b5a-6978-8796-a5b4c3d2e1f0".to_string()),
+        ..gift()
+    };
+    assert_eq!(
+        gift_status(&claimed, PurchaseStatus::Paid),
+        GiftStatus::Claimed
+    );
+    assert_eq!(
+        gift_status(&claimed, PurchaseStatus::Refunded),
+        GiftStatus::Revoked
+    );
+    assert_eq!(
+        gift_status(&unclaimed, PurchaseStatus::Disputed),
+        GiftStatus::Revoked
+    );
+}
+
+#[test]
+fn test_gifts_dont_keep_the_buyers_own_copy() {
+    let own = purchase("cs_test_1", false);
+    let gifted = purchase("cs_test_gift", true);
+    assert!(!owned_through_other_purchase(&[own.clone(), gifted], &own));
+}
+
+#[test]
+fn test_messages_are_escaped_for_email() {
+    assert_eq!(
+        escape_html("<b>hi</b> & \"bye\"\nsee you"),
+        "&lt;b&gt;hi&lt;/b&gt; &amp; &quot;bye&quot;<br>see you"
+    );
+}
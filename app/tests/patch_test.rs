@@ -0,0 +1,184 @@
+use app::server::download::{
+    manifest::{manifest_file, BuildManifest},
+    patch::{
+        delta_key, patch_plan_key, plan_patch, BlockSignatures, Delta, DeltaBuilder, DeltaOp,
+        PatchAction, PatchPlan,
+    },
+};
+use std::num::NonZeroUsize;
+
+const BLOCK_SIZE: NonZeroUsize = NonZeroUsize::new(64).unwrap();
+
+/// Bytes that don't repeat, like a compressed asset
+fn noise(length: usize, seed: u32) -> Vec<u8> {
+    let mut state = seed;
+    (0..length)
+        .map(|_| {
+            state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            (state >> 24) as u8
+        })
+        .collect()
+}
+
+#[test]
+fn test_delta_rebuilds_the_new_file() {
+    let old = noise(10_000, 1);
+    let mut new = old.clone();
+    // Inserted, so everything after it moves
+    new.splice(1_000..1_000, noise(300, 2));
+    new[8_000] ^= 0xff;
+    new.truncate(9_990);
+
+    let delta = Delta::between(&old, &new, BLOCK_SIZE);
+    assert_eq!(delta.apply(&old).unwrap(), new);
+    let data: usize = delta
+        .ops
+        .iter()
+        .map(|op| match op {
+            DeltaOp::Data(data) => data.len(),
+            DeltaOp::Copy { .. } => 0,
+        })
+        .sum();
+    assert!(
+        data < 300 + 3 * BLOCK_SIZE.get(),
+        "{} bytes weren't matched",
+        data
+    );
+    assert!(delta.encode().len() < new.len() / 10);
+}
+
+#[test]
+fn test_delta_of_the_same_file() {
+    let old = noise(1_000, 3);
+    let delta = Delta::between(&old, &old, BLOCK_SIZE);
+    // Including the shorter last block
+    assert_eq!(
+        delta.ops,
+        vec![DeltaOp::Copy {
+            block: 0,
+            count: 16
+        }]
+    );
+    assert_eq!(delta.apply(&old).unwrap(), old);
+
+    let new = noise(100, 4);
+    for (old, new) in [
+        (&[][..], &new[..]),
+        (&old[..], &[][..]),
+        (&new[..8], &new[..8]),
+    ] {
+        assert_eq!(
+            Delta::between(old, new, BLOCK_SIZE).apply(old).unwrap(),
+            new
+        );
+    }
+}
+
+#[test]
+fn test_delta_read_a_chunk_at_a_time() {
+    let old = noise(10_000, 7);
+    let mut new = noise(500, 8);
+    new.extend_from_slice(&old[2_000..9_000]);
+    new[3_000] ^= 0xff;
+    new.extend_from_slice(&old[9_960..]);
+
+    // Chunks that don't line up with the blocks
+    let mut signatures = BlockSignatures::new(BLOCK_SIZE);
+    for chunk in old.chunks(100) {
+        signatures.update(chunk);
+    }
+    let mut builder = DeltaBuilder::new(&signatures);
+    for chunk in new.chunks(37) {
+        builder.update(chunk);
+    }
+    assert!(builder.data_len() < 500 + 3 * BLOCK_SIZE.get() as u64);
+    let delta = builder.finish();
+    assert_eq!(delta, Delta::between(&old, &new, BLOCK_SIZE));
+    assert_eq!(delta.apply(&old).unwrap(), new);
+}
+
+#[test]
+fn test_delta_encoding() {
+    let old = noise(1_000, 5);
+    let mut new = noise(100, 6);
+    new.extend_from_slice(&old[..500]);
+    let delta = Delta::between(&old, &new, BLOCK_SIZE);
+    let encoded = delta.encode();
+    assert_eq!(Delta::decode(&encoded), Some(delta.clone()));
+
+    assert_eq!(Delta::decode(b"NXD2"), None);
+    // Without a block size
+    assert_eq!(Delta::decode(b"NXD1\0\0\0\0\0\0\0\0"), None);
+    assert_eq!(Delta::decode(&encoded[..encoded.len() - 1]), None);
+    // Blocks the installed file doesn't have
+    assert_eq!(delta.apply(&old[..100]), None);
+}
+
+fn manifest(version: &str, files: &[(&str, &[u8])]) -> BuildManifest {
+    BuildManifest {
+        game: "game_1".to_string(),
+        platform: "linux".to_string(),
+        version: version.to_string(),
+        files: files
+            .iter()
+            .map(|(path, contents)| manifest_file(path, contents))
+            .collect(),
+    }
+}
+
+#[test]
+fn test_plan_patch() {
+    let from = manifest(
+        "1.2.0",
+        &[
+            ("game", b"binary"),
+            ("level_1.pak", b"level"),
+            ("old.pak", b"old"),
+        ],
+    );
+    let to = manifest(
+        "1.2.1",
+        &[
+            ("game", b"binary 2"),
+            ("level_1.pak", b"level"),
+            ("new.pak", b"new"),
+        ],
+    );
+    let mut plan = plan_patch(&from, &to, BLOCK_SIZE);
+    assert_eq!((plan.from.as_str(), plan.to.as_str()), ("1.2.0", "1.2.1"));
+    let actions: Vec<(&str, &PatchAction)> = plan
+        .files
+        .iter()
+        .map(|file| (file.path.as_str(), &file.action))
+        .collect();
+    assert_eq!(
+        actions,
+        vec![
+            ("game", &PatchAction::Download),
+            ("level_1.pak", &PatchAction::Keep),
+            ("new.pak", &PatchAction::Download),
+        ]
+    );
+    assert_eq!(plan.removed, vec!["old.pak"]);
+    assert_eq!(plan.download_size(), 8 + 3);
+
+    plan.files[0].action = PatchAction::Patch {
+        delta_size: 2,
+        delta_sha256: "ab".to_string(),
+    };
+    assert_eq!(plan.download_size(), 2 + 3);
+    let json = serde_json::to_value(&plan).unwrap();
+    assert_eq!(json["files"][0]["action"], "patch");
+    assert_eq!(json["files"][0]["delta_size"], 2);
+    assert_eq!(json["files"][1]["action"], "keep");
+    assert_eq!(serde_json::from_value::<PatchPlan>(json).unwrap(), plan);
+
+    assert_eq!(
+        patch_plan_key("game_1", "linux", "1.2.0", "1.2.1"),
+        "game_1/linux/1.2.1/patches/1.2.0/plan.json"
+    );
+    assert_eq!(
+        delta_key("game_1", "linux", "1.2.0", "1.2.1", "game"),
+        "game_1/linux/1.2.1/patches/1.2.0/files/game"
+    );
+}
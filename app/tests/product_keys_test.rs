@@ -0,0 +1,191 @@
+use app::{
+    orders::KeyBatchSummary,
+    server::{
+        product_keys::{
+            batch_report, generate_key, hash_key, key_hint, key_purchase, normalize_key,
+            KEY_LENGTH, KEY_PURCHASE_PREFIX, REDEEM_KEY_RATE_LIMIT,
+        },
+        repository::{
+            product_keys::{
+                key_batch_to_item, parse_key_batch, parse_product_key, product_key_to_item,
+                ProductKey,
+            },
+            purchases::PurchaseStatus,
+        },
+        utilities::LoggedInUser,
+    },
+};
+use rand::{rngs::StdRng, SeedableRng};
+
+fn batch() -> KeyBatchSummary {
+    KeyBatchSummary {
+        batch_id: "0f8e2a5c-6a1d-4c0b-8f53-2f1f0d7b9c11".to_string(),
+        product_id: "game_1".to_string(),
+        label: "Convention".to_string(),
+        created_by: "admin@example.com".to_string(),
+        created_at: 1_700_000_000,
+        key_count: 3,
+    }
+}
+
+fn key(hint: &str, redeemed_at: Option<i64>) -> ProductKey {
+    ProductKey {
+        key_hash: format!("hash_{}", hint),
+        batch_id: batch().batch_id,
+        product_id: "game_1".to_string(),
+        key_hint: hint.to_string(),
+        created_at: 1_700_000_000,
+        redeemed_by_user_uuid: redeemed_at.map(|_| format!("user_{}", hint)),
+        redeemed_at,
+    }
+}
+
+#[test]
+fn test_generated_keys_are_valid_and_normalize_to_themselves() {
+    let mut rng = StdRng::seed_from_u64(7);
+    for _ in 0..100 {
+        let formatted = generate_key(&mut rng);
+        assert_eq!(formatted.len(), KEY_LENGTH + 4);
+        assert_eq!(formatted.split('-').count(), 5);
+        let normalized = normalize_key(&formatted).unwrap();
+        assert_eq!(normalized, formatted.replace('-', ""));
+        assert_eq!(
+            normalize_key(&format!(" {} ", formatted.to_lowercase())),
+            Some(normalized)
+        );
+    }
+}
+
+#[test]
+fn test_confusable_letters_are_read_as_digits() {
+    let mut rng = StdRng::seed_from_u64(11);
+    let formatted = (0..)
+        .map(|_| generate_key(&mut rng))
+        .find(|key| key.contains('0') && key.contains('1'))
+        .unwrap();
+    let typed = formatted.replace('0', "o").replace('1', "I");
+    assert_eq!(normalize_key(&typed), normalize_key(&formatted));
+}
+
+#[test]
+fn test_typos_fail_the_checksum() {
+    let mut rng = StdRng::seed_from_u64(3);
+    let mut caught = 0;
+    let total = 200;
+    for _ in 0..total {
+        let formatted = generate_key(&mut rng);
+        let mut chars: Vec<char> = formatted.chars().collect();
+        chars[0] = if chars[0] == 'A' { 'B' } else { 'A' };
+        let typo: String = chars.into_iter().collect();
+        if normalize_key(&typo).is_none() {
+            caught += 1;
+        }
+    }
+    // Ten bits of checksum lets about one typo in a thousand through
+    assert!(
+        caught >= total - 5,
+        "only {} of {} typos caught",
+        caught,
+        total
+    );
+    assert_eq!(normalize_key("ABCDE-FGHJK"), None);
+    assert_eq!(normalize_key("UUUUU-UUUUU-UUUUU-UUUUU-UUUUU"), None);
+}
+
+#[test]
+fn test_key_hash_and_hint() {
+    let normalized = normalize_key(&generate_key(&mut StdRng::seed_from_u64(1))).unwrap();
+    let hash = hash_key(&normalized);
+    assert_eq!(hash.len(), 64);
+    assert!(hash.chars().all(|c| c.is_ascii_hexdigit()));
+    assert_eq!(key_hint(&normalized), normalized[20..]);
+}
+
+#[test]
+fn test_product_key_and_batch_round_trip() {
+    let unredeemed = key("AAAAA", None);
+    assert_eq!(
+        parse_product_key(&product_key_to_item(&unredeemed)).unwrap(),
+        unredeemed
+    );
+    let redeemed = key("BBBBB", Some(1_700_000_100));
+    assert_eq!(
+        parse_product_key(&product_key_to_item(&redeemed)).unwrap(),
+        redeemed
+    );
+    assert_eq!(
+        parse_key_batch(&key_batch_to_item(&batch())).unwrap(),
+        batch()
+    );
+}
+
+#[test]
+fn test_batch_report_splits_redeemed_keys() {
+    let report = batch_report(
+        batch(),
+        vec![
+            key("CCCCC", Some(1_700_000_300)),
+            key("BBBBB", None),
+            key("AAAAA", Some(1_700_000_200)),
+        ],
+    );
+    assert_eq!(report.batch, batch());
+    assert_eq!(
+        report
+            .redeemed
+            .iter()
+            .map(|key| key.key_hint.as_str())
+            .collect::<Vec<_>>(),
+        vec!["AAAAA", "CCCCC"]
+    );
+    assert_eq!(report.redeemed[0].redeemed_by_user_uuid, "user_AAAAA");
+    assert_eq!(report.unredeemed, vec!["BBBBB".to_string()]);
+}
+
+#[test]
+fn test_key_purchase_is_free_and_keyed_by_the_key() {
+    let user = LoggedInUser {
+        email: "player@example.com".to_string(),
+        user_uuid: "user_AAAAA".to_string(),
+    };
+    let purchase = key_purchase(&key("AAAAA", Some(1_700_000_200)), &user, 1_700_000_500);
+    assert_eq!(
+        purchase.checkout_session_id,
+        format!("{}hash_AAAAA", KEY_PURCHASE_PREFIX)
+    );
+    assert_eq!(purchase.amount_total, 0);
+    assert_eq!(purchase.status, PurchaseStatus::Paid);
+    assert_eq!(purchase.created_at, 1_700_000_200);
+    assert_eq!(purchase.email, "player@example.com");
+    assert!(!purchase.gift);
+}
+
+#[test]
+fn test_redeeming_a_key_again_records_the_same_purchase() {
+    let user = LoggedInUser {
+        email: "player@example.com".to_string(),
+        user_uuid: "user_AAAAA".to_string(),
+    };
+    // The second redemption keeps the first one's time
+    let redeemed = key("AAAAA", Some(1_700_000_200));
+    let first = key_purchase(&redeemed, &user, 1_700_000_200);
+    let again = key_purchase(&redeemed, &user, 1_700_000_900);
+    assert_eq!(first.checkout_session_id, again.checkout_session_id);
+    assert_eq!(first.created_at, again.created_at);
+    assert_eq!(first.product_id, again.product_id);
+    assert_eq!(again.status, PurchaseStatus::Paid);
+}
+
+#[test]
+fn test_rate_limit_buckets_are_per_window() {
+    let window = REDEEM_KEY_RATE_LIMIT.window_seconds;
+    let (bucket, expires_at) = REDEEM_KEY_RATE_LIMIT.bucket("user", 10 * window + 5);
+    assert_eq!(bucket, "redeem_key#user#10");
+    assert_eq!(expires_at, 11 * window);
+    assert_eq!(
+        REDEEM_KEY_RATE_LIMIT.bucket("user", 11 * window - 1).0,
+        bucket
+    );
+    assert_ne!(REDEEM_KEY_RATE_LIMIT.bucket("user", 11 * window).0, bucket);
+    assert_ne!(REDEEM_KEY_RATE_LIMIT.bucket("other", 10 * window).0, bucket);
+}
@@ -0,0 +1,232 @@
+mod common;
+
+use app::{
+    orders::CheckoutStatus,
+    server::{
+        checkout_status::checkout_status_for,
+        globals::dynamo::constants::purchase_attributes::PAYMENT_INTENT_ID,
+        payment_changes::partially_refunded_purchases,
+        repository::purchases::{
+            owned_through_other_purchase, parse_purchase, purchase_to_item, PaymentChange,
+            Purchase, PurchaseStatus,
+        },
+    },
+};
+use std::collections::HashMap;
+use stripe::Refund;
+
+fn purchase(payment_intent_id: Option<&str>) -> Purchase {
+    Purchase {
+        payment_intent_id: payment_intent_id.map(str::to_string),
+        ..common::purchase()
+    }
+}
+
+#[test]
+fn test_purchase_round_trips() {
+    let purchase = purchase(Some("pi_test_1"));
+    assert_eq!(
+        parse_purchase(&purchase_to_item(&purchase)).unwrap(),
+        purchase
+    );
+}
+
+#[test]
+fn test_discounted_purchase_round_trips() {
+    let purchase = Purchase {
+        amount_total: 1499,
+        amount_discount: 500,
+        sale: Some("Launch discount".to_string()),
+        discount_code: Some("promo_1".to_string()),
+        ..purchase(Some("pi_test_1"))
+    };
+    assert_eq!(
+        parse_purchase(&purchase_to_item(&purchase)).unwrap(),
+        purchase
+    );
+}
+
+#[test]
+fn test_taxed_purchase_with_receipt_and_country_round_trips() {
+    let purchase = Purchase {
+        amount_total: 2399,
+        amount_tax: 400,
+        receipt_sent_at: Some(1_700_000_010),
+        country: Some("DE".to_string()),
+        ..purchase(Some("pi_test_1"))
+    };
+    assert_eq!(
+        parse_purchase(&purchase_to_item(&purchase)).unwrap(),
+        purchase
+    );
+}
+
+#[test]
+fn test_purchase_without_payment_intent_leaves_index_key_out() {
+    let purchase = purchase(None);
+    let item = purchase_to_item(&purchase);
+    assert!(!item.contains_key(PAYMENT_INTENT_ID));
+    assert_eq!(parse_purchase(&item).unwrap(), purchase);
+}
+
+#[test]
+fn test_status_round_trips() {
+    for status in [
+        PurchaseStatus::Pending,
+        PurchaseStatus::Failed,
+        PurchaseStatus::Paid,
+        PurchaseStatus::Refunded,
+        PurchaseStatus::Disputed,
+        PurchaseStatus::DisputeLost,
+        PurchaseStatus::FraudWarning,
+    ] {
+        assert_eq!(PurchaseStatus::parse(status.as_str()), Some(status));
+    }
+}
+
+#[test]
+fn test_disputes_suspend_and_a_won_dispute_restores_access() {
+    let disputed = PurchaseStatus::Paid
+        .after(PaymentChange::DisputeOpened)
+        .unwrap();
+    assert!(!disputed.grants_access());
+    assert_eq!(
+        disputed.after(PaymentChange::DisputeWon),
+        Some(PurchaseStatus::Paid)
+    );
+    assert_eq!(
+        disputed.after(PaymentChange::DisputeLost),
+        Some(PurchaseStatus::DisputeLost)
+    );
+    assert_eq!(
+        PurchaseStatus::FraudWarning.after(PaymentChange::DisputeOpened),
+        Some(PurchaseStatus::Disputed)
+    );
+}
+
+#[test]
+fn test_refunds_are_final() {
+    for status in [
+        PurchaseStatus::Paid,
+        PurchaseStatus::Disputed,
+        PurchaseStatus::FraudWarning,
+    ] {
+        assert_eq!(
+            status.after(PaymentChange::Refunded),
+            Some(PurchaseStatus::Refunded)
+        );
+    }
+    assert_eq!(
+        PurchaseStatus::Refunded.after(PaymentChange::Refunded),
+        None
+    );
+    assert_eq!(
+        PurchaseStatus::Refunded.after(PaymentChange::DisputeOpened),
+        None
+    );
+    assert_eq!(PurchaseStatus::Paid.after(PaymentChange::DisputeWon), None);
+}
+
+#[test]
+fn test_revoking_one_purchase_keeps_a_product_bought_again() {
+    let first = purchase(Some("pi_test_1"));
+    let mut second = purchase(Some("pi_test_2"));
+    second.checkout_session_id = "cs_test_2".to_string();
+    assert!(!owned_through_other_purchase(
+        std::slice::from_ref(&first),
+        &first
+    ));
+    assert!(owned_through_other_purchase(
+        &[first.clone(), second.clone()],
+        &first
+    ));
+    second.status = PurchaseStatus::Refunded;
+    assert!(!owned_through_other_purchase(
+        &[first.clone(), second],
+        &first
+    ));
+}
+
+#[test]
+fn test_pending_purchases_wait_for_their_payment() {
+    assert!(!PurchaseStatus::Pending.grants_access());
+    assert_eq!(
+        PurchaseStatus::Pending.after(PaymentChange::DisputeOpened),
+        None
+    );
+    assert_eq!(PurchaseStatus::Failed.after(PaymentChange::Refunded), None);
+    assert_eq!(
+        checkout_status_for(PurchaseStatus::Pending),
+        CheckoutStatus::PaymentPending
+    );
+    assert_eq!(
+        checkout_status_for(PurchaseStatus::Disputed),
+        CheckoutStatus::Revoked
+    );
+}
+
+fn cart_line(id: &str, amount_total: i64, status: PurchaseStatus) -> Purchase {
+    Purchase {
+        checkout_session_id: id.to_string(),
+        amount_total,
+        status,
+        ..common::purchase()
+    }
+}
+
+fn refund_for(purchase_id: Option<&str>) -> Refund {
+    Refund {
+        metadata: purchase_id
+            .map(|id| HashMap::from([("purchase_id".to_string(), id.to_string())])),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_partial_refunds_are_matched_to_their_purchase() {
+    let cart = vec![
+        cart_line("cs_test_1#0", 1999, PurchaseStatus::Paid),
+        cart_line("cs_test_1#1", 999, PurchaseStatus::Paid),
+    ];
+    let ids = |purchases: Vec<&Purchase>| {
+        purchases
+            .iter()
+            .map(|purchase| purchase.checkout_session_id.clone())
+            .collect::<Vec<String>>()
+    };
+    // Refunded by the buyer's request, which names the purchase
+    assert_eq!(
+        ids(partially_refunded_purchases(
+            &cart,
+            &[refund_for(Some("cs_test_1#0"))],
+            1999
+        )),
+        vec!["cs_test_1#0"]
+    );
+    // Refunded from the dashboard, so only the amount says which line it was
+    assert_eq!(
+        ids(partially_refunded_purchases(
+            &cart,
+            &[refund_for(None)],
+            999
+        )),
+        vec!["cs_test_1#1"]
+    );
+    // A goodwill refund of part of a line takes nothing away
+    assert!(partially_refunded_purchases(&cart, &[refund_for(None)], 500).is_empty());
+}
+
+#[test]
+fn test_partial_refunds_only_count_what_wasnt_refunded_before() {
+    let cart = vec![
+        cart_line("cs_test_1#0", 1999, PurchaseStatus::Refunded),
+        cart_line("cs_test_1#1", 999, PurchaseStatus::Paid),
+        cart_line("cs_test_1#2", 999, PurchaseStatus::Paid),
+    ];
+    // Either of the two equally priced lines could have been refunded
+    assert!(partially_refunded_purchases(&cart, &[], 1999 + 999).is_empty());
+    let cart = &cart[..2];
+    let refunded = partially_refunded_purchases(cart, &[], 1999 + 999);
+    assert_eq!(refunded.len(), 1);
+    assert_eq!(refunded[0].checkout_session_id, "cs_test_1#1");
+}
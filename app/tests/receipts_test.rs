@@ -0,0 +1,118 @@
+mod common;
+
+use app::{
+    orders::OrderStatus,
+    server::{
+        order_history::{order_history, order_status_for},
+        receipts::receipt_email,
+        repository::purchases::{Purchase, PurchaseStatus},
+    },
+};
+
+fn purchase(checkout_session_id: &str, created_at: i64) -> Purchase {
+    Purchase {
+        checkout_session_id: checkout_session_id.to_string(),
+        amount_total: 2399,
+        currency: "eur".to_string(),
+        created_at,
+        updated_at: created_at,
+        amount_tax: 400,
+        ..common::purchase()
+    }
+}
+
+#[test]
+fn test_receipt_lists_the_order() {
+    let (subject, body) = receipt_email(&[purchase("cs_test_1", 1_700_000_000)], |_| {
+        "Untitled Game".to_string()
+    });
+    assert!(subject.contains("Untitled Game"));
+    assert!(body.contains("cs_test_1"));
+    assert!(body.contains("November 14, 2023"));
+    assert!(body.contains("<td>Tax</td><td>4.00 EUR</td>"));
+    assert!(body.contains("<td>Total</td><td>23.99 EUR</td>"));
+    assert!(body.contains("/download"));
+    assert!(!body.contains("Discount"));
+}
+
+#[test]
+fn test_gift_receipts_link_to_the_gifts_page() {
+    let purchase = Purchase {
+        gift: true,
+        amount_discount: 500,
+        ..purchase("cs_test_1", 1_700_000_000)
+    };
+    let (_, body) = receipt_email(&[purchase], |_| "<b>Game</b>".to_string());
+    assert!(body.contains("/gifts"));
+    assert!(!body.contains("/download"));
+    assert!(body.contains("<td>Discount</td><td>-5.00 EUR</td>"));
+    assert!(body.contains("&lt;b&gt;Game&lt;/b&gt;"));
+}
+
+#[test]
+fn test_cart_receipts_list_every_product() {
+    let purchases = [
+        Purchase {
+            product_id: "game_1".to_string(),
+            amount_total: 1999,
+            amount_tax: 300,
+            ..purchase("cs_test_1#game_1", 1_700_000_000)
+        },
+        Purchase {
+            product_id: "game_2".to_string(),
+            amount_total: 999,
+            amount_tax: 150,
+            amount_discount: 250,
+            ..purchase("cs_test_1#game_2", 1_700_000_000)
+        },
+    ];
+    let (subject, body) = receipt_email(&purchases, |product_id| product_id.to_uppercase());
+    assert!(subject.contains("GAME_1 and 1 more"));
+    assert!(body.contains("<td>Order</td><td>cs_test_1</td>"));
+    assert!(body.contains("<td>GAME_1</td><td>19.99 EUR</td>"));
+    assert!(body.contains("<td>GAME_2</td><td>9.99 EUR</td>"));
+    assert!(body.contains("<td>Discount</td><td>-2.50 EUR</td>"));
+    assert!(body.contains("<td>Tax</td><td>4.50 EUR</td>"));
+    assert!(body.contains("<td>Total</td><td>29.98 EUR</td>"));
+}
+
+#[test]
+fn test_order_history_is_newest_first() {
+    let orders = order_history(vec![
+        purchase("cs_old", 1_700_000_000),
+        Purchase {
+            status: PurchaseStatus::DisputeLost,
+            ..purchase("cs_new", 1_700_000_100)
+        },
+    ]);
+    assert_eq!(orders[0].order_id, "cs_new");
+    assert_eq!(orders[0].status, OrderStatus::Disputed);
+    assert_eq!(orders[1].order_id, "cs_old");
+    assert_eq!(orders[1].amount_tax, 400);
+    assert_eq!(orders[1].product_title, "Untitled Game");
+}
+
+#[test]
+fn test_order_statuses() {
+    assert_eq!(
+        order_status_for(PurchaseStatus::Pending),
+        OrderStatus::PaymentPending
+    );
+    assert_eq!(
+        order_status_for(PurchaseStatus::Failed),
+        OrderStatus::PaymentFailed
+    );
+    assert_eq!(order_status_for(PurchaseStatus::Paid), OrderStatus::Paid);
+    assert_eq!(
+        order_status_for(PurchaseStatus::Refunded),
+        OrderStatus::Refunded
+    );
+    assert_eq!(
+        order_status_for(PurchaseStatus::Disputed),
+        OrderStatus::Disputed
+    );
+    assert_eq!(
+        order_status_for(PurchaseStatus::FraudWarning),
+        OrderStatus::UnderReview
+    );
+}
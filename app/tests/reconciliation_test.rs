@@ -0,0 +1,242 @@
+mod common;
+
+use app::{
+    errors::NexusError,
+    orders::AnomalyKind,
+    server::{
+        reconciliation::{
+            list_completed_checkouts, purchase_anomaly, reconciliation_days, unmatched_purchases,
+            DEFAULT_RECONCILIATION_DAYS, MAX_RECONCILIATION_DAYS,
+        },
+        repository::purchases::{Purchase, PurchaseStatus},
+        stripe_webhook::handlers::purchases_to_grant,
+    },
+};
+use std::collections::HashSet;
+use stripe::Client as StripeClient;
+
+const BOUGHT_AT: i64 = 1_700_000_000;
+
+fn purchase(checkout_session_id: &str, product_id: &str, status: PurchaseStatus) -> Purchase {
+    Purchase {
+        checkout_session_id: checkout_session_id.to_string(),
+        product_id: product_id.to_string(),
+        status,
+        created_at: BOUGHT_AT,
+        updated_at: BOUGHT_AT,
+        ..common::purchase()
+    }
+}
+
+/// The bundle grants both games
+fn entitlements_of(product_id: &str) -> Vec<String> {
+    match product_id {
+        "bundle" => vec!["game_1".to_string(), "game_2".to_string()],
+        product_id => vec![product_id.to_string()],
+    }
+}
+
+fn library(entitlements: &[&str]) -> Vec<String> {
+    entitlements.iter().map(|e| e.to_string()).collect()
+}
+
+#[test]
+fn test_paid_checkouts() {
+    let paid = purchase("cs_test_1", "bundle", PurchaseStatus::Paid);
+    let check = |recorded: Option<&Purchase>, owned: &[&str]| {
+        purchase_anomaly(
+            PurchaseStatus::Paid,
+            recorded,
+            &library(owned),
+            std::slice::from_ref(&paid),
+            &[],
+            entitlements_of,
+        )
+    };
+    assert_eq!(check(Some(&paid), &["game_1", "game_2"]), None);
+    assert_eq!(check(None, &[]), Some(AnomalyKind::MissingPurchase));
+    assert_eq!(
+        check(Some(&paid), &["game_1"]),
+        Some(AnomalyKind::MissingEntitlement)
+    );
+    assert_eq!(
+        check(
+            Some(&purchase("cs_test_1", "bundle", PurchaseStatus::Pending)),
+            &[]
+        ),
+        Some(AnomalyKind::UnsettledPayment)
+    );
+    // A gift is in the library of whoever claimed it
+    let gift = Purchase {
+        gift: true,
+        ..paid.clone()
+    };
+    assert_eq!(check(Some(&gift), &[]), None);
+}
+
+#[test]
+fn test_unpaid_checkouts() {
+    let check = |status: PurchaseStatus| {
+        purchase_anomaly(
+            PurchaseStatus::Pending,
+            Some(&purchase("cs_test_1", "game_1", status)),
+            &[],
+            &[],
+            &[],
+            entitlements_of,
+        )
+    };
+    assert_eq!(check(PurchaseStatus::Pending), None);
+    assert_eq!(check(PurchaseStatus::Failed), None);
+    assert_eq!(
+        check(PurchaseStatus::Paid),
+        Some(AnomalyKind::EntitlementWithoutPayment)
+    );
+}
+
+#[test]
+fn test_revoked_purchases_must_not_leave_entitlements() {
+    let refunded = purchase("cs_test_1", "game_1", PurchaseStatus::Refunded);
+    let check = |owned: &[&str], purchases: &[Purchase]| {
+        purchase_anomaly(
+            PurchaseStatus::Paid,
+            Some(&refunded),
+            &library(owned),
+            purchases,
+            &[],
+            entitlements_of,
+        )
+    };
+    assert_eq!(check(&[], std::slice::from_ref(&refunded)), None);
+    assert_eq!(
+        check(&["game_1"], std::slice::from_ref(&refunded)),
+        Some(AnomalyKind::EntitlementWithoutPayment)
+    );
+    // Bought again as part of the bundle
+    assert_eq!(
+        check(
+            &["game_1", "game_2"],
+            &[
+                refunded.clone(),
+                purchase("cs_test_2", "bundle", PurchaseStatus::Paid)
+            ]
+        ),
+        None
+    );
+}
+
+#[test]
+fn test_claimed_gifts_keep_entitlements_of_revoked_purchases() {
+    let refunded = purchase("cs_test_1", "game_1", PurchaseStatus::Refunded);
+    // Bought by someone else, so the purchase is stored under them
+    let claimed = Purchase {
+        user_uuid: "someone else".to_string(),
+        gift: true,
+        ..purchase("cs_test_2", "bundle", PurchaseStatus::Paid)
+    };
+    let check = |purchases: &[Purchase], claimed_gifts: &[Purchase]| {
+        purchase_anomaly(
+            PurchaseStatus::Paid,
+            Some(&refunded),
+            &library(&["game_1", "game_2"]),
+            purchases,
+            claimed_gifts,
+            entitlements_of,
+        )
+    };
+    assert_eq!(
+        check(
+            std::slice::from_ref(&refunded),
+            std::slice::from_ref(&claimed)
+        ),
+        None
+    );
+    // A gift they bought for someone else isn't theirs
+    assert_eq!(
+        check(&[refunded.clone(), claimed.clone()], &[]),
+        Some(AnomalyKind::EntitlementWithoutPayment)
+    );
+    let refunded_gift = Purchase {
+        status: PurchaseStatus::Refunded,
+        ..claimed
+    };
+    assert_eq!(
+        check(
+            std::slice::from_ref(&refunded),
+            std::slice::from_ref(&refunded_gift)
+        ),
+        Some(AnomalyKind::EntitlementWithoutPayment)
+    );
+}
+
+#[test]
+fn test_unmatched_purchases() {
+    let recorded = vec![
+        purchase("cs_test_1", "game_1", PurchaseStatus::Paid),
+        purchase("cs_test_2#game_1", "game_1", PurchaseStatus::Paid),
+        purchase("cs_test_3", "game_1", PurchaseStatus::Paid),
+        // Nothing to explain
+        purchase("cs_test_4", "game_1", PurchaseStatus::Refunded),
+        Purchase {
+            currency: String::new(),
+            payment_intent_id: None,
+            ..purchase("key_abc", "game_1", PurchaseStatus::Paid)
+        },
+    ];
+    let completed = HashSet::from(["cs_test_1", "cs_test_2"]);
+    let unmatched: Vec<&str> = unmatched_purchases(&recorded, &completed)
+        .into_iter()
+        .map(|purchase| purchase.checkout_session_id.as_str())
+        .collect();
+    assert_eq!(unmatched, vec!["cs_test_3"]);
+}
+
+#[test]
+fn test_replayed_checkouts_leave_refunded_lines_revoked() {
+    // Both lines as the checkout describes them, which knows nothing of refunds
+    let lines = vec![
+        purchase("cs_test_1#game_1", "game_1", PurchaseStatus::Paid),
+        purchase("cs_test_1#game_2", "game_2", PurchaseStatus::Paid),
+        purchase("cs_test_1#game_3", "game_3", PurchaseStatus::Paid),
+    ];
+    let recorded = vec![
+        Some(lines[0].clone()),
+        Some(purchase(
+            "cs_test_1#game_2",
+            "game_2",
+            PurchaseStatus::Refunded,
+        )),
+        None,
+    ];
+    let granted: Vec<String> = purchases_to_grant(&lines, recorded)
+        .into_iter()
+        .map(|purchase| purchase.product_id)
+        .collect();
+    assert_eq!(granted, vec!["game_1"]);
+}
+
+/// Runs against stripe-mock (`docker run -p 12111:12111 stripe/stripe-mock`) when
+/// `STRIPE_MOCK_URL` is set, e.g. to `http://localhost:12111`
+#[tokio::test]
+async fn test_list_completed_checkouts_against_stripe_mock() {
+    let Ok(url) = std::env::var("STRIPE_MOCK_URL") else {
+        return;
+    };
+    let client = StripeClient::from_url(url.as_str(), "sk_test_123");
+    let checkouts = list_completed_checkouts(&client, BOUGHT_AT).await.unwrap();
+    assert!(!checkouts.is_empty());
+}
+
+#[test]
+fn test_reconciliation_window_is_capped() {
+    assert_eq!(reconciliation_days(0).unwrap(), DEFAULT_RECONCILIATION_DAYS);
+    assert_eq!(reconciliation_days(7).unwrap(), 7);
+    assert_eq!(
+        reconciliation_days(MAX_RECONCILIATION_DAYS).unwrap(),
+        MAX_RECONCILIATION_DAYS
+    );
+    assert!(matches!(
+        reconciliation_days(MAX_RECONCILIATION_DAYS + 1),
+        Err(NexusError::ReconciliationRangeInvalid)
+    ));
+}
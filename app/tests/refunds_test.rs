@@ -0,0 +1,187 @@
+mod common;
+
+use app::{
+    orders::{RefundRequest, RefundRequestStatus},
+    server::{
+        refunds::{is_refundable, needs_stripe_refund, RefundIneligibility, REFUND_POLICY},
+        repository::{
+            downloads::DownloadStats,
+            purchases::{Purchase, PurchaseStatus},
+            refund_requests::{parse_refund_request, refund_request_to_item},
+        },
+    },
+};
+
+const DAY: i64 = 60 * 60 * 24;
+const BOUGHT_AT: i64 = 1_700_000_000;
+
+fn purchase() -> Purchase {
+    Purchase {
+        created_at: BOUGHT_AT,
+        updated_at: BOUGHT_AT,
+        ..common::purchase()
+    }
+}
+
+fn downloaded(download_count: i64) -> DownloadStats {
+    DownloadStats {
+        download_count,
+        first_downloaded_at: Some(BOUGHT_AT + 60),
+    }
+}
+
+#[test]
+fn test_recent_lightly_used_purchases_are_refunded() {
+    let purchase = purchase();
+    assert_eq!(
+        REFUND_POLICY.check(&purchase, &DownloadStats::default(), BOUGHT_AT + DAY),
+        None
+    );
+    assert_eq!(
+        REFUND_POLICY.check(&purchase, &downloaded(5), BOUGHT_AT + 14 * DAY),
+        None
+    );
+}
+
+#[test]
+fn test_refunds_outside_the_policy_are_reviewed() {
+    let purchase = purchase();
+    assert_eq!(
+        REFUND_POLICY.check(&purchase, &DownloadStats::default(), BOUGHT_AT + 20 * DAY),
+        Some(RefundIneligibility::TooLate {
+            days_since_purchase: 20
+        })
+    );
+    assert_eq!(
+        REFUND_POLICY.check(&purchase, &downloaded(6), BOUGHT_AT + DAY),
+        Some(RefundIneligibility::TooManyDownloads { download_count: 6 })
+    );
+    let gift = Purchase {
+        gift: true,
+        ..purchase
+    };
+    assert_eq!(
+        REFUND_POLICY.check(&gift, &DownloadStats::default(), BOUGHT_AT),
+        Some(RefundIneligibility::Gift)
+    );
+    assert_eq!(
+        RefundIneligibility::TooManyDownloads { download_count: 6 }.describe(),
+        "It was downloaded 6 times"
+    );
+}
+
+#[test]
+fn test_only_paid_charges_are_refundable() {
+    assert!(is_refundable(&purchase()));
+    for status in [
+        PurchaseStatus::Pending,
+        PurchaseStatus::Refunded,
+        PurchaseStatus::Disputed,
+    ] {
+        assert!(!is_refundable(&Purchase {
+            status,
+            ..purchase()
+        }));
+    }
+    // Redeemed keys
+    assert!(!is_refundable(&Purchase {
+        currency: String::new(),
+        amount_total: 0,
+        payment_intent_id: None,
+        ..purchase()
+    }));
+    // Fully discounted
+    assert!(!is_refundable(&Purchase {
+        amount_total: 0,
+        ..purchase()
+    }));
+}
+
+#[test]
+fn test_finishing_a_refund_never_refunds_twice() {
+    let request = RefundRequest {
+        purchase_id: "cs_test_1".to_string(),
+        user_uuid: "8d0c6f3e-3a4f-4d43-9b43-7d1b1b0c9a11".to_string(),
+        email: "buyer@example.com".to_string(),
+        product_id: "game_1".to_string(),
+        amount: 1999,
+        currency: "usd".to_string(),
+        reason: String::new(),
+        ineligibility: None,
+        status: RefundRequestStatus::Refunding,
+        requested_at: BOUGHT_AT,
+        reviewed_by: None,
+        reviewed_at: None,
+        stripe_refund_id: None,
+    };
+    assert!(needs_stripe_refund(&purchase(), &request));
+    // Stripe made the refund, but the request failed before the purchase was taken away
+    let refunded_through_stripe = RefundRequest {
+        stripe_refund_id: Some("re_test_1".to_string()),
+        ..request.clone()
+    };
+    assert!(!needs_stripe_refund(&purchase(), &refunded_through_stripe));
+    // Refunded from the dashboard while the request waited
+    let refunded = Purchase {
+        status: PurchaseStatus::Refunded,
+        ..purchase()
+    };
+    assert!(!needs_stripe_refund(&refunded, &request));
+}
+
+#[test]
+fn test_bundle_usage_adds_up() {
+    let usage = DownloadStats::combine([
+        downloaded(2),
+        DownloadStats {
+            download_count: 3,
+            first_downloaded_at: Some(BOUGHT_AT),
+        },
+        DownloadStats::default(),
+    ]);
+    assert_eq!(usage.download_count, 5);
+    assert_eq!(usage.first_downloaded_at, Some(BOUGHT_AT));
+}
+
+#[test]
+fn test_refund_request_round_trip() {
+    let request = RefundRequest {
+        purchase_id: "cs_test_1#game_1".to_string(),
+        user_uuid: "8d0c6f3e-3a4f-4d43-9b43-7d1b1b0c9a11".to_string(),
+        email: "buyer@example.com".to_string(),
+        product_id: "game_1".to_string(),
+        amount: 1999,
+        currency: "usd".to_string(),
+        reason: "Doesn't run on my laptop".to_string(),
+        ineligibility: Some("It was downloaded 6 times".to_string()),
+        status: RefundRequestStatus::PendingReview,
+        requested_at: BOUGHT_AT,
+        reviewed_by: None,
+        reviewed_at: None,
+        stripe_refund_id: None,
+    };
+    assert_eq!(
+        parse_refund_request(&refund_request_to_item(&request)).unwrap(),
+        request
+    );
+    let refunded = RefundRequest {
+        status: RefundRequestStatus::Refunded,
+        ineligibility: None,
+        reviewed_by: Some("admin@example.com".to_string()),
+        reviewed_at: Some(BOUGHT_AT + DAY),
+        stripe_refund_id: Some("re_test_1".to_string()),
+        ..request
+    };
+    assert_eq!(
+        parse_refund_request(&refund_request_to_item(&refunded)).unwrap(),
+        refunded
+    );
+    for status in [
+        RefundRequestStatus::Refunding,
+        RefundRequestStatus::PendingReview,
+        RefundRequestStatus::Refunded,
+        RefundRequestStatus::Denied,
+    ] {
+        assert_eq!(RefundRequestStatus::parse(status.as_str()), Some(status));
+    }
+}
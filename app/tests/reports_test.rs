@@ -0,0 +1,167 @@
+mod common;
+
+use app::{
+    errors::NexusError,
+    orders::ReportPeriod,
+    server::{
+        reports::{period_start, report_range, sales_report, sales_report_csv},
+        repository::purchases::{Purchase, PurchaseStatus},
+    },
+};
+use chrono::NaiveDate;
+
+/// Tuesday 2023-11-14 00:00:00 UTC
+const TUESDAY: i64 = 1_699_920_000;
+const DAY: i64 = 60 * 60 * 24;
+
+fn purchase(checkout_session_id: &str, created_at: i64, status: PurchaseStatus) -> Purchase {
+    Purchase {
+        checkout_session_id: checkout_session_id.to_string(),
+        payment_intent_id: Some(format!("pi_{}", checkout_session_id)),
+        amount_total: 2399,
+        status,
+        created_at,
+        updated_at: created_at,
+        amount_tax: 400,
+        country: Some("US".to_string()),
+        ..common::purchase()
+    }
+}
+
+#[test]
+fn test_period_start() {
+    assert_eq!(period_start(ReportPeriod::Daily, TUESDAY + 3600), TUESDAY);
+    assert_eq!(
+        period_start(ReportPeriod::Weekly, TUESDAY + 3600),
+        TUESDAY - DAY
+    );
+    // Sunday night is still the week that started on Monday
+    assert_eq!(
+        period_start(ReportPeriod::Weekly, TUESDAY + 6 * DAY - 1),
+        TUESDAY - DAY
+    );
+    assert_eq!(
+        period_start(ReportPeriod::Weekly, TUESDAY + 6 * DAY),
+        TUESDAY + 6 * DAY
+    );
+}
+
+#[test]
+fn test_report_range() {
+    let today = NaiveDate::from_ymd_opt(2023, 11, 14).unwrap();
+    assert_eq!(
+        report_range("2023-11-14", "2023-11-14", today).unwrap(),
+        (TUESDAY, TUESDAY + DAY)
+    );
+    assert_eq!(
+        report_range("", "", today).unwrap(),
+        (TUESDAY - 29 * DAY, TUESDAY + DAY)
+    );
+    assert!(matches!(
+        report_range("2023-11-15", "2023-11-14", today),
+        Err(NexusError::ReportRangeInvalid)
+    ));
+    assert!(matches!(
+        report_range("14/11/2023", "", today),
+        Err(NexusError::ReportRangeInvalid)
+    ));
+}
+
+#[test]
+fn test_sales_report_groups_and_rates() {
+    let purchases = vec![
+        purchase("cs_1", TUESDAY, PurchaseStatus::Paid),
+        purchase("cs_2", TUESDAY + 60, PurchaseStatus::Refunded),
+        purchase("cs_3", TUESDAY + 120, PurchaseStatus::DisputeLost),
+        purchase("cs_4", TUESDAY + 180, PurchaseStatus::Paid),
+        // Never paid for
+        purchase("cs_5", TUESDAY + 240, PurchaseStatus::Failed),
+        // A redeemed key
+        Purchase {
+            currency: String::new(),
+            amount_total: 0,
+            amount_tax: 0,
+            payment_intent_id: None,
+            ..purchase("key_1", TUESDAY + 300, PurchaseStatus::Paid)
+        },
+        Purchase {
+            currency: "eur".to_string(),
+            country: Some("DE".to_string()),
+            amount_total: 1999,
+            amount_discount: 500,
+            ..purchase("cs_6", TUESDAY + DAY, PurchaseStatus::Paid)
+        },
+        Purchase {
+            country: None,
+            ..purchase("cs_7", TUESDAY + DAY, PurchaseStatus::Paid)
+        },
+        // Outside the report
+        purchase("cs_8", TUESDAY + 2 * DAY, PurchaseStatus::Paid),
+    ];
+    let report = sales_report(&purchases, ReportPeriod::Daily, TUESDAY, TUESDAY + 2 * DAY);
+    assert_eq!(report.rows.len(), 3);
+
+    let first = &report.rows[0];
+    assert_eq!(
+        (
+            first.period_start,
+            first.currency.as_str(),
+            first.country.as_str()
+        ),
+        (TUESDAY, "usd", "US")
+    );
+    assert_eq!(first.figures.purchases, 4);
+    assert_eq!(first.figures.gross, 4 * 2399);
+    assert_eq!(first.figures.tax, 4 * 400);
+    assert_eq!(first.figures.net, 2 * 1999);
+    assert_eq!(first.figures.refunds, 1);
+    assert_eq!(first.figures.disputes, 1);
+    assert_eq!(first.figures.refund_rate(), 0.25);
+    assert_eq!(first.figures.dispute_rate(), 0.25);
+
+    let second = &report.rows[1];
+    assert_eq!(
+        (
+            second.period_start,
+            second.currency.as_str(),
+            second.country.as_str()
+        ),
+        (TUESDAY + DAY, "eur", "DE")
+    );
+    assert_eq!(second.figures.discounts, 500);
+    assert_eq!(report.rows[2].country, "");
+
+    assert_eq!(report.totals.len(), 2);
+    assert_eq!(report.totals[0].currency, "eur");
+    assert_eq!(report.totals[1].figures.purchases, 5);
+    assert_eq!(report.totals[1].figures.refund_rate(), 0.2);
+
+    let weekly = sales_report(&purchases, ReportPeriod::Weekly, TUESDAY, TUESDAY + 2 * DAY);
+    assert_eq!(weekly.rows.len(), 3);
+    assert_eq!(weekly.rows[0].period_start, TUESDAY - DAY);
+    assert_eq!(weekly.rows[0].currency, "eur");
+    assert_eq!(weekly.rows[1].country, "");
+}
+
+#[test]
+fn test_sales_report_csv() {
+    let report = sales_report(
+        &[
+            purchase("cs_1", TUESDAY, PurchaseStatus::Paid),
+            purchase("cs_2", TUESDAY, PurchaseStatus::Refunded),
+        ],
+        ReportPeriod::Daily,
+        TUESDAY,
+        TUESDAY + DAY,
+    );
+    let csv = sales_report_csv(&report);
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(
+        lines,
+        vec![
+            "period_start,product_id,currency,country,purchases,gross,tax,discounts,net,refunds,\
+             refund_rate,disputes,dispute_rate",
+            "2023-11-14,game_1,usd,US,2,4798,800,0,1999,1,0.5000,0,0.0000",
+        ]
+    );
+}
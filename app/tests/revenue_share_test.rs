@@ -0,0 +1,152 @@
+mod common;
+
+use app::{
+    catalog::RevenueShare,
+    orders::{Collaborator, RevenueTransfer, RevenueTransferStatus},
+    server::{
+        collaborators::is_valid_collaborator_id,
+        repository::{
+            collaborators::{collaborator_to_item, parse_collaborator},
+            purchases::Purchase,
+            revenue_transfers::{parse_revenue_transfer, revenue_transfer_to_item},
+        },
+        revenue_share::{revenue_transfers_for, REVENUE_SHARE_HOLD_SECONDS},
+    },
+};
+
+const BOUGHT_AT: i64 = 1_700_000_000;
+const NOW: i64 = BOUGHT_AT + 60;
+
+fn purchase() -> Purchase {
+    Purchase {
+        checkout_session_id: "cs_test_1#game_1".to_string(),
+        amount_total: 2199,
+        currency: "eur".to_string(),
+        created_at: BOUGHT_AT,
+        updated_at: BOUGHT_AT,
+        amount_tax: 200,
+        ..common::purchase()
+    }
+}
+
+fn share(collaborator_id: &str, percent: u8) -> RevenueShare {
+    RevenueShare {
+        collaborator_id: collaborator_id.to_string(),
+        percent,
+    }
+}
+
+#[test]
+fn test_shares_are_of_what_was_paid_less_tax() {
+    let transfers = revenue_transfers_for(
+        &purchase(),
+        &[share("composer", 10), share("artist", 25)],
+        NOW,
+    );
+    assert_eq!(transfers.len(), 2);
+    // 10% of 19.99 rounds down
+    assert_eq!(transfers[0].amount, 199);
+    assert_eq!(transfers[1].amount, 499);
+    assert_eq!(transfers[0].transfer_id, "cs_test_1#game_1#composer");
+    assert_eq!(transfers[0].purchase_id, "cs_test_1#game_1");
+    assert_eq!(transfers[1].collaborator_id, "artist");
+    assert_eq!(transfers[1].currency, "eur");
+    assert_eq!(transfers[1].status, RevenueTransferStatus::Pending);
+    assert_eq!(
+        transfers[1].available_at,
+        BOUGHT_AT + REVENUE_SHARE_HOLD_SECONDS
+    );
+    assert_eq!(share("a", 100).amount_of(1999), 1999);
+    assert_eq!(share("a", 50).amount_of(-100), 0);
+}
+
+#[test]
+fn test_nothing_is_shared_of_nothing() {
+    let shares = [share("composer", 10)];
+    // Redeemed keys
+    assert!(revenue_transfers_for(
+        &Purchase {
+            currency: String::new(),
+            amount_total: 0,
+            amount_tax: 0,
+            payment_intent_id: None,
+            ..purchase()
+        },
+        &shares,
+        NOW
+    )
+    .is_empty());
+    // Fully discounted
+    assert!(revenue_transfers_for(
+        &Purchase {
+            amount_total: 0,
+            amount_tax: 0,
+            ..purchase()
+        },
+        &shares,
+        NOW
+    )
+    .is_empty());
+    // Too little for a share to come to a cent
+    assert!(revenue_transfers_for(
+        &Purchase {
+            amount_total: 5,
+            amount_tax: 0,
+            ..purchase()
+        },
+        &shares,
+        NOW
+    )
+    .is_empty());
+    assert!(revenue_transfers_for(&purchase(), &[], NOW).is_empty());
+}
+
+#[test]
+fn test_revenue_transfer_round_trip() {
+    let transfer = revenue_transfers_for(&purchase(), &[share("composer", 10)], NOW)
+        .pop()
+        .unwrap();
+    assert_eq!(
+        parse_revenue_transfer(&revenue_transfer_to_item(&transfer)).unwrap(),
+        transfer
+    );
+    let reversed = RevenueTransfer {
+        status: RevenueTransferStatus::Reversed,
+        stripe_transfer_id: Some("tr_test_1".to_string()),
+        stripe_reversal_id: Some("trr_test_1".to_string()),
+        updated_at: NOW + 60,
+        ..transfer
+    };
+    assert_eq!(
+        parse_revenue_transfer(&revenue_transfer_to_item(&reversed)).unwrap(),
+        reversed
+    );
+    for status in [
+        RevenueTransferStatus::Pending,
+        RevenueTransferStatus::Transferred,
+        RevenueTransferStatus::Cancelled,
+        RevenueTransferStatus::Reversed,
+    ] {
+        assert_eq!(RevenueTransferStatus::parse(status.as_str()), Some(status));
+    }
+}
+
+#[test]
+fn test_collaborator_round_trip() {
+    let collaborator = Collaborator {
+        collaborator_id: "composer".to_string(),
+        name: "Sam Composer".to_string(),
+        email: "sam@example.com".to_string(),
+        stripe_account_id: "acct_test_1".to_string(),
+        transfers_enabled: true,
+        created_at: BOUGHT_AT,
+    };
+    assert_eq!(
+        parse_collaborator(&collaborator_to_item(&collaborator)).unwrap(),
+        collaborator
+    );
+    assert!(is_valid_collaborator_id("sound-team_2"));
+    assert!(!is_valid_collaborator_id(""));
+    assert!(!is_valid_collaborator_id("a#b"));
+    assert!(!is_valid_collaborator_id(&"a".repeat(65)));
+}
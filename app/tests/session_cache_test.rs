@@ -0,0 +1,65 @@
+use app::server::{
+    repository::Session,
+    session_cache::{SessionCache, SessionLookup},
+};
+use std::time::{Duration, Instant};
+
+fn session(email: &str) -> SessionLookup {
+    SessionLookup::Known(Session {
+        email: email.to_string(),
+        session_expiry: i64::MAX,
+    })
+}
+
+#[test]
+fn test_entries_expire_after_ttl() {
+    let cache = SessionCache::default();
+    let now = Instant::now();
+    cache.insert_at(
+        "a".to_string(),
+        session("a@a.com"),
+        Duration::from_secs(30),
+        now,
+    );
+    assert_eq!(cache.get_at("a", now), Some(session("a@a.com")));
+    assert_eq!(cache.get_at("a", now + Duration::from_secs(31)), None);
+}
+
+#[test]
+fn test_unknown_sessions_are_cached() {
+    let cache = SessionCache::default();
+    cache.insert_unknown("guess".to_string());
+    assert_eq!(cache.get("guess"), Some(SessionLookup::Unknown));
+}
+
+#[test]
+fn test_invalidation() {
+    let cache = SessionCache::default();
+    let now = Instant::now();
+    let ttl = Duration::from_secs(30);
+    cache.insert_at("a".to_string(), session("a@a.com"), ttl, now);
+    cache.insert_at("b".to_string(), session("b@b.com"), ttl, now);
+    cache.insert_at("c".to_string(), session("a@a.com"), ttl, now);
+    cache.invalidate("b");
+    assert_eq!(cache.get_at("b", now), None);
+    cache.invalidate_email("a@a.com");
+    assert!(cache.is_empty());
+}
+
+#[test]
+fn test_cache_is_bounded() {
+    let cache = SessionCache::default();
+    let now = Instant::now();
+    for i in 0..10_001 {
+        cache.insert_at(
+            i.to_string(),
+            SessionLookup::Unknown,
+            Duration::from_secs(30),
+            now,
+        );
+    }
+    assert_eq!(cache.len(), 10_000);
+    // The oldest entry is the one that got evicted
+    assert_eq!(cache.get_at("0", now), None);
+    assert_eq!(cache.get_at("10000", now), Some(SessionLookup::Unknown));
+}
@@ -0,0 +1,202 @@
+#![cfg(feature = "sql")]
+
+use app::server::repository::{sql::SqlUserRepository, NewUser, UserRepository};
+
+async fn repository(name: &str) -> SqlUserRepository {
+    let path = std::env::temp_dir().join(format!("nexus-{}-{}.db", name, std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    SqlUserRepository::connect(&format!("sqlite://{}?mode=rwc", path.display()))
+        .await
+        .unwrap()
+}
+
+fn new_user(email: &str) -> NewUser {
+    NewUser {
+        display_name: "display name".to_string(),
+        email: email.to_string(),
+        hashed_password: "hash".to_string(),
+        user_uuid: "user-uuid".to_string(),
+        email_verification_uuid: format!("token-{}", email),
+        account_creation_time: 100,
+    }
+}
+
+#[tokio::test]
+async fn test_signup_verify_and_login() {
+    let repository = repository("login").await;
+    repository
+        .create_user(new_user("a@example.com"))
+        .await
+        .unwrap();
+    assert!(repository
+        .create_user(new_user("a@example.com"))
+        .await
+        .is_err());
+
+    let token = repository
+        .find_verification_token("token-a@example.com")
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(token.email, "a@example.com");
+    assert_eq!(token.request_time, 100);
+    repository.mark_email_verified(&token.email).await.unwrap();
+    let credentials = repository
+        .find_credentials("a@example.com")
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(credentials.email_verified);
+
+    assert!(repository
+        .start_session("a@example.com", "stale hash", "session", 500)
+        .await
+        .is_err());
+    repository
+        .start_session("a@example.com", "hash", "session", 500)
+        .await
+        .unwrap();
+    let session = repository.find_session("session").await.unwrap().unwrap();
+    assert_eq!(session.email, "a@example.com");
+    assert_eq!(session.session_expiry, 500);
+    repository.expire_session("a@example.com").await.unwrap();
+    let session = repository.find_session("session").await.unwrap().unwrap();
+    assert_eq!(session.session_expiry, 0);
+}
+
+#[tokio::test]
+async fn test_entitlements_follow_email_change() {
+    let repository = repository("entitlements").await;
+    repository
+        .create_user(new_user("a@example.com"))
+        .await
+        .unwrap();
+    repository
+        .create_user(new_user("b@example.com"))
+        .await
+        .unwrap();
+    repository
+        .grant_entitlement("a@example.com", "game")
+        .await
+        .unwrap();
+    repository
+        .grant_entitlement("a@example.com", "game")
+        .await
+        .unwrap();
+    assert_eq!(
+        repository.entitlements("a@example.com").await.unwrap(),
+        vec!["game".to_string()]
+    );
+
+    assert!(repository
+        .start_email_change("a@example.com", "b@example.com", "change", 200)
+        .await
+        .is_err());
+    repository
+        .start_email_change("a@example.com", "c@example.com", "change", 200)
+        .await
+        .unwrap();
+    assert_eq!(
+        repository.entitlements("c@example.com").await.unwrap(),
+        vec!["game".to_string()]
+    );
+    let credentials = repository
+        .find_credentials("c@example.com")
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(!credentials.email_verified);
+}
+
+#[tokio::test]
+async fn test_buyer_is_found_by_uuid() {
+    let repository = repository("uuid").await;
+    repository
+        .create_user(new_user("a@example.com"))
+        .await
+        .unwrap();
+    repository
+        .mark_email_verified("a@example.com")
+        .await
+        .unwrap();
+    assert_eq!(
+        repository.find_user_uuid("a@example.com").await.unwrap(),
+        Some("user-uuid".to_string())
+    );
+    // The unverified row of an unfinished email change doesn't take the purchase
+    repository
+        .start_email_change("a@example.com", "c@example.com", "change", 200)
+        .await
+        .unwrap();
+    assert_eq!(
+        repository
+            .find_email_by_user_uuid("user-uuid")
+            .await
+            .unwrap(),
+        Some("a@example.com".to_string())
+    );
+    assert_eq!(
+        repository.find_email_by_user_uuid("nobody").await.unwrap(),
+        None
+    );
+}
+
+#[tokio::test]
+async fn test_stripe_customer_is_linked_once_and_deleted_with_the_account() {
+    let repository = repository("customer").await;
+    repository
+        .create_user(new_user("a@example.com"))
+        .await
+        .unwrap();
+    let profile = repository
+        .find_billing_profile("a@example.com")
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(profile.display_name, "display name");
+    assert_eq!(profile.stripe_customer_id, None);
+
+    assert_eq!(
+        repository
+            .link_stripe_customer("a@example.com", "cus_first")
+            .await
+            .unwrap(),
+        "cus_first"
+    );
+    // A second checkout racing the first keeps the customer that got there first
+    assert_eq!(
+        repository
+            .link_stripe_customer("a@example.com", "cus_second")
+            .await
+            .unwrap(),
+        "cus_first"
+    );
+
+    repository
+        .start_email_change("a@example.com", "c@example.com", "change", 200)
+        .await
+        .unwrap();
+    let profile = repository
+        .find_billing_profile("c@example.com")
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(profile.stripe_customer_id.as_deref(), Some("cus_first"));
+
+    repository
+        .start_session("a@example.com", "hash", "session", 500)
+        .await
+        .unwrap();
+    repository.delete_account("user-uuid").await.unwrap();
+    assert!(repository
+        .find_billing_profile("a@example.com")
+        .await
+        .unwrap()
+        .is_none());
+    assert!(repository
+        .find_billing_profile("c@example.com")
+        .await
+        .unwrap()
+        .is_none());
+    assert!(repository.find_session("session").await.unwrap().is_none());
+}
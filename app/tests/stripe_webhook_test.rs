@@ -0,0 +1,227 @@
+use app::{
+    errors::NexusError,
+    server::{
+        repository::dynamo::DynamoUserRepository,
+        stripe_webhook::{
+            handlers::nexus_router,
+            router::{WebhookContext, WebhookHandler, WebhookRouter},
+            signed_event::{SignedStripeEvent, StripeEvent},
+        },
+    },
+};
+use async_trait::async_trait;
+use aws_sdk_dynamodb::config::{BehaviorVersion, Region};
+use axum::{body::Body, extract::FromRequest, http::Request};
+use hmac::{Hmac, Mac};
+use http::StatusCode;
+use leptos::ServerFnError;
+use sha2::Sha256;
+use std::sync::{Arc, Mutex};
+use stripe::EventType;
+
+const TEST_SECRET: &str = "whsec_test_secret";
+
+fn fixture(name: &str) -> String {
+    let path = format!(
+        "{}/tests/fixtures/stripe/{}.json",
+        env!("CARGO_MANIFEST_DIR"),
+        name
+    );
+    std::fs::read_to_string(&path).unwrap_or_else(|e| panic!("{}: {:?}", path, e))
+}
+
+/// A `Stripe-Signature` header for `payload`, signed the way Stripe does it
+fn sign(payload: &str, secret: &str) -> String {
+    let timestamp = chrono::Utc::now().timestamp();
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(format!("{}.{}", timestamp, payload).as_bytes());
+    let signature: String = mac
+        .finalize()
+        .into_bytes()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    format!("t={},v1={}", timestamp, signature)
+}
+
+fn signed(name: &str) -> StripeEvent {
+    let payload = fixture(name);
+    match SignedStripeEvent::verify(&payload, &sign(&payload, TEST_SECRET), TEST_SECRET) {
+        Ok(signed) => signed.event,
+        Err(e) => panic!("{} didn't verify {:?}", name, e),
+    }
+}
+
+/// Clients that are never sent anything, for handlers that don't use them
+fn offline_context() -> WebhookContext {
+    let dynamodb_client = Arc::new(aws_sdk_dynamodb::Client::from_conf(
+        aws_sdk_dynamodb::Config::builder()
+            .behavior_version(BehaviorVersion::latest())
+            .region(Region::new("us-east-1"))
+            .build(),
+    ));
+    WebhookContext {
+        dynamodb_client: dynamodb_client.clone(),
+        user_repository: Arc::new(DynamoUserRepository::new(dynamodb_client)),
+        stripe_client: Arc::new(stripe::Client::new("sk_test_offline")),
+        ses_client: Arc::new(aws_sdk_ses::Client::from_conf(
+            aws_sdk_ses::Config::builder()
+                .behavior_version(aws_sdk_ses::config::BehaviorVersion::latest())
+                .region(aws_sdk_ses::config::Region::new("us-east-1"))
+                .build(),
+        )),
+    }
+}
+
+#[test]
+fn test_signed_fixtures_parse() {
+    let checkout = signed("checkout_session_completed");
+    assert_eq!(checkout.type_(), EventType::CheckoutSessionCompleted);
+    assert_eq!(
+        checkout
+            .checkout_session()
+            .unwrap()
+            .client_reference_id
+            .as_deref(),
+        Some("8d0c6f3e-3a4f-4d43-9b43-7d1b1b0c9a11")
+    );
+
+    let refund = signed("charge_refunded");
+    assert!(refund.charge().unwrap().refunded);
+    assert!(refund.checkout_session().is_err());
+
+    let warning = signed("radar_early_fraud_warning_created");
+    assert_eq!(warning.type_(), EventType::RadarEarlyFraudWarningCreated);
+    assert_eq!(warning.id(), "evt_1PearlyFraudWarning");
+    assert_eq!(
+        warning
+            .early_fraud_warning()
+            .unwrap()
+            .payment_intent
+            .as_deref(),
+        Some("pi_test_1")
+    );
+}
+
+#[test]
+fn test_bad_signatures_are_rejected() {
+    let payload = fixture("charge_refunded");
+    let wrong_secret =
+        SignedStripeEvent::verify(&payload, &sign(&payload, "whsec_someone_else"), TEST_SECRET);
+    assert_eq!(wrong_secret.err().unwrap().0, StatusCode::UNAUTHORIZED);
+
+    let tampered = payload.replace("\"refunded\": true", "\"refunded\": false");
+    let tampered = SignedStripeEvent::verify(&tampered, &sign(&payload, TEST_SECRET), TEST_SECRET);
+    assert_eq!(tampered.err().unwrap().0, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn test_extractor_verifies_requests() {
+    std::env::set_var("STRIPE_WEBHOOK_SECRET", TEST_SECRET);
+    let payload = fixture("checkout_session_completed");
+    let request = Request::builder()
+        .method("POST")
+        .header("Stripe-Signature", sign(&payload, TEST_SECRET))
+        .body(Body::from(payload.clone()))
+        .unwrap();
+    let signed = SignedStripeEvent::from_request(request, &()).await.unwrap();
+    assert_eq!(signed.payload, payload);
+    assert_eq!(signed.event.id(), "evt_1PcheckoutCompleted");
+
+    let request = Request::builder()
+        .method("POST")
+        .header("Stripe-Signature", sign(&payload, "whsec_someone_else"))
+        .body(Body::from(payload))
+        .unwrap();
+    let rejected = SignedStripeEvent::from_request(request, &()).await;
+    assert_eq!(rejected.err().unwrap().0, StatusCode::UNAUTHORIZED);
+}
+
+#[test]
+fn test_store_events_have_handlers() {
+    let router = nexus_router();
+    for event_type in [
+        EventType::CheckoutSessionCompleted,
+        EventType::CheckoutSessionAsyncPaymentSucceeded,
+        EventType::CheckoutSessionAsyncPaymentFailed,
+        EventType::ChargeRefunded,
+        EventType::ChargeDisputeCreated,
+        EventType::ChargeDisputeClosed,
+        EventType::RadarEarlyFraudWarningCreated,
+        EventType::BalanceAvailable,
+    ] {
+        assert!(router.handles(event_type), "{:?}", event_type);
+    }
+    assert!(!router.handles(EventType::CustomerCreated));
+}
+
+struct Recording(Arc<Mutex<Vec<String>>>);
+
+#[async_trait]
+impl WebhookHandler for Recording {
+    async fn handle(
+        &self,
+        _context: &WebhookContext,
+        event: &StripeEvent,
+    ) -> Result<(), ServerFnError<NexusError>> {
+        self.0.lock().unwrap().push(event.id());
+        Ok(())
+    }
+}
+
+/// Expects a charge, and treats a missing purchase as worth retrying
+struct NeedsPurchase;
+
+#[async_trait]
+impl WebhookHandler for NeedsPurchase {
+    async fn handle(
+        &self,
+        _context: &WebhookContext,
+        event: &StripeEvent,
+    ) -> Result<(), ServerFnError<NexusError>> {
+        event.charge()?;
+        Err(ServerFnError::from(NexusError::PurchaseNotFound))
+    }
+
+    fn error_status(&self, error: &ServerFnError<NexusError>) -> StatusCode {
+        match error {
+            ServerFnError::WrappedServerError(NexusError::PurchaseNotFound) => {
+                StatusCode::NOT_FOUND
+            }
+            _ => StatusCode::BAD_REQUEST,
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_dispatch_routes_by_event_type() {
+    let context = offline_context();
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let router = WebhookRouter::new()
+        .on(EventType::CustomerCreated, Recording(seen.clone()))
+        .on(EventType::ChargeRefunded, NeedsPurchase)
+        .on(EventType::RadarEarlyFraudWarningCreated, NeedsPurchase);
+
+    router
+        .dispatch(&context, &signed("customer_created"))
+        .await
+        .unwrap();
+    assert_eq!(*seen.lock().unwrap(), vec!["evt_1PcustomerCreated"]);
+
+    // Nothing is registered for it, so it's acknowledged
+    router
+        .dispatch(&context, &signed("checkout_session_completed"))
+        .await
+        .unwrap();
+
+    let (status, _) = router
+        .dispatch(&context, &signed("charge_refunded"))
+        .await
+        .unwrap_err();
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _) = router
+        .dispatch(&context, &signed("radar_early_fraud_warning_created"))
+        .await
+        .unwrap_err();
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
@@ -0,0 +1,32 @@
+use app::server::repository::users::UserUpdate;
+use aws_sdk_dynamodb::types::AttributeValue;
+
+#[test]
+fn test_update_expression_always_bumps_version() {
+    let update = UserUpdate::new();
+    assert_eq!(update.update_expression(), "SET #version = :next_version");
+}
+
+#[test]
+fn test_update_expression_with_set_and_remove() {
+    let update = UserUpdate::new()
+        .set("session_id", AttributeValue::S("abc".to_string()))
+        .set("session_expiry", AttributeValue::N("0".to_string()))
+        .remove("email_verification_uuid");
+    assert_eq!(
+        update.update_expression(),
+        "SET #a0 = :v0, #a1 = :v1, #version = :next_version REMOVE #r0"
+    );
+}
+
+#[test]
+fn test_condition_expression_handles_unversioned_rows() {
+    assert_eq!(
+        UserUpdate::condition_expression(None),
+        "attribute_exists(#email) AND attribute_not_exists(#version)"
+    );
+    assert_eq!(
+        UserUpdate::condition_expression(Some(3)),
+        "attribute_exists(#email) AND #version = :expected_version"
+    );
+}
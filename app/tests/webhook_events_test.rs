@@ -0,0 +1,56 @@
+use app::server::{
+    admin::email_in_list,
+    repository::webhook_events::{
+        outcome_for_existing, BeginOutcome, WebhookEventStatus, PROCESSING_LEASE_SECS,
+    },
+};
+
+#[test]
+fn test_replayed_deliveries_are_no_ops() {
+    assert_eq!(
+        outcome_for_existing(WebhookEventStatus::Processed, 0, 1_000),
+        BeginOutcome::AlreadyProcessed
+    );
+    assert_eq!(
+        outcome_for_existing(WebhookEventStatus::Failed, 990, 1_000),
+        BeginOutcome::Process
+    );
+}
+
+#[test]
+fn test_received_events_are_leased() {
+    let now = 1_000;
+    assert_eq!(
+        outcome_for_existing(WebhookEventStatus::Received, now - 1, now),
+        BeginOutcome::InProgress
+    );
+    assert_eq!(
+        outcome_for_existing(
+            WebhookEventStatus::Received,
+            now - PROCESSING_LEASE_SECS,
+            now
+        ),
+        BeginOutcome::Process
+    );
+}
+
+#[test]
+fn test_status_round_trips() {
+    for status in [
+        WebhookEventStatus::Received,
+        WebhookEventStatus::Processed,
+        WebhookEventStatus::Failed,
+    ] {
+        assert_eq!(WebhookEventStatus::parse(status.as_str()), Some(status));
+    }
+    assert_eq!(WebhookEventStatus::parse("unknown"), None);
+}
+
+#[test]
+fn test_admin_email_list() {
+    let admins = "admin@example.com, Other@Example.com,";
+    assert!(email_in_list(admins, "admin@example.com"));
+    assert!(email_in_list(admins, "other@example.com"));
+    assert!(!email_in_list(admins, "user@example.com"));
+    assert!(!email_in_list(admins, ""));
+}
@@ -9,7 +9,6 @@ use leptos::{logging::log, provide_context};
 use leptos_axum::handle_server_fns_with_context;
 
 pub mod fileserv;
-pub mod stripe_webhook;
 
 async fn server_fn_handler(
     State(app_state): State<AppState>,
@@ -21,9 +20,11 @@ async fn server_fn_handler(
     handle_server_fns_with_context(
         move || {
             provide_context(app_state.dynamodb_client.clone());
+            provide_context(app_state.user_repository.clone());
             provide_context(app_state.ses_client.clone());
             provide_context(app_state.stripe_client.clone());
             provide_context(app_state.s3_client.clone());
+            provide_context(app_state.key_client.clone());
         },
         request,
     )
@@ -39,9 +40,11 @@ async fn leptos_routes_handler(
         app_state.routes.clone(),
         move || {
             provide_context(app_state.dynamodb_client.clone());
+            provide_context(app_state.user_repository.clone());
             provide_context(app_state.ses_client.clone());
             provide_context(app_state.stripe_client.clone());
             provide_context(app_state.s3_client.clone());
+            provide_context(app_state.key_client.clone());
         },
         NexusApp,
     );
@@ -51,8 +54,16 @@ async fn leptos_routes_handler(
 #[tokio::main]
 async fn main() {
     use app::server::globals::app_state::AppState;
+    #[cfg(not(feature = "sql"))]
+    use app::server::repository::dynamo::DynamoUserRepository;
+    #[cfg(feature = "sql")]
+    use app::server::repository::sql::SqlUserRepository;
+    use app::server::repository::UserRepository;
+    use app::server::stripe_webhook::{
+        replay_failed_webhook_events, replay_webhook_event, stripe_webhook,
+    };
     use app::NexusApp;
-    use aws_config::BehaviorVersion;
+    use aws_config::{retry::RetryConfig, BehaviorVersion};
     use aws_sdk_dynamodb::Client as DynamoClient;
     use aws_sdk_kms::Client as KmsClient;
     use aws_sdk_s3::Client as S3Client;
@@ -61,8 +72,8 @@ async fn main() {
     use fileserv::file_and_error_handler;
     use leptos::get_configuration;
     use leptos_axum::{generate_route_list, LeptosRoutes};
+    use std::sync::Arc;
     use stripe::Client as StripeClient;
-    use stripe_webhook::stripe_webhook;
 
     simple_logger::init_with_level(log::Level::Info).expect("couldn't initialize logging");
 
@@ -79,14 +90,42 @@ async fn main() {
     let routes = generate_route_list(NexusApp);
 
     let aws_sdk_config = aws_config::load_defaults(BehaviorVersion::latest()).await;
+    // DynamoDB calls are retried (with jitter and per-operation timeouts) by
+    // `app::server::globals::dynamo_error::send_with_retry`, so the SDK must not retry as well
+    let dynamo_config = aws_sdk_dynamodb::config::Builder::from(&aws_sdk_config)
+        .retry_config(RetryConfig::disabled())
+        .build();
 
     // let stripe_secret_key =
     //     std::env::var("STRIPE_SECRET_KEY").expect("Missing STRIPE_SECRET_KEY in env");
     let stripe_secret_key = std::env!("STRIPE_SECRET_KEY");
+    let dynamodb_client: Arc<DynamoClient> = DynamoClient::from_conf(dynamo_config).into();
+    // Self-hosted deployments can keep users, sessions and entitlements in SQLite or Postgres.
+    // Only accounts move: every other table is still in DynamoDB, so refuse to start without it
+    #[cfg(feature = "sql")]
+    let user_repository: Arc<dyn UserRepository> = {
+        let missing_tables =
+            app::server::repository::sql::missing_dynamo_tables(&dynamodb_client).await;
+        assert!(
+            missing_tables.is_empty(),
+            "The sql feature only moves accounts out of DynamoDB, but these tables are missing: {}",
+            missing_tables.join(", ")
+        );
+        let database_url = std::env::var("DATABASE_URL").expect("Missing DATABASE_URL in env");
+        Arc::new(
+            SqlUserRepository::connect(&database_url)
+                .await
+                .expect("couldn't connect to the database"),
+        )
+    };
+    #[cfg(not(feature = "sql"))]
+    let user_repository: Arc<dyn UserRepository> =
+        Arc::new(DynamoUserRepository::new(dynamodb_client.clone()));
     let app_state = AppState {
         leptos_options,
         routes: routes.clone(),
-        dynamodb_client: DynamoClient::new(&aws_sdk_config).into(),
+        dynamodb_client,
+        user_repository,
         ses_client: SesClient::new(&aws_sdk_config).into(),
         stripe_client: StripeClient::new(stripe_secret_key).into(),
         s3_client: S3Client::new(&aws_sdk_config).into(),
@@ -96,6 +135,14 @@ async fn main() {
     // build our application with a route
     let app = Router::new()
         .route("/api/webhooks/stripe", axum::routing::post(stripe_webhook))
+        .route(
+            "/api/webhooks/stripe/replay",
+            axum::routing::post(replay_failed_webhook_events),
+        )
+        .route(
+            "/api/webhooks/stripe/replay/:event_id",
+            axum::routing::post(replay_webhook_event),
+        )
         .route(
             "/api/download/launcher/:os_type",
             axum::routing::post(app::server::download::download_launcher::download_launcher),
@@ -106,6 +153,22 @@ async fn main() {
                 app::server::download::download_game_version::download_game_version,
             ),
         )
+        .route(
+            "/api/download/:game/:platform/:version/*path",
+            axum::routing::post(app::server::download::manifest::download_build_file),
+        )
+        .route(
+            "/api/manifest/:game/:platform/:version",
+            axum::routing::post(app::server::download::manifest::get_build_manifest),
+        )
+        .route(
+            "/api/patch/:game/:platform/:from/:to",
+            axum::routing::post(app::server::download::patch::get_patch_plan),
+        )
+        .route(
+            "/api/patch/:game/:platform/:from/:to/*path",
+            axum::routing::post(app::server::download::patch::download_patch_delta),
+        )
         .route(
             "/api/*fn_name",
             get(server_fn_handler).post(server_fn_handler),